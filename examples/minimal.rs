@@ -41,10 +41,11 @@ use std::{
     convert::TryInto,
     iter,
     net::{IpAddr, Ipv4Addr, SocketAddr},
+    path::PathBuf,
 };
 use structopt::StructOpt;
 use tokio::task::JoinHandle;
-use tracing::info;
+use tracing::{info, warn};
 use tracing_subscriber::EnvFilter;
 
 /// Minimal example node.
@@ -52,15 +53,21 @@ use tracing_subscriber::EnvFilter;
 struct Options {
     /// Socket address (e.g. 203.0.113.45:6789) of a node(s) to bootstrap against. Multiple
     /// contacts can be specified by passing the option multiple times. If omitted, will try to use
-    /// contacts cached from previous run, if any.
+    /// contacts cached from previous run (see --bootstrap-cache), if any.
     #[structopt(
         short,
         long,
         name = "bootstrap-contact",
         value_name = "SOCKET_ADDRESS",
-        required_unless = "first"
+        required_unless_one = &["first", "bootstrap-cache"]
     )]
     bootstrap_contacts: Vec<SocketAddr>,
+    /// File to cache the contacts learned while running in, to bootstrap from on the next run.
+    ///
+    /// If starting multiple nodes (see --count), the index of the node is appended to the file
+    /// name of all but the first one.
+    #[structopt(long, name = "bootstrap-cache", value_name = "PATH")]
+    bootstrap_cache: Option<PathBuf>,
     /// Whether this is the first node ("genesis node") of the network. Only one node can be first.
     #[structopt(short, long, conflicts_with = "bootstrap-contact")]
     first: bool,
@@ -102,14 +109,21 @@ async fn main() {
     init_log(opts.verbosity);
 
     let handles: Vec<_> = if opts.count <= 1 {
-        let handle =
-            start_single_node(opts.first, opts.bootstrap_contacts, opts.ip, opts.port).await;
+        let handle = start_single_node(
+            opts.first,
+            opts.bootstrap_contacts,
+            opts.bootstrap_cache,
+            opts.ip,
+            opts.port,
+        )
+        .await;
         iter::once(handle).collect()
     } else {
         start_multiple_nodes(
             opts.count,
             opts.first,
             opts.bootstrap_contacts,
+            opts.bootstrap_cache,
             opts.ip,
             opts.port,
         )
@@ -122,10 +136,11 @@ async fn main() {
 async fn start_single_node(
     first: bool,
     contacts: Vec<SocketAddr>,
+    bootstrap_cache: Option<PathBuf>,
     ip: Option<IpAddr>,
     port: Option<u16>,
 ) -> JoinHandle<()> {
-    let (_contact, handle) = start_node(0, first, contacts.into_iter().collect(), ip, port).await;
+    let (_contact, handle) = start_node(0, first, contacts, bootstrap_cache, ip, port).await;
     handle
 }
 
@@ -137,13 +152,26 @@ async fn start_multiple_nodes(
     count: usize,
     first: bool,
     mut contacts: Vec<SocketAddr>,
+    bootstrap_cache: Option<PathBuf>,
     ip: Option<IpAddr>,
     base_port: Option<u16>,
 ) -> Vec<JoinHandle<()>> {
+    let cache_path = |index: usize| {
+        bootstrap_cache.as_ref().map(|path| {
+            if index == 0 {
+                path.clone()
+            } else {
+                let mut path = path.clone().into_os_string();
+                path.push(format!(".{}", index));
+                path.into()
+            }
+        })
+    };
+
     let mut handles = Vec::new();
     let first_index = if first {
         let (first_contact, first_handle) =
-            start_node(0, true, Vec::default(), ip, base_port).await;
+            start_node(0, true, Vec::default(), cache_path(0), ip, base_port).await;
         contacts.push(first_contact);
         handles.push(first_handle);
         1
@@ -152,8 +180,15 @@ async fn start_multiple_nodes(
     };
 
     for index in first_index..count {
-        let (_contact_info, handle) =
-            start_node(index, false, contacts.clone(), ip, base_port).await;
+        let (_contact_info, handle) = start_node(
+            index,
+            false,
+            contacts.clone(),
+            cache_path(index),
+            ip,
+            base_port,
+        )
+        .await;
         handles.push(handle);
     }
     handles
@@ -164,6 +199,7 @@ async fn start_node(
     index: usize,
     first: bool,
     contacts: Vec<SocketAddr>,
+    bootstrap_cache: Option<PathBuf>,
     ip: Option<IpAddr>,
     base_port: Option<u16>,
 ) -> (SocketAddr, JoinHandle<()>) {
@@ -189,6 +225,7 @@ async fn start_node(
     let config = Config {
         first,
         transport_config,
+        bootstrap_cache,
         ..Default::default()
     };
     let (node, event_stream) = Routing::new(config)
@@ -264,6 +301,18 @@ async fn handle_event(index: usize, node: &mut Routing, event: Event) -> bool {
             dst,
             HexFmt(&content)
         ),
+        Event::MessageDelivered { msg_id } => {
+            info!("Node #{} message delivered - id: {:?}", index, msg_id)
+        }
+        Event::RequestReceived {
+            content, src, dst, ..
+        } => info!(
+            "Node #{} received request - src: {:?}, dst: {:?}, content: {}",
+            index,
+            src,
+            dst,
+            HexFmt(&content)
+        ),
         Event::RelocationStarted { previous_name } => info!(
             "Node #{} relocation started - previous_name: {}",
             index, previous_name
@@ -284,6 +333,10 @@ async fn handle_event(index: usize, node: &mut Routing, event: Event) -> bool {
             index, user, msg
         ),
         Event::ClientLost(addr) => info!("Node #{} received ClientLost({:?})", index, addr),
+        Event::ClientSessionExpired { user, addr } => info!(
+            "Node #{} received ClientSessionExpired {{ user: {:?}, addr: {:?} }}",
+            index, user, addr
+        ),
         Event::AdultsChanged {
             remaining,
             added,
@@ -292,6 +345,10 @@ async fn handle_event(index: usize, node: &mut Routing, event: Event) -> bool {
             "Node #{} adults changed - remaining: {:?}, added: {:?}, removed: {:?}",
             index, remaining, added, removed
         ),
+        Event::NetworkStatsChanged(stats) => {
+            info!("Node #{} network stats changed - {:?}", index, stats)
+        }
+        Event::Lagged(count) => warn!("Node #{} missed {} events", index, count),
     }
 
     true
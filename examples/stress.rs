@@ -25,13 +25,14 @@ use sn_messaging::{
 };
 use sn_routing::{
     Cache, Config, Error as RoutingError, Event as RoutingEvent, NodeElderChange, Routing,
-    TransportConfig,
+    SectionRelation, Topology, TransportConfig,
 };
 use std::{
     collections::BTreeMap,
-    fs::File,
+    fs::{self, File},
     io::BufWriter,
     net::{Ipv4Addr, SocketAddr},
+    path::{Path, PathBuf},
     sync::Arc,
     time::{Duration, Instant},
 };
@@ -54,6 +55,9 @@ const MIN_PRINT_DELAY: Duration = Duration::from_millis(500);
 // Time after which we stop tracking a probe message, regardless of its state (delivered or not).
 const PROBE_WINDOW: Duration = Duration::from_secs(60);
 
+// How often to export the merged network topology, if enabled.
+const TOPOLOGY_EXPORT_INTERVAL: Duration = Duration::from_secs(5);
+
 /// Stress test for sn-routing.
 #[derive(Debug, StructOpt)]
 struct Options {
@@ -61,6 +65,11 @@ struct Options {
     /// is disabled.
     #[structopt(short, long, name = "PATH")]
     log: Option<String>,
+    /// Periodically write the network topology as seen by all the nodes together to the given
+    /// file, as a Graphviz DOT graph. Sections are coloured by how many nodes don't know them or
+    /// know only an outdated key of theirs.
+    #[structopt(short, long, name = "DOT_PATH")]
+    topology: Option<PathBuf>,
     /// How many probe messages to send per second.
     ///
     /// Probe messages are used to determine network health. The higher the percentage of
@@ -131,6 +140,7 @@ async fn main() -> Result<()> {
 
     let probe_interval = Duration::from_secs_f64(1.0 / opts.probe_frequency);
     let mut probes = time::interval(probe_interval);
+    let mut topology_exports = time::interval(TOPOLOGY_EXPORT_INTERVAL);
 
     loop {
         tokio::select! {
@@ -153,6 +163,11 @@ async fn main() -> Result<()> {
                 }
             }
             _ = probes.tick() => network.send_probes().await?,
+            _ = topology_exports.tick(), if opts.topology.is_some() => {
+                if let Some(path) = &opts.topology {
+                    network.export_topology(path).await?
+                }
+            }
         }
     }
 
@@ -411,6 +426,19 @@ impl Network {
             .collect::<Vec<_>>()
     }
 
+    // Merges the topology views of all the joined nodes and writes them to `path` as DOT.
+    async fn export_topology(&self, path: &Path) -> Result<()> {
+        let mut merged = MergedTopology::default();
+        for node in self.nodes.values() {
+            if let Node::Joined { node, .. } = node {
+                merged.add(&node.topology().await);
+            }
+        }
+
+        fs::write(path, merged.to_dot())
+            .with_context(|| format!("failed to write topology to {}", path.display()))
+    }
+
     // Send messages to probe network health.
     async fn send_probes(&mut self) -> Result<()> {
         // Cache the (src, dst) pairs of sent messages to ensure every node from the same
@@ -836,6 +864,79 @@ impl ProbeTracker {
     }
 }
 
+// Network topology as seen by all the nodes together.
+#[derive(Default)]
+struct MergedTopology {
+    num_views: usize,
+    sections: BTreeMap<Prefix, MergedSection>,
+}
+
+#[derive(Default)]
+struct MergedSection {
+    // Number of views the section is known in, per key.
+    keys: BTreeMap<bls::PublicKey, usize>,
+    // Number of the section's own members reporting each key as current.
+    own_keys: BTreeMap<bls::PublicKey, usize>,
+}
+
+impl MergedSection {
+    // The key the most of the section's own members consider current.
+    fn current_key(&self) -> Option<&bls::PublicKey> {
+        self.own_keys
+            .iter()
+            .max_by_key(|(_, count)| **count)
+            .map(|(key, _)| key)
+    }
+
+    // Number of views the section is known in with an outdated key.
+    fn stale(&self) -> usize {
+        let current_key = self.current_key();
+        self.keys
+            .iter()
+            .filter(|(key, _)| Some(*key) != current_key)
+            .map(|(_, count)| count)
+            .sum()
+    }
+}
+
+impl MergedTopology {
+    fn add(&mut self, topology: &Topology) {
+        self.num_views += 1;
+
+        for section in &topology.sections {
+            let merged = self.sections.entry(section.prefix).or_default();
+            *merged.keys.entry(section.key).or_insert(0) += 1;
+            if section.relation == SectionRelation::Our {
+                *merged.own_keys.entry(section.key).or_insert(0) += 1;
+            }
+        }
+    }
+
+    fn to_dot(&self) -> String {
+        Topology::dot_tree(
+            "network",
+            self.sections.iter().map(|(prefix, section)| {
+                let known: usize = section.keys.values().sum();
+                let missing = self.num_views.saturating_sub(known);
+                let stale = section.stale();
+                let color = if missing == 0 && stale == 0 {
+                    "palegreen"
+                } else if 2 * (missing + stale) < self.num_views {
+                    "khaki"
+                } else {
+                    "salmon"
+                };
+                let label = format!(
+                    "known by: {}/{}\\nstale key: {}",
+                    known, self.num_views, stale
+                );
+
+                (*prefix, color, label)
+            }),
+        )
+    }
+}
+
 struct Theme {
     label: Style,
     value: Style,
@@ -1,27 +1,29 @@
-use std::time::{Duration, Instant};
+use std::time::Duration;
+use tokio::time::Instant;
 
 #[derive(Clone, Debug)]
 pub struct Item<T> {
     pub object: T,
     time: Option<Time>,
+    // Position in the order of eviction, the lowest evicted first.
+    pub(super) rank: u64,
 }
 
 #[derive(Clone, Copy, Debug)]
 struct Time {
-    pub(crate) start: Instant,
     pub(crate) expiry: Instant,
 }
 
 impl<T> Item<T> {
     pub fn new(object: T, item_duration: Option<Duration>) -> Self {
-        let time = item_duration.map(|duration| {
-            let start = Instant::now();
-            Time {
-                start,
-                expiry: start + duration,
-            }
+        let time = item_duration.map(|duration| Time {
+            expiry: Instant::now() + duration,
         });
-        Item { object, time }
+        Item {
+            object,
+            time,
+            rank: 0,
+        }
     }
 
     pub fn expired(&self) -> bool {
@@ -29,13 +31,6 @@ impl<T> Item<T> {
             .map(|time| time.expiry < Instant::now())
             .unwrap_or(false)
     }
-
-    pub fn elapsed(&self) -> u128 {
-        self.time
-            .map(|time| Instant::now() - time.start)
-            .unwrap_or_default()
-            .as_millis()
-    }
 }
 
 #[cfg(test)]
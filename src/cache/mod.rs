@@ -10,12 +10,27 @@ mod item;
 
 use self::item::Item;
 use itertools::Itertools;
-use std::collections::BTreeMap;
-use std::hash::Hash;
-use std::time::Duration;
-use tokio::sync::RwLock;
+use std::{
+    collections::{BTreeMap, HashMap},
+    future::Future,
+    hash::Hash,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex, PoisonError, Weak,
+    },
+    time::Duration,
+};
+use tokio::{
+    sync::{Mutex as AsyncMutex, RwLock},
+    task::JoinHandle,
+    time,
+};
 
 /// A [`BTreeMap`]-backed cache supporting capacity- and duration-based expiry.
+///
+/// Over capacity, it evicts the items in insertion order, or in least recently used order if
+/// created `with_eviction(EvictionPolicy::LeastRecentlyUsed)`. Expired items are removed on `set`,
+/// or periodically by the task started with `spawn_sweeper`.
 #[derive(Debug)]
 pub struct Cache<T, V>
 where
@@ -24,6 +39,43 @@ where
     items: RwLock<BTreeMap<T, Item<V>>>,
     item_duration: Option<Duration>,
     capacity: usize,
+    eviction: EvictionPolicy,
+    // Rank of the next item inserted, or accessed in LRU mode.
+    next_rank: AtomicU64,
+    // Keys being loaded by `get_or_insert_with`, to coalesce concurrent loads of the same key.
+    loading: Mutex<HashMap<T, Arc<AsyncMutex<()>>>>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    evictions: AtomicU64,
+    expirations: AtomicU64,
+}
+
+/// Which items a [`Cache`] over its capacity removes first.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum EvictionPolicy {
+    /// The ones inserted the longest ago, whether they were read since or not.
+    Insertion,
+    /// The ones inserted or read the longest ago.
+    LeastRecentlyUsed,
+}
+
+impl Default for EvictionPolicy {
+    fn default() -> Self {
+        Self::Insertion
+    }
+}
+
+/// Statistics of a [`Cache`], since it was created.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct CacheStats {
+    /// Lookups that found a value.
+    pub hits: u64,
+    /// Lookups that found no value, or an expired one.
+    pub misses: u64,
+    /// Items removed to keep the cache within its capacity.
+    pub evictions: u64,
+    /// Expired items removed.
+    pub expirations: u64,
 }
 
 #[allow(clippy::len_without_is_empty)]
@@ -33,31 +85,41 @@ where
 {
     /// Creating capacity based `Cache`.
     pub fn with_capacity(capacity: usize) -> Self {
-        Self {
-            items: RwLock::new(BTreeMap::new()),
-            item_duration: None,
-            capacity,
-        }
+        Self::new(None, capacity)
     }
 
     /// Creating time based `Cache`.
     pub fn with_expiry_duration(duration: Duration) -> Self {
-        Self {
-            items: RwLock::new(BTreeMap::new()),
-            item_duration: Some(duration),
-            capacity: usize::MAX,
-        }
+        Self::new(Some(duration), usize::MAX)
     }
 
     /// Creating dual-feature capacity and time based `Cache`.
     pub fn with_expiry_duration_and_capacity(duration: Duration, capacity: usize) -> Self {
+        Self::new(Some(duration), capacity)
+    }
+
+    fn new(item_duration: Option<Duration>, capacity: usize) -> Self {
         Self {
             items: RwLock::new(BTreeMap::new()),
-            item_duration: Some(duration),
+            item_duration,
             capacity,
+            eviction: EvictionPolicy::default(),
+            next_rank: AtomicU64::new(0),
+            loading: Mutex::new(HashMap::new()),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            evictions: AtomicU64::new(0),
+            expirations: AtomicU64::new(0),
         }
     }
 
+    /// Sets which items to remove first when over capacity, `EvictionPolicy::Insertion` by
+    /// default.
+    pub fn with_eviction(mut self, eviction: EvictionPolicy) -> Self {
+        self.eviction = eviction;
+        self
+    }
+
     /// Returns the number of items in the cache.
     pub async fn len(&self) -> usize {
         self.items.read().await.len()
@@ -76,39 +138,104 @@ where
         self.items.read().await.iter().filter(predicate).count()
     }
 
+    /// Returns the hit, miss, eviction and expiration counts of the cache.
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            evictions: self.evictions.load(Ordering::Relaxed),
+            expirations: self.expirations.load(Ordering::Relaxed),
+        }
+    }
+
     /// Get a value from the cache if one is set and not expired.
     ///
-    /// A clone of the value is returned, so this is only implemented when `V: Clone`.
+    /// A clone of the value is returned, so this is only implemented when `V: Clone`. In LRU
+    /// mode, this makes the item the most recently used one.
     pub async fn get(&self, key: &T) -> Option<V>
     where
         T: Eq + Hash,
         V: Clone,
     {
-        self.items
-            .read()
-            .await
-            .get(key)
-            .filter(|&item| !item.expired())
-            .map(|k| k.object.clone())
+        let value = self.peek(key).await;
+        let counter = if value.is_some() {
+            &self.hits
+        } else {
+            &self.misses
+        };
+        let _ = counter.fetch_add(1, Ordering::Relaxed);
+
+        value
+    }
+
+    // As `get`, without counting the lookup.
+    async fn peek(&self, key: &T) -> Option<V>
+    where
+        V: Clone,
+    {
+        match self.eviction {
+            EvictionPolicy::Insertion => self
+                .items
+                .read()
+                .await
+                .get(key)
+                .filter(|&item| !item.expired())
+                .map(|item| item.object.clone()),
+            EvictionPolicy::LeastRecentlyUsed => {
+                let mut items = self.items.write().await;
+                let item = items.get_mut(key).filter(|item| !item.expired())?;
+                item.rank = self.next_rank();
+                Some(item.object.clone())
+            }
+        }
+    }
+
+    /// Get a value from the cache, or load it with `load` and set it if there is none.
+    ///
+    /// Concurrent calls for the same key are coalesced: while one of them loads the value, the
+    /// others wait for it instead of loading it too. Errors are not cached, so if `load` fails,
+    /// the error is returned to its caller only and the next one waiting loads the value again.
+    pub async fn get_or_insert_with<F, Fut, E>(&self, key: T, load: F) -> Result<V, E>
+    where
+        V: Clone,
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<V, E>>,
+    {
+        if let Some(value) = self.get(&key).await {
+            return Ok(value);
+        }
+
+        let loading = Loading::new(&self.loading, key);
+        let _guard = loading.lock.lock().await;
+
+        // Loaded by the caller we waited for.
+        if let Some(value) = self.peek(&key).await {
+            return Ok(value);
+        }
+
+        let value = load().await?;
+        let _ = self.set(key, value.clone(), None).await;
+        Ok(value)
     }
 
     /// Set a value in the cache and return the previous value, if any.
     ///
     /// This will override an existing value for the same key, if there is one. `custom_duration`
     /// can be set to override `self.item_duration`. If the new item causes the cache to exceed its
-    /// capacity, the oldest entry in the cache will be removed.
+    /// capacity, the oldest entry in the cache will be removed, or in LRU mode, the least recently
+    /// used one.
     pub async fn set(&self, key: T, value: V, custom_duration: Option<Duration>) -> Option<V>
     where
         T: Eq + Hash + Clone,
     {
+        let mut item = Item::new(value, custom_duration.or(self.item_duration));
+        item.rank = self.next_rank();
+
         let replaced = self
             .items
             .write()
             .await
-            .insert(
-                key,
-                Item::new(value, custom_duration.or(self.item_duration)),
-            )
+            .insert(key, item)
             .and_then(|item| (!item.expired()).then(|| item.object));
         self.remove_expired().await;
         self.drop_excess().await;
@@ -128,11 +255,24 @@ where
         }
 
         for key in expired_keys {
-            let _ = self.items.write().await.remove(&key);
+            if self.items.write().await.remove(&key).is_some() {
+                let _ = self.expirations.fetch_add(1, Ordering::Relaxed);
+            }
         }
     }
 
-    /// Remove items that exceed capacity, oldest first.
+    /// Spawns a task removing the expired items every `period`, so they don't linger until the
+    /// next `set`. The task stops once the cache is dropped.
+    pub fn spawn_sweeper(self: &Arc<Self>, period: Duration) -> JoinHandle<()>
+    where
+        T: Send + Sync + 'static,
+        V: Send + Sync + 'static,
+    {
+        let cache = Arc::downgrade(self);
+        tokio::spawn(sweep(cache, period))
+    }
+
+    /// Remove items that exceed capacity, lowest rank first.
     async fn drop_excess(&self) {
         let len = self.len().await;
         if len > self.capacity {
@@ -140,16 +280,17 @@ where
             let excess_keys: Vec<_>;
             {
                 let read_items = self.items.read().await;
-                let mut items = read_items.iter().collect_vec();
-
-                // reversed sort
-                items.sort_by(|(_, item_a), (_, item_b)| item_b.elapsed().cmp(&item_a.elapsed()));
-
-                // take the excess
-                excess_keys = items.iter().take(excess).map(|(key, _)| **key).collect();
+                excess_keys = read_items
+                    .iter()
+                    .sorted_by_key(|(_, item)| item.rank)
+                    .take(excess)
+                    .map(|(key, _)| *key)
+                    .collect();
             }
             for key in excess_keys {
-                let _ = self.items.write().await.remove(&key);
+                if self.items.write().await.remove(&key).is_some() {
+                    let _ = self.evictions.fetch_add(1, Ordering::Relaxed);
+                }
             }
         }
     }
@@ -166,12 +307,72 @@ where
     pub async fn clear(&self) {
         self.items.write().await.clear()
     }
+
+    fn next_rank(&self) -> u64 {
+        self.next_rank.fetch_add(1, Ordering::Relaxed)
+    }
+}
+
+async fn sweep<T, V>(cache: Weak<Cache<T, V>>, period: Duration)
+where
+    T: Ord + Hash + Copy,
+{
+    let mut interval = time::interval(period);
+    // The first tick completes immediately.
+    let _ = interval.tick().await;
+
+    loop {
+        let _ = interval.tick().await;
+        match cache.upgrade() {
+            Some(cache) => cache.remove_expired().await,
+            None => break,
+        }
+    }
+}
+
+// Load of a key by `Cache::get_or_insert_with`, forgotten once no caller is waiting for it
+// anymore, even if the callers were cancelled.
+struct Loading<'a, T: Hash + Eq> {
+    loading: &'a Mutex<HashMap<T, Arc<AsyncMutex<()>>>>,
+    key: T,
+    lock: Arc<AsyncMutex<()>>,
+}
+
+impl<'a, T: Hash + Eq + Copy> Loading<'a, T> {
+    fn new(loading: &'a Mutex<HashMap<T, Arc<AsyncMutex<()>>>>, key: T) -> Self {
+        let lock = loading
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .entry(key)
+            .or_default()
+            .clone();
+
+        Self { loading, key, lock }
+    }
+}
+
+impl<'a, T: Hash + Eq> Drop for Loading<'a, T> {
+    fn drop(&mut self) {
+        let mut loading = self.loading.lock().unwrap_or_else(PoisonError::into_inner);
+        // The one in the map and ours.
+        if Arc::strong_count(&self.lock) <= 2 {
+            let _ = loading.remove(&self.key);
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::cache::Cache;
-    use std::time::Duration;
+    use crate::cache::{Cache, CacheStats, EvictionPolicy};
+    use futures::future;
+    use std::{
+        convert::Infallible,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+        time::Duration,
+    };
 
     const KEY: i8 = 0;
     const VALUE: &str = "VALUE";
@@ -303,4 +504,85 @@ mod tests {
         assert!(cache.get(&KEY).await.is_none());
         assert_eq!(cache.get(&key).await, Some(value));
     }
+
+    #[tokio::test]
+    async fn drop_excess_least_recently_used() {
+        let cache = Cache::with_capacity(2).with_eviction(EvictionPolicy::LeastRecentlyUsed);
+        let _ = cache.set(0, VALUE, None).await;
+        let _ = cache.set(1, VALUE, None).await;
+        // Reading makes the first item the most recently used one.
+        assert_eq!(cache.get(&0).await, Some(VALUE));
+        let _ = cache.set(2, VALUE, None).await;
+        assert_eq!(cache.get(&0).await, Some(VALUE));
+        assert!(cache.get(&1).await.is_none());
+
+        // Reading doesn't count in the default mode.
+        let cache = Cache::with_capacity(2);
+        let _ = cache.set(0, VALUE, None).await;
+        let _ = cache.set(1, VALUE, None).await;
+        assert_eq!(cache.get(&0).await, Some(VALUE));
+        let _ = cache.set(2, VALUE, None).await;
+        assert!(cache.get(&0).await.is_none());
+        assert_eq!(cache.get(&1).await, Some(VALUE));
+    }
+
+    #[tokio::test]
+    async fn count_hits_misses_and_evictions() {
+        let cache = Cache::with_capacity(1);
+        let _ = cache.set(KEY, VALUE, None).await;
+        let _ = cache.get(&KEY).await;
+        let _ = cache.set(1, VALUE, None).await;
+        let _ = cache.get(&KEY).await;
+
+        assert_eq!(
+            cache.stats(),
+            CacheStats {
+                hits: 1,
+                misses: 1,
+                evictions: 1,
+                expirations: 0,
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn get_or_insert_with_coalesces_loads() {
+        let cache = Cache::with_capacity(usize::MAX);
+        let loads = &AtomicUsize::new(0);
+
+        let load = || async move {
+            let _ = loads.fetch_add(1, Ordering::Relaxed);
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            Ok::<_, Infallible>(VALUE)
+        };
+
+        let values = future::join_all((0..4).map(|_| cache.get_or_insert_with(KEY, load))).await;
+        assert!(values.into_iter().all(|value| value == Ok(VALUE)));
+        assert_eq!(loads.load(Ordering::Relaxed), 1);
+        assert!(cache.loading.lock().unwrap().is_empty());
+
+        // Failed loads are not cached.
+        let result = cache
+            .get_or_insert_with(1, || async { Err("failed") })
+            .await;
+        assert_eq!(result, Err("failed"));
+        assert!(cache.get(&1).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn sweeper_removes_expired_items() {
+        let cache = Arc::new(Cache::with_expiry_duration(Duration::from_millis(1)));
+        let _ = cache.set(KEY, VALUE, None).await;
+        let sweeper = cache.spawn_sweeper(Duration::from_millis(5));
+
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(cache.is_empty().await);
+        assert_eq!(cache.stats().expirations, 1);
+
+        // The sweeper stops with the cache.
+        drop(cache);
+        assert!(tokio::time::timeout(Duration::from_secs(1), sweeper)
+            .await
+            .is_ok());
+    }
 }
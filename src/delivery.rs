@@ -0,0 +1,68 @@
+// Copyright 2021 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under The General Public License (GPL), version 3.
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied. Please review the Licences for the specific language governing
+// permissions and limitations relating to use of the SAFE Network Software.
+
+use crate::error::{Error, Result};
+use bytes::Bytes;
+use sn_messaging::{Itinerary, MessageId};
+use std::time::Duration;
+use tokio::sync::oneshot;
+
+/// How a message sent with `Routing::send_message_with_receipt` is retransmitted until its
+/// receipt arrives.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct DeliveryPolicy {
+    /// How long to wait for the receipt before sending the message again.
+    pub retransmit_after: Duration,
+    /// How many times to send the message at most, the first time included.
+    pub max_attempts: u32,
+}
+
+impl Default for DeliveryPolicy {
+    fn default() -> Self {
+        Self {
+            retransmit_after: Duration::from_secs(10),
+            max_attempts: 3,
+        }
+    }
+}
+
+/// Message sent with `Routing::send_message_with_receipt`, on its way to the destination.
+#[derive(Debug)]
+pub struct Delivery {
+    msg_id: MessageId,
+    receipt_rx: oneshot::Receiver<()>,
+}
+
+impl Delivery {
+    pub(crate) fn new(msg_id: MessageId, receipt_rx: oneshot::Receiver<()>) -> Self {
+        Self { msg_id, receipt_rx }
+    }
+
+    /// Id of the message, as in its `Event::MessageDelivered`.
+    pub fn msg_id(&self) -> MessageId {
+        self.msg_id
+    }
+
+    /// Waits for the receipt of the message. Returns `Error::DeliveryTimeout` if it didn't arrive
+    /// in time after the last attempt to send the message.
+    pub async fn delivered(self) -> Result<()> {
+        self.receipt_rx.await.map_err(|_| Error::DeliveryTimeout)
+    }
+}
+
+// Message we sent and are waiting for the receipt of.
+pub(crate) struct PendingDelivery {
+    pub itinerary: Itinerary,
+    pub content: Bytes,
+    pub policy: DeliveryPolicy,
+    // Number of times the message was sent so far.
+    pub attempts: u32,
+    // Token of the timeout to send the message again on.
+    pub timer_token: u64,
+    pub receipt_tx: oneshot::Sender<()>,
+}
@@ -7,7 +7,7 @@
 // permissions and limitations relating to use of the SAFE Network Software.
 
 use crate::{
-    error::Result, messages::RoutingMsgUtils, node::Node, routing::command::Command,
+    error::Result, messages::RoutingMsgUtils, node::Node, rng, routing::command::Command,
     section::SectionKeyShare,
 };
 use bls_dkg::key_gen::message::Message as DkgMessage;
@@ -58,7 +58,7 @@ impl DkgCommand {
                     recipients.len(),
                     message,
                     DestInfo {
-                        dest: XorName::random(),
+                        dest: rng::random_name(),
                         dest_section_pk: key,
                     },
                 ))
@@ -92,7 +92,7 @@ impl DkgCommand {
                     recipients.len(),
                     message,
                     DestInfo {
-                        dest: XorName::random(),
+                        dest: rng::random_name(),
                         dest_section_pk: key,
                     },
                 ))
@@ -10,7 +10,13 @@ use super::{SignatureAggregator, Signed, SignedShare};
 use crate::{error::Result, messages::PlainMessageUtils};
 use serde::{Serialize, Serializer};
 use sn_messaging::node::Proposal;
+use std::{collections::HashMap, time::Duration};
 use thiserror::Error;
+use tokio::time::Instant;
+use xor_name::XorName;
+
+// How long to remember when the first share of a proposal arrived, if it doesn't get agreed on.
+const FIRST_SEEN_EXPIRY: Duration = Duration::from_secs(5 * 60);
 
 pub trait ProposalUtils {
     fn prove(
@@ -64,20 +70,35 @@ impl<'a> Serialize for SignableView<'a> {
     }
 }
 
-// Aggregator of `Proposal`s.
+// Aggregator of `Proposal`s. Also remembers when the first share of every pending proposal
+// arrived, so it can tell how long the agreement took.
 #[derive(Default)]
-pub(crate) struct ProposalAggregator(SignatureAggregator);
+pub(crate) struct ProposalAggregator {
+    aggregator: SignatureAggregator,
+    first_seen: HashMap<XorName, Instant>,
+}
 
 impl ProposalAggregator {
+    // Returns the proposal with its aggregated signature, and the time since its first share
+    // arrived, once enough shares are collected.
     pub fn add(
         &mut self,
         proposal: Proposal,
         signed_share: SignedShare,
-    ) -> Result<(Proposal, Signed), ProposalError> {
+    ) -> Result<(Proposal, Signed, Duration), ProposalError> {
         let bytes =
             bincode::serialize(&SignableView(&proposal)).map_err(|_| ProposalError::Invalid)?;
-        let signed = self.0.add(&bytes, signed_share)?;
-        Ok((proposal, signed))
+
+        let now = Instant::now();
+        self.first_seen
+            .retain(|_, first_seen| now.duration_since(*first_seen) < FIRST_SEEN_EXPIRY);
+        let digest = XorName::from_content(&[&bytes]);
+        let first_seen = *self.first_seen.entry(digest).or_insert(now);
+
+        let signed = self.aggregator.add(&bytes, signed_share)?;
+        let _ = self.first_seen.remove(&digest);
+
+        Ok((proposal, signed, now.duration_since(first_seen)))
     }
 }
 
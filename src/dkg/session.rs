@@ -12,6 +12,7 @@ use crate::{
         dkg_msgs_utils::{DkgFailureSignedSetUtils, DkgFailureSignedUtils},
     },
     ed25519::{self, Keypair},
+    rng,
     routing::command,
     section::{SectionAuthorityProviderUtils, SectionKeyShare},
 };
@@ -27,6 +28,7 @@ use std::{
     net::SocketAddr,
     time::Duration,
 };
+use tokio::time::Instant;
 use xor_name::XorName;
 
 // Interval to progress DKG timed phase
@@ -41,6 +43,7 @@ pub(crate) struct Session {
     pub(crate) key_gen: KeyGen,
     pub(crate) timer_token: u64,
     pub(crate) failures: DkgFailureSignedSet,
+    pub(crate) started: Instant,
     // Flag to track whether this session has completed (either with success or failure). We don't
     // remove complete sessions because the other participants might still need us to respond to
     // their messages.
@@ -60,7 +63,7 @@ impl Session {
         trace!("process DKG message {:?}", message);
         let responses = self
             .key_gen
-            .handle_message(&mut rand::thread_rng(), message)
+            .handle_message(&mut rng::new(), message)
             .unwrap_or_default();
 
         // Only a valid DkgMessage, which results in some responses, shall reset the ticker.
@@ -122,7 +125,7 @@ impl Session {
 
         trace!("DKG for {:?} progressing", self.elder_candidates);
 
-        match self.key_gen.timed_phase_transition(&mut rand::thread_rng()) {
+        match self.key_gen.timed_phase_transition(&mut rng::new()) {
             Ok(messages) => {
                 let mut commands: Vec<_> = messages
                     .into_iter()
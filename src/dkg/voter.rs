@@ -9,6 +9,8 @@
 use crate::{
     dkg::session::{Backlog, Session},
     ed25519::{self, Keypair},
+    metrics::{Metric, Metrics},
+    rng,
     section::{ElderCandidatesUtils, SectionAuthorityProviderUtils, SectionKeyShare},
     supermajority,
 };
@@ -18,6 +20,7 @@ use sn_messaging::{
     SectionAuthorityProvider,
 };
 use std::collections::{BTreeSet, HashMap};
+use tokio::time::Instant;
 use xor_name::XorName;
 
 use super::commands::DkgCommand;
@@ -45,6 +48,8 @@ pub(crate) struct DkgVoter {
     // we created the corresponding session. To avoid losing those messages, we store them in this
     // backlog and replay them once we create the session.
     backlog: Backlog,
+
+    metrics: Metrics,
 }
 
 impl Default for DkgVoter {
@@ -52,11 +57,20 @@ impl Default for DkgVoter {
         Self {
             sessions: HashMap::default(),
             backlog: Backlog::new(),
+            metrics: Metrics::default(),
         }
     }
 }
 
 impl DkgVoter {
+    pub fn set_metrics(&mut self, metrics: Metrics) {
+        self.metrics = metrics;
+    }
+
+    pub fn sessions(&self) -> impl Iterator<Item = (&DkgKey, &Session)> {
+        self.sessions.iter()
+    }
+
     // Starts a new DKG session.
     pub fn start(
         &mut self,
@@ -82,12 +96,12 @@ impl DkgVoter {
 
         // Special case: only one participant.
         if elder_candidates.elders.len() == 1 {
-            let secret_key_set = bls::SecretKeySet::random(0, &mut rand::thread_rng());
+            let secret_key_set = bls::SecretKeySet::random(0, &mut rng::new());
             let section_auth = SectionAuthorityProvider::from_elder_candidates(
                 elder_candidates,
                 secret_key_set.public_keys(),
             );
-            return vec![DkgCommand::HandleOutcome {
+            let commands = vec![DkgCommand::HandleOutcome {
                 section_auth,
                 outcome: SectionKeyShare {
                     public_key_set: secret_key_set.public_keys(),
@@ -95,6 +109,8 @@ impl DkgVoter {
                     secret_key_share: secret_key_set.secret_key_share(0),
                 },
             }];
+            record_completion(&self.metrics, Instant::now(), &commands);
+            return commands;
         }
 
         let threshold = supermajority(elder_candidates.elders.len()) - 1;
@@ -110,6 +126,7 @@ impl DkgVoter {
                     participant_index,
                     timer_token: 0,
                     failures: DkgFailureSignedSet::default(),
+                    started: Instant::now(),
                     complete: false,
                 };
 
@@ -121,6 +138,7 @@ impl DkgVoter {
                         .into_iter()
                         .flat_map(|message| session.process_message(&dkg_key, keypair, message)),
                 );
+                record_completion(&self.metrics, session.started, &commands);
 
                 let _ = self.sessions.insert(dkg_key, session);
 
@@ -147,7 +165,9 @@ impl DkgVoter {
             .iter_mut()
             .find(|(_, session)| session.timer_token() == timer_token)
         {
-            session.handle_timeout(dkg_key, keypair)
+            let commands = session.handle_timeout(dkg_key, keypair);
+            record_completion(&self.metrics, session.started, &commands);
+            commands
         } else {
             vec![]
         }
@@ -161,7 +181,9 @@ impl DkgVoter {
         message: DkgMessage,
     ) -> Vec<DkgCommand> {
         if let Some(session) = self.sessions.get_mut(dkg_key) {
-            session.process_message(dkg_key, keypair, message)
+            let commands = session.process_message(dkg_key, keypair, message);
+            record_completion(&self.metrics, session.started, &commands);
+            commands
         } else {
             self.backlog.push(*dkg_key, message);
             vec![]
@@ -174,8 +196,29 @@ impl DkgVoter {
         non_participants: &BTreeSet<XorName>,
         signed: DkgFailureSigned,
     ) -> Option<DkgCommand> {
-        self.sessions
-            .get_mut(dkg_key)?
-            .process_failure(dkg_key, non_participants, signed)
+        let session = self.sessions.get_mut(dkg_key)?;
+        let command = session.process_failure(dkg_key, non_participants, signed);
+        record_completion(&self.metrics, session.started, &command);
+        command
+    }
+}
+
+// Records the duration of a session that the given commands complete, if any.
+fn record_completion<'a>(
+    metrics: &Metrics,
+    started: Instant,
+    commands: impl IntoIterator<Item = &'a DkgCommand>,
+) {
+    for command in commands {
+        match command {
+            DkgCommand::HandleOutcome { .. } => {
+                metrics.observe(Metric::DkgDuration, "success", started.elapsed())
+            }
+            DkgCommand::HandleFailureAgreement(_) => {
+                metrics.observe(Metric::DkgDuration, "failure", started.elapsed());
+                metrics.inc(Metric::DkgFailures, "");
+            }
+            _ => (),
+        }
     }
 }
@@ -9,6 +9,7 @@
 use crate::{
     dkg::ProposalError,
     messages::{CreateError, ExtendSignedChainError},
+    routing::JoinResponseKind,
 };
 use qp2p::Error as Qp2pError;
 use secured_linked_list::error::Error as SecuredLinkedListError;
@@ -80,4 +81,39 @@ pub enum Error {
     NoMatchingElder,
     #[error("Node cannot join the network since it is not externally reachable: {0}")]
     NodeNotReachable(SocketAddr),
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Invalid state snapshot: {0}")]
+    InvalidSnapshot(String),
+    #[error("Unsupported state snapshot version: {0}")]
+    UnsupportedSnapshotVersion(u16),
+    #[error("State snapshot is no longer valid for our section")]
+    StaleSnapshot,
+    #[error("Timeout while waiting for the section to agree on our leave request")]
+    LeaveTimeout,
+    #[error("Timeout while waiting for the response to a request")]
+    RequestTimeout,
+    #[error("Timeout while waiting for the receipt of a message")]
+    DeliveryTimeout,
+    #[error("Timeout while waiting for the recipient to acknowledge the chunks of a message")]
+    TransferTimeout,
+    #[error("Invalid network parameters: {0}")]
+    InvalidNetworkParams(&'static str),
+    #[error("Invalid join policy: {0}")]
+    InvalidJoinPolicy(&'static str),
+    #[error("Invalid rate limits: {0}")]
+    InvalidRateLimits(&'static str),
+    #[error("Peer {0} is not reachable")]
+    PeerUnreachable(SocketAddr),
+    #[error("Address {0} is already in use")]
+    AddressInUse(SocketAddr),
+    #[error("Failed to bootstrap: none of the contacts is reachable")]
+    BootstrapFailed,
+    #[error("Timeout while joining the network, contacted {contacts:?}, received {responses:?}")]
+    BootstrapTimeout {
+        /// Peers we sent join requests to.
+        contacts: Vec<SocketAddr>,
+        /// Responses we received, with their senders.
+        responses: Vec<(SocketAddr, JoinResponseKind)>,
+    },
 }
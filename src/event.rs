@@ -6,11 +6,16 @@
 // KIND, either express or implied. Please review the Licences for the specific language governing
 // permissions and limitations relating to use of the SAFE Network Software.
 
+use crate::{
+    messages::{ClientIdentity, TraceHop},
+    network::NetworkStats,
+    request::RequestSlot,
+};
 use bytes::Bytes;
 use ed25519_dalek::Keypair;
 use hex_fmt::HexFmt;
 pub use qp2p::{RecvStream, SendStream};
-use sn_messaging::{client::ClientMsg, node::Signed, DstLocation, EndUser, SrcLocation};
+use sn_messaging::{client::ClientMsg, node::Signed, DstLocation, EndUser, MessageId, SrcLocation};
 use std::{
     collections::BTreeSet,
     fmt::{self, Debug, Formatter},
@@ -21,7 +26,7 @@ use xor_name::{Prefix, XorName};
 
 /// A flag in EldersChanged event, indicating
 /// whether the node got promoted, demoted or did not change.
-#[derive(Debug)]
+#[derive(Clone, Copy, Debug)]
 pub enum NodeElderChange {
     /// The node was promoted to Elder.
     Promoted,
@@ -54,6 +59,7 @@ pub struct Elders {
 /// `Request` and `Response` events from section locations are only raised once the majority has
 /// been reached, i.e. enough members of the section have sent the same message.
 #[allow(clippy::large_enum_variant)]
+#[derive(Clone)]
 pub enum Event {
     /// Received a message.
     MessageReceived {
@@ -67,6 +73,31 @@ pub enum Event {
         signed: Option<Signed>,
         /// The Sender's Section PK.
         section_pk: bls::PublicKey,
+        /// The nodes that relayed the message, in order, if it was sent with
+        /// `Routing::send_traced_message`.
+        trace: Option<Vec<TraceHop>>,
+    },
+    /// The receipt of a message sent with `Routing::send_message_with_receipt` arrived from its
+    /// destination.
+    MessageDelivered {
+        /// Id of the message, as returned by `Delivery::msg_id`.
+        msg_id: MessageId,
+    },
+    /// Received a request sent with `Routing::request`. Answer it by taking the handle out of
+    /// `handle` and passing it to `Routing::respond`.
+    RequestReceived {
+        /// The content of the request.
+        content: Bytes,
+        /// The source location that sent the request.
+        src: SrcLocation,
+        /// The destination location that receives the request.
+        dst: DstLocation,
+        /// The signed if the request was set to be aggregated at source.
+        signed: Option<Signed>,
+        /// The Sender's Section PK.
+        section_pk: bls::PublicKey,
+        /// Slot to take the handle to respond to the request with out of.
+        handle: RequestSlot,
     },
     /// A new peer joined our section.
     MemberJoined {
@@ -122,9 +153,21 @@ pub enum Event {
         /// The SocketAddr and PublicKey that sent the message.
         /// (Note: socket_id will be a random hash, to map against the actual socketaddr)
         user: EndUser,
+        /// The key the client proved to control with a `ClientHandshake`, if it did.
+        identity: Option<ClientIdentity>,
     },
-    /// Failed in sending a message to client, or connection to client is lost
+    /// Failed in sending a message to client, or connection to client is lost. Its session is
+    /// ended, so messages to its `EndUser` can no longer be delivered.
     ClientLost(SocketAddr),
+    /// The session of a client ended because the client was idle for too long, or to make room
+    /// for new clients. Messages to `user` can no longer be delivered, until the client sends a
+    /// message again.
+    ClientSessionExpired {
+        /// The client.
+        user: EndUser,
+        /// Address of the client.
+        addr: SocketAddr,
+    },
     /// Notify the current list of adult nodes, in case of churning.
     AdultsChanged {
         /// Remaining Adults in our section.
@@ -134,6 +177,12 @@ pub enum Event {
         /// Removed Adults in our section.
         removed: BTreeSet<XorName>,
     },
+    /// Our estimate of the size of the network moved significantly, or we learned about enough
+    /// sections to be confident about it (or not anymore). See `Routing::network_stats`.
+    NetworkStatsChanged(NetworkStats),
+    /// The event stream fell behind and this many events were dropped since the previous ones
+    /// delivered to it. Only ever produced by the stream itself.
+    Lagged(u64),
 }
 
 impl Debug for Event {
@@ -148,6 +197,24 @@ impl Debug for Event {
                 src,
                 dst
             ),
+            Self::MessageDelivered { msg_id } => formatter
+                .debug_struct("MessageDelivered")
+                .field("msg_id", msg_id)
+                .finish(),
+            Self::RequestReceived {
+                content,
+                src,
+                dst,
+                handle,
+                ..
+            } => write!(
+                formatter,
+                "RequestReceived {{ content: \"{:<8}\", src: {:?}, dst: {:?}, id: {:?} }}",
+                HexFmt(content),
+                src,
+                dst,
+                handle.id()
+            ),
             Self::MemberJoined {
                 name,
                 previous_name,
@@ -194,12 +261,21 @@ impl Debug for Event {
                 .field("new_keypair", new_keypair)
                 .finish(),
             Self::RestartRequired => write!(formatter, "RestartRequired"),
-            Self::ClientMsgReceived { msg, user, .. } => write!(
+            Self::ClientMsgReceived {
+                msg,
+                user,
+                identity,
+            } => write!(
                 formatter,
-                "ClientMsgReceived {{ msg: {:?}, src: {:?} }}",
-                msg, user,
+                "ClientMsgReceived {{ msg: {:?}, src: {:?}, identity: {:?} }}",
+                msg, user, identity,
             ),
             Self::ClientLost(addr) => write!(formatter, "ClientLost({:?})", addr),
+            Self::ClientSessionExpired { user, addr } => formatter
+                .debug_struct("ClientSessionExpired")
+                .field("user", user)
+                .field("addr", addr)
+                .finish(),
             Self::AdultsChanged {
                 remaining,
                 added,
@@ -210,6 +286,10 @@ impl Debug for Event {
                 .field("added", added)
                 .field("removed", removed)
                 .finish(),
+            Self::NetworkStatsChanged(stats) => {
+                write!(formatter, "NetworkStatsChanged({:?})", stats)
+            }
+            Self::Lagged(count) => write!(formatter, "Lagged({})", count),
         }
     }
 }
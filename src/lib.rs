@@ -69,15 +69,27 @@ extern crate tracing;
 // Public API
 // ############################################################################
 pub use self::{
-    cache::Cache,
+    cache::{Cache, CacheStats, EvictionPolicy},
+    delivery::{Delivery, DeliveryPolicy},
     error::{Error, Result},
     event::{Event, NodeElderChange, SendStream},
+    messages::{ClientHandshake, ClientIdentity, ClientSignature, TraceHop},
+    metrics::Metrics,
+    network::NetworkStats,
+    network_params::NetworkParams,
     peer::PeerUtils,
-    routing::{Config, EventStream, Routing},
+    request::{RequestHandle, RequestId, RequestSlot, Response},
+    routing::{
+        CommandLimits, CompressionConfig, Config, DebugSnapshot, DkgSessionSnapshot, EventFilter,
+        EventStream, JoinPolicy, JoinProgress, JoinResponseKind, MemberSnapshot, MemoryNetwork,
+        PeerSnapshot, RateLimit, RateLimits, RelocateStatus, Routing, SectionRelation,
+        SectionSnapshot, Topology, TopologySection, TransportBackend,
+    },
     section::{
         SectionAuthorityProviderUtils, FIRST_SECTION_MAX_AGE, FIRST_SECTION_MIN_AGE, MIN_ADULT_AGE,
         MIN_AGE,
     },
+    simulation::Simulation,
 };
 pub use qp2p::Config as TransportConfig;
 
@@ -88,18 +100,24 @@ pub use xor_name::{Prefix, XorName, XOR_NAME_LEN}; // TODO remove pub on API upd
 // ############################################################################
 
 mod cache;
+mod delivery;
 mod dkg;
 mod ed25519;
 mod error;
 mod event;
 mod message_filter;
 mod messages;
+mod metrics;
 mod network;
+mod network_params;
 mod node;
 mod peer;
 mod relocation;
+mod request;
+mod rng;
 mod routing;
 mod section;
+mod simulation;
 
 /// Recommended section size. sn_routing will keep adding nodes until the section reaches this size.
 /// More nodes might be added if requested by the upper layers.
@@ -6,14 +6,27 @@
 // KIND, either express or implied. Please review the Licences for the specific language governing
 // permissions and limitations relating to use of the SAFE Network Software.
 
-use crate::cache::Cache;
+use crate::rng;
+use rand::Rng;
 use sn_messaging::{node::RoutingMsg, DstLocation, MessageId};
-use std::time::Duration;
+use std::{
+    collections::{hash_map::DefaultHasher, VecDeque},
+    f64::consts::LN_2,
+    hash::{BuildHasher, Hash, Hasher},
+    marker::PhantomData,
+    sync::{Mutex, PoisonError},
+    time::Duration,
+};
+use tokio::time::Instant;
 use xor_name::XorName;
 
-const INCOMING_EXPIRY_DURATION: Duration = Duration::from_secs(20 * 60);
-const OUTGOING_EXPIRY_DURATION: Duration = Duration::from_secs(10 * 60);
+pub(crate) const INCOMING_EXPIRY_DURATION: Duration = Duration::from_secs(20 * 60);
+pub(crate) const OUTGOING_EXPIRY_DURATION: Duration = Duration::from_secs(10 * 60);
+pub(crate) const FALSE_POSITIVE_PPM: u32 = 100;
+// Number of distinct messages per direction the filter is sized for, per expiry duration.
 const MAX_ENTRIES: usize = 15_000;
+// Number of generations of a `RotatingFilter`.
+const GENERATIONS: u32 = 4;
 
 /// An enum representing a result of message filtering
 #[derive(Eq, PartialEq)]
@@ -34,29 +47,43 @@ impl FilteringResult {
 }
 
 // Structure to filter (throttle) incoming and outgoing messages.
+//
+// The ids of the messages are kept in bloom filters of constant size, so a message is taken for a
+// duplicate with the given probability (in parts per million) even if it isn't one. The ids are
+// remembered for at least the expiry duration and at most a third longer, unless more than
+// `MAX_ENTRIES` messages come within the expiry duration, in which case they are forgotten sooner
+// rather than the false positives becoming more frequent.
 pub(crate) struct MessageFilter {
-    incoming: Cache<MessageId, ()>,
-    outgoing: Cache<(MessageId, XorName), ()>,
+    incoming: Mutex<RotatingFilter<MessageId>>,
+    outgoing: Mutex<RotatingFilter<(MessageId, XorName)>>,
 }
 
 impl MessageFilter {
-    pub fn new() -> Self {
+    pub fn new(
+        incoming_expiry: Duration,
+        outgoing_expiry: Duration,
+        false_positive_ppm: u32,
+    ) -> Self {
+        let false_positive_rate = f64::from(false_positive_ppm) / 1_000_000.0;
+
         Self {
-            incoming: Cache::with_expiry_duration_and_capacity(
-                INCOMING_EXPIRY_DURATION,
+            incoming: Mutex::new(RotatingFilter::new(
+                incoming_expiry,
                 MAX_ENTRIES,
-            ),
-            outgoing: Cache::with_expiry_duration_and_capacity(
-                OUTGOING_EXPIRY_DURATION,
+                false_positive_rate,
+            )),
+            outgoing: Mutex::new(RotatingFilter::new(
+                outgoing_expiry,
                 MAX_ENTRIES,
-            ),
+                false_positive_rate,
+            )),
         }
     }
 
     // Filter outgoing `SNRoutingMessage`. Return whether this specific message has been seen recently
     // (and thus should not be sent, due to deduplication).
     //
-    pub async fn filter_outgoing(&self, msg: &RoutingMsg, pub_id: &XorName) -> FilteringResult {
+    pub fn filter_outgoing(&self, msg: &RoutingMsg, pub_id: &XorName) -> FilteringResult {
         // Not filtering direct messages.
         if let DstLocation::DirectAndUnrouted = msg.dst {
             return FilteringResult::NewMessage;
@@ -64,37 +91,305 @@ impl MessageFilter {
 
         if self
             .outgoing
-            .set((msg.id, *pub_id), (), None)
-            .await
-            .is_some()
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(&(msg.id, *pub_id), Instant::now())
         {
+            FilteringResult::NewMessage
+        } else {
             trace!("Outgoing message filtered: {:?}", msg.id);
             FilteringResult::KnownMessage
-        } else {
-            FilteringResult::NewMessage
         }
     }
 
     // Returns `true` if not already having it.
-    pub async fn add_to_filter(&self, msg_id: &MessageId) -> bool {
-        let cur_value = self.incoming.set(*msg_id, (), None).await;
+    pub fn add_to_filter(&self, msg_id: &MessageId) -> bool {
+        let is_new = self
+            .incoming
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(msg_id, Instant::now());
 
-        if cur_value.is_some() {
+        if !is_new {
             trace!("Incoming message filtered: {:?}", msg_id);
         }
 
-        cur_value.is_none()
-    }
-
-    // Resets both incoming and outgoing filters.
-    pub async fn reset(&mut self) {
-        self.incoming.clear().await;
-        self.outgoing.clear().await;
+        is_new
     }
 }
 
 impl Default for MessageFilter {
     fn default() -> Self {
-        Self::new()
+        Self::new(
+            INCOMING_EXPIRY_DURATION,
+            OUTGOING_EXPIRY_DURATION,
+            FALSE_POSITIVE_PPM,
+        )
+    }
+}
+
+// Bloom filter split in generations of equal duration. Items are added to the newest generation
+// and looked up in all of them, and the oldest generation is dropped for a new one every
+// `expiry / (GENERATIONS - 1)`, or as soon as the newest one is full.
+struct RotatingFilter<T> {
+    // Oldest first.
+    generations: VecDeque<BloomFilter>,
+    hasher: KeyedHasher,
+    // Number of bits of each generation.
+    bits: usize,
+    // Number of bits set per item.
+    hashes: u32,
+    // Number of items a generation is sized for.
+    capacity: usize,
+    period: Duration,
+    rotated_at: Instant,
+    _item: PhantomData<fn(&T)>,
+}
+
+impl<T: Hash> RotatingFilter<T> {
+    // Creates a filter remembering up to `capacity` items per `expiry` with a probability of
+    // `false_positive_rate` to take an item for one it saw before.
+    fn new(expiry: Duration, capacity: usize, false_positive_rate: f64) -> Self {
+        let capacity = (capacity / (GENERATIONS - 1) as usize).max(1);
+        // Lookups go through every generation, each adding its own false positives.
+        let false_positive_rate = false_positive_rate / f64::from(GENERATIONS);
+
+        let bits = (-(capacity as f64) * false_positive_rate.ln() / (LN_2 * LN_2)).ceil();
+        let bits = (bits as usize).max(64);
+        let hashes = ((bits as f64 / capacity as f64) * LN_2).round().max(1.0) as u32;
+
+        Self {
+            generations: (0..GENERATIONS).map(|_| BloomFilter::new(bits)).collect(),
+            hasher: KeyedHasher::new(),
+            bits,
+            hashes,
+            capacity,
+            period: expiry / (GENERATIONS - 1),
+            rotated_at: Instant::now(),
+            _item: PhantomData,
+        }
+    }
+
+    // Adds the item. Returns whether it's new, i.e. it wasn't in the filter already.
+    fn insert(&mut self, item: &T, now: Instant) -> bool {
+        self.rotate(now);
+
+        let indices = self.indices(item);
+        if self
+            .generations
+            .iter()
+            .any(|generation| generation.contains(&indices))
+        {
+            return false;
+        }
+
+        if self
+            .generations
+            .back()
+            .map_or(false, |newest| newest.len >= self.capacity)
+        {
+            self.rotate_once();
+            self.rotated_at = now;
+        }
+        if let Some(newest) = self.generations.back_mut() {
+            newest.insert(&indices);
+        }
+
+        true
+    }
+
+    fn rotate(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.rotated_at);
+        if elapsed < self.period {
+            return;
+        }
+
+        let rotations = if self.period.as_nanos() > 0 {
+            (elapsed.as_nanos() / self.period.as_nanos()).min(u128::from(GENERATIONS)) as u32
+        } else {
+            GENERATIONS
+        };
+        for _ in 0..rotations {
+            self.rotate_once();
+        }
+
+        // Keep the generations aligned on the period, unless they were all dropped.
+        self.rotated_at = if rotations < GENERATIONS {
+            self.rotated_at + self.period * rotations
+        } else {
+            now
+        };
+    }
+
+    // Drops the oldest generation, reusing its memory for the new one.
+    fn rotate_once(&mut self) {
+        if let Some(mut oldest) = self.generations.pop_front() {
+            oldest.clear();
+            self.generations.push_back(oldest);
+        }
+    }
+
+    fn indices(&self, item: &T) -> Vec<usize> {
+        let mut hasher = self.hasher.build_hasher();
+        item.hash(&mut hasher);
+        let hash = hasher.finish();
+
+        // Double hashing: the i-th index is `h1 + i * h2`.
+        let h1 = hash & 0xffff_ffff;
+        let h2 = (hash >> 32) | 1;
+        (0..u64::from(self.hashes))
+            .map(|i| (h1.wrapping_add(i.wrapping_mul(h2)) % self.bits as u64) as usize)
+            .collect()
+    }
+}
+
+// Builds hashers keyed with a key drawn from the node's random generator, so the bloom filter
+// indices of the items can't be predicted by others, yet are reproducible in a seeded simulation.
+struct KeyedHasher {
+    key: [u64; 2],
+}
+
+impl KeyedHasher {
+    fn new() -> Self {
+        Self {
+            key: rng::new().gen(),
+        }
+    }
+}
+
+impl BuildHasher for KeyedHasher {
+    type Hasher = DefaultHasher;
+
+    fn build_hasher(&self) -> DefaultHasher {
+        let mut hasher = DefaultHasher::new();
+        self.key.hash(&mut hasher);
+        hasher
+    }
+}
+
+struct BloomFilter {
+    bits: Vec<u64>,
+    // Number of items inserted.
+    len: usize,
+}
+
+impl BloomFilter {
+    fn new(bits: usize) -> Self {
+        Self {
+            bits: vec![0; (bits + 63) / 64],
+            len: 0,
+        }
+    }
+
+    fn contains(&self, indices: &[usize]) -> bool {
+        indices
+            .iter()
+            .all(|index| self.bits[index / 64] & (1 << (index % 64)) != 0)
+    }
+
+    fn insert(&mut self, indices: &[usize]) {
+        for index in indices {
+            self.bits[index / 64] |= 1 << (index % 64);
+        }
+        self.len += 1;
+    }
+
+    fn clear(&mut self) {
+        for word in &mut self.bits {
+            *word = 0;
+        }
+        self.len = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    const EXPIRY: Duration = Duration::from_secs(30);
+
+    #[test]
+    fn filter_duplicates() {
+        let mut filter = RotatingFilter::new(EXPIRY, 1000, 0.001);
+        let now = Instant::now();
+
+        assert!(filter.insert(&0, now));
+        assert!(!filter.insert(&0, now));
+        assert!(filter.insert(&1, now));
+    }
+
+    #[test]
+    fn hasher_keys_from_seeded_rng() {
+        let indices = || {
+            rng::seed(42);
+            let filter = RotatingFilter::<u64>::new(EXPIRY, 1000, 0.001);
+            filter.indices(&7)
+        };
+
+        // Run on fresh threads so the seeding doesn't leak into other tests.
+        let first = thread::spawn(indices).join().unwrap();
+        let second = thread::spawn(indices).join().unwrap();
+        assert_eq!(first, second);
+
+        let unseeded = RotatingFilter::<u64>::new(EXPIRY, 1000, 0.001);
+        assert_ne!(unseeded.indices(&7), first);
+    }
+
+    #[test]
+    fn false_positive_rate_within_bounds() {
+        let capacity = 3000;
+        let mut filter = RotatingFilter::new(EXPIRY, capacity, 0.01);
+        let now = Instant::now();
+
+        for item in 0..capacity {
+            let _ = filter.insert(&item, now);
+        }
+
+        let false_positives = (capacity..capacity + 10_000)
+            .filter(|item| !filter.insert(item, now))
+            .count();
+        // Twice the configured rate, leaving room for the randomness of the hashes.
+        assert!(false_positives < 200, "{} false positives", false_positives);
+    }
+
+    #[test]
+    fn forget_after_expiry() {
+        let mut filter = RotatingFilter::new(EXPIRY, 1000, 0.001);
+        let start = Instant::now();
+        let before_expiry = start + EXPIRY - Duration::from_secs(1);
+
+        assert!(filter.insert(&0, start));
+        assert!(!filter.insert(&0, before_expiry));
+        assert!(filter.insert(&1, before_expiry));
+
+        // Forgotten at most a third of the expiry duration after it expired.
+        assert!(filter.insert(&0, start + EXPIRY * 4 / 3 + Duration::from_secs(1)));
+        // Remembered for the whole expiry duration from when it was added.
+        assert!(!filter.insert(&1, before_expiry + EXPIRY - Duration::from_secs(1)));
+    }
+
+    #[test]
+    fn constant_memory() {
+        let mut filter = RotatingFilter::new(EXPIRY, 300, 0.001);
+        let words: usize = filter.generations.iter().map(|g| g.bits.len()).sum();
+        let now = Instant::now();
+
+        // Far more items than it's sized for, within the expiry duration.
+        for item in 0..10_000 {
+            let _ = filter.insert(&item, now);
+        }
+
+        assert_eq!(filter.generations.len(), GENERATIONS as usize);
+        assert_eq!(
+            filter
+                .generations
+                .iter()
+                .map(|g| g.bits.len())
+                .sum::<usize>(),
+            words
+        );
+        // The recent items are still filtered.
+        assert!(!filter.insert(&9_999, now));
     }
 }
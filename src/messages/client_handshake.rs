@@ -0,0 +1,187 @@
+// Copyright 2021 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under The General Public License (GPL), version 3.
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied. Please review the Licences for the specific language governing
+// permissions and limitations relating to use of the SAFE Network Software.
+
+use crate::{
+    ed25519::{self, Verifier},
+    error::{Error, Result},
+};
+use serde::{Deserialize, Serialize};
+use sn_messaging::EndUser;
+use xor_name::XorName;
+
+// Prefix of the handshake messages, telling them apart from the `WireMsg`s on the same connection.
+const MAGIC: &[u8] = b"SNRH";
+const SIGNATURE_DOMAIN: &[u8] = b"sn_routing client handshake";
+
+/// Message of the handshake a client can perform with a node to authenticate itself.
+///
+/// The client sends `Hello` and answers the `Challenge` it gets with the `Proof` that it controls
+/// a key. From then on, the node attaches the proven identity to the `Event::ClientMsgReceived`
+/// of the messages from the client, and its `EndUser` is derived from the identity instead of the
+/// address of the client, so it stays the same when the client reconnects from another address
+/// and authenticates again. Only the node the client authenticated with knows its current
+/// address, so messages to it take an extra hop through that node when they reach the section at
+/// another elder.
+///
+/// Handshake messages are sent as they are, not wrapped in a `WireMsg`.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub enum ClientHandshake {
+    /// Start of the handshake, from the client.
+    Hello,
+    /// Challenge for the client to sign, from the node.
+    Challenge {
+        /// Random nonce, valid for this handshake only.
+        nonce: [u8; 32],
+        /// Name of the node.
+        node: XorName,
+    },
+    /// Signature of the challenge by the client.
+    Proof {
+        /// The identity the client claims.
+        identity: ClientIdentity,
+        /// Signature of the challenge with the key of `identity`.
+        signature: ClientSignature,
+    },
+    /// The client is authenticated, as the given user.
+    Accepted {
+        /// The destination to send messages to the client to, from now on.
+        user: EndUser,
+    },
+    /// The proof was invalid or the challenge has expired.
+    Rejected,
+}
+
+impl ClientHandshake {
+    /// Creates the `Proof` answering the given challenge with an ed25519 key.
+    pub fn prove_ed25519(keypair: &ed25519::Keypair, nonce: &[u8; 32], node: &XorName) -> Self {
+        Self::Proof {
+            identity: ClientIdentity::Ed25519(keypair.public),
+            signature: ClientSignature::Ed25519(ed25519::sign(
+                &signable_bytes(nonce, node),
+                keypair,
+            )),
+        }
+    }
+
+    /// Creates the `Proof` answering the given challenge with a BLS key.
+    pub fn prove_bls(secret_key: &bls::SecretKey, nonce: &[u8; 32], node: &XorName) -> Self {
+        Self::Proof {
+            identity: ClientIdentity::Bls(secret_key.public_key()),
+            signature: ClientSignature::Bls(secret_key.sign(&signable_bytes(nonce, node))),
+        }
+    }
+
+    /// Serialises the message, to be sent to the other party.
+    pub fn encode(&self) -> Result<Vec<u8>> {
+        let mut bytes = MAGIC.to_vec();
+        bincode::serialize_into(&mut bytes, self).map_err(|_| Error::InvalidPayload)?;
+        Ok(bytes)
+    }
+
+    /// Deserialises a message received from the other party. Returns `None` if the bytes are not a
+    /// handshake message.
+    pub fn decode(bytes: &[u8]) -> Option<Self> {
+        if bytes.starts_with(MAGIC) {
+            bincode::deserialize(&bytes[MAGIC.len()..]).ok()
+        } else {
+            None
+        }
+    }
+}
+
+/// Public key a client proved to control in a `ClientHandshake`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub enum ClientIdentity {
+    /// ed25519 key.
+    Ed25519(ed25519::PublicKey),
+    /// BLS key.
+    Bls(bls::PublicKey),
+}
+
+impl ClientIdentity {
+    // Name the identity is known by, e.g. as the socket id of its `EndUser`.
+    pub(crate) fn name(&self) -> XorName {
+        match self {
+            Self::Ed25519(public_key) => XorName::from_content(&[public_key.as_bytes()]),
+            Self::Bls(public_key) => XorName::from_content(&[&public_key.to_bytes()]),
+        }
+    }
+
+    // Verifies the signature of the given challenge with this key.
+    pub(crate) fn verify(
+        &self,
+        signature: &ClientSignature,
+        nonce: &[u8; 32],
+        node: &XorName,
+    ) -> bool {
+        let bytes = signable_bytes(nonce, node);
+        match (self, signature) {
+            (Self::Ed25519(public_key), ClientSignature::Ed25519(signature)) => {
+                public_key.verify(&bytes, signature).is_ok()
+            }
+            (Self::Bls(public_key), ClientSignature::Bls(signature)) => {
+                public_key.verify(signature, &bytes)
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Signature of a client in a `ClientHandshake`.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub enum ClientSignature {
+    /// ed25519 signature.
+    Ed25519(ed25519::Signature),
+    /// BLS signature.
+    Bls(bls::Signature),
+}
+
+fn signable_bytes(nonce: &[u8; 32], node: &XorName) -> Vec<u8> {
+    [SIGNATURE_DOMAIN, &nonce[..], &node.0[..]].concat()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+
+    #[test]
+    fn prove_and_verify() -> Result<()> {
+        let nonce = [7; 32];
+        let node = XorName::random();
+
+        let keypair = ed25519::Keypair::generate(&mut rand::thread_rng());
+        let secret_key = bls::SecretKey::random();
+
+        for proof in vec![
+            ClientHandshake::prove_ed25519(&keypair, &nonce, &node),
+            ClientHandshake::prove_bls(&secret_key, &nonce, &node),
+        ] {
+            assert_eq!(
+                ClientHandshake::decode(&proof.encode()?),
+                Some(proof.clone())
+            );
+
+            if let ClientHandshake::Proof {
+                identity,
+                signature,
+            } = proof
+            {
+                assert!(identity.verify(&signature, &nonce, &node));
+                assert!(!identity.verify(&signature, &[8; 32], &node));
+                assert!(!identity.verify(&signature, &nonce, &XorName::random()));
+            } else {
+                panic!("not a proof");
+            }
+        }
+
+        assert_eq!(ClientHandshake::decode(b"not a handshake"), None);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,288 @@
+// Copyright 2021 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under The General Public License (GPL), version 3.
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied. Please review the Licences for the specific language governing
+// permissions and limitations relating to use of the SAFE Network Software.
+
+use super::TraceHop;
+use crate::{
+    error::{Error, Result},
+    request::RequestId,
+    routing::transfer::Chunk,
+};
+use bytes::Bytes;
+use serde::{Deserialize, Serialize};
+use sn_messaging::MessageId;
+
+// Prefix of every encoded envelope: a magic number followed by the version of the encoding, so
+// `UserMessage`s not wrapped in an envelope, or wrapped by a node with an incompatible version, are
+// told apart instead of being taken for the wrong variant. Payloads without the magic number come
+// from nodes predating envelopes and are taken as plain user content.
+const MAGIC: &[u8; 3] = b"SNE";
+const VERSION: u8 = 1;
+const HEADER_LEN: usize = MAGIC.len() + 1;
+
+// Indices of some variants, which bincode encodes after the header, ahead of their fields, as a
+// little-endian `u32`.
+const RELAYED_INDEX: u8 = 6;
+const CHUNK_INDEX: u8 = 9;
+const CHUNK_ACK_INDEX: u8 = 10;
+const SOCKET_ID_KEYS_INDEX: u8 = 12;
+
+/// Payload of `Variant::UserMessage`.
+///
+/// Besides the content supplied by the upper layers, this carries the routing messages that have
+/// no dedicated `Variant` in `sn_messaging`. Every `UserMessage` sent by routing is wrapped in an
+/// `Envelope` and unwrapped again before being handed over to the upper layers.
+///
+/// The indices of the variants are part of the encoding, so new variants go at the end and the
+/// existing ones are only changed together with `VERSION`.
+///
+/// Introducing envelopes changed the wire format of `UserMessage`: payloads received from nodes
+/// predating them, which carry the user content as is, are still decoded as `Envelope::User`,
+/// unless that content happens to start with the magic number, but nodes predating envelopes hand
+/// everything we send them, header included, over to their upper layers unchanged.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub(crate) enum Envelope {
+    /// Content supplied by the upper layers.
+    User(Vec<u8>),
+    /// Request by the source node to be voted offline, because it's leaving the network.
+    Leave,
+    /// Content supplied by the upper layers to which the recipient is expected to respond.
+    Request { id: RequestId, content: Vec<u8> },
+    /// Content supplied by the upper layers in response to the request with the given id.
+    Response { id: RequestId, content: Vec<u8> },
+    /// Content supplied by the upper layers, with the nodes relaying it to be recorded.
+    TracedUser(Vec<u8>),
+    /// Request for the route taken by this very message. Traced like `TracedUser` and answered by
+    /// the destination with the recorded hops in a `Response`.
+    TraceProbe { id: RequestId },
+    /// Traced message (serialised `RoutingMsg`) relayed by the sender of this envelope, together
+    /// with the hops it went through so far, the last one being the sender's.
+    Relayed { msg: Vec<u8>, trace: Vec<TraceHop> },
+    /// Content supplied by the upper layers, to be acknowledged by the recipient with a `Receipt`.
+    /// The id stays the same when the message is retransmitted.
+    AcknowledgedUser { id: MessageId, content: Vec<u8> },
+    /// Acknowledgement of the `AcknowledgedUser` message with the given id.
+    Receipt { id: MessageId },
+    /// Chunk of a large message sent with `Routing::send_large_message`.
+    Chunk(Chunk),
+    /// Acknowledgement of the first `received` chunks of the large message being transferred.
+    ChunkAck { transfer_id: u64, received: u32 },
+    /// Share of the sender, the elder with the given index, of the section signature the socket
+    /// ids of the clients are encrypted with the key of. Answered with the recipient's own share,
+    /// unless it's itself the answer to one.
+    SocketIdKeyShare {
+        section_key: bls::PublicKey,
+        index: usize,
+        share: bls::SignatureShare,
+        reply: bool,
+    },
+    /// Signatures the socket id keys of the sender are derived from, with their section keys,
+    /// handed over to the newly promoted elders.
+    SocketIdKeys(Vec<(bls::PublicKey, bls::Signature)>),
+}
+
+impl Envelope {
+    pub fn user(content: Bytes) -> Self {
+        Self::User(content.to_vec())
+    }
+
+    // Whether the nodes relaying this envelope are to append their hop to its trace.
+    pub fn is_traced(&self) -> bool {
+        matches!(self, Self::TracedUser(_) | Self::TraceProbe { .. })
+    }
+
+    // Whether the encoded envelope is a chunk of a large message or the acknowledgement of one,
+    // without decoding the whole chunk.
+    pub fn is_transfer(bytes: &[u8]) -> bool {
+        matches!(
+            Self::index(bytes),
+            Some(CHUNK_INDEX) | Some(CHUNK_ACK_INDEX)
+        )
+    }
+
+    // Whether the encoded envelope is handled by adults too when sent to them directly, without
+    // being routed: relayed traced messages, including trace probes, and the socket id keys handed
+    // over to nodes that might not have learned of their promotion to elder yet.
+    pub fn is_direct_to_adults(bytes: &[u8]) -> bool {
+        matches!(
+            Self::index(bytes),
+            Some(RELAYED_INDEX) | Some(SOCKET_ID_KEYS_INDEX)
+        )
+    }
+
+    fn index(bytes: &[u8]) -> Option<u8> {
+        match Self::body(bytes)?.get(..4) {
+            Some(&[index, 0, 0, 0]) => Some(index),
+            _ => None,
+        }
+    }
+
+    // The encoded envelope without its header, if it has a header of our version.
+    fn body(bytes: &[u8]) -> Option<&[u8]> {
+        if bytes.starts_with(MAGIC) && bytes.get(MAGIC.len()) == Some(&VERSION) {
+            Some(&bytes[HEADER_LEN..])
+        } else {
+            None
+        }
+    }
+
+    pub fn encode(&self) -> Result<Vec<u8>> {
+        let mut bytes = Vec::with_capacity(HEADER_LEN);
+        bytes.extend_from_slice(MAGIC);
+        bytes.push(VERSION);
+        bincode::serialize_into(&mut bytes, self).map_err(|_| Error::InvalidPayload)?;
+        Ok(bytes)
+    }
+
+    // Decodes the payload of a `UserMessage`, taking one without a header for plain user content
+    // sent by a node predating envelopes. Fails if the header is of another version or the body
+    // is malformed.
+    pub fn decode(bytes: &[u8]) -> Result<Self> {
+        if !bytes.starts_with(MAGIC) {
+            return Ok(Self::User(bytes.to_vec()));
+        }
+
+        let body = Self::body(bytes).ok_or(Error::InvalidPayload)?;
+        bincode::deserialize(body).map_err(|_| Error::InvalidPayload)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::routing::transfer::OutgoingTransfer;
+    use anyhow::Result;
+
+    #[test]
+    fn encode_decode() -> Result<()> {
+        let envelope = Envelope::user(Bytes::from_static(b"hello"));
+        assert_eq!(Envelope::decode(&envelope.encode()?)?, envelope);
+
+        let envelope = Envelope::Leave;
+        assert_eq!(Envelope::decode(&envelope.encode()?)?, envelope);
+
+        let envelope = Envelope::Request {
+            id: RequestId::random(),
+            content: b"ping".to_vec(),
+        };
+        assert_eq!(Envelope::decode(&envelope.encode()?)?, envelope);
+
+        let envelope = Envelope::AcknowledgedUser {
+            id: MessageId::new(),
+            content: b"hello".to_vec(),
+        };
+        assert_eq!(Envelope::decode(&envelope.encode()?)?, envelope);
+
+        let envelope = Envelope::TraceProbe {
+            id: RequestId::random(),
+        };
+        assert!(envelope.is_traced());
+        assert_eq!(Envelope::decode(&envelope.encode()?)?, envelope);
+
+        Ok(())
+    }
+
+    #[test]
+    fn reject_other_versions() -> Result<()> {
+        let envelope = Envelope::user(Bytes::from_static(b"hello"));
+        let mut bytes = envelope.encode()?;
+
+        bytes[MAGIC.len()] = VERSION + 1;
+        assert!(Envelope::decode(&bytes).is_err());
+
+        // Our version, but not an envelope after the header.
+        assert!(Envelope::decode(&[MAGIC as &[u8], &[VERSION, 0xff]].concat()).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn accept_headerless_user_content() -> Result<()> {
+        // Sent by a node predating envelopes.
+        assert_eq!(
+            Envelope::decode(b"hello")?,
+            Envelope::user(Bytes::from_static(b"hello"))
+        );
+        assert_eq!(Envelope::decode(&[])?, Envelope::User(vec![]));
+
+        // Neither counts as a transfer or as handled by adults, whatever the content.
+        let content = [CHUNK_INDEX, 0, 0, 0];
+        assert!(!Envelope::is_transfer(&content));
+        assert!(!Envelope::is_direct_to_adults(&content));
+
+        Ok(())
+    }
+
+    #[test]
+    fn variant_indices() -> Result<()> {
+        let transfer = OutgoingTransfer::new(Bytes::from_static(b"large"));
+        let indexed = [
+            (
+                Envelope::Relayed {
+                    msg: vec![],
+                    trace: vec![],
+                },
+                RELAYED_INDEX,
+            ),
+            (Envelope::Chunk(transfer.chunk(0)), CHUNK_INDEX),
+            (
+                Envelope::ChunkAck {
+                    transfer_id: transfer.id(),
+                    received: 1,
+                },
+                CHUNK_ACK_INDEX,
+            ),
+            (Envelope::SocketIdKeys(vec![]), SOCKET_ID_KEYS_INDEX),
+        ];
+
+        for (envelope, index) in &indexed {
+            assert_eq!(bincode::serialize(envelope)?[..4], [*index, 0, 0, 0]);
+            assert_eq!(Envelope::index(&envelope.encode()?), Some(*index));
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn tell_transfers() -> Result<()> {
+        let transfer = OutgoingTransfer::new(Bytes::from_static(b"large"));
+        assert!(Envelope::is_transfer(
+            &Envelope::Chunk(transfer.chunk(0)).encode()?
+        ));
+        assert!(Envelope::is_transfer(
+            &Envelope::ChunkAck {
+                transfer_id: transfer.id(),
+                received: 1,
+            }
+            .encode()?
+        ));
+        assert!(!Envelope::is_transfer(
+            &Envelope::user(Bytes::from_static(b"hello")).encode()?
+        ));
+        assert!(!Envelope::is_transfer(&[]));
+
+        Ok(())
+    }
+
+    #[test]
+    fn tell_direct_to_adults() -> Result<()> {
+        let relayed = Envelope::Relayed {
+            msg: vec![],
+            trace: vec![],
+        };
+        assert!(Envelope::is_direct_to_adults(&relayed.encode()?));
+        assert!(Envelope::is_direct_to_adults(
+            &Envelope::SocketIdKeys(vec![]).encode()?
+        ));
+        assert!(!Envelope::is_direct_to_adults(&Envelope::Leave.encode()?));
+        assert!(!Envelope::is_direct_to_adults(
+            &Envelope::user(Bytes::from_static(b"hello")).encode()?
+        ));
+
+        Ok(())
+    }
+}
@@ -6,10 +6,19 @@
 // KIND, either express or implied. Please review the Licences for the specific language governing
 // permissions and limitations relating to use of the SAFE Network Software.
 
+mod client_handshake;
+mod envelope;
 mod plain_message;
 mod src_authority;
-
-pub use self::{plain_message::PlainMessageUtils, src_authority::SrcAuthorityUtils};
+mod trace;
+
+pub(crate) use self::envelope::Envelope;
+pub use self::{
+    client_handshake::{ClientHandshake, ClientIdentity, ClientSignature},
+    plain_message::PlainMessageUtils,
+    src_authority::SrcAuthorityUtils,
+    trace::TraceHop,
+};
 use crate::{
     dkg::SectionSignedUtils,
     ed25519::{self, Verifier},
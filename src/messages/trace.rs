@@ -0,0 +1,105 @@
+// Copyright 2021 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under The General Public License (GPL), version 3.
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied. Please review the Licences for the specific language governing
+// permissions and limitations relating to use of the SAFE Network Software.
+
+use crate::{
+    ed25519::{self, Signature, Verifier},
+    error::{Error, Result},
+    node::Node,
+};
+use serde::{Deserialize, Serialize};
+use sn_messaging::MessageId;
+use std::time::{SystemTime, UNIX_EPOCH};
+use xor_name::XorName;
+
+/// Record of a node relaying a traced message, signed by that node. See
+/// `Routing::send_traced_message`.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct TraceHop {
+    /// Name of the relaying node.
+    pub name: XorName,
+    /// Latest key of the section of the relaying node, as known by it.
+    pub section_key: bls::PublicKey,
+    /// When the node relayed the message, in milliseconds since the Unix epoch, by its clock.
+    pub timestamp: u64,
+    /// Names of the nodes the message was relayed to.
+    pub targets: Vec<XorName>,
+    signature: Signature,
+}
+
+impl TraceHop {
+    // Creates the record of `node` relaying the message with the given id to `targets`.
+    pub(crate) fn new(
+        node: &Node,
+        msg_id: MessageId,
+        section_key: bls::PublicKey,
+        targets: Vec<XorName>,
+    ) -> Result<Self> {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_millis() as u64)
+            .unwrap_or(0);
+        let bytes = signable_bytes(&msg_id, &section_key, timestamp, &targets)?;
+
+        Ok(Self {
+            name: node.name(),
+            section_key,
+            timestamp,
+            targets,
+            signature: ed25519::sign(&bytes, &node.keypair),
+        })
+    }
+
+    // Verifies that this record was signed by the node it names, for the message with the given
+    // id.
+    pub(crate) fn verify(&self, msg_id: &MessageId) -> bool {
+        let public_key = if let Ok(public_key) = ed25519::pub_key(&self.name) {
+            public_key
+        } else {
+            return false;
+        };
+
+        signable_bytes(msg_id, &self.section_key, self.timestamp, &self.targets)
+            .map(|bytes| public_key.verify(&bytes, &self.signature).is_ok())
+            .unwrap_or(false)
+    }
+}
+
+fn signable_bytes(
+    msg_id: &MessageId,
+    section_key: &bls::PublicKey,
+    timestamp: u64,
+    targets: &[XorName],
+) -> Result<Vec<u8>> {
+    bincode::serialize(&(msg_id, section_key, timestamp, targets))
+        .map_err(|_| Error::InvalidMessage)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ed25519::Keypair, section::test_utils::gen_addr};
+    use anyhow::Result;
+
+    #[test]
+    fn sign_and_verify() -> Result<()> {
+        let node = Node::new(Keypair::generate(&mut rand::thread_rng()), gen_addr());
+        let msg_id = MessageId::new();
+        let section_key = bls::SecretKey::random().public_key();
+
+        let hop = TraceHop::new(&node, msg_id, section_key, vec![XorName::random()])?;
+        assert_eq!(hop.name, node.name());
+        assert!(hop.verify(&msg_id));
+        assert!(!hop.verify(&MessageId::new()));
+
+        let mut forged = hop.clone();
+        forged.targets.push(XorName::random());
+        assert!(!forged.verify(&msg_id));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,418 @@
+// Copyright 2021 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under The General Public License (GPL), version 3.
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied. Please review the Licences for the specific language governing
+// permissions and limitations relating to use of the SAFE Network Software.
+
+use sn_messaging::node::{Proposal, Variant};
+use std::{
+    collections::BTreeMap,
+    fmt::{self, Debug, Formatter, Write},
+    sync::{Arc, Mutex, PoisonError},
+    time::Duration,
+};
+
+// Upper bounds (in seconds) of the histogram buckets.
+const BUCKETS: &[f64] = &[
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0, 120.0, 300.0,
+];
+
+/// Metrics of a routing node, see `Routing::metrics`.
+///
+/// This is a handle to the live registry the node keeps updating, so it can be obtained once and
+/// rendered repeatedly, e.g. on every scrape of a Prometheus endpoint.
+#[derive(Clone, Default)]
+pub struct Metrics(Arc<Mutex<Registry>>);
+
+impl Metrics {
+    /// Renders the current values in the Prometheus text exposition format.
+    pub fn to_prometheus(&self) -> String {
+        let registry = self.0.lock().unwrap_or_else(PoisonError::into_inner);
+        let mut output = String::new();
+
+        for metric in Metric::ALL {
+            let _ = writeln!(output, "# HELP {} {}", metric.name(), metric.help());
+
+            if metric.is_histogram() {
+                let _ = writeln!(output, "# TYPE {} histogram", metric.name());
+                for ((_, label), histogram) in registry
+                    .histograms
+                    .iter()
+                    .filter(|((other, _), _)| other == metric)
+                {
+                    histogram.render(&mut output, *metric, label);
+                }
+            } else {
+                let (kind, values) = if metric.is_gauge() {
+                    ("gauge", &registry.gauges)
+                } else {
+                    ("counter", &registry.counters)
+                };
+                let _ = writeln!(output, "# TYPE {} {}", metric.name(), kind);
+                let mut samples = values
+                    .iter()
+                    .filter(|((other, _), _)| other == metric)
+                    .peekable();
+
+                if samples.peek().is_none() && metric.label_name().is_none() {
+                    let _ = writeln!(output, "{} 0", metric.name());
+                }
+
+                for ((_, label), value) in samples {
+                    let _ = writeln!(
+                        output,
+                        "{}{} {}",
+                        metric.name(),
+                        labels(*metric, label, None),
+                        value
+                    );
+                }
+            }
+        }
+
+        output
+    }
+
+    pub(crate) fn inc(&self, metric: Metric, label: &'static str) {
+        self.add(metric, label, 1)
+    }
+
+    pub(crate) fn add(&self, metric: Metric, label: &'static str, value: u64) {
+        let mut registry = self.0.lock().unwrap_or_else(PoisonError::into_inner);
+        *registry.counters.entry((metric, label)).or_default() += value;
+    }
+
+    pub(crate) fn set(&self, metric: Metric, label: &'static str, value: u64) {
+        let mut registry = self.0.lock().unwrap_or_else(PoisonError::into_inner);
+        let _ = registry.gauges.insert((metric, label), value);
+    }
+
+    pub(crate) fn observe(&self, metric: Metric, label: &'static str, duration: Duration) {
+        let mut registry = self.0.lock().unwrap_or_else(PoisonError::into_inner);
+        registry
+            .histograms
+            .entry((metric, label))
+            .or_default()
+            .observe(duration);
+    }
+
+    #[cfg(test)]
+    pub(crate) fn counter(&self, metric: Metric, label: &'static str) -> u64 {
+        let registry = self.0.lock().unwrap_or_else(PoisonError::into_inner);
+        registry
+            .counters
+            .get(&(metric, label))
+            .copied()
+            .unwrap_or(0)
+    }
+}
+
+impl Debug for Metrics {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "Metrics")
+    }
+}
+
+// Kinds of the collected metrics. Every metric is either a counter, a gauge or a histogram of
+// durations, and is broken down by at most one label.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Ord, PartialOrd)]
+pub(crate) enum Metric {
+    MessagesHandled,
+    SendOutcomes,
+    FilterHits,
+    DkgDuration,
+    DkgFailures,
+    AgreementLatency,
+    Relocations,
+    JoinRejections,
+    RateLimited,
+    PeersBlocked,
+    CommandQueueDepth,
+    CommandsDropped,
+    MessagesShed,
+    Compression,
+    CompressionInputBytes,
+    CompressionOutputBytes,
+    InvalidEnvelopes,
+}
+
+impl Metric {
+    const ALL: &'static [Self] = &[
+        Self::MessagesHandled,
+        Self::SendOutcomes,
+        Self::FilterHits,
+        Self::DkgDuration,
+        Self::DkgFailures,
+        Self::AgreementLatency,
+        Self::Relocations,
+        Self::JoinRejections,
+        Self::RateLimited,
+        Self::PeersBlocked,
+        Self::CommandQueueDepth,
+        Self::CommandsDropped,
+        Self::MessagesShed,
+        Self::Compression,
+        Self::CompressionInputBytes,
+        Self::CompressionOutputBytes,
+        Self::InvalidEnvelopes,
+    ];
+
+    fn name(self) -> &'static str {
+        match self {
+            Self::MessagesHandled => "sn_routing_messages_handled_total",
+            Self::SendOutcomes => "sn_routing_sends_total",
+            Self::FilterHits => "sn_routing_message_filter_hits_total",
+            Self::DkgDuration => "sn_routing_dkg_duration_seconds",
+            Self::DkgFailures => "sn_routing_dkg_failures_total",
+            Self::AgreementLatency => "sn_routing_agreement_latency_seconds",
+            Self::Relocations => "sn_routing_relocations_total",
+            Self::JoinRejections => "sn_routing_join_rejections_total",
+            Self::RateLimited => "sn_routing_rate_limited_messages_total",
+            Self::PeersBlocked => "sn_routing_peers_blocked_total",
+            Self::CommandQueueDepth => "sn_routing_command_queue_depth",
+            Self::CommandsDropped => "sn_routing_commands_dropped_total",
+            Self::MessagesShed => "sn_routing_messages_shed_total",
+            Self::Compression => "sn_routing_compression_total",
+            Self::CompressionInputBytes => "sn_routing_compression_input_bytes_total",
+            Self::CompressionOutputBytes => "sn_routing_compression_output_bytes_total",
+            Self::InvalidEnvelopes => "sn_routing_invalid_envelopes_total",
+        }
+    }
+
+    fn help(self) -> &'static str {
+        match self {
+            Self::MessagesHandled => "Routing messages handled, by variant.",
+            Self::SendOutcomes => "Outcomes of sending a message to its delivery group.",
+            Self::FilterHits => "Messages dropped by the message filter as duplicates.",
+            Self::DkgDuration => "Duration of the DKG sessions we participated in, by outcome.",
+            Self::DkgFailures => "DKG sessions that ended in an agreed failure.",
+            Self::AgreementLatency => {
+                "Time from receiving the first share of a proposal to its agreement, by proposal."
+            }
+            Self::Relocations => "Relocations of our section members (peer) or of us (self).",
+            Self::JoinRejections => "Join requests rejected by our section, by reason.",
+            Self::RateLimited => {
+                "Incoming messages dropped for exceeding the rate limits, by class."
+            }
+            Self::PeersBlocked => {
+                "Peers temporarily blocked for repeatedly exceeding the rate limits."
+            }
+            Self::CommandQueueDepth => "Commands waiting to be handled, by priority.",
+            Self::CommandsDropped => "Commands dropped as their queue was full, by priority.",
+            Self::MessagesShed => {
+                "Incoming messages dropped as the node was saturated, by sender (client, user or \
+                 network)."
+            }
+            Self::Compression => {
+                "Outgoing messages over the compression threshold, by outcome (compressed, \
+                 incompressible or unsupported by the recipient)."
+            }
+            Self::CompressionInputBytes => {
+                "Size of the messages we compressed, before compression."
+            }
+            Self::CompressionOutputBytes => {
+                "Size of the messages we compressed, after compression."
+            }
+            Self::InvalidEnvelopes => {
+                "User messages dropped as their envelope was of another version or malformed."
+            }
+        }
+    }
+
+    fn label_name(self) -> Option<&'static str> {
+        match self {
+            Self::MessagesHandled => Some("variant"),
+            Self::SendOutcomes => Some("status"),
+            Self::FilterHits => Some("direction"),
+            Self::DkgDuration => Some("outcome"),
+            Self::DkgFailures => None,
+            Self::AgreementLatency => Some("proposal"),
+            Self::Relocations => Some("kind"),
+            Self::JoinRejections => Some("reason"),
+            Self::RateLimited => Some("class"),
+            Self::PeersBlocked => None,
+            Self::CommandQueueDepth => Some("priority"),
+            Self::CommandsDropped => Some("priority"),
+            Self::MessagesShed => Some("sender"),
+            Self::Compression => Some("outcome"),
+            Self::CompressionInputBytes => None,
+            Self::CompressionOutputBytes => None,
+            Self::InvalidEnvelopes => None,
+        }
+    }
+
+    fn is_histogram(self) -> bool {
+        matches!(self, Self::DkgDuration | Self::AgreementLatency)
+    }
+
+    fn is_gauge(self) -> bool {
+        matches!(self, Self::CommandQueueDepth)
+    }
+}
+
+#[derive(Default)]
+struct Registry {
+    counters: BTreeMap<(Metric, &'static str), u64>,
+    gauges: BTreeMap<(Metric, &'static str), u64>,
+    histograms: BTreeMap<(Metric, &'static str), Histogram>,
+}
+
+struct Histogram {
+    // Number of observations per bucket of `BUCKETS`, not cumulative.
+    buckets: Vec<u64>,
+    sum: f64,
+    count: u64,
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Self {
+            buckets: vec![0; BUCKETS.len()],
+            sum: 0.0,
+            count: 0,
+        }
+    }
+}
+
+impl Histogram {
+    fn observe(&mut self, duration: Duration) {
+        let value = duration.as_secs_f64();
+        if let Some(index) = BUCKETS.iter().position(|bound| value <= *bound) {
+            self.buckets[index] += 1;
+        }
+        self.sum += value;
+        self.count += 1;
+    }
+
+    fn render(&self, output: &mut String, metric: Metric, label: &str) {
+        let mut cumulative = 0;
+        for (bound, count) in BUCKETS.iter().zip(&self.buckets) {
+            cumulative += count;
+            let _ = writeln!(
+                output,
+                "{}_bucket{} {}",
+                metric.name(),
+                labels(metric, label, Some(&bound.to_string())),
+                cumulative
+            );
+        }
+        let _ = writeln!(
+            output,
+            "{}_bucket{} {}",
+            metric.name(),
+            labels(metric, label, Some("+Inf")),
+            self.count
+        );
+        let _ = writeln!(
+            output,
+            "{}_sum{} {}",
+            metric.name(),
+            labels(metric, label, None),
+            self.sum
+        );
+        let _ = writeln!(
+            output,
+            "{}_count{} {}",
+            metric.name(),
+            labels(metric, label, None),
+            self.count
+        );
+    }
+}
+
+// Formats the label set of a sample, e.g. `{variant="Sync"}`. Label values are all static
+// identifiers, so they need no escaping.
+fn labels(metric: Metric, label: &str, le: Option<&str>) -> String {
+    let pairs: Vec<_> = metric
+        .label_name()
+        .map(|name| format!("{}=\"{}\"", name, label))
+        .into_iter()
+        .chain(le.map(|le| format!("le=\"{}\"", le)))
+        .collect();
+
+    if pairs.is_empty() {
+        String::new()
+    } else {
+        format!("{{{}}}", pairs.join(","))
+    }
+}
+
+// Label of a message in `Metric::MessagesHandled`.
+pub(crate) fn variant_label(variant: &Variant) -> &'static str {
+    match variant {
+        Variant::SectionKnowledge { .. } => "SectionKnowledge",
+        Variant::UserMessage(_) => "UserMessage",
+        Variant::Sync { .. } => "Sync",
+        Variant::Relocate(_) => "Relocate",
+        Variant::RelocatePromise(_) => "RelocatePromise",
+        Variant::StartConnectivityTest(_) => "StartConnectivityTest",
+        Variant::JoinRequest(_) => "JoinRequest",
+        Variant::JoinResponse(_) => "JoinResponse",
+        Variant::JoinAsRelocatedRequest(_) => "JoinAsRelocatedRequest",
+        Variant::JoinAsRelocatedResponse(_) => "JoinAsRelocatedResponse",
+        Variant::BouncedUntrustedMessage { .. } => "BouncedUntrustedMessage",
+        Variant::SectionKnowledgeQuery { .. } => "SectionKnowledgeQuery",
+        Variant::DkgStart { .. } => "DkgStart",
+        Variant::DkgMessage { .. } => "DkgMessage",
+        Variant::DkgFailureObservation { .. } => "DkgFailureObservation",
+        Variant::DkgFailureAgreement(_) => "DkgFailureAgreement",
+        Variant::Propose { .. } => "Propose",
+    }
+}
+
+// Label of a proposal in `Metric::AgreementLatency`.
+pub(crate) fn proposal_label(proposal: &Proposal) -> &'static str {
+    match proposal {
+        Proposal::Online { .. } => "Online",
+        Proposal::Offline(_) => "Offline",
+        Proposal::SectionInfo(_) => "SectionInfo",
+        Proposal::OurElders(_) => "OurElders",
+        Proposal::AccumulateAtSrc { .. } => "AccumulateAtSrc",
+        Proposal::JoinsAllowed(_) => "JoinsAllowed",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render_prometheus() {
+        let metrics = Metrics::default();
+        metrics.inc(Metric::MessagesHandled, "Sync");
+        metrics.inc(Metric::MessagesHandled, "Sync");
+        metrics.inc(Metric::MessagesHandled, "Propose");
+        metrics.set(Metric::CommandQueueDepth, "user", 5);
+        metrics.set(Metric::CommandQueueDepth, "user", 3);
+        metrics.observe(Metric::DkgDuration, "success", Duration::from_millis(300));
+        metrics.observe(Metric::DkgDuration, "success", Duration::from_secs(3));
+
+        assert_eq!(metrics.counter(Metric::MessagesHandled, "Sync"), 2);
+
+        let output = metrics.to_prometheus();
+        let lines: Vec<_> = output.lines().collect();
+
+        assert!(lines.contains(&"# TYPE sn_routing_messages_handled_total counter"));
+        assert!(lines.contains(&"sn_routing_messages_handled_total{variant=\"Sync\"} 2"));
+        assert!(lines.contains(&"sn_routing_messages_handled_total{variant=\"Propose\"} 1"));
+        assert!(lines.contains(&"sn_routing_dkg_failures_total 0"));
+
+        assert!(lines.contains(&"# TYPE sn_routing_command_queue_depth gauge"));
+        assert!(lines.contains(&"sn_routing_command_queue_depth{priority=\"user\"} 3"));
+
+        assert!(lines.contains(&"# TYPE sn_routing_dkg_duration_seconds histogram"));
+        assert!(lines.contains(
+            &"sn_routing_dkg_duration_seconds_bucket{outcome=\"success\",le=\"0.25\"} 0"
+        ));
+        assert!(lines
+            .contains(&"sn_routing_dkg_duration_seconds_bucket{outcome=\"success\",le=\"0.5\"} 1"));
+        assert!(lines
+            .contains(&"sn_routing_dkg_duration_seconds_bucket{outcome=\"success\",le=\"5\"} 2"));
+        assert!(lines.contains(
+            &"sn_routing_dkg_duration_seconds_bucket{outcome=\"success\",le=\"+Inf\"} 2"
+        ));
+        assert!(lines.contains(&"sn_routing_dkg_duration_seconds_count{outcome=\"success\"} 2"));
+    }
+}
@@ -10,7 +10,7 @@
 // mod prefix_map;
 mod stats;
 
-use self::stats::NetworkStats;
+pub use self::stats::NetworkStats;
 use crate::{
     dkg::{verify_signed, SectionSignedUtils, Signed},
     peer::PeerUtils,
@@ -78,8 +78,8 @@ pub trait NetworkUtils {
     /// excluding self section.
     fn section_by_name(&self, name: &XorName) -> Result<SectionAuthorityProvider>;
 
-    /// Returns network statistics.
-    fn network_stats(&self, our: &SectionAuthorityProvider) -> NetworkStats;
+    /// Returns network statistics. `our_members` is the number of active members of our section.
+    fn network_stats(&self, our: &SectionAuthorityProvider, our_members: usize) -> NetworkStats;
 
     fn network_elder_counts(&self, our: &SectionAuthorityProvider) -> (u64, u64, bool);
 }
@@ -210,14 +210,20 @@ impl NetworkUtils for Network {
             .map(|value| value.section_auth.value.clone())
     }
 
-    /// Returns network statistics.
-    fn network_stats(&self, our: &SectionAuthorityProvider) -> NetworkStats {
-        let (known_elders, total_elders, total_elders_exact) = self.network_elder_counts(our);
+    /// Returns network statistics. `our_members` is the number of active members of our section.
+    fn network_stats(&self, our: &SectionAuthorityProvider, our_members: usize) -> NetworkStats {
+        let (known_elders, estimated_elders, confident) = self.network_elder_counts(our);
+
+        // Assume the other sections are about as big as ours, so there are as many nodes as in
+        // our section for every section at the depth of our prefix.
+        let estimated_nodes = (our_members as f64 * (our.prefix.bit_count() as f64).exp2()).ceil();
 
         NetworkStats {
+            known_sections: 1 + self.sections.iter().count() as u64,
             known_elders,
-            total_elders,
-            total_elders_exact,
+            estimated_elders,
+            estimated_nodes: estimated_nodes as u64,
+            confident,
         }
     }
 
@@ -295,6 +301,43 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn network_stats() -> Result<()> {
+        let sk = bls::SecretKey::random();
+        let chain = SecuredLinkedList::new(sk.public_key());
+
+        let p00: Prefix = "00".parse().unwrap();
+        let p01: Prefix = "01".parse().unwrap();
+        let p1: Prefix = "1".parse().unwrap();
+
+        let (our, _, _) = section::test_utils::gen_section_authority_provider(p00, 5);
+        let mut map = Network::new();
+        let _ = map.update_section(gen_section_auth(&sk, p01)?, None, &chain);
+
+        // Sections (00) and (01) cover half of the name space.
+        let stats = map.network_stats(&our, 10);
+        assert_eq!(stats.known_sections, 2);
+        assert_eq!(stats.known_elders, 10);
+        assert_eq!(stats.estimated_elders, 20);
+        assert_eq!(stats.estimated_nodes, 40);
+        assert!(!stats.confident);
+
+        let _ = map.update_section(gen_section_auth(&sk, p1)?, None, &chain);
+
+        let new_stats = map.network_stats(&our, 10);
+        assert_eq!(new_stats.known_sections, 3);
+        assert_eq!(new_stats.known_elders, 15);
+        assert_eq!(new_stats.estimated_elders, 15);
+        assert!(new_stats.confident);
+        assert!(new_stats.differs_significantly(&stats));
+
+        let stats = new_stats;
+        assert!(!map.network_stats(&our, 10).differs_significantly(&stats));
+        assert!(map.network_stats(&our, 12).differs_significantly(&stats));
+
+        Ok(())
+    }
+
     fn gen_section_auth(
         sk: &bls::SecretKey,
         prefix: Prefix,
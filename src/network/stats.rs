@@ -6,21 +6,50 @@
 // KIND, either express or implied. Please review the Licences for the specific language governing
 // permissions and limitations relating to use of the SAFE Network Software.
 
+// Relative change of the estimated network size considered significant enough to be reported.
+const SIGNIFICANT_CHANGE: f64 = 0.1;
+
+/// Statistics about the network as seen by our node. See `Routing::network_stats`.
+#[derive(Clone, Debug, PartialEq)]
 pub struct NetworkStats {
-    pub(super) known_elders: u64,
-    pub(super) total_elders: u64,
-    pub(super) total_elders_exact: bool,
+    /// Number of sections we know of, including ours.
+    pub known_sections: u64,
+    /// Number of elders of the sections we know of.
+    pub known_elders: u64,
+    /// Estimated total number of elders in the network, extrapolated from the part of the name
+    /// space covered by the sections we know of.
+    pub estimated_elders: u64,
+    /// Estimated total number of nodes in the network, extrapolated from the size of our section
+    /// and the length of its prefix.
+    pub estimated_nodes: u64,
+    /// Whether the sections we know of cover the whole name space. If so, `known_elders` is the
+    /// exact number of elders and the estimates are not based on a partial view of the network.
+    pub confident: bool,
 }
 
 impl NetworkStats {
-    pub fn print(&self) {
-        if self.total_elders_exact {
-            info!("*** Exact total network elders: {} ***", self.known_elders)
+    pub(crate) fn print(&self) {
+        if self.confident {
+            info!(
+                "*** Exact total network elders: {}, Estimated total network nodes: {} ***",
+                self.known_elders, self.estimated_nodes
+            )
         } else {
             info!(
-                "*** Known network elders: {}, Estimated total network elders: {} ***",
-                self.known_elders, self.total_elders
+                "*** Known network elders: {}, Estimated total network elders: {}, Estimated total network nodes: {} ***",
+                self.known_elders, self.estimated_elders, self.estimated_nodes
             )
         }
     }
+
+    // Whether these stats moved significantly compared to `previous`: the estimated number of
+    // nodes changed by at least `SIGNIFICANT_CHANGE` or the confidence flipped.
+    pub(crate) fn differs_significantly(&self, previous: &Self) -> bool {
+        if self.confident != previous.confident {
+            return true;
+        }
+
+        let change = (self.estimated_nodes as f64 - previous.estimated_nodes as f64).abs();
+        change >= SIGNIFICANT_CHANGE * previous.estimated_nodes.max(1) as f64
+    }
 }
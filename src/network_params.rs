@@ -0,0 +1,208 @@
+// Copyright 2021 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under The General Public License (GPL), version 3.
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied. Please review the Licences for the specific language governing
+// permissions and limitations relating to use of the SAFE Network Software.
+
+use crate::{
+    error::{Error, Result},
+    message_filter::{FALSE_POSITIVE_PPM, INCOMING_EXPIRY_DURATION, OUTGOING_EXPIRY_DURATION},
+    routing::{KEY_CACHE_SIZE, RESOURCE_PROOF_DATA_SIZE, RESOURCE_PROOF_DIFFICULTY},
+    ELDER_SIZE, FIRST_SECTION_MAX_AGE, FIRST_SECTION_MIN_AGE, MIN_AGE, RECOMMENDED_SECTION_SIZE,
+};
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+use tiny_keccak::{Hasher, Sha3};
+
+const NONCE_DOMAIN: &[u8] = b"sn_routing resource proof nonce";
+
+/// Parameters that shape the network: section sizes, ages, joining difficulty, etc.
+///
+/// Every node of a network must use the same parameters, otherwise the elders would disagree on
+/// things like who should be promoted or when to split. The genesis node fixes them for the whole
+/// lifetime of the network and every joining node has to be configured with the same ones (see
+/// `Config::network_params`). This is enforced while joining: the resource proof a joining node
+/// solves is bound to the parameters, so the elders reject the proof of a node configured with
+/// different ones. The defaults correspond to the public constants such as `ELDER_SIZE` or
+/// `MIN_AGE`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct NetworkParams {
+    /// Number of elders per section.
+    pub elder_size: usize,
+    /// Recommended section size. Nodes keep being added until the section reaches this size, and
+    /// a section splits only if both post-split sections would have at least this many adults.
+    pub recommended_section_size: usize,
+    /// The minimum age a node can have. Nodes older than this are adults.
+    pub min_age: u8,
+    /// Lower bound of the age the nodes of the first section start with.
+    pub first_section_min_age: u8,
+    /// Difficulty of the resource proof a joining node has to solve.
+    pub resource_proof_difficulty: u8,
+    /// Size of the data of the resource proof a joining node has to solve.
+    pub resource_proof_data_size: usize,
+    /// Number of recent section key shares an elder keeps around to sign with.
+    pub key_cache_size: u8,
+    /// How long the id of a received message is remembered to filter out duplicates.
+    pub incoming_msg_expiry: Duration,
+    /// How long the id of a sent message is remembered to avoid sending it again.
+    pub outgoing_msg_expiry: Duration,
+    /// Probability, in parts per million, of a message being taken for a duplicate although it
+    /// isn't one, as the message filter trades accuracy for constant memory.
+    pub msg_filter_false_positive_ppm: u32,
+}
+
+impl NetworkParams {
+    /// The age new nodes join the network with.
+    pub fn min_adult_age(&self) -> u8 {
+        self.min_age + 1
+    }
+
+    // Checks the parameters are consistent with each other.
+    pub(crate) fn validate(&self) -> Result<()> {
+        if self.elder_size == 0 {
+            return Err(Error::InvalidNetworkParams("elder_size must be positive"));
+        }
+
+        if self.recommended_section_size < self.elder_size {
+            return Err(Error::InvalidNetworkParams(
+                "recommended_section_size must not be less than elder_size",
+            ));
+        }
+
+        if self.first_section_min_age <= self.min_age {
+            return Err(Error::InvalidNetworkParams(
+                "first_section_min_age must be greater than min_age",
+            ));
+        }
+
+        if self.first_section_min_age >= FIRST_SECTION_MAX_AGE {
+            return Err(Error::InvalidNetworkParams(
+                "first_section_min_age must be less than FIRST_SECTION_MAX_AGE",
+            ));
+        }
+
+        if self.msg_filter_false_positive_ppm == 0
+            || self.msg_filter_false_positive_ppm >= 1_000_000
+        {
+            return Err(Error::InvalidNetworkParams(
+                "msg_filter_false_positive_ppm must be between 1 and 999999",
+            ));
+        }
+
+        if self.key_cache_size == 0 {
+            return Err(Error::InvalidNetworkParams(
+                "key_cache_size must be positive",
+            ));
+        }
+
+        Ok(())
+    }
+
+    // Derives the nonce the resource proof is actually solved for from the one the elders sent in
+    // their challenge. Nodes with different parameters derive different nonces, so the proof of a
+    // node that doesn't share our parameters fails validation.
+    pub(crate) fn bind_nonce(&self, nonce: &[u8; 32]) -> [u8; 32] {
+        let mut hasher = Sha3::v256();
+        hasher.update(NONCE_DOMAIN);
+        hasher.update(nonce);
+        hasher.update(&(self.elder_size as u64).to_le_bytes());
+        hasher.update(&(self.recommended_section_size as u64).to_le_bytes());
+        hasher.update(&[
+            self.min_age,
+            self.first_section_min_age,
+            self.resource_proof_difficulty,
+            self.key_cache_size,
+        ]);
+        hasher.update(&(self.resource_proof_data_size as u64).to_le_bytes());
+        hasher.update(&self.incoming_msg_expiry.as_nanos().to_le_bytes());
+        hasher.update(&self.outgoing_msg_expiry.as_nanos().to_le_bytes());
+        hasher.update(&self.msg_filter_false_positive_ppm.to_le_bytes());
+
+        let mut output = [0; 32];
+        hasher.finalize(&mut output);
+        output
+    }
+}
+
+impl Default for NetworkParams {
+    fn default() -> Self {
+        Self {
+            elder_size: ELDER_SIZE,
+            recommended_section_size: RECOMMENDED_SECTION_SIZE,
+            min_age: MIN_AGE,
+            first_section_min_age: FIRST_SECTION_MIN_AGE,
+            resource_proof_difficulty: RESOURCE_PROOF_DIFFICULTY,
+            resource_proof_data_size: RESOURCE_PROOF_DATA_SIZE,
+            key_cache_size: KEY_CACHE_SIZE,
+            incoming_msg_expiry: INCOMING_EXPIRY_DURATION,
+            outgoing_msg_expiry: OUTGOING_EXPIRY_DURATION,
+            msg_filter_false_positive_ppm: FALSE_POSITIVE_PPM,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_matches::assert_matches;
+
+    #[test]
+    fn default_params_are_valid() {
+        assert_matches!(NetworkParams::default().validate(), Ok(()));
+    }
+
+    #[test]
+    fn small_network_params_are_valid() {
+        let params = NetworkParams {
+            elder_size: 3,
+            recommended_section_size: 6,
+            ..Default::default()
+        };
+        assert_matches!(params.validate(), Ok(()));
+    }
+
+    #[test]
+    fn inconsistent_params_are_invalid() {
+        let params = NetworkParams {
+            elder_size: 7,
+            recommended_section_size: 3,
+            ..Default::default()
+        };
+        assert_matches!(params.validate(), Err(Error::InvalidNetworkParams(_)));
+
+        let params = NetworkParams {
+            first_section_min_age: MIN_AGE,
+            ..Default::default()
+        };
+        assert_matches!(params.validate(), Err(Error::InvalidNetworkParams(_)));
+
+        let params = NetworkParams {
+            msg_filter_false_positive_ppm: 0,
+            ..Default::default()
+        };
+        assert_matches!(params.validate(), Err(Error::InvalidNetworkParams(_)));
+
+        let params = NetworkParams {
+            first_section_min_age: FIRST_SECTION_MAX_AGE,
+            ..Default::default()
+        };
+        assert_matches!(params.validate(), Err(Error::InvalidNetworkParams(_)));
+    }
+
+    #[test]
+    fn bind_nonce_to_params() {
+        let nonce = [7; 32];
+        let params = NetworkParams::default();
+        let small_params = NetworkParams {
+            elder_size: 3,
+            recommended_section_size: 6,
+            ..Default::default()
+        };
+
+        assert_eq!(params.bind_nonce(&nonce), params.bind_nonce(&nonce));
+        assert_ne!(params.bind_nonce(&nonce), params.bind_nonce(&[8; 32]));
+        assert_ne!(params.bind_nonce(&nonce), small_params.bind_nonce(&nonce));
+    }
+}
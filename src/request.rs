@@ -0,0 +1,261 @@
+// Copyright 2021 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under The General Public License (GPL), version 3.
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied. Please review the Licences for the specific language governing
+// permissions and limitations relating to use of the SAFE Network Software.
+
+use crate::{
+    error::{Error, Result},
+    messages::{RoutingMsgUtils, SrcAuthorityUtils},
+    rng,
+};
+use bytes::Bytes;
+use hex_fmt::HexFmt;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use sn_messaging::{
+    location::{Aggregation, Itinerary},
+    node::{RoutingMsg, Signed},
+    DstLocation, SrcLocation,
+};
+use std::{
+    fmt::{self, Debug, Formatter},
+    sync::{Arc, Mutex, PoisonError},
+    time::Duration,
+};
+use tokio::{sync::oneshot, time::Instant};
+
+/// Correlation id tying a response to the request it answers.
+#[derive(Clone, Copy, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub struct RequestId(u64);
+
+impl RequestId {
+    pub(crate) fn random() -> Self {
+        Self(rng::new().gen())
+    }
+}
+
+impl Debug for RequestId {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "RequestId({:016x})", self.0)
+    }
+}
+
+/// Identifies a received request so it can be answered with `Routing::respond`. A request is
+/// answered once, so the handle can't be cloned, only taken out of its `RequestSlot`.
+#[derive(Debug, Eq, PartialEq)]
+pub struct RequestHandle {
+    id: RequestId,
+    src: SrcLocation,
+    dst: DstLocation,
+}
+
+impl RequestHandle {
+    pub(crate) fn new(id: RequestId, src: SrcLocation, dst: DstLocation) -> Self {
+        Self { id, src, dst }
+    }
+
+    /// Correlation id of the request.
+    pub fn id(&self) -> RequestId {
+        self.id
+    }
+
+    /// Location the request came from and the response goes to.
+    pub fn src(&self) -> &SrcLocation {
+        &self.src
+    }
+
+    /// Location the request was sent to and the response comes from.
+    pub fn dst(&self) -> &DstLocation {
+        &self.dst
+    }
+
+    // Itinerary of the response. A request sent to a section is answered by its elders, each
+    // contributing a signature share, so the requester receives a single aggregated response.
+    pub(crate) fn response_itinerary(&self) -> Result<Itinerary> {
+        reply_itinerary(&self.src, &self.dst)
+    }
+}
+
+/// Holds the `RequestHandle` of a request raised as `Event::RequestReceived`. Every stream the event
+/// is delivered to gets a copy of the same slot, and the first consumer to take the handle out of it
+/// is the one to respond.
+#[derive(Clone)]
+pub struct RequestSlot {
+    id: RequestId,
+    handle: Arc<Mutex<Option<RequestHandle>>>,
+}
+
+impl RequestSlot {
+    pub(crate) fn new(handle: RequestHandle) -> Self {
+        Self {
+            id: handle.id,
+            handle: Arc::new(Mutex::new(Some(handle))),
+        }
+    }
+
+    /// Correlation id of the request.
+    pub fn id(&self) -> RequestId {
+        self.id
+    }
+
+    /// Takes the handle to respond to the request with. Returns `None` if it was taken already.
+    pub fn take(&self) -> Option<RequestHandle> {
+        self.handle
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .take()
+    }
+}
+
+impl Debug for RequestSlot {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "RequestSlot({:?})", self.id)
+    }
+}
+
+// Itinerary of a reply from `dst` to a message sent from `src`: from a node directly, from a section
+// aggregated at the destination.
+pub(crate) fn reply_itinerary(src: &SrcLocation, dst: &DstLocation) -> Result<Itinerary> {
+    let (reply_src, aggregation) = match dst {
+        DstLocation::Node(name) => (SrcLocation::Node(*name), Aggregation::None),
+        DstLocation::Section(name) => (SrcLocation::Section(*name), Aggregation::AtDestination),
+        _ => return Err(Error::InvalidDstLocation),
+    };
+
+    Ok(Itinerary {
+        src: reply_src,
+        dst: src.to_dst(),
+        aggregation,
+    })
+}
+
+// Whether `msg` is a reply from `dst`, sent with the given aggregation. A reply from a section is
+// only taken if aggregated from the signature shares of its elders, not to be spoofed by any single
+// node, within the section or not.
+pub(crate) fn is_reply_from(msg: &RoutingMsg, dst: &DstLocation, aggregation: Aggregation) -> bool {
+    if msg.src.src_location().to_dst() != *dst {
+        return false;
+    }
+
+    match aggregation {
+        Aggregation::None => !msg.src.is_section(),
+        Aggregation::AtDestination => msg.src.is_section() && msg.signed().is_some(),
+        _ => false,
+    }
+}
+
+// Request we sent and are waiting for the response to.
+pub(crate) struct PendingRequest {
+    // Destination of the request, which the response is to come from.
+    dst: DstLocation,
+    // Aggregation of the response.
+    aggregation: Aggregation,
+    // When the requester gives up waiting.
+    expires_at: Instant,
+    response_tx: oneshot::Sender<Response>,
+}
+
+impl PendingRequest {
+    pub fn new(
+        itinerary: &Itinerary,
+        timeout: Duration,
+        response_tx: oneshot::Sender<Response>,
+    ) -> Result<Self> {
+        let reply = reply_itinerary(&itinerary.src, &itinerary.dst)?;
+
+        Ok(Self {
+            dst: itinerary.dst,
+            aggregation: reply.aggregation,
+            expires_at: Instant::now() + timeout,
+            response_tx,
+        })
+    }
+
+    // Whether `msg` comes from where the response to this request is to come from.
+    pub fn is_answered_by(&self, msg: &RoutingMsg) -> bool {
+        is_reply_from(msg, &self.dst, self.aggregation)
+    }
+
+    // Whether the requester gave up waiting for the response.
+    pub fn is_expired(&self, now: Instant) -> bool {
+        self.response_tx.is_closed() || now >= self.expires_at
+    }
+
+    pub fn respond(self, response: Response) {
+        let _ = self.response_tx.send(response);
+    }
+}
+
+/// Response to a request sent with `Routing::request`.
+#[derive(Clone)]
+pub struct Response {
+    /// The content of the response.
+    pub content: Bytes,
+    /// The location the response came from.
+    pub src: SrcLocation,
+    /// The section signature if the response was aggregated from the responding elders.
+    pub signed: Option<Signed>,
+    /// The responder's section key.
+    pub section_pk: bls::PublicKey,
+}
+
+impl Debug for Response {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_struct("Response")
+            .field("content", &format_args!("{:<8}", HexFmt(&self.content)))
+            .field("src", &self.src)
+            .field("signed", &self.signed.is_some())
+            .field("section_pk", &self.section_pk)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_matches::assert_matches;
+    use xor_name::XorName;
+
+    #[test]
+    fn response_itinerary() -> Result<()> {
+        let requester = XorName::random();
+        let section = XorName::random();
+
+        let handle = RequestHandle::new(
+            RequestId::random(),
+            SrcLocation::Node(requester),
+            DstLocation::Section(section),
+        );
+        let itinerary = handle.response_itinerary()?;
+        assert_eq!(itinerary.src, SrcLocation::Section(section));
+        assert_eq!(itinerary.dst, DstLocation::Node(requester));
+        assert!(itinerary.aggregate_at_dst());
+
+        let handle = RequestHandle::new(
+            RequestId::random(),
+            SrcLocation::Node(requester),
+            DstLocation::DirectAndUnrouted,
+        );
+        assert_matches!(handle.response_itinerary(), Err(Error::InvalidDstLocation));
+
+        Ok(())
+    }
+
+    #[test]
+    fn take_handle_once() {
+        let id = RequestId::random();
+        let slot = RequestSlot::new(RequestHandle::new(
+            id,
+            SrcLocation::Node(XorName::random()),
+            DstLocation::Section(XorName::random()),
+        ));
+        let copy = slot.clone();
+        assert_eq!(copy.id(), id);
+
+        assert_matches!(copy.take(), Some(handle) if handle.id() == id);
+        assert_matches!(slot.take(), None);
+    }
+}
@@ -0,0 +1,203 @@
+// Copyright 2021 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under The General Public License (GPL), version 3.
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied. Please review the Licences for the specific language governing
+// permissions and limitations relating to use of the SAFE Network Software.
+
+use crate::{error::Result, routing::persistence};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::VecDeque,
+    fs, io,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+use tokio::{sync::watch, task, time};
+
+// Maximum number of contacts kept in the cache.
+const CAPACITY: usize = 64;
+
+// How long the cache waits after a change before saving it, so the bursts of changes while the
+// network churns are written only once.
+const SAVE_DELAY: Duration = Duration::from_secs(1);
+
+// Addresses of elders we learned about, persisted so that on the next start the node can join
+// through them in addition to (or instead of) the hard-coded contacts. Most recently seen first.
+pub(crate) struct BootstrapCache {
+    path: PathBuf,
+    contents: Contents,
+    // Hands the contents over to the task saving them, once started.
+    saver: Option<watch::Sender<Contents>>,
+}
+
+#[derive(Clone, Default, Serialize, Deserialize)]
+struct Contents {
+    // Genesis key of the network the contacts belong to. A cache left over from another network
+    // is discarded once we know which network we're part of.
+    genesis_key: Option<bls::PublicKey>,
+    contacts: VecDeque<SocketAddr>,
+}
+
+impl BootstrapCache {
+    // Loads the cache from `path`. A missing or unreadable file yields an empty cache.
+    pub fn load(path: &Path) -> Self {
+        let contents = match fs::read(path) {
+            Ok(bytes) => bincode::deserialize(&bytes).unwrap_or_else(|err| {
+                warn!(
+                    "Ignoring invalid bootstrap cache {}: {}",
+                    path.display(),
+                    err
+                );
+                Contents::default()
+            }),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Contents::default(),
+            Err(err) => {
+                warn!("Failed to read bootstrap cache {}: {}", path.display(), err);
+                Contents::default()
+            }
+        };
+
+        Self {
+            path: path.to_path_buf(),
+            contents,
+            saver: None,
+        }
+    }
+
+    pub fn contacts(&self) -> impl Iterator<Item = &SocketAddr> {
+        self.contents.contacts.iter()
+    }
+
+    // Starts saving the cache in the background whenever it changes, so the node never waits for
+    // the disk while handling the changes.
+    pub fn start_saving(&mut self) {
+        let (saver, contents_rx) = watch::channel(self.contents.clone());
+        let _ = task::spawn(save_on_change(self.path.clone(), contents_rx));
+        self.saver = Some(saver);
+    }
+
+    // Moves the given contacts of the network with the given genesis key to the front of the
+    // cache, evicting the oldest ones if the cache overflows, and the ones of any other network.
+    // Returns whether the cache changed.
+    pub fn insert(
+        &mut self,
+        genesis_key: &bls::PublicKey,
+        contacts: impl IntoIterator<Item = SocketAddr>,
+    ) -> bool {
+        let mut changed = false;
+
+        if self.contents.genesis_key.as_ref() != Some(genesis_key) {
+            if self.contents.genesis_key.is_some() {
+                warn!("Discarding the bootstrap cache of another network");
+            }
+
+            self.contents.genesis_key = Some(*genesis_key);
+            self.contents.contacts.clear();
+            changed = true;
+        }
+
+        let cached = &mut self.contents.contacts;
+        for (index, addr) in contacts.into_iter().enumerate() {
+            if cached.get(index) == Some(&addr) {
+                continue;
+            }
+
+            cached.retain(|existing| *existing != addr);
+            cached.insert(index.min(cached.len()), addr);
+            changed = true;
+        }
+
+        cached.truncate(CAPACITY);
+
+        if changed {
+            if let Some(saver) = &self.saver {
+                let _ = saver.send(self.contents.clone());
+            }
+        }
+
+        changed
+    }
+}
+
+// Saves the contents of the cache each time they change, at most once per `SAVE_DELAY`.
+async fn save_on_change(path: PathBuf, mut contents_rx: watch::Receiver<Contents>) {
+    while contents_rx.changed().await.is_ok() {
+        time::sleep(SAVE_DELAY).await;
+
+        let contents = contents_rx.borrow().clone();
+        let path = path.clone();
+        match task::spawn_blocking(move || save(&path, &contents)).await {
+            Ok(Ok(())) => (),
+            Ok(Err(error)) => warn!("Failed to save the bootstrap cache: {}", error),
+            Err(error) => warn!("Failed to save the bootstrap cache: {}", error),
+        }
+    }
+}
+
+fn save(path: &Path, contents: &Contents) -> Result<()> {
+    let bytes = bincode::serialize(contents)
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+    persistence::write_atomically(path, &bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::section::test_utils::gen_addr;
+    use anyhow::Result;
+    use std::env;
+
+    #[tokio::test]
+    async fn save_in_background_and_load() -> Result<()> {
+        let path = env::temp_dir().join(format!(
+            "sn_routing_bootstrap_cache_{:016x}",
+            rand::random::<u64>()
+        ));
+        let genesis_key = bls::SecretKey::random().public_key();
+
+        let mut cache = BootstrapCache::load(&path);
+        assert_eq!(cache.contacts().count(), 0);
+        cache.start_saving();
+
+        let addrs: Vec<_> = (0..3).map(|_| gen_addr()).collect();
+        assert!(cache.insert(&genesis_key, addrs.iter().copied()));
+        assert!(!cache.insert(&genesis_key, addrs.iter().copied()));
+
+        time::sleep(2 * SAVE_DELAY).await;
+        let cache = BootstrapCache::load(&path);
+        itertools::assert_equal(cache.contacts(), &addrs);
+
+        fs::remove_file(&path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn recent_contacts_come_first() {
+        let mut cache = BootstrapCache::load(Path::new("unused"));
+        let genesis_key = bls::SecretKey::random().public_key();
+        let old: Vec<_> = (0..CAPACITY).map(|_| gen_addr()).collect();
+        let _ = cache.insert(&genesis_key, old.iter().copied());
+
+        let new = gen_addr();
+        assert!(cache.insert(&genesis_key, vec![old[5], new]));
+
+        let contacts: Vec<_> = cache.contacts().copied().collect();
+        assert_eq!(contacts.len(), CAPACITY);
+        assert_eq!(contacts[..2], [old[5], new]);
+        assert!(!contacts.contains(&old[CAPACITY - 1]));
+    }
+
+    #[test]
+    fn discard_contacts_of_other_network() {
+        let mut cache = BootstrapCache::load(Path::new("unused"));
+        let old = gen_addr();
+        let _ = cache.insert(&bls::SecretKey::random().public_key(), vec![old]);
+
+        let new = gen_addr();
+        assert!(cache.insert(&bls::SecretKey::random().public_key(), vec![new]));
+        itertools::assert_equal(cache.contacts(), &[new]);
+    }
+}
@@ -6,32 +6,39 @@
 // KIND, either express or implied. Please review the Licences for the specific language governing
 // permissions and limitations relating to use of the SAFE Network Software.
 
-use super::{send_message, verify_message};
+use super::{send_message, verify_message, JoinPolicy, JoinProgress, JoinResponseKind};
 use crate::{
     ed25519,
     error::{Error, Result},
     messages::RoutingMsgUtils,
     node::Node,
     peer::PeerUtils,
-    routing::comm::{Comm, ConnectionEvent},
+    rng,
+    routing::{
+        comm::{Comm, ConnectionEvent},
+        compression,
+    },
     section::{SectionAuthorityProviderUtils, SectionUtils},
-    FIRST_SECTION_MAX_AGE, FIRST_SECTION_MIN_AGE, MIN_ADULT_AGE,
+    NetworkParams, FIRST_SECTION_MAX_AGE,
 };
 use futures::future;
-use rand::seq::IteratorRandom;
+use rand::{seq::IteratorRandom, Rng};
 use resource_proof::ResourceProof;
 use sn_messaging::{
     node::{
         JoinRejectionReason, JoinRequest, JoinResponse, ResourceProofResponse, RoutingMsg, Section,
         Variant,
     },
-    DestInfo, DstLocation, MessageType, WireMsg,
+    DestInfo, DstLocation, MessageType, SectionAuthorityProvider, WireMsg,
 };
 use std::{
     collections::{HashSet, VecDeque},
     net::SocketAddr,
 };
-use tokio::sync::mpsc;
+use tokio::{
+    sync::mpsc,
+    time::{self, Instant},
+};
 use tracing::Instrument;
 use xor_name::{Prefix, XorName};
 
@@ -39,22 +46,24 @@ const BACKLOG_CAPACITY: usize = 100;
 
 /// Join the network as new node.
 ///
-/// NOTE: It's not guaranteed this function ever returns. This can happen due to messages being
-/// lost in transit or other reasons. It's the responsibility of the caller to handle this case,
-/// for example by using a timeout.
+/// NOTE: Unless `policy` sets a deadline, it's not guaranteed this function ever returns. This can
+/// happen due to the network never accepting us or other reasons.
 pub(crate) async fn join(
     node: Node,
     comm: &Comm,
     incoming_conns: &mut mpsc::Receiver<ConnectionEvent>,
-    bootstrap_addr: SocketAddr,
+    bootstrap_addrs: Vec<SocketAddr>,
+    params: &NetworkParams,
+    policy: &JoinPolicy,
+    progress_tx: Option<mpsc::UnboundedSender<JoinProgress>>,
 ) -> Result<(Node, Section, Vec<(RoutingMsg, SocketAddr, DestInfo)>)> {
     let (send_tx, send_rx) = mpsc::channel(1);
 
     let span = trace_span!("bootstrap", name = %node.name());
 
-    let state = Join::new(node, send_tx, incoming_conns);
+    let state = Join::new(node, send_tx, incoming_conns, params, policy, progress_tx);
 
-    future::join(state.run(bootstrap_addr), send_messages(send_rx, comm))
+    future::join(state.run(bootstrap_addrs), send_messages(send_rx, comm))
         .instrument(span)
         .await
         .0
@@ -68,6 +77,15 @@ struct Join<'a> {
     node: Node,
     // Backlog for unknown messages
     backlog: VecDeque<(RoutingMsg, SocketAddr, DestInfo)>,
+    params: &'a NetworkParams,
+    policy: &'a JoinPolicy,
+    progress_tx: Option<mpsc::UnboundedSender<JoinProgress>>,
+    deadline: Option<Instant>,
+    // The last join request we sent, to re-send it if it goes unanswered.
+    last_request: Option<(MessageType, Vec<(XorName, SocketAddr)>)>,
+    // Peers we sent join requests to and the responses we got, for `Error::BootstrapTimeout`.
+    contacts: Vec<SocketAddr>,
+    responses: Vec<(SocketAddr, JoinResponseKind)>,
 }
 
 impl<'a> Join<'a> {
@@ -75,30 +93,44 @@ impl<'a> Join<'a> {
         node: Node,
         send_tx: mpsc::Sender<(MessageType, Vec<(XorName, SocketAddr)>)>,
         recv_rx: &'a mut mpsc::Receiver<ConnectionEvent>,
+        params: &'a NetworkParams,
+        policy: &'a JoinPolicy,
+        progress_tx: Option<mpsc::UnboundedSender<JoinProgress>>,
     ) -> Self {
         Self {
             send_tx,
             recv_rx,
             node,
             backlog: VecDeque::with_capacity(BACKLOG_CAPACITY),
+            params,
+            policy,
+            progress_tx,
+            deadline: policy.deadline.map(|deadline| Instant::now() + deadline),
+            last_request: None,
+            contacts: Vec::new(),
+            responses: Vec::new(),
         }
     }
 
-    // Send `JoinRequest` and wait for the response. If the response is:
-    // - `Retry`: repeat with the new info.
+    // Send `JoinRequest` to all the bootstrap contacts at once and wait for the response. The
+    // first valid one decides which section we continue joining. If the response is:
+    // - `Retry`: repeat with the new info, unless it's stale (see `is_stale_retry`).
     // - `Redirect`: repeat with the new set of addresses.
     // - `ResourceChallenge`: carry out resource proof calculation.
     // - `Approval`: returns the initial `Section` value to use by this node,
     //    completing the bootstrap.
     async fn run(
         self,
-        bootstrap_addr: SocketAddr,
+        bootstrap_addrs: Vec<SocketAddr>,
     ) -> Result<(Node, Section, Vec<(RoutingMsg, SocketAddr, DestInfo)>)> {
         // Use our XorName as we do not know their name or section key yet.
-        let section_key = bls::SecretKey::random().public_key();
+        let section_key = rng::new().gen::<bls::SecretKey>().public_key();
         let dest_xorname = self.node.name();
 
-        let recipients = vec![(dest_xorname, bootstrap_addr)];
+        let recipients = bootstrap_addrs
+            .into_iter()
+            .map(|addr| (dest_xorname, addr))
+            .collect();
 
         self.join(section_key, recipients).await
     }
@@ -121,6 +153,10 @@ impl<'a> Join<'a> {
 
         // Avoid sending more than one request to the same peer.
         let mut used_recipient = HashSet::<SocketAddr>::new();
+        // Prefix of the section we're joining, once a response told us, and the keys we moved on
+        // from, to tell stale `Retry`s from the ones with newer info.
+        let mut section_prefix = None;
+        let mut superseded_keys = HashSet::new();
 
         loop {
             used_recipient.extend(recipients.iter().map(|(_, addr)| addr));
@@ -157,6 +193,20 @@ impl<'a> Join<'a> {
                         continue;
                     }
 
+                    if is_stale_retry(
+                        &section_auth,
+                        sender,
+                        section_prefix.as_ref(),
+                        &superseded_keys,
+                        &recipients,
+                    ) {
+                        debug!(
+                            "Ignoring stale JoinResponse::Retry {:?} from {:?}",
+                            section_auth, sender
+                        );
+                        continue;
+                    }
+
                     let new_recipients: Vec<(XorName, SocketAddr)> = section_auth
                         .elders
                         .iter()
@@ -167,9 +217,9 @@ impl<'a> Join<'a> {
 
                     // For the first section, using age random among 6 to 100 to avoid
                     // relocating too many nodes at the same time.
-                    if prefix.is_empty() && self.node.age() < FIRST_SECTION_MIN_AGE {
-                        let age: u8 = (FIRST_SECTION_MIN_AGE..FIRST_SECTION_MAX_AGE)
-                            .choose(&mut rand::thread_rng())
+                    if prefix.is_empty() && self.node.age() < self.params.first_section_min_age {
+                        let age: u8 = (self.params.first_section_min_age..FIRST_SECTION_MAX_AGE)
+                            .choose(&mut rng::new())
                             .unwrap_or(FIRST_SECTION_MAX_AGE);
 
                         let new_keypair =
@@ -181,10 +231,11 @@ impl<'a> Join<'a> {
                     }
 
                     if prefix.matches(&self.node.name()) {
-                        // After section split, new node must join with the age of MIN_ADULT_AGE.
-                        if !prefix.is_empty() && self.node.age() != MIN_ADULT_AGE {
+                        // After section split, new node must join with the minimum adult age.
+                        let min_adult_age = self.params.min_adult_age();
+                        if !prefix.is_empty() && self.node.age() != min_adult_age {
                             let new_keypair =
-                                ed25519::gen_keypair(&prefix.range_inclusive(), MIN_ADULT_AGE);
+                                ed25519::gen_keypair(&prefix.range_inclusive(), min_adult_age);
                             let new_name = ed25519::name(&new_keypair.public);
 
                             info!("Setting Node name to {}", new_name);
@@ -195,6 +246,8 @@ impl<'a> Join<'a> {
                             "Newer Join response for our prefix {:?} from {:?}",
                             section_auth, sender
                         );
+                        let _ = superseded_keys.insert(section_key);
+                        section_prefix = Some(prefix);
                         section_key = section_auth.section_key();
                         let join_request = JoinRequest {
                             section_key,
@@ -239,6 +292,8 @@ impl<'a> Join<'a> {
                             "Newer Join response for our prefix {:?} from {:?}",
                             section_auth, sender
                         );
+                        let _ = superseded_keys.insert(section_key);
+                        section_prefix = Some(section_auth.prefix);
                         section_key = section_auth.section_key();
                         let join_request = JoinRequest {
                             section_key,
@@ -261,8 +316,20 @@ impl<'a> Join<'a> {
                     nonce,
                     nonce_signature,
                 } => {
+                    if data_size != self.params.resource_proof_data_size
+                        || difficulty != self.params.resource_proof_difficulty
+                    {
+                        error!(
+                            "Resource challenge from {} doesn't match our network parameters",
+                            sender
+                        );
+                        return Err(Error::InvalidNetworkParams(
+                            "resource proof parameters differ from the network's",
+                        ));
+                    }
+
                     let rp = ResourceProof::new(data_size, difficulty);
-                    let data = rp.create_proof_data(&nonce);
+                    let data = rp.create_proof_data(&self.params.bind_nonce(&nonce));
                     let mut prover = rp.create_prover(data.clone());
                     let solution = prover.solve();
 
@@ -291,6 +358,17 @@ impl<'a> Join<'a> {
     ) -> Result<()> {
         info!("Sending {:?} to {:?}", join_request, recipients);
 
+        let addrs: Vec<_> = recipients.iter().map(|(_, addr)| *addr).collect();
+        for addr in &addrs {
+            if !self.contacts.contains(addr) {
+                self.contacts.push(*addr);
+            }
+        }
+        self.report_progress(JoinProgress::RequestSent {
+            recipients: addrs,
+            resource_proof: join_request.resource_proof_response.is_some(),
+        });
+
         let variant = Variant::JoinRequest(Box::new(join_request));
         let message = RoutingMsg::single_src(
             &self.node,
@@ -299,29 +377,79 @@ impl<'a> Join<'a> {
             section_key,
         )?;
 
-        let _ = self
-            .send_tx
-            .send((
-                MessageType::Routing {
-                    msg: message,
-                    dest_info: DestInfo {
-                        dest: recipients[0].0,
-                        dest_section_pk: section_key,
-                    },
-                },
-                recipients.to_vec(),
-            ))
-            .await;
+        let message = MessageType::Routing {
+            msg: message,
+            dest_info: DestInfo {
+                dest: recipients[0].0,
+                dest_section_pk: section_key,
+            },
+        };
+        self.last_request = Some((message.clone(), recipients.to_vec()));
+        let _ = self.send_tx.send((message, recipients.to_vec())).await;
 
         Ok(())
     }
 
+    // Waits for the next valid `JoinResponse`, re-sending the last request with increasing
+    // intervals while none arrives, until the deadline (if any) passes.
     async fn receive_join_response(&mut self) -> Result<(JoinResponse, SocketAddr, DestInfo)> {
+        let mut interval = self.policy.retry_interval;
+
+        loop {
+            let wait = if let Some(deadline) = self.deadline {
+                let now = Instant::now();
+                if now >= deadline {
+                    return Err(self.timeout_error());
+                }
+                interval.min(deadline - now)
+            } else {
+                interval
+            };
+
+            if let Ok(result) = time::timeout(wait, self.next_join_response()).await {
+                let (response, sender, dest_info) = result?;
+                let kind = JoinResponseKind::from(&response);
+                self.responses.push((sender, kind));
+                self.report_progress(JoinProgress::ResponseReceived {
+                    sender,
+                    response: kind,
+                });
+
+                return Ok((response, sender, dest_info));
+            }
+
+            if self
+                .deadline
+                .map_or(false, |deadline| Instant::now() >= deadline)
+            {
+                return Err(self.timeout_error());
+            }
+
+            debug!(
+                "No JoinResponse within {:?}, re-sending the join request",
+                wait
+            );
+            self.report_progress(JoinProgress::NoResponse { waited: wait });
+
+            if let Some((message, recipients)) = self.last_request.clone() {
+                let _ = self.send_tx.send((message, recipients)).await;
+            }
+
+            interval = self.policy.next_retry_interval(interval);
+        }
+    }
+
+    async fn next_join_response(&mut self) -> Result<(JoinResponse, SocketAddr, DestInfo)> {
         let destination = self.node.name();
 
         while let Some(event) = self.recv_rx.recv().await {
             // we are interested only in `JoinResponse` type of messages
             let (routing_msg, dest_info, join_response, sender) = match event {
+                ConnectionEvent::Received((sender, bytes)) if compression::is_frame(&bytes) => {
+                    // Compression is negotiated once joined.
+                    trace!("Ignoring compression frame from {} while joining", sender);
+                    continue;
+                }
                 ConnectionEvent::Received((sender, bytes)) => match WireMsg::deserialize(bytes) {
                     Ok(MessageType::Node { .. })
                     | Ok(MessageType::Client { .. })
@@ -422,6 +550,23 @@ impl<'a> Join<'a> {
         Err(Error::InvalidState)
     }
 
+    fn timeout_error(&self) -> Error {
+        error!(
+            "Failed to join the network in time, contacted {:?}, received {:?}",
+            self.contacts, self.responses
+        );
+        Error::BootstrapTimeout {
+            contacts: self.contacts.clone(),
+            responses: self.responses.clone(),
+        }
+    }
+
+    fn report_progress(&self, progress: JoinProgress) {
+        if let Some(tx) = &self.progress_tx {
+            let _ = tx.send(progress);
+        }
+    }
+
     fn backlog_message(&mut self, message: RoutingMsg, sender: SocketAddr, dest_info: DestInfo) {
         while self.backlog.len() >= BACKLOG_CAPACITY {
             let _ = self.backlog.pop_front();
@@ -431,6 +576,36 @@ impl<'a> Join<'a> {
     }
 }
 
+// Whether a `Retry` carries older info than we already have. The join requests are raced to
+// several contacts, some possibly out of date, so their answers can arrive after we moved on to a
+// newer section key. `Retry` comes without a proof chain, so newer is judged by who sends it: once
+// we know the section we're joining, only its elders we last sent our request to can point us to
+// another key of it, while anyone can point us to a section split off it. Keys we already moved on
+// from are never taken back.
+fn is_stale_retry(
+    section_auth: &SectionAuthorityProvider,
+    sender: SocketAddr,
+    section_prefix: Option<&Prefix>,
+    superseded_keys: &HashSet<bls::PublicKey>,
+    recipients: &[(XorName, SocketAddr)],
+) -> bool {
+    if superseded_keys.contains(&section_auth.section_key()) {
+        return true;
+    }
+
+    let section_prefix = if let Some(prefix) = section_prefix {
+        prefix
+    } else {
+        return false;
+    };
+
+    if section_auth.prefix.bit_count() != section_prefix.bit_count() {
+        return section_auth.prefix.bit_count() < section_prefix.bit_count();
+    }
+
+    !recipients.iter().any(|(_, addr)| *addr == sender)
+}
+
 // Keep reading messages from `rx` and send them using `comm`.
 async fn send_messages(
     mut rx: mpsc::Receiver<(MessageType, Vec<(XorName, SocketAddr)>)>,
@@ -460,8 +635,8 @@ mod tests {
         pin_mut,
     };
     use secured_linked_list::SecuredLinkedList;
-    use sn_messaging::{node::NodeState, SectionAuthorityProvider};
-    use std::collections::BTreeMap;
+    use sn_messaging::node::NodeState;
+    use std::{collections::BTreeMap, time::Duration};
     use tokio::task;
 
     #[tokio::test]
@@ -485,10 +660,12 @@ mod tests {
             gen_addr(),
         );
         let peer = node.peer();
-        let state = Join::new(node, send_tx, &mut recv_rx);
+        let params = NetworkParams::default();
+        let policy = JoinPolicy::default();
+        let state = Join::new(node, send_tx, &mut recv_rx, &params, &policy, None);
 
         // Create the bootstrap task, but don't run it yet.
-        let bootstrap = async move { state.run(bootstrap_addr).await.map_err(Error::from) };
+        let bootstrap = async move { state.run(vec![bootstrap_addr]).await.map_err(Error::from) };
 
         // Create the task that executes the body of the test, but don't run it either.
         let others = async {
@@ -585,9 +762,11 @@ mod tests {
             gen_addr(),
         );
         let name = node.name();
-        let state = Join::new(node, send_tx, &mut recv_rx);
+        let params = NetworkParams::default();
+        let policy = JoinPolicy::default();
+        let state = Join::new(node, send_tx, &mut recv_rx, &params, &policy, None);
 
-        let bootstrap_task = state.run(bootstrap_node.addr);
+        let bootstrap_task = state.run(vec![bootstrap_node.addr]);
         let test_task = async move {
             // Receive JoinRequest
             let (message, recipients) = send_rx
@@ -676,9 +855,11 @@ mod tests {
             gen_addr(),
         );
         let node_name = node.name();
-        let state = Join::new(node, send_tx, &mut recv_rx);
+        let params = NetworkParams::default();
+        let policy = JoinPolicy::default();
+        let state = Join::new(node, send_tx, &mut recv_rx, &params, &policy, None);
 
-        let bootstrap_task = state.run(bootstrap_node.addr);
+        let bootstrap_task = state.run(vec![bootstrap_node.addr]);
         let test_task = async {
             let (message, _) = send_rx
                 .recv()
@@ -753,9 +934,11 @@ mod tests {
         );
 
         let node_name = node.name();
-        let state = Join::new(node, send_tx, &mut recv_rx);
+        let params = NetworkParams::default();
+        let policy = JoinPolicy::default();
+        let state = Join::new(node, send_tx, &mut recv_rx, &params, &policy, None);
 
-        let bootstrap_task = state.run(bootstrap_node.addr);
+        let bootstrap_task = state.run(vec![bootstrap_node.addr]);
         let test_task = async {
             let (message, _) = send_rx
                 .recv()
@@ -788,6 +971,118 @@ mod tests {
         test_result
     }
 
+    #[tokio::test]
+    async fn join_races_all_contacts() -> Result<()> {
+        let (send_tx, mut send_rx) = mpsc::channel(1);
+        let (_recv_tx, mut recv_rx) = mpsc::channel(1);
+
+        let node = Node::new(
+            ed25519::gen_keypair(&Prefix::default().range_inclusive(), MIN_ADULT_AGE),
+            gen_addr(),
+        );
+        let node_name = node.name();
+        let contacts = vec![gen_addr(), gen_addr(), gen_addr()];
+
+        let params = NetworkParams::default();
+        let policy = JoinPolicy::default();
+        let state = Join::new(node, send_tx, &mut recv_rx, &params, &policy, None);
+
+        let bootstrap_task = state.run(contacts.clone());
+        let test_task = async {
+            let (message, recipients) = send_rx
+                .recv()
+                .await
+                .ok_or_else(|| anyhow!("JoinRequest was not received"))?;
+
+            assert_matches!(message, MessageType::Routing { msg, .. } =>
+                            assert_matches!(msg.variant, Variant::JoinRequest(_)));
+            itertools::assert_equal(recipients, contacts.iter().map(|addr| (node_name, *addr)));
+
+            Ok(())
+        };
+
+        pin_mut!(bootstrap_task);
+        pin_mut!(test_task);
+
+        match future::select(bootstrap_task, test_task).await {
+            Either::Left(_) => unreachable!(),
+            Either::Right((output, _)) => output,
+        }
+    }
+
+    #[tokio::test]
+    async fn join_times_out() -> Result<()> {
+        time::pause();
+
+        let (send_tx, mut send_rx) = mpsc::channel(1);
+        let (_recv_tx, mut recv_rx) = mpsc::channel(1);
+        let (progress_tx, mut progress_rx) = mpsc::unbounded_channel();
+
+        let bootstrap_addr = gen_addr();
+        let node = Node::new(
+            ed25519::gen_keypair(&Prefix::default().range_inclusive(), MIN_ADULT_AGE),
+            gen_addr(),
+        );
+
+        let params = NetworkParams::default();
+        let policy = JoinPolicy {
+            deadline: Some(Duration::from_secs(10)),
+            retry_interval: Duration::from_secs(4),
+            backoff_factor: 2,
+            max_retry_interval: Duration::from_secs(60),
+        };
+        let state = Join::new(
+            node,
+            send_tx,
+            &mut recv_rx,
+            &params,
+            &policy,
+            Some(progress_tx),
+        );
+
+        let bootstrap_task = state.run(vec![bootstrap_addr]);
+
+        // The initial request and one retry after 4s. The next retry would be due after another
+        // 8s, which is past the deadline.
+        let test_task = async {
+            for _ in 0..2 {
+                let (message, recipients) = send_rx
+                    .recv()
+                    .await
+                    .ok_or_else(|| anyhow!("JoinRequest was not received"))?;
+                assert_matches!(message, MessageType::Routing { msg, .. } =>
+                                assert_matches!(msg.variant, Variant::JoinRequest(_)));
+                assert_eq!(recipients[0].1, bootstrap_addr);
+            }
+
+            Ok::<_, Error>(())
+        };
+
+        let (join_result, test_result) = future::join(bootstrap_task, test_task).await;
+        test_result?;
+
+        assert_matches!(
+            join_result,
+            Err(RoutingError::BootstrapTimeout { contacts, responses }) => {
+                assert_eq!(contacts, [bootstrap_addr]);
+                assert!(responses.is_empty());
+            }
+        );
+        assert_matches!(
+            progress_rx.recv().await,
+            Some(JoinProgress::RequestSent {
+                resource_proof: false,
+                ..
+            })
+        );
+        assert_matches!(
+            progress_rx.recv().await,
+            Some(JoinProgress::NoResponse { waited }) => assert_eq!(waited, Duration::from_secs(4))
+        );
+
+        Ok(())
+    }
+
     #[tokio::test]
     async fn join_invalid_retry_prefix_response() -> Result<()> {
         let (send_tx, mut send_rx) = mpsc::channel(1);
@@ -815,7 +1110,9 @@ mod tests {
             }
         };
 
-        let state = Join::new(node, send_tx, &mut recv_rx);
+        let params = NetworkParams::default();
+        let policy = JoinPolicy::default();
+        let state = Join::new(node, send_tx, &mut recv_rx, &params, &policy, None);
 
         let section_key = bls::SecretKey::random().public_key();
         let elders = (0..ELDER_SIZE)
@@ -875,6 +1172,113 @@ mod tests {
         }
     }
 
+    #[tokio::test]
+    async fn join_ignores_stale_retry_response() -> Result<()> {
+        let (send_tx, mut send_rx) = mpsc::channel(1);
+        let (recv_tx, mut recv_rx) = mpsc::channel(1);
+
+        let (old_section_auth, mut old_nodes, _) =
+            gen_section_authority_provider(Prefix::default(), ELDER_SIZE);
+        let (section_auth, mut nodes, _) =
+            gen_section_authority_provider(Prefix::default(), ELDER_SIZE);
+        let (new_section_auth, _, _) =
+            gen_section_authority_provider(Prefix::default(), ELDER_SIZE);
+        let up_to_date_contact = nodes.remove(0);
+        let stale_contact = old_nodes.remove(0);
+
+        let node = Node::new(
+            ed25519::gen_keypair(&Prefix::default().range_inclusive(), MIN_AGE + 2),
+            gen_addr(),
+        );
+        let node_name = node.name();
+        let params = NetworkParams::default();
+        let policy = JoinPolicy::default();
+        let state = Join::new(node, send_tx, &mut recv_rx, &params, &policy, None);
+
+        let bootstrap_task = state.run(vec![up_to_date_contact.addr, stale_contact.addr]);
+        let test_task = async {
+            let (message, _) = send_rx
+                .recv()
+                .await
+                .ok_or_else(|| anyhow!("JoinRequest was not received"))?;
+            assert_matches!(message, MessageType::Routing { msg, .. } =>
+                            assert_matches!(msg.variant, Variant::JoinRequest(_)));
+
+            // The up-to-date contact answers first...
+            send_response(
+                &recv_tx,
+                Variant::JoinResponse(Box::new(JoinResponse::Retry(section_auth.clone()))),
+                &up_to_date_contact,
+                section_auth.section_key(),
+                node_name,
+            )?;
+
+            let (message, _) = send_rx
+                .recv()
+                .await
+                .ok_or_else(|| anyhow!("JoinRequest was not received"))?;
+            assert_matches!(message, MessageType::Routing { msg, .. } =>
+                assert_matches!(msg.variant, Variant::JoinRequest(request) =>
+                    assert_eq!(request.section_key, section_auth.section_key())));
+
+            // ...then the stale one, which isn't an elder of the section we're now joining.
+            send_response(
+                &recv_tx,
+                Variant::JoinResponse(Box::new(JoinResponse::Retry(old_section_auth.clone()))),
+                &stale_contact,
+                old_section_auth.section_key(),
+                node_name,
+            )?;
+            task::yield_now().await;
+
+            // An elder of the section we're joining can still point us to its newer key, but not
+            // back to one we moved on from.
+            send_response(
+                &recv_tx,
+                Variant::JoinResponse(Box::new(JoinResponse::Retry(new_section_auth.clone()))),
+                &up_to_date_contact,
+                new_section_auth.section_key(),
+                node_name,
+            )?;
+
+            let (message, _) = send_rx
+                .recv()
+                .await
+                .ok_or_else(|| anyhow!("JoinRequest was not received"))?;
+            assert_matches!(message, MessageType::Routing { msg, .. } =>
+                assert_matches!(msg.variant, Variant::JoinRequest(request) =>
+                    assert_eq!(request.section_key, new_section_auth.section_key())));
+
+            assert!(is_stale_retry(
+                &section_auth,
+                up_to_date_contact.addr,
+                Some(&Prefix::default()),
+                &[section_auth.section_key()].iter().copied().collect(),
+                &[(up_to_date_contact.name(), up_to_date_contact.addr)],
+            ));
+
+            // A section split off the one we're joining is newer, whoever tells us about it.
+            let child = Prefix::default().pushed(node_name.bit(0));
+            assert!(!is_stale_retry(
+                &gen_section_authority_provider(child, ELDER_SIZE).0,
+                stale_contact.addr,
+                Some(&Prefix::default()),
+                &HashSet::new(),
+                &[],
+            ));
+
+            Ok(())
+        };
+
+        pin_mut!(bootstrap_task);
+        pin_mut!(test_task);
+
+        match future::select(bootstrap_task, test_task).await {
+            Either::Left(_) => unreachable!(),
+            Either::Right((output, _)) => output,
+        }
+    }
+
     // test helper
     fn send_response(
         recv_tx: &mpsc::Sender<ConnectionEvent>,
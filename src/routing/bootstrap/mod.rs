@@ -6,10 +6,14 @@
 // KIND, either express or implied. Please review the Licences for the specific language governing
 // permissions and limitations relating to use of the SAFE Network Software.
 
+mod cache;
 mod join;
+mod policy;
 mod relocate;
 
+pub(crate) use cache::BootstrapCache;
 pub(crate) use join::join;
+pub use policy::{JoinPolicy, JoinProgress, JoinResponseKind};
 pub(crate) use relocate::JoinAsRelocated;
 
 use crate::{
@@ -21,7 +25,11 @@ use std::net::SocketAddr;
 use xor_name::XorName;
 
 // Send message using `comm`.
-async fn send_message(comm: &Comm, message: MessageType, recipients: Vec<(XorName, SocketAddr)>) {
+pub(super) async fn send_message(
+    comm: &Comm,
+    message: MessageType,
+    recipients: Vec<(XorName, SocketAddr)>,
+) {
     match comm
         .send(&recipients, recipients.len(), message.clone())
         .await
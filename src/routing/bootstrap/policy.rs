@@ -0,0 +1,164 @@
+// Copyright 2021 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under The General Public License (GPL), version 3.
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied. Please review the Licences for the specific language governing
+// permissions and limitations relating to use of the SAFE Network Software.
+
+use crate::error::{Error, Result};
+use sn_messaging::node::JoinResponse;
+use std::{cmp, net::SocketAddr, time::Duration};
+
+/// How long a new node keeps trying to join the network and how often it re-sends its join
+/// requests when they go unanswered.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct JoinPolicy {
+    /// Give up joining with `Error::BootstrapTimeout` once this much time has passed. `None` means
+    /// keep trying forever.
+    pub deadline: Option<Duration>,
+    /// How long to wait for a response before re-sending the last join request.
+    pub retry_interval: Duration,
+    /// Factor the wait for a response grows by with every unanswered attempt. 1 keeps it constant,
+    /// 0 is rejected, like zero intervals are.
+    pub backoff_factor: u32,
+    /// Upper bound of the wait for a response.
+    pub max_retry_interval: Duration,
+}
+
+impl JoinPolicy {
+    // Checks the policy lets the requests be re-sent at all, and not in a busy loop.
+    pub(crate) fn validate(&self) -> Result<()> {
+        if self.retry_interval == Duration::from_secs(0) {
+            return Err(Error::InvalidJoinPolicy("retry_interval must be positive"));
+        }
+
+        if self.max_retry_interval == Duration::from_secs(0) {
+            return Err(Error::InvalidJoinPolicy(
+                "max_retry_interval must be positive",
+            ));
+        }
+
+        if self.backoff_factor == 0 {
+            return Err(Error::InvalidJoinPolicy("backoff_factor must be positive"));
+        }
+
+        Ok(())
+    }
+
+    pub(crate) fn next_retry_interval(&self, interval: Duration) -> Duration {
+        cmp::min(
+            interval.saturating_mul(self.backoff_factor),
+            self.max_retry_interval,
+        )
+    }
+}
+
+impl Default for JoinPolicy {
+    fn default() -> Self {
+        Self {
+            deadline: None,
+            retry_interval: Duration::from_secs(30),
+            backoff_factor: 2,
+            max_retry_interval: Duration::from_secs(120),
+        }
+    }
+}
+
+/// Kind of `JoinResponse` received while joining, as reported in `Error::BootstrapTimeout` and
+/// `JoinProgress`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum JoinResponseKind {
+    /// The section asked us to try again with its up-to-date info.
+    Retry,
+    /// We were redirected to another section.
+    Redirect,
+    /// We were asked to solve a resource proof.
+    ResourceChallenge,
+    /// We were rejected, e.g. because the section doesn't take new nodes at the moment.
+    Rejected,
+    /// We were approved to join.
+    Approval,
+}
+
+impl From<&JoinResponse> for JoinResponseKind {
+    fn from(response: &JoinResponse) -> Self {
+        match response {
+            JoinResponse::Retry(_) => Self::Retry,
+            JoinResponse::Redirect(_) => Self::Redirect,
+            JoinResponse::ResourceChallenge { .. } => Self::ResourceChallenge,
+            JoinResponse::Rejected(_) => Self::Rejected,
+            JoinResponse::Approval { .. } => Self::Approval,
+        }
+    }
+}
+
+/// Step of the joining process, sent to `Config::join_progress` as it happens.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum JoinProgress {
+    /// A join request was sent to the given peers.
+    RequestSent {
+        /// Addresses of the peers.
+        recipients: Vec<SocketAddr>,
+        /// Whether the request carries the solution of a resource proof.
+        resource_proof: bool,
+    },
+    /// No response arrived in time, so the last request is going to be sent again.
+    NoResponse {
+        /// How long we waited.
+        waited: Duration,
+    },
+    /// A valid response was received.
+    ResponseReceived {
+        /// Address of the responding peer.
+        sender: SocketAddr,
+        /// What it responded with.
+        response: JoinResponseKind,
+    },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_matches::assert_matches;
+
+    #[test]
+    fn validate() {
+        assert_matches!(JoinPolicy::default().validate(), Ok(()));
+
+        let policy = JoinPolicy {
+            retry_interval: Duration::from_secs(0),
+            ..Default::default()
+        };
+        assert_matches!(policy.validate(), Err(Error::InvalidJoinPolicy(_)));
+
+        let policy = JoinPolicy {
+            max_retry_interval: Duration::from_secs(0),
+            ..Default::default()
+        };
+        assert_matches!(policy.validate(), Err(Error::InvalidJoinPolicy(_)));
+
+        let policy = JoinPolicy {
+            backoff_factor: 0,
+            ..Default::default()
+        };
+        assert_matches!(policy.validate(), Err(Error::InvalidJoinPolicy(_)));
+    }
+
+    #[test]
+    fn retry_interval_backs_off_up_to_max() {
+        let policy = JoinPolicy {
+            retry_interval: Duration::from_secs(1),
+            backoff_factor: 3,
+            max_retry_interval: Duration::from_secs(5),
+            ..Default::default()
+        };
+
+        let interval = policy.next_retry_interval(policy.retry_interval);
+        assert_eq!(interval, Duration::from_secs(3));
+        let interval = policy.next_retry_interval(interval);
+        assert_eq!(interval, Duration::from_secs(5));
+        let interval = policy.next_retry_interval(interval);
+        assert_eq!(interval, Duration::from_secs(5));
+    }
+}
@@ -6,29 +6,38 @@
 // KIND, either express or implied. Please review the Licences for the specific language governing
 // permissions and limitations relating to use of the SAFE Network Software.
 
-use crate::error::{Error, Result};
-use crate::XorName;
+use super::{
+    compression::{self, CompressionConfig, Compressor, Frame},
+    rate_limit::{MessageClass, RateLimiter, RateLimits, SectionMembers, Verdict},
+    transport::{self, Transport, TransportBackend},
+};
+use crate::{
+    error::{Error, Result},
+    metrics::{Metric, Metrics},
+    XorName,
+};
 use bytes::Bytes;
 use futures::stream::{FuturesUnordered, StreamExt};
 use hex_fmt::HexFmt;
-use qp2p::{Endpoint, QuicP2p};
 use sn_messaging::MessageType;
 use std::{
     fmt::{self, Debug, Formatter},
     net::SocketAddr,
-    sync::RwLock,
+    sync::{Mutex, MutexGuard, PoisonError, RwLock},
 };
-use tokio::{sync::mpsc, task};
+use tokio::{sync::mpsc, time::Instant};
 
 // Communication component of the node to interact with other nodes.
 pub(crate) struct Comm {
-    _quic_p2p: QuicP2p,
-    endpoint: Endpoint,
+    transport: Box<dyn Transport>,
     // Sender for connection events. Kept here so we can clone it and pass it to the incoming
     // messages handler every time we establish new connection. It's kept in an `Option` so we can
     // take it out and drop it on `terminate` which together with all the incoming message handlers
     // terminating closes the corresponding receiver.
     event_tx: RwLock<Option<mpsc::Sender<ConnectionEvent>>>,
+    metrics: Metrics,
+    rate_limiter: Mutex<RateLimiter>,
+    compressor: Mutex<Compressor>,
 }
 
 impl Comm {
@@ -36,65 +45,40 @@ impl Comm {
         transport_config: qp2p::Config,
         event_tx: mpsc::Sender<ConnectionEvent>,
     ) -> Result<Self> {
-        let quic_p2p = QuicP2p::with_config(Some(transport_config), &[], true)
-            .map_err(|err| Error::InvalidConfig { err })?;
-
-        // Don't bootstrap, just create an endpoint to listen to
-        // the incoming messages from other nodes.
-        // This also returns the a channel where we can listen for
-        // disconnection events.
-        let (endpoint, _incoming_connections, incoming_messages, disconnections) = quic_p2p
-            .new_endpoint()
-            .await
-            .map_err(|err| Error::CannotConnectEndpoint { err })?;
-
-        let _ = task::spawn(handle_incoming_messages(
-            incoming_messages,
-            event_tx.clone(),
-        ));
+        Self::with_backend(&TransportBackend::Quic, transport_config, event_tx).await
+    }
 
-        let _ = task::spawn(handle_disconnection_events(
-            disconnections,
-            event_tx.clone(),
-        ));
+    pub async fn with_backend(
+        backend: &TransportBackend,
+        transport_config: qp2p::Config,
+        event_tx: mpsc::Sender<ConnectionEvent>,
+    ) -> Result<Self> {
+        let transport = transport::bind(backend, transport_config, event_tx.clone()).await?;
 
         Ok(Self {
-            _quic_p2p: quic_p2p,
-            endpoint,
+            transport,
             event_tx: RwLock::new(Some(event_tx)),
+            metrics: Metrics::default(),
+            rate_limiter: Mutex::new(RateLimiter::new(RateLimits::default())),
+            compressor: Mutex::new(Compressor::new(CompressionConfig::disabled())),
         })
     }
 
     pub async fn bootstrap(
+        backend: &TransportBackend,
         transport_config: qp2p::Config,
         event_tx: mpsc::Sender<ConnectionEvent>,
     ) -> Result<(Self, SocketAddr)> {
-        let quic_p2p = QuicP2p::with_config(Some(transport_config), &[], true)
-            .map_err(|err| Error::InvalidConfig { err })?;
-
-        // Bootstrap to the network returning the connection to a node.
-        // We can use the returned channels to listen for incoming messages and disconnection events
-        let (endpoint, _incoming_connections, incoming_messages, disconnections, bootstrap_addr) =
-            quic_p2p
-                .bootstrap()
-                .await
-                .map_err(|err| Error::CannotConnectEndpoint { err })?;
-
-        let _ = task::spawn(handle_incoming_messages(
-            incoming_messages,
-            event_tx.clone(),
-        ));
-
-        let _ = task::spawn(handle_disconnection_events(
-            disconnections,
-            event_tx.clone(),
-        ));
+        let (transport, bootstrap_addr) =
+            transport::bootstrap(backend, transport_config, event_tx.clone()).await?;
 
         Ok((
             Self {
-                _quic_p2p: quic_p2p,
-                endpoint,
+                transport,
                 event_tx: RwLock::new(Some(event_tx)),
+                metrics: Metrics::default(),
+                rate_limiter: Mutex::new(RateLimiter::new(RateLimits::default())),
+                compressor: Mutex::new(Compressor::new(CompressionConfig::disabled())),
             },
             bootstrap_addr,
         ))
@@ -102,7 +86,7 @@ impl Comm {
 
     // Close all existing connections and stop accepting new ones.
     pub fn terminate(&self) {
-        self.endpoint.close();
+        self.transport.close();
         let _ = self
             .event_tx
             .write()
@@ -110,8 +94,101 @@ impl Comm {
             .take();
     }
 
+    // Metrics of the whole node. Created here as the comm outlives the node state, e.g. on
+    // relocation.
+    pub fn metrics(&self) -> &Metrics {
+        &self.metrics
+    }
+
+    pub fn set_rate_limits(&self, limits: RateLimits) {
+        self.rate_limiter
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .set_limits(limits)
+    }
+
+    // Addresses of our section members, exempt from the rate limits. Kept up to date by the node
+    // state.
+    pub fn section_members(&self) -> SectionMembers {
+        self.rate_limiter
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .members()
+            .clone()
+    }
+
+    // Whether a message of `class` from `sender` is within the rate limits and should be handled.
+    // Counts the messages that aren't, and blocks the sender if it keeps exceeding the limits.
+    pub fn admit(&self, sender: SocketAddr, class: MessageClass) -> bool {
+        let verdict = self
+            .rate_limiter
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .check(sender, class, Instant::now());
+
+        match verdict {
+            Verdict::Admit => true,
+            Verdict::Drop => {
+                trace!("Rate limited {} message from {}", class.label(), sender);
+                self.metrics.inc(Metric::RateLimited, class.label());
+                false
+            }
+            Verdict::Block => {
+                warn!(
+                    "Blocking {} for repeatedly exceeding the rate limits",
+                    sender
+                );
+                self.metrics.inc(Metric::RateLimited, class.label());
+                self.metrics.inc(Metric::PeersBlocked, "");
+                false
+            }
+        }
+    }
+
+    // Whether `addr` is temporarily blocked for exceeding the rate limits. All its messages are
+    // ignored meanwhile.
+    pub fn is_blocked(&self, addr: &SocketAddr) -> bool {
+        self.rate_limiter
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .is_blocked(addr, Instant::now())
+    }
+
+    // Compression is disabled until set, e.g. while bootstrapping.
+    pub fn set_compression(&self, config: CompressionConfig) {
+        self.compressor().set_config(config)
+    }
+
+    // Decodes a message received from `sender`, decompressing it if it was compressed. Returns
+    // `None` if there is nothing further to handle, e.g. as the sender only announced it supports
+    // compression.
+    pub async fn receive(&self, sender: SocketAddr, bytes: Bytes) -> Option<Bytes> {
+        let frame = self.compressor().receive(sender, bytes);
+        match frame {
+            Ok(Frame::Message(bytes)) => Some(bytes),
+            Ok(Frame::Capabilities { reply }) => {
+                trace!("{} supports compression", sender);
+                if let Some(reply) = reply {
+                    if let Err(error) = self.transport.send(sender, reply).await {
+                        debug!("Failed to announce compression to {}: {}", sender, error);
+                    }
+                }
+                None
+            }
+            Err(error) => {
+                error!("Failed to decompress message from {}: {}", sender, error);
+                None
+            }
+        }
+    }
+
+    // Forgets whether the peer at `addr` supports compression, once disconnected from it.
+    pub fn forget_peer(&self, addr: &SocketAddr) {
+        self.compressor().forget(addr)
+    }
+
     pub fn our_connection_info(&self) -> SocketAddr {
-        self.endpoint.socket_addr()
+        self.transport.local_addr()
     }
 
     /// Sends a message on an existing connection. If no such connection exists, returns an error.
@@ -123,8 +200,8 @@ impl Comm {
         msg.update_dest_info(None, Some(recipient.0));
 
         let bytes = msg.serialize()?;
-        self.endpoint
-            .try_send_message(bytes, &recipient.1)
+        self.transport
+            .send_on_existing_connection(recipient.1, bytes)
             .await
             .map_err(|err| {
                 error!("Sending to {:?} failed with {}", recipient, err);
@@ -134,28 +211,26 @@ impl Comm {
         Ok(())
     }
 
-    /// Tests whether the peer is reachable.
-    pub async fn is_reachable(&self, peer: &SocketAddr) -> Result<(), Error> {
-        let qp2p_config = qp2p::Config {
-            local_ip: Some(self.endpoint.local_addr().ip()),
-            local_port: Some(0),
-            forward_port: false,
-            ..Default::default()
-        };
-
-        let qp2p = QuicP2p::with_config(Some(qp2p_config), &[], false)
-            .map_err(|err| Error::InvalidConfig { err })?;
-        let (connectivity_endpoint, _, _, _) = qp2p
-            .new_endpoint()
+    /// Sends raw bytes, not a `MessageType`, on an existing connection. Used for the messages of
+    /// the client handshake.
+    pub async fn send_bytes_on_existing_connection(
+        &self,
+        recipient: SocketAddr,
+        bytes: Bytes,
+    ) -> Result<(), Error> {
+        self.transport
+            .send_on_existing_connection(recipient, bytes)
             .await
-            .map_err(|err| Error::CannotConnectEndpoint { err })?;
+    }
 
-        connectivity_endpoint
-            .is_reachable(peer)
+    /// Tests whether the peer is reachable.
+    pub async fn is_reachable(&self, peer: &SocketAddr) -> Result<(), Error> {
+        self.transport
+            .is_reachable(*peer)
             .await
             .map_err(|err| {
                 info!("Peer {} is NOT externally reachable: {}", peer, err);
-                Error::AddressNotReachable { err }
+                err
             })
             .map(|()| {
                 info!("Peer {} is externally reachable.", peer);
@@ -172,6 +247,25 @@ impl Comm {
     /// on if the minimum delivery group size is met or not. The failed recipients are sent along
     /// with the status. It returns a `SendStatus::AllRecipients` if message is sent to all the recipients.
     pub async fn send(
+        &self,
+        recipients: &[(XorName, SocketAddr)],
+        delivery_group_size: usize,
+        msg: MessageType,
+    ) -> Result<SendStatus> {
+        let result = self.try_send(recipients, delivery_group_size, msg).await;
+
+        let status = match &result {
+            Ok(SendStatus::AllRecipients) => "all_recipients",
+            Ok(SendStatus::MinDeliveryGroupSizeReached(_)) => "min_delivery_group_size_reached",
+            Ok(SendStatus::MinDeliveryGroupSizeFailed(_)) => "min_delivery_group_size_failed",
+            Err(_) => "error",
+        };
+        self.metrics.inc(Metric::SendOutcomes, status);
+
+        result
+    }
+
+    async fn try_send(
         &self,
         recipients: &[(XorName, SocketAddr)],
         delivery_group_size: usize,
@@ -201,37 +295,37 @@ impl Comm {
         msg.update_dest_info(None, Some(recipients[0].0));
 
         let msg_bytes = msg.serialize().map_err(Error::Messaging)?;
+        let compressed = self.compress(&msg_bytes, recipients);
 
         // Run all the sends concurrently (using `FuturesUnordered`). If any of them fails, pick
         // the next recipient and try to send to them. Proceed until the needed number of sends
         // succeeds or if there are no more recipients to pick.
-        let send = |recipient: (XorName, SocketAddr), msg_bytes: Bytes| async move {
-            trace!(
-                "Sending message ({} bytes) to {} of {:?}",
-                msg_bytes.len(),
-                delivery_group_size,
-                recipient.1
-            );
+        let send = |recipient: (XorName, SocketAddr)| {
+            let (announcement, msg_bytes) = self.frames(recipient.1, &msg_bytes, &compressed);
+
+            async move {
+                if let Some(announcement) = announcement {
+                    // Nodes that don't support compression drop the announcement, and a failure
+                    // to send it shows in sending the message itself.
+                    let _ = self.transport.send(recipient.1, announcement).await;
+                }
 
-            let result = self
-                .send_to(&recipient.1, msg_bytes)
-                .await
-                .map_err(|err| match err {
-                    qp2p::Error::Connection(qp2p::ConnectionError::LocallyClosed) => {
-                        Error::ConnectionClosed
-                    }
-                    _ => {
-                        trace!("during sending, received error {:?}", err);
-                        Error::AddressNotReachable { err }
-                    }
-                });
+                trace!(
+                    "Sending message ({} bytes) to {} of {:?}",
+                    msg_bytes.len(),
+                    delivery_group_size,
+                    recipient.1
+                );
+
+                let result = self.transport.send(recipient.1, msg_bytes).await;
 
-            (result, recipient.1)
+                (result, recipient.1)
+            }
         };
 
         let mut tasks: FuturesUnordered<_> = recipients[0..delivery_group_size]
             .iter()
-            .map(|(name, recipient)| send((*name, *recipient), msg_bytes.clone()))
+            .map(|(name, recipient)| send((*name, *recipient)))
             .collect();
 
         let mut next = delivery_group_size;
@@ -250,7 +344,7 @@ impl Comm {
                     failed_recipients.push(addr);
 
                     if next < recipients.len() {
-                        tasks.push(send(recipients[next], msg_bytes.clone()));
+                        tasks.push(send(recipients[next]));
                         next += 1;
                     }
                 }
@@ -276,16 +370,58 @@ impl Comm {
         }
     }
 
-    // Low-level send
-    async fn send_to(&self, recipient: &SocketAddr, msg: Bytes) -> Result<(), qp2p::Error> {
-        trace!("Low level send for msg over qp2p");
-        self.endpoint.send_message(msg, recipient).await
+    // Compresses the message if it's over the threshold and any of the recipients supports
+    // compression. Returns `None` if not, or if it doesn't get any smaller.
+    fn compress(&self, msg_bytes: &[u8], recipients: &[(XorName, SocketAddr)]) -> Option<Bytes> {
+        {
+            let compressor = self.compressor();
+            if !compressor.is_worth_compressing(msg_bytes.len())
+                || !recipients.iter().any(|(_, addr)| compressor.supports(addr))
+            {
+                return None;
+            }
+        }
+
+        if let Some(compressed) = compression::compress(msg_bytes) {
+            self.metrics.inc(Metric::Compression, "compressed");
+            self.metrics
+                .add(Metric::CompressionInputBytes, "", msg_bytes.len() as u64);
+            self.metrics
+                .add(Metric::CompressionOutputBytes, "", compressed.len() as u64);
+            Some(compressed)
+        } else {
+            self.metrics.inc(Metric::Compression, "incompressible");
+            None
+        }
+    }
+
+    // Returns what to send to `recipient`: our announcement of compression if this is the first
+    // message to it, and the message, compressed if it supports compression.
+    fn frames(
+        &self,
+        recipient: SocketAddr,
+        msg_bytes: &Bytes,
+        compressed: &Option<Bytes>,
+    ) -> (Option<Bytes>, Bytes) {
+        let mut compressor = self.compressor();
+        let announcement = compressor.announcement(recipient);
+
+        let bytes = if compressor.supports(&recipient) {
+            compressed.as_ref().unwrap_or(msg_bytes).clone()
+        } else {
+            if compressor.is_worth_compressing(msg_bytes.len()) {
+                self.metrics.inc(Metric::Compression, "unsupported");
+            }
+            msg_bytes.clone()
+        };
+
+        (announcement, bytes)
     }
-}
 
-impl Drop for Comm {
-    fn drop(&mut self) {
-        self.endpoint.close()
+    fn compressor(&self) -> MutexGuard<Compressor> {
+        self.compressor
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
     }
 }
 
@@ -303,26 +439,6 @@ impl Debug for ConnectionEvent {
     }
 }
 
-async fn handle_disconnection_events(
-    mut disconnections: qp2p::DisconnectionEvents,
-    event_tx: mpsc::Sender<ConnectionEvent>,
-) {
-    while let Some(peer_addr) = disconnections.next().await {
-        let _ = event_tx
-            .send(ConnectionEvent::Disconnected(peer_addr))
-            .await;
-    }
-}
-
-async fn handle_incoming_messages(
-    mut incoming_msgs: qp2p::IncomingMessages,
-    event_tx: mpsc::Sender<ConnectionEvent>,
-) {
-    while let Some((src, msg)) = incoming_msgs.next().await {
-        let _ = event_tx.send(ConnectionEvent::Received((src, msg))).await;
-    }
-}
-
 /// Returns the status of the send operation.
 #[derive(Debug, Clone)]
 pub enum SendStatus {
@@ -337,7 +453,7 @@ mod tests {
     use anyhow::Result;
     use assert_matches::assert_matches;
     use futures::future;
-    use qp2p::Config;
+    use qp2p::{Config, QuicP2p};
     use sn_data_types::PublicKey;
     use sn_messaging::{section_info::SectionInfoMsg, DestInfo, WireMsg};
     use std::{net::Ipv4Addr, slice, time::Duration};
@@ -364,6 +480,11 @@ mod tests {
             .await?;
 
         assert_matches!(status, SendStatus::AllRecipients);
+        assert_eq!(
+            comm.metrics()
+                .counter(Metric::SendOutcomes, "all_recipients"),
+            1
+        );
 
         if let Some(bytes) = peer0.rx.recv().await {
             original_message.update_dest_info(None, Some(peer0._name));
@@ -568,6 +689,47 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn send_uncompressed_to_peer_without_compression() -> Result<()> {
+        let (tx, mut rx0) = mpsc::channel(1);
+        let comm0 = Comm::new(transport_config(), tx).await?;
+        let addr0 = comm0.our_connection_info();
+
+        let (tx, _rx) = mpsc::channel(1);
+        let comm1 = Comm::new(transport_config(), tx).await?;
+        comm1.set_compression(CompressionConfig {
+            enabled: true,
+            threshold: 0,
+        });
+        let addr1 = comm1.our_connection_info();
+
+        let message = new_section_info_message();
+        let _ = comm1
+            .send(slice::from_ref(&(XorName::random(), addr0)), 1, message)
+            .await?;
+
+        // The announcement comes first, and the message itself as it is, since `comm0` never
+        // announced it supports compression.
+        let mut messages = vec![];
+        for _ in 0..2 {
+            if let Some(ConnectionEvent::Received((src, bytes))) =
+                time::timeout(TIMEOUT, rx0.recv()).await?
+            {
+                assert_eq!(src, addr1);
+                messages.extend(comm0.receive(src, bytes.clone()).await.map(|_| bytes));
+            }
+        }
+
+        assert_eq!(messages.len(), 1);
+        assert!(WireMsg::deserialize(messages.remove(0)).is_ok());
+        assert_eq!(
+            comm1.metrics().counter(Metric::Compression, "unsupported"),
+            1
+        );
+
+        Ok(())
+    }
+
     #[tokio::test]
     async fn incoming_connection_lost() -> Result<()> {
         let (tx, mut rx0) = mpsc::channel(1);
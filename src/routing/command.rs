@@ -6,13 +6,21 @@
 // KIND, either express or implied. Please review the Licences for the specific language governing
 // permissions and limitations relating to use of the SAFE Network Software.
 
-use crate::{routing::Peer, section::SectionKeyShare, XorName};
+use super::{command_queue::Priority, transfer::Chunk};
+use crate::{
+    delivery::DeliveryPolicy,
+    messages::Envelope,
+    request::{RequestHandle, Response},
+    routing::Peer,
+    section::SectionKeyShare,
+    XorName,
+};
 use bytes::Bytes;
 use hex_fmt::HexFmt;
 use sn_messaging::{
-    node::{DkgFailureSignedSet, Proposal, RoutingMsg, Signed, SignedRelocateDetails},
+    node::{DkgFailureSignedSet, Proposal, RoutingMsg, Signed, SignedRelocateDetails, Variant},
     section_info::SectionInfoMsg,
-    DestInfo, Itinerary, MessageType, SectionAuthorityProvider,
+    DestInfo, Itinerary, MessageId, MessageType, SectionAuthorityProvider,
 };
 use std::{
     fmt::{self, Debug, Formatter},
@@ -20,7 +28,7 @@ use std::{
     sync::atomic::{AtomicU64, Ordering},
     time::Duration,
 };
-use tokio::sync::mpsc;
+use tokio::sync::{mpsc, oneshot};
 
 /// Command for node.
 #[allow(clippy::large_enum_variant)]
@@ -67,6 +75,42 @@ pub(crate) enum Command {
         content: Bytes,
         additional_proof_chain_key: Option<bls::PublicKey>,
     },
+    /// Send `UserMessage` to be acknowledged by its destination, retransmitting it until the
+    /// receipt arrives. The sender is notified once it does.
+    SendMessageWithReceipt {
+        itinerary: Itinerary,
+        content: Bytes,
+        policy: DeliveryPolicy,
+        msg_id: MessageId,
+        receipt_tx: oneshot::Sender<()>,
+    },
+    /// Send a chunk of a large message to the node `dst`.
+    SendChunk { dst: XorName, chunk: Chunk },
+    /// Send `UserMessage` with the given content, with the nodes relaying it recorded.
+    SendTracedMessage {
+        itinerary: Itinerary,
+        content: Bytes,
+    },
+    /// Send a probe tracing the route to the given node. The sender is notified with the response
+    /// carrying the recorded hops if it arrives within `timeout`.
+    SendTraceProbe {
+        dst: XorName,
+        timeout: Duration,
+        response_tx: oneshot::Sender<Response>,
+    },
+    /// Send `UserMessage` carrying a request. The sender is notified with the response if it
+    /// arrives within `timeout`.
+    SendRequest {
+        itinerary: Itinerary,
+        content: Bytes,
+        timeout: Duration,
+        response_tx: oneshot::Sender<Response>,
+    },
+    /// Send `UserMessage` carrying the response to a previously received request.
+    SendResponse {
+        handle: RequestHandle,
+        content: Bytes,
+    },
     /// Schedule a timeout after the given duration. When the timeout expires, a `HandleTimeout`
     /// command is raised. The token is used to identify the timeout.
     ScheduleTimeout { duration: Duration, token: u64 },
@@ -96,6 +140,8 @@ pub(crate) enum Command {
     StartConnectivityTest(XorName),
     /// Test Connectivity
     TestConnectivity(XorName),
+    /// Ask our elders to vote us offline. The sender is notified once they agree on it.
+    Leave(oneshot::Sender<()>),
 }
 
 impl Command {
@@ -121,6 +167,55 @@ impl Command {
             message: MessageType::Routing { dest_info, msg },
         }
     }
+
+    /// Priority of the command in the queue of the `Dispatcher`.
+    pub fn priority(&self) -> Priority {
+        match self {
+            Self::HandleAgreement { .. }
+            | Self::HandleDkgOutcome { .. }
+            | Self::HandleDkgFailure(_)
+            | Self::SetJoinsAllowed(_)
+            | Self::ProposeOnline { .. }
+            | Self::ProposeOffline(_) => Priority::Consensus,
+            Self::HandleMessage { message, .. } => variant_priority(&message.variant),
+            Self::SendMessage { message, .. } => match message {
+                MessageType::Routing { msg, .. } => variant_priority(&msg.variant),
+                MessageType::Client { .. } => Priority::User,
+                MessageType::SectionInfo { .. } | MessageType::Node { .. } => Priority::Network,
+            },
+            // Losing a chunk or its acknowledgement stalls the whole transfer until it's resent.
+            Self::SendChunk { .. } => Priority::Network,
+            Self::SendUserMessage { .. }
+            | Self::SendMessageWithReceipt { .. }
+            | Self::SendTracedMessage { .. }
+            | Self::SendTraceProbe { .. }
+            | Self::SendRequest { .. }
+            | Self::SendResponse { .. } => Priority::User,
+            Self::HandleSectionInfoMsg { .. }
+            | Self::HandleTimeout(_)
+            | Self::HandleConnectionLost(_)
+            | Self::HandlePeerLost(_)
+            | Self::ScheduleTimeout { .. }
+            | Self::Relocate { .. }
+            | Self::StartConnectivityTest(_)
+            | Self::TestConnectivity(_)
+            | Self::Leave(_) => Priority::Network,
+        }
+    }
+}
+
+fn variant_priority(variant: &Variant) -> Priority {
+    match variant {
+        Variant::DkgStart { .. }
+        | Variant::DkgMessage { .. }
+        | Variant::DkgFailureObservation { .. }
+        | Variant::DkgFailureAgreement(_)
+        | Variant::Propose { .. }
+        | Variant::Sync { .. } => Priority::Consensus,
+        Variant::UserMessage(content) if Envelope::is_transfer(content) => Priority::Network,
+        Variant::UserMessage(_) => Priority::User,
+        _ => Priority::Network,
+    }
 }
 
 impl Debug for Command {
@@ -187,6 +282,52 @@ impl Debug for Command {
                 .field("content", &format_args!("{:10}", HexFmt(content)))
                 .field("additional_proof_chain_key", additional_proof_chain_key)
                 .finish(),
+            Self::SendMessageWithReceipt {
+                itinerary,
+                content,
+                policy,
+                msg_id,
+                ..
+            } => f
+                .debug_struct("SendMessageWithReceipt")
+                .field("itinerary", itinerary)
+                .field("content", &format_args!("{:10}", HexFmt(content)))
+                .field("policy", policy)
+                .field("msg_id", msg_id)
+                .finish(),
+            Self::SendChunk { dst, chunk } => f
+                .debug_struct("SendChunk")
+                .field("dst", dst)
+                .field("transfer_id", &format_args!("{:016x}", chunk.transfer_id))
+                .field("index", &chunk.index)
+                .field("count", &chunk.count)
+                .finish(),
+            Self::SendTracedMessage { itinerary, content } => f
+                .debug_struct("SendTracedMessage")
+                .field("itinerary", itinerary)
+                .field("content", &format_args!("{:10}", HexFmt(content)))
+                .finish(),
+            Self::SendTraceProbe { dst, timeout, .. } => f
+                .debug_struct("SendTraceProbe")
+                .field("dst", dst)
+                .field("timeout", timeout)
+                .finish(),
+            Self::SendRequest {
+                itinerary,
+                content,
+                timeout,
+                ..
+            } => f
+                .debug_struct("SendRequest")
+                .field("itinerary", itinerary)
+                .field("content", &format_args!("{:10}", HexFmt(content)))
+                .field("timeout", timeout)
+                .finish(),
+            Self::SendResponse { handle, content } => f
+                .debug_struct("SendResponse")
+                .field("handle", handle)
+                .field("content", &format_args!("{:10}", HexFmt(content)))
+                .finish(),
             Self::ScheduleTimeout { duration, token } => f
                 .debug_struct("ScheduleTimeout")
                 .field("duration", duration)
@@ -219,6 +360,7 @@ impl Debug for Command {
             Self::StartConnectivityTest(name) => {
                 f.debug_tuple("StartConnectivityTest").field(name).finish()
             }
+            Self::Leave(_) => f.debug_tuple("Leave").finish(),
         }
     }
 }
@@ -0,0 +1,366 @@
+// Copyright 2021 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under The General Public License (GPL), version 3.
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied. Please review the Licences for the specific language governing
+// permissions and limitations relating to use of the SAFE Network Software.
+
+use super::command::Command;
+use crate::metrics::{Metric, Metrics};
+use std::{
+    collections::VecDeque,
+    sync::{Mutex, MutexGuard, PoisonError},
+};
+use tokio::sync::Notify;
+
+/// Limits on the commands a node has yet to handle and on how many it handles at once.
+///
+/// Commands wait in a queue per priority: the ones the section relies on to keep running (DKG,
+/// agreement, elder changes) are handled first, the other network commands next and the ones
+/// carrying user and client messages last.
+///
+/// When the queues fill up, the node sheds load in this order:
+/// - messages from clients are dropped as soon as any queue is full,
+/// - user and other non-consensus messages from other nodes are dropped when their queue is full,
+///   their senders retry the ones they rely on,
+/// - consensus messages from nodes wait for room in their queue, which stops the node from
+///   reading further messages off its connections until the workers catch up. They're handled by
+///   dedicated workers, so this only lasts while the section is busy agreeing,
+/// - user commands the node produces itself while handling others are dropped when the user
+///   queue is full. The consensus and network ones are queued beyond the capacity instead, as
+///   dropping them could stall the section.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct CommandLimits {
+    /// Capacity of the queue of consensus commands.
+    pub consensus: usize,
+    /// Capacity of the queue of the other network commands.
+    pub network: usize,
+    /// Capacity of the queue of commands carrying user and client messages.
+    pub user: usize,
+    /// Number of commands handled concurrently. At least one of them can be of any priority.
+    pub workers: usize,
+    /// How many of the `workers` only handle consensus commands, so those keep being handled even
+    /// while the other workers are busy, e.g. sending to unresponsive peers.
+    pub consensus_workers: usize,
+}
+
+impl CommandLimits {
+    fn capacity(&self, priority: Priority) -> usize {
+        match priority {
+            Priority::Consensus => self.consensus,
+            Priority::Network => self.network,
+            Priority::User => self.user,
+        }
+    }
+}
+
+impl Default for CommandLimits {
+    fn default() -> Self {
+        Self {
+            consensus: 10_000,
+            network: 10_000,
+            user: 2_000,
+            workers: 32,
+            consensus_workers: 4,
+        }
+    }
+}
+
+// Priorities of the commands, the most urgent first.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Ord, PartialOrd)]
+pub(crate) enum Priority {
+    Consensus,
+    Network,
+    User,
+}
+
+impl Priority {
+    const ALL: [Self; 3] = [Self::Consensus, Self::Network, Self::User];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Self::Consensus => "consensus",
+            Self::Network => "network",
+            Self::User => "user",
+        }
+    }
+}
+
+// Queue of the commands waiting for a worker of the `Dispatcher`.
+pub(crate) struct CommandQueue {
+    state: Mutex<State>,
+    // Wakes the workers handling commands of any priority.
+    any_ready: Notify,
+    // Wakes the workers handling consensus commands only.
+    consensus_ready: Notify,
+    // Wakes the ones waiting for room in the queue of each priority.
+    room: [Notify; 3],
+    metrics: Metrics,
+}
+
+struct State {
+    limits: CommandLimits,
+    queues: [VecDeque<Command>; 3],
+}
+
+impl CommandQueue {
+    pub fn new(limits: CommandLimits, metrics: Metrics) -> Self {
+        Self {
+            state: Mutex::new(State {
+                limits,
+                queues: Default::default(),
+            }),
+            any_ready: Notify::new(),
+            consensus_ready: Notify::new(),
+            room: Default::default(),
+            metrics,
+        }
+    }
+
+    pub fn set_limits(&self, limits: CommandLimits) {
+        self.lock().limits = limits;
+    }
+
+    // Queues the command. Returns `false` if it was dropped as it's a user command and the user
+    // queue is full. Other commands are queued even beyond the capacity of their queue.
+    pub fn push(&self, command: Command) -> bool {
+        let bounded = command.priority() == Priority::User;
+        match self.enqueue(command, bounded) {
+            Ok(()) => true,
+            Err(command) => {
+                warn!("Dropping command as the user queue is full: {:?}", command);
+                self.metrics
+                    .inc(Metric::CommandsDropped, Priority::User.label());
+                false
+            }
+        }
+    }
+
+    // Queues the command, waiting for room in its queue if it's full.
+    pub async fn push_wait(&self, mut command: Command) {
+        let room = &self.room[command.priority() as usize];
+        loop {
+            // As in `pop`, room made after `try_push` leaves a permit behind.
+            command = match self.try_push(command) {
+                Ok(()) => return,
+                Err(command) => command,
+            };
+            room.notified().await
+        }
+    }
+
+    // Queues the command unless its queue is full, in which case it's given back.
+    pub fn try_push(&self, command: Command) -> Result<(), Command> {
+        self.enqueue(command, true)
+    }
+
+    // Queues the command, giving it back if `bounded` and its queue is full.
+    fn enqueue(&self, command: Command, bounded: bool) -> Result<(), Command> {
+        let priority = command.priority();
+        let mut state = self.lock();
+        let capacity = state.limits.capacity(priority);
+        let queue = &mut state.queues[priority as usize];
+
+        if bounded && queue.len() >= capacity {
+            return Err(command);
+        }
+
+        queue.push_back(command);
+        self.metrics.set(
+            Metric::CommandQueueDepth,
+            priority.label(),
+            queue.len() as u64,
+        );
+        drop(state);
+
+        if priority == Priority::Consensus {
+            self.consensus_ready.notify_one();
+        }
+        self.any_ready.notify_one();
+
+        Ok(())
+    }
+
+    // Whether the queue of the given priority is full.
+    pub fn is_full(&self, priority: Priority) -> bool {
+        let state = self.lock();
+        state.queues[priority as usize].len() >= state.limits.capacity(priority)
+    }
+
+    // Whether the queue of any priority is full.
+    pub fn is_saturated(&self) -> bool {
+        Priority::ALL.iter().any(|priority| self.is_full(*priority))
+    }
+
+    // Takes the most urgent command of at most the given priority, waiting for one if there is
+    // none.
+    pub async fn pop(&self, lowest: Priority) -> Command {
+        let ready = if lowest == Priority::Consensus {
+            &self.consensus_ready
+        } else {
+            &self.any_ready
+        };
+
+        loop {
+            if let Some(command) = self.try_pop(lowest) {
+                return command;
+            }
+
+            // A command pushed after `try_pop` leaves a permit behind, so it's not missed.
+            ready.notified().await
+        }
+    }
+
+    fn try_pop(&self, lowest: Priority) -> Option<Command> {
+        let mut state = self.lock();
+        for priority in Priority::ALL.iter().filter(|priority| **priority <= lowest) {
+            let queue = &mut state.queues[*priority as usize];
+            if let Some(command) = queue.pop_front() {
+                self.metrics.set(
+                    Metric::CommandQueueDepth,
+                    priority.label(),
+                    queue.len() as u64,
+                );
+                drop(state);
+
+                // Wake one of the pushes waiting for room in this queue, if any.
+                self.room[*priority as usize].notify_one();
+                return Some(command);
+            }
+        }
+
+        None
+    }
+
+    fn lock(&self) -> MutexGuard<State> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_matches::assert_matches;
+    use std::{sync::Arc, time::Duration};
+    use tokio::{sync::oneshot, time};
+    use xor_name::XorName;
+
+    #[tokio::test]
+    async fn consensus_commands_first() {
+        let metrics = Metrics::default();
+        let queue = CommandQueue::new(CommandLimits::default(), metrics.clone());
+
+        let (response_tx, _) = oneshot::channel();
+        assert!(queue.push(Command::SendTraceProbe {
+            dst: XorName::random(),
+            timeout: Duration::from_secs(10),
+            response_tx,
+        }));
+        assert!(queue.push(Command::HandleTimeout(0)));
+        assert!(queue.push(Command::ProposeOffline(XorName::random())));
+        assert!(queue.push(Command::HandleTimeout(1)));
+
+        assert_matches!(queue.pop(Priority::User).await, Command::ProposeOffline(_));
+        assert_matches!(queue.pop(Priority::User).await, Command::HandleTimeout(0));
+        assert_matches!(queue.pop(Priority::User).await, Command::HandleTimeout(1));
+
+        // Consensus workers leave the other commands alone.
+        assert!(
+            time::timeout(Duration::from_millis(10), queue.pop(Priority::Consensus))
+                .await
+                .is_err()
+        );
+
+        assert_matches!(
+            queue.pop(Priority::User).await,
+            Command::SendTraceProbe { .. }
+        );
+        assert_eq!(metrics.counter(Metric::CommandsDropped, "user"), 0);
+    }
+
+    #[tokio::test]
+    async fn drop_user_commands_when_full() {
+        let metrics = Metrics::default();
+        let queue = CommandQueue::new(
+            CommandLimits {
+                consensus: 1,
+                network: 1,
+                user: 2,
+                ..Default::default()
+            },
+            metrics.clone(),
+        );
+        let trace_probe = || Command::SendTraceProbe {
+            dst: XorName::random(),
+            timeout: Duration::from_secs(10),
+            response_tx: oneshot::channel().0,
+        };
+
+        assert!(queue.push(trace_probe()));
+        assert!(queue.push(trace_probe()));
+        assert!(!queue.push(trace_probe()));
+        assert_eq!(metrics.counter(Metric::CommandsDropped, "user"), 1);
+
+        // Consensus and network commands are never dropped.
+        assert!(queue.push(Command::ProposeOffline(XorName::random())));
+        assert!(queue.push(Command::ProposeOffline(XorName::random())));
+        assert!(queue.push(Command::HandleTimeout(0)));
+        assert!(queue.push(Command::HandleTimeout(1)));
+        assert_eq!(metrics.counter(Metric::CommandsDropped, "consensus"), 0);
+        assert_eq!(metrics.counter(Metric::CommandsDropped, "network"), 0);
+
+        // But they still count against the capacity of messages received from peers.
+        assert!(queue.is_full(Priority::Consensus));
+        assert_matches!(
+            queue.try_push(Command::HandleTimeout(2)),
+            Err(Command::HandleTimeout(2))
+        );
+
+        // The user commands are taken last.
+        for _ in 0..5 {
+            let _ = queue.pop(Priority::User).await;
+        }
+        assert!(queue.push(trace_probe()));
+    }
+
+    #[tokio::test]
+    async fn wait_for_room() {
+        let queue = Arc::new(CommandQueue::new(
+            CommandLimits {
+                consensus: 1,
+                ..Default::default()
+            },
+            Metrics::default(),
+        ));
+
+        queue
+            .push_wait(Command::ProposeOffline(XorName::random()))
+            .await;
+        assert!(queue.is_full(Priority::Consensus));
+        assert!(queue.is_saturated());
+        assert!(!queue.is_full(Priority::User));
+
+        // The second command waits until the first one is taken.
+        let name = XorName::random();
+        let mut blocked = tokio::spawn({
+            let queue = queue.clone();
+            async move { queue.push_wait(Command::ProposeOffline(name)).await }
+        });
+        assert!(time::timeout(Duration::from_millis(10), &mut blocked)
+            .await
+            .is_err());
+
+        // Taking the first one wakes it up.
+        let _ = queue.pop(Priority::Consensus).await;
+        time::timeout(Duration::from_millis(100), blocked)
+            .await
+            .expect("push woken up by pop")
+            .expect("push task panicked");
+        assert!(queue.is_full(Priority::Consensus));
+        assert_matches!(
+            queue.pop(Priority::Consensus).await,
+            Command::ProposeOffline(queued) if queued == name
+        );
+    }
+}
@@ -0,0 +1,286 @@
+// Copyright 2021 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under The General Public License (GPL), version 3.
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied. Please review the Licences for the specific language governing
+// permissions and limitations relating to use of the SAFE Network Software.
+
+//! Compression of the messages exchanged between nodes.
+//!
+//! A node that compresses messages announces it to every peer it sends to with a capabilities
+//! frame, the first time it does. Messages are only compressed for the peers that announced it to
+//! us in turn, so the nodes that don't support compression keep receiving plain messages. They
+//! just drop the capabilities frames they don't understand. A node that learns a peer supports
+//! compression after having announced it to that peer announces it again, in case the peer missed
+//! it, e.g. while joining.
+//!
+//! Compressed messages are the LZ4 compressed serialised `WireMsg`, prefixed with `COMPRESSED`
+//! and the codec, so they can be told apart from the plain ones.
+
+use crate::error::{Error, Result};
+use bytes::{BufMut, Bytes, BytesMut};
+use std::{collections::HashSet, convert::TryInto, net::SocketAddr};
+
+const CAPABILITIES: &[u8] = b"SNRC";
+const COMPRESSED: &[u8] = b"SNRZ";
+
+const CODEC_LZ4: u8 = 1;
+
+// Largest message we decompress, not to be made to allocate any amount of memory.
+const MAX_DECOMPRESSED_SIZE: usize = 64 * 1024 * 1024;
+// Number of peers we remember the capabilities of.
+const MAX_PEERS: usize = 10_000;
+
+/// Compression of the messages this node sends to other nodes. Only the messages to nodes that
+/// support compression are compressed, and only when that makes them smaller. Compressed messages
+/// from other nodes are always accepted.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct CompressionConfig {
+    /// Whether to compress messages.
+    pub enabled: bool,
+    /// Messages smaller than this, in bytes, are sent as they are.
+    pub threshold: usize,
+}
+
+impl CompressionConfig {
+    pub(crate) fn disabled() -> Self {
+        Self {
+            enabled: false,
+            ..Default::default()
+        }
+    }
+}
+
+impl Default for CompressionConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            threshold: 1024,
+        }
+    }
+}
+
+// Incoming frame, once decoded.
+#[derive(Debug, Eq, PartialEq)]
+pub(crate) enum Frame {
+    // The sender accepts compressed messages. Carries our announcement to send back, if any.
+    Capabilities { reply: Option<Bytes> },
+    // Message, decompressed if it was compressed.
+    Message(Bytes),
+}
+
+// What we know about compression with our peers.
+pub(crate) struct Compressor {
+    config: CompressionConfig,
+    // Peers that announced they accept compressed messages.
+    supported: HashSet<SocketAddr>,
+    // Peers we announced it to.
+    announced: HashSet<SocketAddr>,
+}
+
+impl Compressor {
+    pub fn new(config: CompressionConfig) -> Self {
+        Self {
+            config,
+            supported: HashSet::new(),
+            announced: HashSet::new(),
+        }
+    }
+
+    pub fn set_config(&mut self, config: CompressionConfig) {
+        self.config = config;
+    }
+
+    // Whether messages of `len` bytes are to be compressed, for the peers that support it.
+    pub fn is_worth_compressing(&self, len: usize) -> bool {
+        self.config.enabled && len >= self.config.threshold
+    }
+
+    pub fn supports(&self, peer: &SocketAddr) -> bool {
+        self.supported.contains(peer)
+    }
+
+    // Returns the capabilities frame to send to `peer` if we haven't announced our support to it
+    // yet.
+    pub fn announcement(&mut self, peer: SocketAddr) -> Option<Bytes> {
+        if !self.config.enabled || self.announced.contains(&peer) {
+            return None;
+        }
+
+        if self.announced.len() >= MAX_PEERS {
+            self.announced.clear();
+        }
+        let _ = self.announced.insert(peer);
+
+        Some(capabilities_frame())
+    }
+
+    // Decodes a frame from `sender`, taking note of its capabilities.
+    pub fn receive(&mut self, sender: SocketAddr, bytes: Bytes) -> Result<Frame> {
+        if bytes.starts_with(CAPABILITIES) {
+            let reply = if self.supported.contains(&sender) {
+                None
+            } else {
+                if self.supported.len() >= MAX_PEERS {
+                    self.supported.clear();
+                }
+                let _ = self.supported.insert(sender);
+
+                if self.config.enabled && self.announced.contains(&sender) {
+                    Some(capabilities_frame())
+                } else {
+                    None
+                }
+            };
+
+            return Ok(Frame::Capabilities { reply });
+        }
+
+        decode(bytes)
+    }
+
+    // Forgets the capabilities of `peer`, e.g. once disconnected, as the node reachable at its
+    // address next may be a different one.
+    pub fn forget(&mut self, peer: &SocketAddr) {
+        let _ = self.supported.remove(peer);
+        let _ = self.announced.remove(peer);
+    }
+}
+
+// Whether `bytes` is a compression frame, i.e. a capabilities announcement or a compressed
+// message, rather than a plain message.
+pub(crate) fn is_frame(bytes: &[u8]) -> bool {
+    bytes.starts_with(CAPABILITIES) || bytes.starts_with(COMPRESSED)
+}
+
+// Compresses the message. Returns `None` if it doesn't get any smaller.
+pub(crate) fn compress(bytes: &[u8]) -> Option<Bytes> {
+    let compressed = lz4_flex::compress_prepend_size(bytes);
+    if COMPRESSED.len() + 1 + compressed.len() >= bytes.len() {
+        return None;
+    }
+
+    let mut frame = BytesMut::with_capacity(COMPRESSED.len() + 1 + compressed.len());
+    frame.put_slice(COMPRESSED);
+    frame.put_u8(CODEC_LZ4);
+    frame.put_slice(&compressed);
+    Some(frame.freeze())
+}
+
+fn decode(bytes: Bytes) -> Result<Frame> {
+    if !bytes.starts_with(COMPRESSED) {
+        return Ok(Frame::Message(bytes));
+    }
+
+    let payload = &bytes[COMPRESSED.len()..];
+    match payload.split_first() {
+        Some((&CODEC_LZ4, compressed)) => {
+            let size = compressed
+                .get(..4)
+                .and_then(|size| size.try_into().ok())
+                .map(u32::from_le_bytes)
+                .ok_or(Error::InvalidPayload)?;
+            if size as usize > MAX_DECOMPRESSED_SIZE {
+                return Err(Error::InvalidPayload);
+            }
+
+            lz4_flex::decompress_size_prepended(compressed)
+                .map(|bytes| Frame::Message(Bytes::from(bytes)))
+                .map_err(|_| Error::InvalidPayload)
+        }
+        _ => Err(Error::InvalidPayload),
+    }
+}
+
+fn capabilities_frame() -> Bytes {
+    let mut frame = BytesMut::with_capacity(CAPABILITIES.len() + 1);
+    frame.put_slice(CAPABILITIES);
+    frame.put_u8(CODEC_LZ4);
+    frame.freeze()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::section::test_utils::gen_addr;
+    use assert_matches::assert_matches;
+
+    #[test]
+    fn compress_and_decode() -> Result<()> {
+        let message = Bytes::from(b"sync ".repeat(1000));
+        let compressed = compress(&message).expect("repetitive message compresses");
+        assert!(compressed.len() < message.len());
+        assert!(is_frame(&compressed));
+        assert!(!is_frame(&message));
+        assert_eq!(decode(compressed)?, Frame::Message(message));
+
+        // Messages that don't compress are sent as they are, and received as such.
+        let message = Bytes::from_static(b"short");
+        assert_eq!(compress(&message), None);
+        assert_eq!(decode(message.clone())?, Frame::Message(message));
+
+        Ok(())
+    }
+
+    #[test]
+    fn reject_invalid_frames() {
+        let mut corrupted = BytesMut::from(COMPRESSED);
+        corrupted.put_u8(CODEC_LZ4);
+        corrupted.put_u32_le(10);
+        corrupted.put_slice(b"garbage");
+        assert_matches!(decode(corrupted.freeze()), Err(Error::InvalidPayload));
+
+        // Claims to decompress into more than we're willing to allocate.
+        let mut bomb = BytesMut::from(COMPRESSED);
+        bomb.put_u8(CODEC_LZ4);
+        bomb.put_u32_le(u32::MAX);
+        assert_matches!(decode(bomb.freeze()), Err(Error::InvalidPayload));
+
+        let mut unknown_codec = BytesMut::from(COMPRESSED);
+        unknown_codec.put_u8(0xff);
+        assert_matches!(decode(unknown_codec.freeze()), Err(Error::InvalidPayload));
+    }
+
+    #[test]
+    fn negotiate() -> Result<()> {
+        let mut ours = Compressor::new(CompressionConfig::default());
+        let mut theirs = Compressor::new(CompressionConfig::default());
+        let our_addr = gen_addr();
+        let their_addr = gen_addr();
+
+        // Announced once per peer.
+        let announcement = ours.announcement(their_addr).expect("first announcement");
+        assert!(is_frame(&announcement));
+        assert_eq!(ours.announcement(their_addr), None);
+
+        assert!(!theirs.supports(&our_addr));
+        assert_eq!(
+            theirs.receive(our_addr, announcement)?,
+            Frame::Capabilities { reply: None }
+        );
+        assert!(theirs.supports(&our_addr));
+        assert!(!ours.supports(&their_addr));
+
+        // Their own announcement comes with their first message to us. As we learn they support
+        // compression after announcing it to them, we announce it again in case they missed it.
+        let announcement = theirs.announcement(our_addr).expect("their announcement");
+        assert_eq!(
+            ours.receive(their_addr, announcement.clone())?,
+            Frame::Capabilities {
+                reply: Some(announcement)
+            }
+        );
+        assert!(ours.supports(&their_addr));
+
+        // Nodes with compression disabled don't announce it.
+        let mut disabled = Compressor::new(CompressionConfig::disabled());
+        assert_eq!(disabled.announcement(their_addr), None);
+        assert!(!disabled.is_worth_compressing(usize::MAX));
+
+        theirs.forget(&our_addr);
+        assert!(!theirs.supports(&our_addr));
+
+        Ok(())
+    }
+}
@@ -8,50 +8,449 @@
 
 use super::{delivery_group, Core};
 use crate::{
+    delivery::{DeliveryPolicy, PendingDelivery},
     error::Result,
-    messages::RoutingMsgUtils,
+    messages::{
+        ClientHandshake, ClientIdentity, ClientSignature, Envelope, RoutingMsgUtils, TraceHop,
+    },
+    metrics::Metric,
     network::NetworkUtils,
     node::Node,
     peer::PeerUtils,
-    routing::{command::Command, enduser_registry::SocketId},
-    section::{NodeStateUtils, SectionAuthorityProviderUtils, SectionUtils},
-    Error, Event,
+    relocation::RelocateState,
+    request::{PendingRequest, RequestHandle, RequestId, Response},
+    rng,
+    routing::{
+        command::{self, Command},
+        debug_snapshot::{
+            DebugSnapshot, DkgSessionSnapshot, MemberSnapshot, PeerSnapshot, RelocateStatus,
+            SectionSnapshot,
+        },
+        enduser_registry::SocketId,
+        event_stream::EventSender,
+        persistence::NodeSnapshot,
+        socket_id::{self, PendingShare},
+        topology::{SectionRelation, Topology, TopologySection},
+        transfer::Chunk,
+    },
+    section::{
+        NodeStateUtils, SectionAuthorityProviderUtils, SectionKeyShare, SectionPeersUtils,
+        SectionUtils,
+    },
+    Error, Event, NetworkParams,
 };
 use bytes::Bytes;
 use secured_linked_list::SecuredLinkedList;
 use sn_messaging::{
     node::{Network, NodeState, Peer, Proposal, RoutingMsg, Section, Variant},
     section_info::Error as TargetSectionError,
-    DestInfo, EndUser, Itinerary, MessageId, SectionAuthorityProvider, SrcLocation,
+    Aggregation, DestInfo, DstLocation, EndUser, Itinerary, MessageId, SectionAuthorityProvider,
+    SrcLocation,
+};
+use std::{collections::BTreeSet, iter, net::SocketAddr, time::Duration};
+use tokio::{
+    sync::{oneshot, watch},
+    time::Instant,
 };
-use std::net::SocketAddr;
-use tokio::sync::mpsc;
 use xor_name::{Prefix, XorName};
 
 impl Core {
     // Creates `Core` for the first node in the network
-    pub fn first_node(node: Node, event_tx: mpsc::Sender<Event>) -> Result<Self> {
+    pub fn first_node(node: Node, event_tx: EventSender, params: NetworkParams) -> Result<Self> {
         let (section, section_key_share) = Section::first_node(node.peer())?;
-        Ok(Self::new(node, section, Some(section_key_share), event_tx))
+        let mut core = Self::new(node, section, None, event_tx, params);
+
+        // Being the only elder, we derive the socket id key from our share alone.
+        let _ = core.end_users.start_socket_id_key(&section_key_share);
+        let public_key = section_key_share.public_key_set.public_key();
+        core.section_keys_provider
+            .insert_dkg_outcome(section_key_share);
+        core.section_keys_provider.finalise_dkg(&public_key);
+
+        Ok(core)
+    }
+
+    // Creates `Core` for a node resuming from a previously persisted state.
+    pub fn restore(
+        node: Node,
+        section: Section,
+        network: Network,
+        key_shares: Vec<SectionKeyShare>,
+        socket_id_keys: Vec<(bls::PublicKey, bls::Signature)>,
+        joins_allowed: bool,
+        event_tx: EventSender,
+        params: NetworkParams,
+    ) -> Self {
+        let mut core = Self::new(node, section, None, event_tx, params);
+        for share in key_shares {
+            let public_key = share.public_key_set.public_key();
+            core.section_keys_provider.insert_dkg_outcome(share);
+            core.section_keys_provider.finalise_dkg(&public_key);
+        }
+        // Only the keys of our section are of any use.
+        let chain = core.section.chain();
+        let socket_id_keys: Vec<_> = socket_id_keys
+            .into_iter()
+            .filter(|(section_key, _)| chain.has_key(section_key))
+            .collect();
+        core.end_users
+            .insert_socket_id_key_signatures(socket_id_keys);
+        core.network = network;
+        core.joins_allowed = joins_allowed;
+        core
+    }
+
+    // Captures the parts of our state that need to survive a restart.
+    pub fn persistent_state(&self) -> NodeSnapshot {
+        NodeSnapshot::new(
+            &self.node,
+            &self.section,
+            &self.network,
+            self.section_keys_provider.key_shares(),
+            self.joins_allowed,
+            self.params,
+            self.end_users.socket_id_key_signatures(),
+        )
+    }
+
+    pub fn debug_snapshot(&self) -> DebugSnapshot {
+        let section_auth = self.section.authority_provider();
+
+        DebugSnapshot {
+            name: self.node.name(),
+            age: self.node.age(),
+            is_elder: self.is_elder(),
+            prefix: *self.section.prefix(),
+            section_chain_keys: self.section.chain().keys().copied().collect(),
+            elders: section_auth.elders.iter().map(PeerSnapshot::from).collect(),
+            members: self
+                .section
+                .members()
+                .all()
+                .map(|info| MemberSnapshot {
+                    name: *info.peer.name(),
+                    addr: *info.peer.addr(),
+                    age: info.peer.age(),
+                    state: info.state,
+                })
+                .collect(),
+            network: self.network.all().map(SectionSnapshot::from).collect(),
+            dkg_sessions: self
+                .dkg_voter
+                .sessions()
+                .map(|(dkg_key, session)| DkgSessionSnapshot {
+                    generation: dkg_key.generation,
+                    prefix: session.elder_candidates.prefix,
+                    participants: session
+                        .elder_candidates
+                        .elders
+                        .iter()
+                        .map(PeerSnapshot::from)
+                        .collect(),
+                    complete: session.complete,
+                })
+                .collect(),
+            split_barrier: self
+                .split_barrier
+                .pending()
+                .map(SectionSnapshot::from)
+                .collect(),
+            relocate_state: self.relocate_state.as_ref().map(|state| match state {
+                RelocateState::Delayed(_) => RelocateStatus::Delayed,
+                RelocateState::InProgress(_) => RelocateStatus::InProgress,
+            }),
+            joins_allowed: self.joins_allowed,
+        }
+    }
+
+    pub fn topology(&self) -> Topology {
+        let our_prefix = self.section.prefix();
+        let chain = self.section.chain();
+        let generation = |key: &bls::PublicKey| {
+            chain
+                .keys()
+                .position(|other| other == key)
+                .map(|i| i as u64)
+        };
+
+        let our = TopologySection {
+            prefix: *our_prefix,
+            relation: SectionRelation::Our,
+            key: *chain.last_key(),
+            generation: generation(chain.last_key()),
+            elders: self
+                .section
+                .authority_provider()
+                .elders
+                .iter()
+                .map(PeerSnapshot::from)
+                .collect(),
+        };
+        let others = self.network.sections.iter().map(|info| {
+            let section_auth = &info.section_auth.value;
+            let vouching_key = info
+                .key_signed
+                .as_ref()
+                .map_or(&info.section_auth.signed.public_key, |signed| {
+                    &signed.public_key
+                });
+
+            TopologySection {
+                prefix: section_auth.prefix,
+                relation: SectionRelation::new(our_prefix, &section_auth.prefix),
+                key: section_auth.section_key(),
+                generation: generation(vouching_key),
+                elders: section_auth.elders.iter().map(PeerSnapshot::from).collect(),
+            }
+        });
+
+        let mut sections: Vec<_> = iter::once(our).chain(others).collect();
+        sections.sort_by_key(|section| section.prefix);
+
+        Topology {
+            name: self.node.name(),
+            sections,
+        }
     }
 
     pub fn get_enduser_by_addr(&self, sender: &SocketAddr) -> Option<&EndUser> {
-        self.end_users.get_enduser_by_addr(sender)
+        self.end_users.get_enduser_by_addr(sender, Instant::now())
     }
 
-    pub fn get_socket_addr(&self, id: SocketId) -> Option<&SocketAddr> {
+    pub fn get_socket_addr(&self, id: SocketId) -> Option<SocketAddr> {
         self.end_users.get_socket_addr(id)
     }
 
+    // Starts or refreshes the session of the client at `sender`. The sessions expired meanwhile
+    // are ended first, and reported with `Event::ClientSessionExpired`.
     pub fn try_add(&mut self, sender: SocketAddr) -> Result<EndUser> {
+        let now = Instant::now();
+
+        for (addr, user) in self.end_users.remove_expired(&sender, now) {
+            debug!("Session of client {} ({:?}) expired", addr, user);
+            if !self
+                .event_tx
+                .send(Event::ClientSessionExpired { user, addr })
+            {
+                error!("All event streams have been closed");
+            }
+        }
+
         let section_prefix = self.section.prefix();
-        self.end_users.try_add(sender, section_prefix)
+        self.end_users.try_add(sender, section_prefix, now)
+    }
+
+    pub fn get_client_identity(&self, addr: &SocketAddr) -> Option<ClientIdentity> {
+        self.end_users.get_identity_by_addr(addr, Instant::now())
+    }
+
+    // Returns the challenge for the client at `addr` to prove its identity with.
+    pub fn start_client_handshake(&mut self, addr: SocketAddr) -> Result<ClientHandshake> {
+        let nonce = self.end_users.start_handshake(addr, Instant::now())?;
+        Ok(ClientHandshake::Challenge {
+            nonce,
+            node: self.node.name(),
+        })
+    }
+
+    pub fn complete_client_handshake(
+        &mut self,
+        addr: SocketAddr,
+        identity: ClientIdentity,
+        signature: &ClientSignature,
+    ) -> Result<EndUser> {
+        self.end_users.complete_handshake(
+            addr,
+            identity,
+            signature,
+            &self.node.name(),
+            self.section.prefix(),
+            Instant::now(),
+        )
+    }
+
+    pub fn remove_end_user(&mut self, addr: &SocketAddr) -> Option<EndUser> {
+        self.end_users.remove(addr)
+    }
+
+    // Starts deriving the socket id key for our current section key, unless we are not its elder
+    // or started already, and sends our share of it to the other elders. The share is sent again
+    // every `SHARE_RETRY_INTERVAL` to the elders that didn't answer it with theirs yet.
+    pub(crate) fn share_socket_id_key(&mut self) -> Result<Vec<Command>> {
+        let key_share = if let Ok(key_share) = self.section_keys_provider.key_share() {
+            key_share
+        } else {
+            return Ok(vec![]);
+        };
+
+        let section_key = key_share.public_key_set.public_key();
+        if !self.is_elder()
+            || section_key != *self.section.chain().last_key()
+            || self.end_users.has_socket_id_key(&section_key)
+        {
+            return Ok(vec![]);
+        }
+
+        let envelope = Envelope::SocketIdKeyShare {
+            section_key,
+            index: key_share.index,
+            share: self.end_users.start_socket_id_key(key_share),
+            reply: false,
+        };
+
+        let recipients: Vec<_> = self
+            .section
+            .authority_provider()
+            .peers()
+            .filter(|peer| *peer.name() != self.node.name())
+            .map(|peer| (*peer.name(), *peer.addr()))
+            .collect();
+        if recipients.is_empty() {
+            self.socket_id_share = None;
+            return Ok(vec![]);
+        }
+
+        let timer_token = command::next_timer_token();
+        self.socket_id_share = Some(PendingShare {
+            section_key,
+            unacknowledged: recipients.iter().map(|(name, _)| *name).collect(),
+            timer_token,
+        });
+
+        Ok(vec![
+            self.send_direct(recipients, envelope)?,
+            Command::ScheduleTimeout {
+                duration: socket_id::SHARE_RETRY_INTERVAL,
+                token: timer_token,
+            },
+        ])
+    }
+
+    // Sends our share of the socket id key again to the elders that didn't answer it yet, unless
+    // our section key changed since.
+    pub(crate) fn resend_socket_id_key_share(&mut self) -> Result<Vec<Command>> {
+        let section_key = *self.section.chain().last_key();
+        let key_share = match self.section_keys_provider.key_share() {
+            Ok(key_share)
+                if self.is_elder() && key_share.public_key_set.public_key() == section_key =>
+            {
+                key_share
+            }
+            _ => {
+                self.socket_id_share = None;
+                return Ok(vec![]);
+            }
+        };
+
+        let unacknowledged = match &self.socket_id_share {
+            Some(pending) if pending.section_key == section_key => &pending.unacknowledged,
+            _ => {
+                self.socket_id_share = None;
+                return Ok(vec![]);
+            }
+        };
+        let recipients: Vec<_> = self
+            .section
+            .authority_provider()
+            .peers()
+            .filter(|peer| unacknowledged.contains(peer.name()))
+            .map(|peer| (*peer.name(), *peer.addr()))
+            .collect();
+        if recipients.is_empty() {
+            self.socket_id_share = None;
+            return Ok(vec![]);
+        }
+
+        trace!(
+            "Sending socket id key share again to {} elders",
+            recipients.len()
+        );
+
+        let envelope = Envelope::SocketIdKeyShare {
+            section_key,
+            index: key_share.index,
+            share: socket_id::sign_share(key_share),
+            reply: false,
+        };
+        let command = self.send_direct(recipients, envelope)?;
+
+        let timer_token = command::next_timer_token();
+        if let Some(pending) = &mut self.socket_id_share {
+            pending.timer_token = timer_token;
+        }
+
+        Ok(vec![
+            command,
+            Command::ScheduleTimeout {
+                duration: socket_id::SHARE_RETRY_INTERVAL,
+                token: timer_token,
+            },
+        ])
+    }
+
+    // Hands the socket id keys over to the elders promoted since `old_elders`, so they can tell
+    // where to send to the clients given their socket ids before.
+    pub(crate) fn hand_over_socket_id_keys(
+        &self,
+        old_elders: &BTreeSet<XorName>,
+    ) -> Result<Vec<Command>> {
+        let signatures = self.end_users.socket_id_key_signatures();
+        if signatures.is_empty() {
+            return Ok(vec![]);
+        }
+
+        let recipients: Vec<_> = self
+            .section
+            .authority_provider()
+            .peers()
+            .filter(|peer| !old_elders.contains(peer.name()))
+            .map(|peer| (*peer.name(), *peer.addr()))
+            .collect();
+        if recipients.is_empty() {
+            return Ok(vec![]);
+        }
+
+        Ok(vec![self.send_direct(
+            recipients,
+            Envelope::SocketIdKeys(signatures),
+        )?])
+    }
+
+    // Sends the envelope directly to the given members of our section.
+    pub(crate) fn send_direct(
+        &self,
+        recipients: Vec<(XorName, SocketAddr)>,
+        envelope: Envelope,
+    ) -> Result<Command> {
+        let section_key = *self.section.chain().last_key();
+        let msg = RoutingMsg::single_src(
+            &self.node,
+            DstLocation::DirectAndUnrouted,
+            Variant::UserMessage(envelope.encode()?),
+            section_key,
+        )?;
+        let dest_info = DestInfo {
+            dest: self.section.prefix().name(),
+            dest_section_pk: section_key,
+        };
+
+        let delivery_group_size = recipients.len();
+        Ok(Command::send_message_to_nodes(
+            recipients,
+            delivery_group_size,
+            msg,
+            dest_info,
+        ))
     }
 
     pub fn node(&self) -> &Node {
         &self.node
     }
 
+    pub fn params(&self) -> &NetworkParams {
+        &self.params
+    }
+
     pub fn section(&self) -> &Section {
         &self.section
     }
@@ -121,9 +520,8 @@ impl Core {
     }
 
     pub async fn send_event(&self, event: Event) {
-        // Note: cloning the sender to avoid mutable access. Should have negligible cost.
-        if self.event_tx.clone().send(event).await.is_err() {
-            error!("Event receiver has been closed");
+        if !self.event_tx.send(event) {
+            error!("All event streams have been closed");
         }
     }
 
@@ -138,6 +536,7 @@ impl Core {
             &self.node.name(),
             &self.section,
             &self.network,
+            self.params.elder_size,
         )?;
 
         let target_name = msg.dst.name().ok_or(Error::CannotRoute)?;
@@ -146,13 +545,10 @@ impl Core {
         let mut targets = vec![];
 
         for peer in presumed_targets {
-            if self
-                .msg_filter
-                .filter_outgoing(msg, peer.name())
-                .await
-                .is_new()
-            {
+            if self.msg_filter.filter_outgoing(msg, peer.name()).is_new() {
                 let _ = targets.push((*peer.name(), *peer.addr()));
+            } else {
+                self.metrics.inc(Metric::FilterHits, "outgoing");
             }
         }
 
@@ -168,12 +564,24 @@ impl Core {
             msg.section_pk,
         );
 
+        let is_traced = match &msg.variant {
+            Variant::UserMessage(content) => {
+                matches!(Envelope::decode(content), Ok(envelope) if envelope.is_traced())
+            }
+            _ => false,
+        };
+        let msg = if is_traced {
+            self.wrap_traced_message(msg, &targets).await?
+        } else {
+            msg.clone()
+        };
+
         let command = Command::send_message_to_nodes(
             targets,
             dg_size,
-            msg.clone(),
+            msg,
             DestInfo {
-                dest: XorName::random(),
+                dest: rng::random_name(),
                 dest_section_pk: dest_pk,
             },
         );
@@ -181,9 +589,39 @@ impl Core {
         Ok(Some(command))
     }
 
+    // Wraps a traced message for relaying it to `targets`, appending our hop to the ones it went
+    // through so far.
+    async fn wrap_traced_message(
+        &self,
+        msg: &RoutingMsg,
+        targets: &[(XorName, SocketAddr)],
+    ) -> Result<RoutingMsg> {
+        let mut trace = self.traces.get(&msg.id).await.unwrap_or_default();
+        trace.push(TraceHop::new(
+            &self.node,
+            msg.id,
+            *self.section.chain().last_key(),
+            targets.iter().map(|(name, _)| *name).collect(),
+        )?);
+
+        let envelope = Envelope::Relayed {
+            msg: bincode::serialize(msg).map_err(|_| Error::InvalidMessage)?,
+            trace,
+        };
+
+        RoutingMsg::single_src(
+            &self.node,
+            DstLocation::DirectAndUnrouted,
+            Variant::UserMessage(envelope.encode()?),
+            self.section.authority_provider().section_key(),
+        )
+    }
+
     #[allow(unused)]
     pub fn check_key_status(&self, bls_pk: &bls::PublicKey) -> Result<(), TargetSectionError> {
-        let elders_candidates = self.section.promote_and_demote_elders(&self.node.name());
+        let elders_candidates = self
+            .section
+            .promote_and_demote_elders(&self.node.name(), &self.params);
         // Whenever there is a elders candidate, it is considered as having ongoing DKG.
         if !elders_candidates.is_empty() {
             trace!("Non empty elder candidates {:?}", elders_candidates);
@@ -220,6 +658,201 @@ impl Core {
         &self,
         itinerary: Itinerary,
         content: Bytes,
+    ) -> Result<Vec<Command>> {
+        self.send_envelope(itinerary, Envelope::user(content)).await
+    }
+
+    // Sends a request and registers `response_tx` to be notified with its response.
+    pub async fn send_request(
+        &mut self,
+        itinerary: Itinerary,
+        content: Bytes,
+        timeout: Duration,
+        response_tx: oneshot::Sender<Response>,
+    ) -> Result<Vec<Command>> {
+        // Each elder would pick a different id, so a request from a section could never be
+        // aggregated.
+        if !matches!(itinerary.src, SrcLocation::Node(_)) {
+            return Err(Error::InvalidSrcLocation);
+        }
+
+        let pending = PendingRequest::new(&itinerary, timeout, response_tx)?;
+        let id = RequestId::random();
+        let envelope = Envelope::Request {
+            id,
+            content: content.to_vec(),
+        };
+        let commands = self.send_envelope(itinerary, envelope).await?;
+        self.add_pending_request(id, pending);
+
+        Ok(commands)
+    }
+
+    pub async fn send_traced_user_message(
+        &self,
+        itinerary: Itinerary,
+        content: Bytes,
+    ) -> Result<Vec<Command>> {
+        self.send_envelope(itinerary, Envelope::TracedUser(content.to_vec()))
+            .await
+    }
+
+    // Sends a probe tracing the route to the node `dst`. `response_tx` is notified with the
+    // response carrying the recorded hops, if it arrives within `timeout`.
+    pub async fn send_trace_probe(
+        &mut self,
+        dst: XorName,
+        timeout: Duration,
+        response_tx: oneshot::Sender<Response>,
+    ) -> Result<Vec<Command>> {
+        let itinerary = Itinerary {
+            src: SrcLocation::Node(self.node.name()),
+            dst: DstLocation::Node(dst),
+            aggregation: Aggregation::None,
+        };
+
+        let pending = PendingRequest::new(&itinerary, timeout, response_tx)?;
+        let id = RequestId::random();
+        let commands = self
+            .send_envelope(itinerary, Envelope::TraceProbe { id })
+            .await?;
+        self.add_pending_request(id, pending);
+
+        Ok(commands)
+    }
+
+    // Sends a message the destination is to acknowledge with a receipt. `receipt_tx` is notified
+    // once it arrives. Until then, the message is sent again every `policy.retransmit_after`, up
+    // to `policy.max_attempts` times in total.
+    pub async fn send_message_with_receipt(
+        &mut self,
+        itinerary: Itinerary,
+        content: Bytes,
+        policy: DeliveryPolicy,
+        msg_id: MessageId,
+        receipt_tx: oneshot::Sender<()>,
+    ) -> Result<Vec<Command>> {
+        // As for requests, the elders of a section would each wait for their own receipt.
+        if !matches!(itinerary.src, SrcLocation::Node(_)) {
+            return Err(Error::InvalidSrcLocation);
+        }
+
+        let envelope = Envelope::AcknowledgedUser {
+            id: msg_id,
+            content: content.to_vec(),
+        };
+        let mut commands = self.send_envelope(itinerary, envelope).await?;
+
+        let timer_token = command::next_timer_token();
+        commands.push(Command::ScheduleTimeout {
+            duration: policy.retransmit_after,
+            token: timer_token,
+        });
+
+        let _ = self.pending_deliveries.insert(
+            msg_id,
+            PendingDelivery {
+                itinerary,
+                content,
+                policy,
+                attempts: 1,
+                timer_token,
+                receipt_tx,
+            },
+        );
+
+        Ok(commands)
+    }
+
+    // Sends the message with the given id again as its receipt didn't arrive in time, or gives up
+    // on it after the last attempt.
+    pub async fn retransmit(&mut self, msg_id: MessageId) -> Result<Vec<Command>> {
+        let pending = if let Some(pending) = self.pending_deliveries.get_mut(&msg_id) {
+            pending
+        } else {
+            return Ok(vec![]);
+        };
+
+        if pending.attempts >= pending.policy.max_attempts {
+            warn!(
+                "No receipt for {:?} after {} attempts, giving up",
+                msg_id, pending.attempts
+            );
+            // Dropping `receipt_tx` tells the sender.
+            let _ = self.pending_deliveries.remove(&msg_id);
+            return Ok(vec![]);
+        }
+
+        pending.attempts += 1;
+        pending.timer_token = command::next_timer_token();
+        trace!("Retransmitting {:?}, attempt {}", msg_id, pending.attempts);
+
+        let itinerary = pending.itinerary;
+        let envelope = Envelope::AcknowledgedUser {
+            id: msg_id,
+            content: pending.content.to_vec(),
+        };
+        let timeout = Command::ScheduleTimeout {
+            duration: pending.policy.retransmit_after,
+            token: pending.timer_token,
+        };
+
+        let mut commands = self.send_envelope(itinerary, envelope).await?;
+        commands.push(timeout);
+
+        Ok(commands)
+    }
+
+    // Registers the transfer of a large message to the node `dst`. Returns the receiver of the
+    // number of chunks `dst` acknowledged.
+    pub fn start_transfer(&mut self, transfer_id: u64, dst: XorName) -> watch::Receiver<u32> {
+        let (acks_tx, acks_rx) = watch::channel(0);
+        let _ = self.outgoing_transfers.insert(transfer_id, (dst, acks_tx));
+        acks_rx
+    }
+
+    pub fn end_transfer(&mut self, transfer_id: u64) {
+        let _ = self.outgoing_transfers.remove(&transfer_id);
+    }
+
+    pub async fn send_chunk(&self, dst: XorName, chunk: Chunk) -> Result<Vec<Command>> {
+        let itinerary = Itinerary {
+            src: SrcLocation::Node(self.node.name()),
+            dst: DstLocation::Node(dst),
+            aggregation: Aggregation::None,
+        };
+        self.send_envelope(itinerary, Envelope::Chunk(chunk)).await
+    }
+
+    fn add_pending_request(&mut self, id: RequestId, pending: PendingRequest) {
+        self.prune_pending_requests();
+        let _ = self.pending_requests.insert(id, pending);
+    }
+
+    // Forgets the requests whose requesters gave up waiting for the response.
+    pub(crate) fn prune_pending_requests(&mut self) {
+        let now = Instant::now();
+        self.pending_requests
+            .retain(|_, pending| !pending.is_expired(now));
+    }
+
+    pub async fn send_response(
+        &self,
+        handle: &RequestHandle,
+        content: Bytes,
+    ) -> Result<Vec<Command>> {
+        let envelope = Envelope::Response {
+            id: handle.id(),
+            content: content.to_vec(),
+        };
+        self.send_envelope(handle.response_itinerary()?, envelope)
+            .await
+    }
+
+    pub async fn send_envelope(
+        &self,
+        itinerary: Itinerary,
+        envelope: Envelope,
     ) -> Result<Vec<Command>> {
         let are_we_src = itinerary.src.equals(&self.node.name())
             || itinerary.src.equals(&self.section().prefix().name());
@@ -245,7 +878,7 @@ impl Core {
         };
         let dest_section_pk = self.section_key_by_name(&dst_name);
 
-        let variant = Variant::UserMessage(content.to_vec());
+        let variant = Variant::UserMessage(envelope.encode()?);
 
         // If the msg is to be aggregated at dst, we don't vote among our peers, we simply send the
         // msg as our vote to the dst.
@@ -290,6 +923,45 @@ impl Core {
         Ok(commands)
     }
 
+    // Asks the elders of our section to vote us offline. `notifier` fires once we learn that they
+    // agreed on it.
+    pub fn leave(&mut self, notifier: oneshot::Sender<()>) -> Result<Vec<Command>> {
+        let recipients: Vec<_> = self
+            .section
+            .authority_provider()
+            .peers()
+            .filter(|peer| *peer.name() != self.node.name())
+            .map(|peer| (*peer.name(), *peer.addr()))
+            .collect();
+
+        if recipients.is_empty() {
+            // Nobody else to notify.
+            let _ = notifier.send(());
+            return Ok(vec![]);
+        }
+
+        info!("Requesting to leave the section");
+        self.leave_notifier = Some(notifier);
+
+        let msg = RoutingMsg::single_src(
+            &self.node,
+            DstLocation::DirectAndUnrouted,
+            Variant::UserMessage(Envelope::Leave.encode()?),
+            self.section.authority_provider().section_key(),
+        )?;
+        let dest_info = DestInfo {
+            dest: self.section.prefix().name(),
+            dest_section_pk: *self.section_chain().last_key(),
+        };
+
+        Ok(vec![Command::send_message_to_nodes(
+            recipients.clone(),
+            recipients.len(),
+            msg,
+            dest_info,
+        )])
+    }
+
     // Setting the JoinsAllowed triggers a round Proposal::SetJoinsAllowed to update the flag.
     pub fn set_joins_allowed(&self, joins_allowed: bool) -> Result<Vec<Command>> {
         let mut commands = Vec::new();
@@ -10,6 +10,7 @@ use super::Core;
 use crate::{
     dkg::{commands::DkgCommands, DkgFailureSignedSetUtils},
     error::Result,
+    messages::SrcAuthorityUtils,
     peer::PeerUtils,
     routing::command::Command,
     section::{NodeStateUtils, SectionAuthorityProviderUtils, SectionPeersUtils, SectionUtils},
@@ -17,7 +18,8 @@ use crate::{
 };
 use bls_dkg::key_gen::message::Message as DkgMessage;
 use sn_messaging::node::{
-    DkgFailureSigned, DkgFailureSignedSet, DkgKey, ElderCandidates, Proposal,
+    DkgFailureSigned, DkgFailureSignedSet, DkgKey, ElderCandidates, MembershipState, NodeState,
+    Proposal, RoutingMsg,
 };
 use std::{collections::BTreeSet, iter, net::SocketAddr, slice};
 use xor_name::XorName;
@@ -73,7 +75,7 @@ impl Core {
         let generation = self.section.chain().main_branch_len() as u64;
         let elder_candidates = self
             .section
-            .promote_and_demote_elders(&self.node.name())
+            .promote_and_demote_elders(&self.node.name(), &self.params)
             .into_iter()
             .find(|elder_candidates| signeds.verify(elder_candidates, generation));
         let elder_candidates = if let Some(elder_candidates) = elder_candidates {
@@ -99,7 +101,7 @@ impl Core {
                 generation,
                 elder_candidates
             );
-            self.cast_offline_proposals(&signeds.non_participants)
+            self.cast_offline_proposals(&signeds.non_participants, NodeState::leave)
         }
     }
 
@@ -139,11 +141,50 @@ impl Core {
         Ok(commands)
     }
 
+    // Handles a request by a member of our section to be voted offline as it's leaving the
+    // network voluntarily.
+    pub fn handle_leave_request(&mut self, msg: &RoutingMsg) -> Result<Vec<Command>> {
+        if msg.src.is_section() {
+            return Err(Error::InvalidSrcLocation);
+        }
+
+        let name = msg.src.name();
+
+        if !self.is_elder() {
+            trace!("Ignoring leave request from {} - not elder", name);
+            return Ok(vec![]);
+        }
+
+        if !self.section.members().is_joined(&name) {
+            trace!("Ignoring leave request from {} - not a member", name);
+            return Ok(vec![]);
+        }
+
+        info!("{} requested to leave the section", name);
+        self.cast_offline_proposals(&iter::once(name).collect(), NodeState::leave_voluntarily)
+    }
+
+    // Returns whether our section considers us as no longer being a member.
+    pub(crate) fn has_left(&self) -> bool {
+        matches!(
+            self.section.members().get(&self.node.name()),
+            Some(NodeState {
+                state: MembershipState::Left,
+                ..
+            })
+        )
+    }
+
     pub fn propose_offline(&self, name: XorName) -> Result<Vec<Command>> {
-        self.cast_offline_proposals(&iter::once(name).collect())
+        self.cast_offline_proposals(&iter::once(name).collect(), NodeState::leave)
     }
 
-    fn cast_offline_proposals(&self, names: &BTreeSet<XorName>) -> Result<Vec<Command>> {
+    // Proposes the given members offline, with the state `leave` turns their current one into.
+    fn cast_offline_proposals(
+        &self,
+        names: &BTreeSet<XorName>,
+        leave: fn(NodeState) -> Result<NodeState>,
+    ) -> Result<Vec<Command>> {
         // Don't send the `Offline` proposal to the peer being lost as that send would fail,
         // triggering a chain of further `Offline` proposals.
         let elders: Vec<_> = self
@@ -155,7 +196,7 @@ impl Core {
         let mut result: Vec<Command> = Vec::new();
         for name in names.iter() {
             if let Some(info) = self.section.members().get(name) {
-                let info = info.leave()?;
+                let info = leave(*info)?;
                 if let Ok(commands) = self.send_proposal(&elders, Proposal::Offline(info)) {
                     result.extend(commands);
                 }
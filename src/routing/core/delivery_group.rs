@@ -13,7 +13,7 @@ use crate::{
     network::NetworkUtils,
     peer::PeerUtils,
     section::{SectionAuthorityProviderUtils, SectionPeersUtils, SectionUtils},
-    supermajority, ELDER_SIZE,
+    supermajority,
 };
 use itertools::Itertools;
 use sn_messaging::{
@@ -42,6 +42,7 @@ pub(crate) fn delivery_targets(
     our_name: &XorName,
     section: &Section,
     network: &Network,
+    elder_size: usize,
 ) -> Result<(Vec<Peer>, usize)> {
     if !section.is_elder(our_name) {
         // We are not Elder - return all the elders of our section, so the message can be properly
@@ -53,10 +54,10 @@ pub(crate) fn delivery_targets(
 
     let (best_section, dg_size) = match dst {
         DstLocation::Section(target_name) => {
-            section_candidates(target_name, our_name, section, network)?
+            section_candidates(target_name, our_name, section, network, elder_size)?
         }
         DstLocation::EndUser(user) => {
-            section_candidates(&user.xorname, our_name, section, network)?
+            section_candidates(&user.xorname, our_name, section, network, elder_size)?
         }
         DstLocation::Node(target_name) => {
             if target_name == our_name {
@@ -66,7 +67,7 @@ pub(crate) fn delivery_targets(
                 return Ok((vec![node], 1));
             }
 
-            candidates(target_name, our_name, section, network)?
+            candidates(target_name, our_name, section, network, elder_size)?
         }
         DstLocation::DirectAndUnrouted => return Err(Error::CannotRoute),
     };
@@ -79,6 +80,7 @@ fn section_candidates(
     our_name: &XorName,
     section: &Section,
     network: &Network,
+    elder_size: usize,
 ) -> Result<(Vec<Peer>, usize)> {
     // Find closest section to `target_name` out of the ones we know (including our own)
     let info = iter::once(section.authority_provider())
@@ -96,7 +98,7 @@ fn section_candidates(
         return Ok((chosen_section, dg_size));
     }
 
-    candidates(target_name, our_name, section, network)
+    candidates(target_name, our_name, section, network, elder_size)
 }
 
 // Obtain the delivery group candidates for this target
@@ -105,6 +107,7 @@ fn candidates(
     our_name: &XorName,
     section: &Section,
     network: &Network,
+    elder_size: usize,
 ) -> Result<(Vec<Peer>, usize)> {
     // All sections we know (including our own), sorted by distance to `target_name`.
     let sections = iter::once(section.authority_provider())
@@ -113,7 +116,7 @@ fn candidates(
         .map(|info| (&info.prefix, info.elder_count(), info.peers()));
 
     // gives at least 1 honest target among recipients.
-    let min_dg_size = 1 + ELDER_SIZE - supermajority(ELDER_SIZE);
+    let min_dg_size = 1 + elder_size - supermajority(elder_size);
     let mut dg_size = min_dg_size;
     let mut candidates = Vec::new();
     for (idx, (prefix, len, connected)) in sections.enumerate() {
@@ -172,6 +175,7 @@ mod tests {
             test_utils::{gen_addr, gen_section_authority_provider},
             NodeStateUtils, SectionAuthorityProviderUtils, MIN_ADULT_AGE,
         },
+        ELDER_SIZE,
     };
     use anyhow::{Context, Result};
     use rand::seq::IteratorRandom;
@@ -192,7 +196,8 @@ mod tests {
             .context("too few elders")?;
 
         let dst = DstLocation::Node(dst_name);
-        let (recipients, dg_size) = delivery_targets(&dst, &our_name, &section, &network)?;
+        let (recipients, dg_size) =
+            delivery_targets(&dst, &our_name, &section, &network, ELDER_SIZE)?;
 
         // Send only to the dst node.
         assert_eq!(dg_size, 1);
@@ -213,7 +218,8 @@ mod tests {
         assert!(section.update_member(node_state));
 
         let dst = DstLocation::Node(dst_name);
-        let (recipients, dg_size) = delivery_targets(&dst, &our_name, &section, &network)?;
+        let (recipients, dg_size) =
+            delivery_targets(&dst, &our_name, &section, &network, ELDER_SIZE)?;
 
         // Send only to the dst node.
         assert_eq!(dg_size, 1);
@@ -228,7 +234,8 @@ mod tests {
 
         let dst_name = section.prefix().substituted_in(rand::random());
         let dst = DstLocation::Section(dst_name);
-        let (recipients, dg_size) = delivery_targets(&dst, &our_name, &section, &network)?;
+        let (recipients, dg_size) =
+            delivery_targets(&dst, &our_name, &section, &network, ELDER_SIZE)?;
 
         // Send to all our elders except us.
         let expected_recipients = section
@@ -256,7 +263,8 @@ mod tests {
 
         let dst_name = choose_elder_name(section_auth1)?;
         let dst = DstLocation::Node(dst_name);
-        let (recipients, dg_size) = delivery_targets(&dst, &our_name, &section, &network)?;
+        let (recipients, dg_size) =
+            delivery_targets(&dst, &our_name, &section, &network, ELDER_SIZE)?;
 
         // Send only to the dst node.
         assert_eq!(dg_size, 1);
@@ -275,7 +283,8 @@ mod tests {
 
         let dst_name = section_auth1.prefix.substituted_in(rand::random());
         let dst = DstLocation::Node(dst_name);
-        let (recipients, dg_size) = delivery_targets(&dst, &our_name, &section, &network)?;
+        let (recipients, dg_size) =
+            delivery_targets(&dst, &our_name, &section, &network, ELDER_SIZE)?;
 
         // Send to all elders in the dst section
         let expected_recipients = section_auth1
@@ -301,7 +310,8 @@ mod tests {
             .pushed(false)
             .substituted_in(rand::random());
         let dst = DstLocation::Node(dst_name);
-        let (recipients, dg_size) = delivery_targets(&dst, &our_name, &section, &network)?;
+        let (recipients, dg_size) =
+            delivery_targets(&dst, &our_name, &section, &network, ELDER_SIZE)?;
 
         // Send to all elders in the dst section
         let expected_recipients = elders_info1
@@ -325,7 +335,8 @@ mod tests {
 
         let dst_name = section_auth1.prefix.substituted_in(rand::random());
         let dst = DstLocation::Section(dst_name);
-        let (recipients, dg_size) = delivery_targets(&dst, &our_name, &section, &network)?;
+        let (recipients, dg_size) =
+            delivery_targets(&dst, &our_name, &section, &network, ELDER_SIZE)?;
 
         // Send to all elders in the final dst section
         let expected_recipients = section_auth1
@@ -351,7 +362,8 @@ mod tests {
             .pushed(false)
             .substituted_in(rand::random());
         let dst = DstLocation::Section(dst_name);
-        let (recipients, dg_size) = delivery_targets(&dst, &our_name, &section, &network)?;
+        let (recipients, dg_size) =
+            delivery_targets(&dst, &our_name, &section, &network, ELDER_SIZE)?;
 
         // Send to a subset of elders in the intermediary dst section
         let min_dg_size =
@@ -373,7 +385,8 @@ mod tests {
 
         let dst_name = choose_elder_name(section.authority_provider())?;
         let dst = DstLocation::Node(dst_name);
-        let (recipients, dg_size) = delivery_targets(&dst, &our_name, &section, &network)?;
+        let (recipients, dg_size) =
+            delivery_targets(&dst, &our_name, &section, &network, ELDER_SIZE)?;
 
         // Send to all elders
         assert_eq!(dg_size, section.authority_provider().elder_count());
@@ -388,7 +401,8 @@ mod tests {
 
         let dst_name = section.prefix().substituted_in(rand::random());
         let dst = DstLocation::Node(dst_name);
-        let (recipients, dg_size) = delivery_targets(&dst, &our_name, &section, &network)?;
+        let (recipients, dg_size) =
+            delivery_targets(&dst, &our_name, &section, &network, ELDER_SIZE)?;
 
         // Send to all elders
         assert_eq!(dg_size, section.authority_provider().elder_count());
@@ -403,7 +417,8 @@ mod tests {
 
         let dst_name = section.prefix().substituted_in(rand::random());
         let dst = DstLocation::Section(dst_name);
-        let (recipients, dg_size) = delivery_targets(&dst, &our_name, &section, &network)?;
+        let (recipients, dg_size) =
+            delivery_targets(&dst, &our_name, &section, &network, ELDER_SIZE)?;
 
         // Send to all elders
         assert_eq!(dg_size, section.authority_provider().elder_count());
@@ -420,7 +435,8 @@ mod tests {
             .pushed(true)
             .substituted_in(rand::random());
         let dst = DstLocation::Node(dst_name);
-        let (recipients, dg_size) = delivery_targets(&dst, &our_name, &section, &network)?;
+        let (recipients, dg_size) =
+            delivery_targets(&dst, &our_name, &section, &network, ELDER_SIZE)?;
 
         // Send to all elders
         assert_eq!(dg_size, section.authority_provider().elder_count());
@@ -437,7 +453,8 @@ mod tests {
             .pushed(true)
             .substituted_in(rand::random());
         let dst = DstLocation::Section(dst_name);
-        let (recipients, dg_size) = delivery_targets(&dst, &our_name, &section, &network)?;
+        let (recipients, dg_size) =
+            delivery_targets(&dst, &our_name, &section, &network, ELDER_SIZE)?;
 
         // Send to all elders
         assert_eq!(dg_size, section.authority_provider().elder_count());
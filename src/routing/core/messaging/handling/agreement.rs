@@ -14,11 +14,12 @@ use crate::{
     messages::RoutingMsgUtils,
     network::NetworkUtils,
     peer::PeerUtils,
+    rng,
     routing::command::Command,
     section::{
         ElderCandidatesUtils, SectionAuthorityProviderUtils, SectionPeersUtils, SectionUtils,
     },
-    Error, Event, MIN_AGE,
+    Error, Event,
 };
 use secured_linked_list::SecuredLinkedList;
 use sn_messaging::{
@@ -112,9 +113,9 @@ impl Core {
                 return Ok(commands);
             }
 
-            let new_age = cmp::max(MIN_AGE, old_info.value.peer.age() / 2);
+            let new_age = cmp::max(self.params.min_age, old_info.value.peer.age() / 2);
 
-            if new_age > MIN_AGE {
+            if new_age > self.params.min_age {
                 // TODO: consider handling the relocation inside the bootstrap phase, to avoid
                 // having to send this `NodeApproval`.
                 commands.push(self.send_node_approval(old_info.clone())?);
@@ -135,6 +136,7 @@ impl Core {
         }
 
         info!("handle Online: {:?}", new_info.value.peer);
+        self.update_section_members();
 
         self.send_event(Event::MemberJoined {
             name: *new_info.value.peer.name(),
@@ -154,7 +156,7 @@ impl Core {
         commands.extend(result);
         commands.push(self.send_node_approval(new_info)?);
 
-        self.print_network_stats();
+        self.update_network_stats();
 
         Ok(commands)
     }
@@ -169,6 +171,7 @@ impl Core {
         let peer = node_state.peer;
         let age = peer.age();
         let signature = signed.signature.clone();
+        let left_voluntarily = node_state.has_left_voluntarily();
 
         if !self.section.update_member(SectionSigned {
             value: node_state,
@@ -179,8 +182,15 @@ impl Core {
         }
 
         info!("handle Offline: {:?}", peer);
+        self.update_section_members();
 
-        commands.extend(self.relocate_peers(peer.name(), &signature)?);
+        if left_voluntarily {
+            // The peer left voluntarily, so don't treat this as churn. Let it know its request
+            // has been agreed on, as it's no longer a recipient of our syncs.
+            commands.extend(self.send_sync_to_leaving_peer(&peer)?);
+        } else {
+            commands.extend(self.relocate_peers(peer.name(), &signature)?);
+        }
 
         let result = self.promote_and_demote_elders()?;
         if result.is_empty() {
@@ -194,6 +204,7 @@ impl Core {
             age,
         })
         .await;
+        self.update_network_stats();
 
         Ok(commands)
     }
@@ -212,7 +223,9 @@ impl Core {
         if equal_or_extension {
             // Our section of sub-section
 
-            let infos = self.section.promote_and_demote_elders(&self.node.name());
+            let infos = self
+                .section
+                .promote_and_demote_elders(&self.node.name(), &self.params);
             if !infos.contains(&section_auth.value.elder_candidates()) {
                 // SectionInfo out of date, ignore.
                 return Ok(commands);
@@ -242,7 +255,7 @@ impl Core {
                     len,
                     sync_message,
                     DestInfo {
-                        dest: XorName::random(),
+                        dest: rng::random_name(),
                         dest_section_pk: signed.public_key,
                     },
                 ));
@@ -8,7 +8,7 @@
 
 use super::Core;
 use crate::{
-    messages::{MessageStatus, SrcAuthorityUtils},
+    messages::{Envelope, MessageStatus, SrcAuthorityUtils},
     section::{SectionAuthorityProviderUtils, SectionUtils},
     Result,
 };
@@ -30,10 +30,16 @@ impl Core {
                     return Ok(MessageStatus::Useless);
                 }
             }
-            Variant::UserMessage(_) => {
+            Variant::UserMessage(content) => {
                 // If elder, always handle UserMessage, otherwise
-                // handle it only if addressed directly to us as a node.
-                if !self.is_elder() && msg.dst != DstLocation::Node(self.node.name()) {
+                // handle it only if addressed directly to us as a node, or sent to us directly
+                // and meant for adults too (e.g. relaying a traced message).
+                let is_for_us = match msg.dst {
+                    DstLocation::Node(name) => name == self.node.name(),
+                    DstLocation::DirectAndUnrouted => Envelope::is_direct_to_adults(content),
+                    _ => false,
+                };
+                if !self.is_elder() && !is_for_us {
                     return Ok(MessageStatus::Useless);
                 }
             }
@@ -17,14 +17,22 @@ use crate::{
     dkg::{commands::DkgCommands, ProposalError, SignedShare},
     error::{Error, Result},
     event::Event,
-    messages::{MessageStatus, RoutingMsgUtils, SrcAuthorityUtils, VerifyStatus},
+    messages::{
+        Envelope, MessageStatus, RoutingMsgUtils, SrcAuthorityUtils, TraceHop, VerifyStatus,
+    },
+    metrics::{self, Metric},
     network::NetworkUtils,
     peer::PeerUtils,
     relocation::{RelocatePayloadUtils, RelocateState, SignedRelocateDetailsUtils},
-    routing::command::Command,
+    request::{self, RequestHandle, RequestId, RequestSlot, Response},
+    routing::{
+        command::Command,
+        socket_id,
+        transfer::{Chunk, Received},
+    },
     section::{
         SectionAuthorityProviderUtils, SectionKeyShare, SectionPeersUtils, SectionUtils,
-        FIRST_SECTION_MAX_AGE, FIRST_SECTION_MIN_AGE, MIN_ADULT_AGE,
+        FIRST_SECTION_MAX_AGE,
     },
 };
 use bytes::Bytes;
@@ -37,9 +45,11 @@ use sn_messaging::{
         SignedRelocateDetails, SrcAuthority, Variant,
     },
     section_info::{GetSectionResponse, SectionInfoMsg},
-    DestInfo, DstLocation, EndUser, MessageType, SectionAuthorityProvider,
+    Aggregation, DestInfo, DstLocation, EndUser, Itinerary, MessageId, MessageType,
+    SectionAuthorityProvider, SrcLocation,
 };
 use std::{collections::BTreeSet, iter, net::SocketAddr};
+use tokio::time::Instant;
 use xor_name::XorName;
 
 // Message handling
@@ -50,6 +60,11 @@ impl Core {
         msg: RoutingMsg,
         dest_info: DestInfo,
     ) -> Result<Vec<Command>> {
+        self.metrics.inc(
+            Metric::MessagesHandled,
+            metrics::variant_label(&msg.variant),
+        );
+
         let mut commands = vec![];
 
         // Check if the message is for us.
@@ -178,7 +193,26 @@ impl Core {
         }
     }
 
-    pub(crate) fn handle_timeout(&mut self, token: u64) -> Result<Vec<Command>> {
+    pub(crate) async fn handle_timeout(&mut self, token: u64) -> Result<Vec<Command>> {
+        self.prune_pending_requests();
+
+        let delivery = self
+            .pending_deliveries
+            .iter()
+            .find(|(_, pending)| pending.timer_token == token)
+            .map(|(msg_id, _)| *msg_id);
+        if let Some(msg_id) = delivery {
+            return self.retransmit(msg_id).await;
+        }
+
+        if self
+            .socket_id_share
+            .as_ref()
+            .map_or(false, |pending| pending.timer_token == token)
+        {
+            return self.resend_socket_id_key_share();
+        }
+
         self.dkg_voter
             .handle_timeout(&self.node.keypair, token)
             .into_commands(&self.node, *self.section_chain().last_key())
@@ -191,7 +225,14 @@ impl Core {
         signed_share: SignedShare,
     ) -> Result<Vec<Command>> {
         match self.proposal_aggregator.add(proposal, signed_share) {
-            Ok((proposal, signed)) => Ok(vec![Command::HandleAgreement { proposal, signed }]),
+            Ok((proposal, signed, latency)) => {
+                self.metrics.observe(
+                    Metric::AgreementLatency,
+                    metrics::proposal_label(&proposal),
+                    latency,
+                );
+                Ok(vec![Command::HandleAgreement { proposal, signed }])
+            }
             Err(ProposalError::Aggregation(sn_messaging::node::Error::NotEnoughShares)) => {
                 Ok(vec![])
             }
@@ -219,7 +260,9 @@ impl Core {
             self.section_keys_provider.finalise_dkg(&public_key)
         }
 
-        result
+        let mut commands = result?;
+        commands.extend(self.share_socket_id_key()?);
+        Ok(commands)
     }
 
     pub(crate) fn handle_dkg_failure(&mut self, signeds: DkgFailureSignedSet) -> Result<Command> {
@@ -308,10 +351,49 @@ impl Core {
                 let sender = sender.ok_or(Error::InvalidSrcLocation)?;
                 self.handle_join_as_relocated_request(msg.src.peer(sender)?, *join_request)
             }
-            Variant::UserMessage(ref content) => {
-                let bytes = Bytes::from(content.clone());
-                self.handle_user_message(msg, bytes).await
-            }
+            Variant::UserMessage(ref content) => match self.decode_envelope(&msg, content)? {
+                Envelope::User(content) => {
+                    self.handle_user_message(msg, Bytes::from(content), None)
+                        .await
+                }
+                Envelope::Leave => self.handle_leave_request(&msg),
+                Envelope::Request { id, content } => {
+                    self.handle_request(msg, id, Bytes::from(content)).await
+                }
+                Envelope::Response { id, content } => {
+                    self.handle_response(msg, id, Bytes::from(content))
+                }
+                Envelope::TracedUser(content) => {
+                    let trace = self.traces.remove(&msg.id).await.unwrap_or_default();
+                    self.handle_user_message(msg, Bytes::from(content), Some(trace))
+                        .await
+                }
+                Envelope::TraceProbe { id } => self.handle_trace_probe(msg, id).await,
+                Envelope::AcknowledgedUser { id, content } => {
+                    self.handle_acknowledged_message(msg, id, Bytes::from(content))
+                        .await
+                }
+                Envelope::Receipt { id } => self.handle_receipt(msg, id).await,
+                Envelope::Chunk(chunk) => self.handle_chunk(msg, chunk).await,
+                Envelope::ChunkAck {
+                    transfer_id,
+                    received,
+                } => self.handle_chunk_ack(&msg, transfer_id, received),
+                Envelope::SocketIdKeyShare {
+                    section_key,
+                    index,
+                    share,
+                    reply,
+                } => self.handle_socket_id_key_share(&msg, section_key, index, share, reply),
+                Envelope::SocketIdKeys(signatures) => self.handle_socket_id_keys(&msg, signatures),
+                Envelope::Relayed {
+                    msg: relayed,
+                    trace,
+                } => {
+                    self.handle_relayed_message(sender, msg.src.name(), relayed, trace, dest_info)
+                        .await
+                }
+            },
             Variant::BouncedUntrustedMessage {
                 msg: bounced_msg,
                 dest_info,
@@ -442,10 +524,24 @@ impl Core {
         }
     }
 
+    // Unwraps the envelope of a `UserMessage`. One from a node speaking another version of the
+    // envelopes can't be understood, so it's dropped, but not silently.
+    fn decode_envelope(&self, msg: &RoutingMsg, content: &[u8]) -> Result<Envelope> {
+        Envelope::decode(content).map_err(|error| {
+            warn!(
+                "Dropping user message {:?} from {:?}: invalid or unsupported envelope",
+                msg.id, msg.src
+            );
+            self.metrics.inc(Metric::InvalidEnvelopes, "");
+            error
+        })
+    }
+
     async fn handle_user_message(
         &mut self,
         msg: RoutingMsg,
         content: Bytes,
+        trace: Option<Vec<TraceHop>>,
     ) -> Result<Vec<Command>> {
         trace!("handle user message {:?}", msg);
         if let DstLocation::EndUser(EndUser {
@@ -453,7 +549,7 @@ impl Core {
             socket_id,
         }) = msg.dst
         {
-            if let Some(socket_addr) = self.get_socket_addr(socket_id).copied() {
+            if let Some(socket_addr) = self.get_socket_addr(socket_id) {
                 trace!("sending user message {:?} to client {:?}", msg, socket_addr);
                 return Ok(vec![Command::SendMessage {
                     recipients: vec![(xor_name, socket_addr)],
@@ -467,11 +563,14 @@ impl Core {
                     },
                 }]);
             } else {
+                // Only the elder a client authenticated with knows its address (see
+                // `EndUserRegistry`), so pass the message on to the other elders in case it's one
+                // of them. The message filters stop it from going round in circles.
                 trace!(
-                    "Cannot route user message, socket id not found for {:?}",
+                    "Socket id not found for {:?}, relaying it to the other elders",
                     msg
                 );
-                return Err(Error::EmptyRecipientList);
+                return Ok(self.relay_message(&msg).await?.into_iter().collect());
             }
         }
 
@@ -481,12 +580,320 @@ impl Core {
             dst: msg.dst,
             signed: msg.signed(),
             section_pk: msg.section_pk,
+            trace,
         })
         .await;
 
         Ok(vec![])
     }
 
+    // Handles a message sent with `Routing::send_message_with_receipt`. Every copy of it is
+    // acknowledged, as the receipts of the previous ones may have been lost, but it's handed over
+    // to the upper layers only once.
+    async fn handle_acknowledged_message(
+        &mut self,
+        msg: RoutingMsg,
+        id: MessageId,
+        content: Bytes,
+    ) -> Result<Vec<Command>> {
+        if let DstLocation::EndUser(_) = msg.dst {
+            return Err(Error::InvalidDstLocation);
+        }
+
+        let itinerary = request::reply_itinerary(&msg.src.src_location(), &msg.dst)?;
+        let mut commands = if self.acknowledged.set(id, (), None).await.is_none() {
+            self.handle_user_message(msg, content, None).await?
+        } else {
+            trace!("Acknowledging {:?} again", id);
+            vec![]
+        };
+
+        commands.extend(
+            self.send_envelope(itinerary, Envelope::Receipt { id })
+                .await?,
+        );
+
+        Ok(commands)
+    }
+
+    async fn handle_receipt(&mut self, msg: RoutingMsg, id: MessageId) -> Result<Vec<Command>> {
+        // A receipt from a section has to be aggregated at destination, like responses, so a
+        // single elder can't claim the delivery on behalf of the whole section.
+        let is_from_dst = match self.pending_deliveries.get(&id) {
+            Some(pending) => {
+                let itinerary = &pending.itinerary;
+                request::reply_itinerary(&itinerary.src, &itinerary.dst).map_or(false, |reply| {
+                    request::is_reply_from(&msg, &itinerary.dst, reply.aggregation)
+                })
+            }
+            None => {
+                trace!("Ignoring receipt of unknown or already delivered {:?}", id);
+                return Ok(vec![]);
+            }
+        };
+        if !is_from_dst {
+            warn!("Ignoring receipt of {:?} from unexpected {:?}", id, msg.src);
+            return Err(Error::InvalidSrcLocation);
+        }
+
+        if let Some(pending) = self.pending_deliveries.remove(&id) {
+            let _ = pending.receipt_tx.send(());
+        }
+        self.send_event(Event::MessageDelivered { msg_id: id })
+            .await;
+
+        Ok(vec![])
+    }
+
+    // Receives a chunk of a large message and acknowledges the chunks received so far. The
+    // message is handed over once complete.
+    async fn handle_chunk(&mut self, msg: RoutingMsg, chunk: Chunk) -> Result<Vec<Command>> {
+        if msg.src.is_section() {
+            return Err(Error::InvalidSrcLocation);
+        }
+        if msg.dst != DstLocation::Node(self.node.name()) {
+            return Err(Error::InvalidDstLocation);
+        }
+
+        let sender = msg.src.name();
+        let transfer_id = chunk.transfer_id;
+        let count = chunk.count;
+
+        let received = match self
+            .incoming_transfers
+            .receive(sender, chunk, Instant::now())
+        {
+            Received::Partial(received) | Received::Duplicate(received) => received,
+            Received::Complete(content) => {
+                self.send_event(Event::MessageReceived {
+                    content,
+                    src: msg.src.src_location(),
+                    dst: msg.dst,
+                    signed: msg.signed(),
+                    section_pk: msg.section_pk,
+                    trace: None,
+                })
+                .await;
+                count
+            }
+            Received::Rejected => {
+                warn!(
+                    "Dropping invalid chunk of transfer {:016x} from {}",
+                    transfer_id, sender
+                );
+                return Err(Error::InvalidMessage);
+            }
+        };
+
+        let itinerary = Itinerary {
+            src: SrcLocation::Node(self.node.name()),
+            dst: DstLocation::Node(sender),
+            aggregation: Aggregation::None,
+        };
+        self.send_envelope(
+            itinerary,
+            Envelope::ChunkAck {
+                transfer_id,
+                received,
+            },
+        )
+        .await
+    }
+
+    fn handle_chunk_ack(
+        &mut self,
+        msg: &RoutingMsg,
+        transfer_id: u64,
+        received: u32,
+    ) -> Result<Vec<Command>> {
+        match self.outgoing_transfers.get(&transfer_id) {
+            Some((dst, acks_tx)) if !msg.src.is_section() && msg.src.name() == *dst => {
+                let _ = acks_tx.send(received);
+                Ok(vec![])
+            }
+            Some(_) => Err(Error::InvalidSrcLocation),
+            None => {
+                trace!("Ignoring ack of unknown transfer {:016x}", transfer_id);
+                Ok(vec![])
+            }
+        }
+    }
+
+    // Unwraps a traced message relayed to us by `relayer` and hands it over to be handled as if
+    // sent to us directly, keeping the hops it went through for when we relay or handle it.
+    async fn handle_relayed_message(
+        &mut self,
+        sender: Option<SocketAddr>,
+        relayer: XorName,
+        msg: Vec<u8>,
+        trace: Vec<TraceHop>,
+        dest_info: DestInfo,
+    ) -> Result<Vec<Command>> {
+        let msg: RoutingMsg = bincode::deserialize(&msg).map_err(|_| Error::InvalidMessage)?;
+        RoutingMsg::check_signature(&msg)?;
+
+        if trace.last().map(|hop| hop.name) != Some(relayer)
+            || !trace.iter().all(|hop| hop.verify(&msg.id))
+        {
+            warn!("Discarding relayed {:?} with an invalid trace", msg.id);
+            return Err(Error::InvalidMessage);
+        }
+
+        // The message is relayed to us by every member of the delivery group, each time in a
+        // different wrapper.
+        if !self.msg_filter.add_to_filter(&msg.id) {
+            self.metrics.inc(Metric::FilterHits, "incoming");
+            return Ok(vec![]);
+        }
+
+        let _ = self.traces.set(msg.id, trace, None).await;
+
+        Ok(vec![Command::HandleMessage {
+            sender,
+            message: msg,
+            dest_info,
+        }])
+    }
+
+    fn handle_socket_id_key_share(
+        &mut self,
+        msg: &RoutingMsg,
+        section_key: bls::PublicKey,
+        index: usize,
+        share: bls::SignatureShare,
+        reply: bool,
+    ) -> Result<Vec<Command>> {
+        // The sender may already be an elder of a section update we haven't caught up with yet, so
+        // accept the shares of any member. They are checked against the section key set anyway.
+        if msg.src.is_section() || !self.section.members().is_joined(&msg.src.name()) {
+            return Err(Error::InvalidSrcLocation);
+        }
+
+        let sender = msg.src.name();
+        self.end_users
+            .add_socket_id_key_share(section_key, index, share)?;
+
+        if reply {
+            if let Some(pending) = &mut self.socket_id_share {
+                if pending.section_key == section_key {
+                    let _ = pending.unacknowledged.remove(&sender);
+                }
+            }
+            return Ok(vec![]);
+        }
+
+        // Answer with our own share, which the sender may not have, e.g. having just restarted.
+        let key_share = match self.section_keys_provider.key_share() {
+            Ok(key_share) if key_share.public_key_set.public_key() == section_key => key_share,
+            _ => return Ok(vec![]),
+        };
+        let addr = if let Some(info) = self.section.members().get(&sender) {
+            *info.peer.addr()
+        } else {
+            return Ok(vec![]);
+        };
+        let envelope = Envelope::SocketIdKeyShare {
+            section_key,
+            index: key_share.index,
+            share: socket_id::sign_share(key_share),
+            reply: true,
+        };
+
+        Ok(vec![self.send_direct(vec![(sender, addr)], envelope)?])
+    }
+
+    // Takes the socket id keys handed over by an elder, as we've just been promoted.
+    fn handle_socket_id_keys(
+        &mut self,
+        msg: &RoutingMsg,
+        signatures: Vec<(bls::PublicKey, bls::Signature)>,
+    ) -> Result<Vec<Command>> {
+        if msg.src.is_section() || !self.section.members().is_joined(&msg.src.name()) {
+            return Err(Error::InvalidSrcLocation);
+        }
+
+        // The signatures are verified against their section keys, which are to be ours.
+        let chain = self.section.chain();
+        let signatures: Vec<_> = signatures
+            .into_iter()
+            .filter(|(section_key, _)| chain.has_key(section_key))
+            .collect();
+        self.end_users.insert_socket_id_key_signatures(signatures);
+
+        Ok(vec![])
+    }
+
+    // Answers a probe sent with `Routing::trace_route` with the hops it went through.
+    async fn handle_trace_probe(&mut self, msg: RoutingMsg, id: RequestId) -> Result<Vec<Command>> {
+        let trace = self.traces.remove(&msg.id).await.unwrap_or_default();
+        let content = bincode::serialize(&trace).map_err(|_| Error::InvalidMessage)?;
+        let handle = RequestHandle::new(id, msg.src.src_location(), msg.dst);
+
+        self.send_response(&handle, Bytes::from(content)).await
+    }
+
+    async fn handle_request(
+        &mut self,
+        msg: RoutingMsg,
+        id: RequestId,
+        content: Bytes,
+    ) -> Result<Vec<Command>> {
+        if let DstLocation::EndUser(_) = msg.dst {
+            return Err(Error::InvalidDstLocation);
+        }
+
+        let src = msg.src.src_location();
+        self.send_event(Event::RequestReceived {
+            content,
+            src,
+            dst: msg.dst,
+            signed: msg.signed(),
+            section_pk: msg.section_pk,
+            handle: RequestSlot::new(RequestHandle::new(id, src, msg.dst)),
+        })
+        .await;
+
+        Ok(vec![])
+    }
+
+    fn handle_response(
+        &mut self,
+        msg: RoutingMsg,
+        id: RequestId,
+        content: Bytes,
+    ) -> Result<Vec<Command>> {
+        self.prune_pending_requests();
+
+        match self.pending_requests.get(&id) {
+            Some(pending) if pending.is_answered_by(&msg) => (),
+            Some(_) => {
+                // Not from the destination of the request, or from a section but not aggregated.
+                // The request stays pending for the genuine response.
+                warn!(
+                    "Ignoring response to {:?} from unexpected {:?}",
+                    id,
+                    msg.src.src_location()
+                );
+                return Err(Error::InvalidSrcLocation);
+            }
+            None => {
+                trace!("Ignoring response to unknown or already answered {:?}", id);
+                return Ok(vec![]);
+            }
+        }
+
+        if let Some(pending) = self.pending_requests.remove(&id) {
+            pending.respond(Response {
+                content,
+                src: msg.src.src_location(),
+                signed: msg.signed(),
+                section_pk: msg.section_pk,
+            });
+        }
+
+        Ok(vec![])
+    }
+
     pub(crate) async fn handle_sync(
         &mut self,
         section: Section,
@@ -513,6 +920,13 @@ impl Core {
         self.section.merge(section)?;
         self.network.merge(network, self.section.chain());
 
+        if self.leave_notifier.is_some() && self.has_left() {
+            info!("Our leave request has been agreed on");
+            if let Some(notifier) = self.leave_notifier.take() {
+                let _ = notifier.send(());
+            }
+        }
+
         if !self.is_elder() {
             let current_adults: BTreeSet<_> = self
                 .section
@@ -576,6 +990,7 @@ impl Core {
                 "Rejecting JoinRequest from {} - joins currently not allowed.",
                 peer,
             );
+            self.metrics.inc(Metric::JoinRejections, "joins_disallowed");
             let variant = Variant::JoinResponse(Box::new(JoinResponse::Rejected(
                 JoinRejectionReason::JoinsDisallowed,
             )));
@@ -589,13 +1004,14 @@ impl Core {
         }
 
         // Start as Adult as long as passed resource signed.
-        let mut age = MIN_ADULT_AGE;
+        let mut age = self.params.min_adult_age();
 
         // During the first section, node shall use ranged age to avoid too many nodes got
         // relocated at the same time. After the first section got split, later on nodes shall
-        // only start with age of MIN_ADULT_AGE
+        // only start with the minimum adult age.
         if self.section.prefix().is_empty() {
-            if peer.age() < FIRST_SECTION_MIN_AGE || peer.age() > FIRST_SECTION_MAX_AGE {
+            if peer.age() < self.params.first_section_min_age || peer.age() > FIRST_SECTION_MAX_AGE
+            {
                 debug!(
                     "Ignoring JoinRequest from {} - first-section node having wrong age {:?}",
                     peer,
@@ -605,12 +1021,12 @@ impl Core {
             } else {
                 age = peer.age();
             }
-        } else if peer.age() != MIN_ADULT_AGE {
-            // After section split, new node has to join with age of MIN_ADULT_AGE.
+        } else if peer.age() != self.params.min_adult_age() {
+            // After section split, new node has to join with the minimum adult age.
             let variant = Variant::JoinResponse(Box::new(JoinResponse::Retry(
                 self.section.authority_provider().clone(),
             )));
-            trace!("New node after section split must join with age of MIN_ADULT_AGE. Sending {:?} to {}", variant, peer);
+            trace!("New node after section split must join with the minimum adult age. Sending {:?} to {}", variant, peer);
             return Ok(vec![self.send_direct_message(
                 (*peer.name(), *peer.addr()),
                 variant,
@@ -751,7 +1167,10 @@ impl Core {
     pub(crate) fn promote_and_demote_elders(&mut self) -> Result<Vec<Command>> {
         let mut commands = vec![];
 
-        for info in self.section.promote_and_demote_elders(&self.node.name()) {
+        for info in self
+            .section
+            .promote_and_demote_elders(&self.node.name(), &self.params)
+        {
             commands.extend(self.send_dkg_start(info)?);
         }
 
@@ -8,16 +8,10 @@
 
 use super::Core;
 use crate::{
-    ed25519,
-    peer::PeerUtils,
-    routing::{
-        command::Command,
-        core::{RESOURCE_PROOF_DATA_SIZE, RESOURCE_PROOF_DIFFICULTY},
-    },
-    section::SectionUtils,
-    Error, Result,
+    ed25519, peer::PeerUtils, rng, routing::command::Command, section::SectionUtils, Error, Result,
 };
 use ed25519_dalek::Verifier;
+use rand::Rng;
 use sn_messaging::node::{JoinResponse, Peer, ResourceProofResponse, Variant};
 use xor_name::XorName;
 
@@ -44,17 +38,20 @@ impl Core {
             return false;
         }
 
+        // The proof is solved for the nonce bound to the network parameters, so it fails if the
+        // joining node was configured with different ones.
+        let nonce = self.params.bind_nonce(&response.nonce);
         self.resource_proof
-            .validate_all(&response.nonce, &response.data, response.solution)
+            .validate_all(&nonce, &response.data, response.solution)
     }
 
     pub(crate) fn send_resource_proof_challenge(&self, peer: &Peer) -> Result<Command> {
-        let nonce: [u8; 32] = rand::random();
+        let nonce: [u8; 32] = rng::new().gen();
         let serialized =
             bincode::serialize(&(peer.name(), &nonce)).map_err(|_| Error::InvalidMessage)?;
         let response = Variant::JoinResponse(Box::new(JoinResponse::ResourceChallenge {
-            data_size: RESOURCE_PROOF_DATA_SIZE,
-            difficulty: RESOURCE_PROOF_DIFFICULTY,
+            data_size: self.params.resource_proof_data_size,
+            difficulty: self.params.resource_proof_difficulty,
             nonce,
             nonce_signature: ed25519::sign(&serialized, &self.node.keypair),
         }));
@@ -11,9 +11,11 @@ use crate::{
     dkg::DkgKeyUtils,
     error::Result,
     messages::RoutingMsgUtils,
+    metrics::Metric,
     network::NetworkUtils,
     peer::PeerUtils,
     relocation::RelocateState,
+    rng,
     routing::command::Command,
     section::{ElderCandidatesUtils, SectionAuthorityProviderUtils, SectionUtils},
 };
@@ -79,7 +81,7 @@ impl Core {
                 self.section.authority_provider().section_key(),
             )?;
             let dest_info = DestInfo {
-                dest: XorName::random(),
+                dest: rng::random_name(),
                 dest_section_pk: *self.section.chain().last_key(),
             };
             Ok(Command::send_message_to_nodes(
@@ -130,7 +132,7 @@ impl Core {
                 recipients.len(),
                 message,
                 DestInfo {
-                    dest: XorName::random(),
+                    dest: rng::random_name(),
                     dest_section_pk: *self.section_chain().last_key(),
                 },
             ))
@@ -154,11 +156,37 @@ impl Core {
         Ok(commands)
     }
 
+    pub(crate) fn send_sync_to_leaving_peer(&self, peer: &Peer) -> Result<Vec<Command>> {
+        let variant = Variant::Sync {
+            section: self.section.clone(),
+            network: Network::new(),
+        };
+        trace!("Send {:?} to {:?}", variant, peer);
+
+        let message = RoutingMsg::single_src(
+            &self.node,
+            DstLocation::DirectAndUnrouted,
+            variant,
+            self.section.authority_provider().section_key(),
+        )?;
+
+        Ok(vec![Command::send_message_to_node(
+            (*peer.name(), *peer.addr()),
+            message,
+            DestInfo {
+                dest: *peer.name(),
+                dest_section_pk: *self.section_chain().last_key(),
+            },
+        )])
+    }
+
     pub(crate) fn send_relocate(
         &self,
         recipient: &Peer,
         details: RelocateDetails,
     ) -> Result<Vec<Command>> {
+        self.metrics.inc(Metric::Relocations, "peer");
+
         let src = details.pub_id;
         let dst = DstLocation::Node(details.pub_id);
         let variant = Variant::Relocate(details);
@@ -314,7 +342,7 @@ impl Core {
                 count,
                 message.clone(),
                 DestInfo {
-                    dest: XorName::random(), // will be updated when sending
+                    dest: rng::random_name(), // will be updated when sending
                     dest_section_pk,
                 },
             ));
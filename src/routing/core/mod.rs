@@ -12,18 +12,28 @@ mod connectivity;
 mod delivery_group;
 mod messaging;
 
-use super::{command::Command, enduser_registry::EndUserRegistry, split_barrier::SplitBarrier};
+use super::{
+    bootstrap::BootstrapCache, command::Command, enduser_registry::EndUserRegistry,
+    event_stream::EventSender, rate_limit::SectionMembers, socket_id::PendingShare,
+    split_barrier::SplitBarrier, transfer::IncomingTransfers,
+};
 use crate::{
+    cache::Cache,
+    delivery::PendingDelivery,
     dkg::{DkgVoter, ProposalAggregator},
     error::Result,
     event::{Elders, Event, NodeElderChange},
     message_filter::MessageFilter,
-    messages::RoutingMsgUtils,
-    network::NetworkUtils,
+    messages::{RoutingMsgUtils, TraceHop},
+    metrics::{Metric, Metrics},
+    network::{NetworkStats, NetworkUtils},
     node::Node,
     peer::PeerUtils,
     relocation::RelocateState,
+    request::{PendingRequest, RequestId},
+    rng,
     section::{SectionAuthorityProviderUtils, SectionKeyShare, SectionKeysProvider, SectionUtils},
+    NetworkParams,
 };
 use itertools::Itertools;
 use resource_proof::ResourceProof;
@@ -33,13 +43,26 @@ use sn_messaging::{
     node::{Network, Proposal, RoutingMsg, Section, SectionSigned, Variant},
     DestInfo, DstLocation, MessageId, SectionAuthorityProvider, WireMsg,
 };
-use std::collections::BTreeSet;
-use tokio::sync::mpsc;
+use std::{
+    collections::{BTreeSet, HashMap},
+    iter,
+    time::Duration,
+};
+use tokio::sync::{oneshot, watch};
 use xor_name::{Prefix, XorName};
 
 pub const RESOURCE_PROOF_DATA_SIZE: usize = 64;
 pub const RESOURCE_PROOF_DIFFICULTY: u8 = 2;
-const KEY_CACHE_SIZE: u8 = 5;
+pub const KEY_CACHE_SIZE: u8 = 5;
+
+// How long, and for how many messages at most, the hops of the traced messages we receive are kept
+// for them to be relayed further or handled.
+const TRACE_EXPIRY: Duration = Duration::from_secs(60);
+const TRACE_CAPACITY: usize = 1024;
+// How long, and for how many messages at most, the ids of the messages we acknowledged are kept,
+// so their retransmissions are acknowledged again without being handled again.
+const RECEIPT_EXPIRY: Duration = Duration::from_secs(300);
+const RECEIPT_CAPACITY: usize = 4096;
 
 // State + logic of a routing node.
 pub(crate) struct Core {
@@ -54,10 +77,34 @@ pub(crate) struct Core {
     dkg_voter: DkgVoter,
     relocate_state: Option<RelocateState>,
     msg_filter: MessageFilter,
-    pub(super) event_tx: mpsc::Sender<Event>,
+    pub(super) event_tx: EventSender,
     joins_allowed: bool,
     resource_proof: ResourceProof,
     end_users: EndUserRegistry,
+    // Our share of the socket id key for the current section key, until the other elders answer.
+    socket_id_share: Option<PendingShare>,
+    // Notified once our own leave request has been agreed on.
+    leave_notifier: Option<oneshot::Sender<()>>,
+    params: NetworkParams,
+    // Requests we sent and are waiting for the response to.
+    pending_requests: HashMap<RequestId, PendingRequest>,
+    // Messages we sent and are waiting for the receipt of.
+    pending_deliveries: HashMap<MessageId, PendingDelivery>,
+    // Messages we received and acknowledged.
+    acknowledged: Cache<MessageId, ()>,
+    // Large messages being sent, by transfer id, with their recipients and where to report the
+    // number of the chunks they acknowledged to.
+    outgoing_transfers: HashMap<u64, (XorName, watch::Sender<u32>)>,
+    // Large messages being received.
+    incoming_transfers: IncomingTransfers,
+    pub(super) bootstrap_cache: Option<BootstrapCache>,
+    metrics: Metrics,
+    // Addresses of our section members, exempt from the rate limits.
+    section_members: SectionMembers,
+    // Network stats last reported via `Event::NetworkStatsChanged`.
+    network_stats: NetworkStats,
+    // Hops of the traced messages relayed to us, by message id.
+    traces: Cache<MessageId, Vec<TraceHop>>,
 }
 
 impl Core {
@@ -66,37 +113,93 @@ impl Core {
         node: Node,
         section: Section,
         section_key_share: Option<SectionKeyShare>,
-        event_tx: mpsc::Sender<Event>,
+        event_tx: EventSender,
+        params: NetworkParams,
     ) -> Self {
-        let section_keys_provider = SectionKeysProvider::new(KEY_CACHE_SIZE, section_key_share);
+        let section_keys_provider =
+            SectionKeysProvider::new(params.key_cache_size, section_key_share);
+        let network = Network::new();
+        let network_stats = network.network_stats(
+            section.authority_provider(),
+            section.active_members().count(),
+        );
 
         Self {
             node,
             section,
-            network: Network::new(),
+            network,
             section_keys_provider,
             proposal_aggregator: ProposalAggregator::default(),
             split_barrier: SplitBarrier::new(),
             message_aggregator: SignatureAggregator::default(),
             dkg_voter: DkgVoter::default(),
             relocate_state: None,
-            msg_filter: MessageFilter::new(),
+            msg_filter: MessageFilter::new(
+                params.incoming_msg_expiry,
+                params.outgoing_msg_expiry,
+                params.msg_filter_false_positive_ppm,
+            ),
             event_tx,
             joins_allowed: true,
-            resource_proof: ResourceProof::new(RESOURCE_PROOF_DATA_SIZE, RESOURCE_PROOF_DIFFICULTY),
+            resource_proof: ResourceProof::new(
+                params.resource_proof_data_size,
+                params.resource_proof_difficulty,
+            ),
             end_users: EndUserRegistry::new(),
+            socket_id_share: None,
+            leave_notifier: None,
+            params,
+            pending_requests: HashMap::new(),
+            pending_deliveries: HashMap::new(),
+            acknowledged: Cache::with_expiry_duration_and_capacity(
+                RECEIPT_EXPIRY,
+                RECEIPT_CAPACITY,
+            ),
+            outgoing_transfers: HashMap::new(),
+            incoming_transfers: IncomingTransfers::default(),
+            bootstrap_cache: None,
+            metrics: Metrics::default(),
+            section_members: SectionMembers::default(),
+            network_stats,
+            traces: Cache::with_expiry_duration_and_capacity(TRACE_EXPIRY, TRACE_CAPACITY),
         }
     }
 
+    // Makes this node record its metrics into the given registry.
+    pub(super) fn set_metrics(&mut self, metrics: Metrics) {
+        self.dkg_voter.set_metrics(metrics.clone());
+        self.metrics = metrics;
+    }
+
+    // Makes this node keep the given set of its section members' addresses up to date.
+    pub(super) fn set_section_members(&mut self, section_members: SectionMembers) {
+        self.section_members = section_members;
+        self.update_section_members();
+    }
+
+    pub(crate) fn update_section_members(&self) {
+        self.section_members.set(
+            self.section
+                .active_members()
+                .map(|peer| *peer.addr())
+                .filter(|addr| *addr != self.node.addr),
+        )
+    }
+
     ////////////////////////////////////////////////////////////////////////////
     // Miscellaneous
     ////////////////////////////////////////////////////////////////////////////
 
-    pub async fn add_to_filter(&mut self, wire_msg: &WireMsg) -> bool {
+    pub fn add_to_filter(&self, wire_msg: &WireMsg) -> bool {
         if let Ok(true) = wire_msg.is_join_request() {
             return true;
         }
-        self.msg_filter.add_to_filter(&wire_msg.msg_id()).await
+        let is_new = self.msg_filter.add_to_filter(&wire_msg.msg_id());
+        if !is_new {
+            self.metrics.inc(Metric::FilterHits, "incoming");
+        }
+
+        is_new
     }
 
     async fn check_for_entropy(
@@ -141,6 +244,8 @@ impl Core {
             .update_section(section_auth, None, &section_chain)
         {
             info!("Neighbour section knowledge updated: {:?}", prefix);
+            self.update_bootstrap_cache();
+            self.update_network_stats();
         } else {
             warn!("Neighbour section update failed");
         }
@@ -152,13 +257,15 @@ impl Core {
 
         self.section_keys_provider
             .finalise_dkg(self.section.chain().last_key());
+        commands.extend(self.share_socket_id_key()?);
+        self.update_section_members();
 
         if new.prefix != old.prefix {
             info!("Split");
         }
 
         if new.last_key != old.last_key {
-            self.msg_filter.reset().await;
+            self.update_bootstrap_cache();
 
             if new.is_elder {
                 info!(
@@ -183,8 +290,6 @@ impl Core {
                     );
                 }
 
-                self.print_network_stats();
-
                 // Sending SectionKnowledge to other sections for new SAP.
                 let section_auth = self.section.section_signed_authority_provider();
                 let variant = Variant::SectionKnowledge {
@@ -205,7 +310,7 @@ impl Core {
                         .collect();
                     let len = targets.len();
                     let dest_info = DestInfo {
-                        dest: XorName::random(),
+                        dest: rng::random_name(),
                         dest_section_pk: sap.section_key(),
                     };
                     trace!("Sending updated SectionInfo to all known sections");
@@ -217,6 +322,10 @@ impl Core {
                 commands.extend(self.send_sync(self.section.clone(), self.network.clone())?);
             }
 
+            if new.is_elder && old.is_elder {
+                commands.extend(self.hand_over_socket_id_keys(&old.elders)?);
+            }
+
             let current: BTreeSet<_> = self.section.authority_provider().names();
             let added = current.difference(&old.elders).copied().collect();
             let removed = old.elders.difference(&current).copied().collect();
@@ -236,7 +345,8 @@ impl Core {
             } else if old.is_elder && !new.is_elder {
                 info!("Demoted");
                 self.network = Network::new();
-                self.section_keys_provider = SectionKeysProvider::new(KEY_CACHE_SIZE, None);
+                self.section_keys_provider =
+                    SectionKeysProvider::new(self.params.key_cache_size, None);
                 NodeElderChange::Demoted
             } else {
                 NodeElderChange::None
@@ -274,6 +384,7 @@ impl Core {
             };
 
             self.send_event(event).await;
+            self.update_network_stats();
         }
 
         if !new.is_elder {
@@ -300,10 +411,46 @@ impl Core {
         }
     }
 
-    pub(crate) fn print_network_stats(&self) {
-        self.network
-            .network_stats(self.section.authority_provider())
-            .print()
+    // Records the elders of our section and of the sections we know about in the bootstrap cache,
+    // if we have one. The cache saves them in the background.
+    pub(crate) fn update_bootstrap_cache(&mut self) {
+        let cache = if let Some(cache) = &mut self.bootstrap_cache {
+            cache
+        } else {
+            return;
+        };
+
+        let our_addr = self.node.addr;
+        let contacts: Vec<_> = iter::once(self.section.authority_provider())
+            .chain(self.network.all())
+            .flat_map(|sap| sap.addresses())
+            .filter(|addr| *addr != our_addr)
+            .collect();
+
+        let _ = cache.insert(self.section.chain().root_key(), contacts);
+    }
+
+    pub(crate) fn network_stats(&self) -> NetworkStats {
+        self.network.network_stats(
+            self.section.authority_provider(),
+            self.section.active_members().count(),
+        )
+    }
+
+    // Logs the current network stats and raises `Event::NetworkStatsChanged` if they moved
+    // significantly since the last time they were reported.
+    pub(crate) fn update_network_stats(&mut self) {
+        let stats = self.network_stats();
+        stats.print();
+
+        if !stats.differs_significantly(&self.network_stats) {
+            return;
+        }
+
+        self.network_stats = stats.clone();
+        if !self.event_tx.send(Event::NetworkStatsChanged(stats)) {
+            error!("All event streams have been closed");
+        }
     }
 }
 
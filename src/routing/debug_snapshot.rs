@@ -0,0 +1,121 @@
+// Copyright 2021 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under The General Public License (GPL), version 3.
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied. Please review the Licences for the specific language governing
+// permissions and limitations relating to use of the SAFE Network Software.
+
+use crate::section::SectionAuthorityProviderUtils;
+use serde::Serialize;
+use sn_messaging::{node::MembershipState, SectionAuthorityProvider};
+use std::net::SocketAddr;
+use xor_name::{Prefix, XorName};
+
+/// Point-in-time view of the routing state of a node, for diagnostics. See
+/// `Routing::debug_snapshot`.
+///
+/// It is meant to be dumped (e.g. as JSON) by tools, not to be relied upon programmatically: its
+/// contents may change between versions.
+#[derive(Clone, Debug, Serialize)]
+pub struct DebugSnapshot {
+    /// Our name.
+    pub name: XorName,
+    /// Our age.
+    pub age: u8,
+    /// Whether we are an elder.
+    pub is_elder: bool,
+    /// Prefix of our section.
+    pub prefix: Prefix,
+    /// Keys of our section chain, oldest first.
+    pub section_chain_keys: Vec<bls::PublicKey>,
+    /// Elders of our section.
+    pub elders: Vec<PeerSnapshot>,
+    /// All the members of our section we know of, including the ones that left or are relocated.
+    pub members: Vec<MemberSnapshot>,
+    /// Other sections we know of.
+    pub network: Vec<SectionSnapshot>,
+    /// DKG sessions we participate in.
+    pub dkg_sessions: Vec<DkgSessionSnapshot>,
+    /// Agreed-on elders of one of our subsections, waiting for the agreement of the other one to
+    /// complete a split.
+    pub split_barrier: Vec<SectionSnapshot>,
+    /// Status of our own relocation, if any.
+    pub relocate_state: Option<RelocateStatus>,
+    /// Whether our section currently accepts new nodes.
+    pub joins_allowed: bool,
+}
+
+/// Name and address of a peer.
+#[derive(Clone, Debug, Serialize)]
+pub struct PeerSnapshot {
+    /// Name of the peer.
+    pub name: XorName,
+    /// Address of the peer.
+    pub addr: SocketAddr,
+}
+
+impl From<(&XorName, &SocketAddr)> for PeerSnapshot {
+    fn from((name, addr): (&XorName, &SocketAddr)) -> Self {
+        Self {
+            name: *name,
+            addr: *addr,
+        }
+    }
+}
+
+/// A member of our section.
+#[derive(Clone, Debug, Serialize)]
+pub struct MemberSnapshot {
+    /// Name of the member.
+    pub name: XorName,
+    /// Address of the member.
+    pub addr: SocketAddr,
+    /// Age of the member.
+    pub age: u8,
+    /// Membership state of the member.
+    pub state: MembershipState,
+}
+
+/// A section with its elders.
+#[derive(Clone, Debug, Serialize)]
+pub struct SectionSnapshot {
+    /// Prefix of the section.
+    pub prefix: Prefix,
+    /// Section key.
+    pub key: bls::PublicKey,
+    /// Elders of the section.
+    pub elders: Vec<PeerSnapshot>,
+}
+
+impl From<&SectionAuthorityProvider> for SectionSnapshot {
+    fn from(section_auth: &SectionAuthorityProvider) -> Self {
+        Self {
+            prefix: section_auth.prefix,
+            key: section_auth.section_key(),
+            elders: section_auth.elders.iter().map(PeerSnapshot::from).collect(),
+        }
+    }
+}
+
+/// A DKG session.
+#[derive(Clone, Debug, Serialize)]
+pub struct DkgSessionSnapshot {
+    /// Generation (section chain length) the session was started at.
+    pub generation: u64,
+    /// Prefix of the section the new key is generated for.
+    pub prefix: Prefix,
+    /// Participants of the session.
+    pub participants: Vec<PeerSnapshot>,
+    /// Whether the session has completed, either successfully or with a failure.
+    pub complete: bool,
+}
+
+/// Status of the relocation of a node.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize)]
+pub enum RelocateStatus {
+    /// The node was picked for relocation while an elder and waits to be demoted first.
+    Delayed,
+    /// The node is joining its destination section.
+    InProgress,
+}
@@ -6,11 +6,17 @@
 // KIND, either express or implied. Please review the Licences for the specific language governing
 // permissions and limitations relating to use of the SAFE Network Software.
 
-use super::{bootstrap::JoinAsRelocated, Comm, Command, Core};
+use super::{
+    bootstrap::JoinAsRelocated,
+    command_queue::{CommandLimits, CommandQueue, Priority},
+    transfer::{self, OutgoingTransfer},
+    Comm, Command, Core,
+};
 use crate::{
-    error::Result, event::Event, messages::RoutingMsgUtils, peer::PeerUtils,
+    error::Result, event::Event, messages::RoutingMsgUtils, metrics::Metric, peer::PeerUtils,
     routing::comm::SendStatus, section::SectionPeersUtils, section::SectionUtils, Error, XorName,
 };
+use bytes::Bytes;
 use itertools::Itertools;
 use sn_data_types::PublicKey;
 use sn_messaging::{
@@ -31,6 +37,7 @@ use tracing::Instrument;
 pub(crate) struct Dispatcher {
     pub(super) core: RwLock<Core>,
     pub(super) comm: Comm,
+    queue: CommandQueue,
 
     cancel_timer_tx: watch::Sender<bool>,
     cancel_timer_rx: watch::Receiver<bool>,
@@ -39,9 +46,11 @@ pub(crate) struct Dispatcher {
 impl Dispatcher {
     pub fn new(state: Core, comm: Comm) -> Self {
         let (cancel_timer_tx, cancel_timer_rx) = watch::channel(false);
+        let queue = CommandQueue::new(CommandLimits::default(), comm.metrics().clone());
         Self {
             core: RwLock::new(state),
             comm,
+            queue,
             cancel_timer_tx,
             cancel_timer_rx,
         }
@@ -52,12 +61,127 @@ impl Dispatcher {
         self.core.read().await.send_event(event).await
     }
 
+    /// Starts the workers handling the queued commands, within the given limits.
+    pub fn start(self: &Arc<Self>, limits: CommandLimits) {
+        self.queue.set_limits(limits);
+
+        let consensus_workers = limits
+            .consensus_workers
+            .min(limits.workers.saturating_sub(1));
+        let other_workers = limits.workers.saturating_sub(consensus_workers).max(1);
+
+        for _ in 0..consensus_workers {
+            let _ = tokio::spawn(self.clone().run_worker(Priority::Consensus));
+        }
+        for _ in 0..other_workers {
+            let _ = tokio::spawn(self.clone().run_worker(Priority::User));
+        }
+    }
+
     /// Handles the given command and transitively any new commands that are produced during its
-    /// handling.
+    /// handling. The new commands are queued, to be handled by the workers in the order of their
+    /// priority.
     pub async fn handle_commands(self: Arc<Self>, command: Command) -> Result<()> {
         let commands = self.handle_command(command).await?;
         for command in commands {
-            self.clone().spawn_handle_commands(command)
+            // These wait for a timer or a whole join, so they'd only hold up a worker.
+            if matches!(
+                command,
+                Command::ScheduleTimeout { .. } | Command::Relocate { .. }
+            ) {
+                self.clone().spawn_handle_commands(command)
+            } else {
+                let _ = self.queue.push(command);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Queues the command for a message received from another node, following the load shedding
+    /// policy of `CommandLimits`: consensus messages wait for room in their queue, the others are
+    /// dropped if their queue is full. This way a backlog of network or user messages never holds
+    /// up reading the consensus messages behind them off the connections.
+    pub async fn handle_incoming(&self, command: Command) {
+        let priority = command.priority();
+        if priority == Priority::Consensus {
+            self.queue.push_wait(command).await;
+        } else if let Err(command) = self.queue.try_push(command) {
+            trace!(
+                "Shedding incoming {} message: {:?}",
+                priority.label(),
+                command
+            );
+            self.comm
+                .metrics()
+                .inc(Metric::MessagesShed, priority.label());
+        }
+    }
+
+    /// Whether any of the command queues is full, in which case the messages from clients are
+    /// dropped.
+    pub fn is_saturated(&self) -> bool {
+        self.queue.is_saturated()
+    }
+
+    /// Sends `content` to the node `dst` in chunks, keeping at most `transfer::WINDOW` chunks
+    /// beyond the ones `dst` acknowledged in flight. Returns once `dst` acknowledged them all.
+    pub async fn send_large_message(self: Arc<Self>, dst: XorName, content: Bytes) -> Result<()> {
+        let transfer = OutgoingTransfer::new(content);
+        let mut acks_rx = self.core.write().await.start_transfer(transfer.id(), dst);
+
+        let result = self
+            .clone()
+            .run_transfer(&transfer, dst, &mut acks_rx)
+            .await;
+
+        self.core.write().await.end_transfer(transfer.id());
+        result
+    }
+
+    async fn run_transfer(
+        self: Arc<Self>,
+        transfer: &OutgoingTransfer,
+        dst: XorName,
+        acks_rx: &mut watch::Receiver<u32>,
+    ) -> Result<()> {
+        let count = transfer.count();
+        let mut acked = 0;
+        let mut next = 0;
+        let mut retries = 0;
+
+        while acked < count {
+            while next < count && next < acked + transfer::WINDOW {
+                let command = Command::SendChunk {
+                    dst,
+                    chunk: transfer.chunk(next),
+                };
+                self.clone().handle_commands(command).await?;
+                next += 1;
+            }
+
+            match time::timeout(transfer::ACK_TIMEOUT, acks_rx.changed()).await {
+                Ok(Ok(())) => {
+                    let received = *acks_rx.borrow();
+                    if received > acked {
+                        acked = received;
+                        retries = 0;
+                    }
+                }
+                Ok(Err(_)) => return Err(Error::InvalidState),
+                Err(_) if retries < transfer::MAX_RETRIES => {
+                    // Go back to the first chunk that wasn't acknowledged.
+                    trace!(
+                        "No ack for transfer {:016x} to {}, resending from chunk {}",
+                        transfer.id(),
+                        dst,
+                        acked
+                    );
+                    retries += 1;
+                    next = acked;
+                }
+                Err(_) => return Err(Error::TransferTimeout),
+            }
         }
 
         Ok(())
@@ -92,7 +216,7 @@ impl Dispatcher {
     }
 
     // Terminate this routing instance - cancel all scheduled timers including any future ones,
-    // close all network connections and stop accepting new connections.
+    // stop the workers, close all network connections and stop accepting new connections.
     pub fn terminate(&self) {
         let _ = self.cancel_timer_tx.send(true);
         self.comm.terminate()
@@ -136,6 +260,9 @@ impl Dispatcher {
                     };
 
                     if let Some(variant) = failure {
+                        self.comm
+                            .metrics()
+                            .inc(Metric::JoinRejections, "node_not_reachable");
                         let section_key = *self.core.read().await.section().chain.last_key();
                         if let SrcAuthority::Node { public_key, .. } = message.src {
                             trace!("Sending {:?} to {}", variant, sender);
@@ -164,7 +291,7 @@ impl Dispatcher {
                 .await
                 .handle_section_info_msg(sender, message, dest_info)
                 .await),
-            Command::HandleTimeout(token) => self.core.write().await.handle_timeout(token),
+            Command::HandleTimeout(token) => self.core.write().await.handle_timeout(token).await,
             Command::HandleAgreement { proposal, signed } => {
                 self.core
                     .write()
@@ -209,6 +336,59 @@ impl Dispatcher {
                     .send_user_message(itinerary, content)
                     .await
             }
+            Command::SendMessageWithReceipt {
+                itinerary,
+                content,
+                policy,
+                msg_id,
+                receipt_tx,
+            } => {
+                self.core
+                    .write()
+                    .await
+                    .send_message_with_receipt(itinerary, content, policy, msg_id, receipt_tx)
+                    .await
+            }
+            Command::SendChunk { dst, chunk } => {
+                self.core.read().await.send_chunk(dst, chunk).await
+            }
+            Command::SendTracedMessage { itinerary, content } => {
+                self.core
+                    .write()
+                    .await
+                    .send_traced_user_message(itinerary, content)
+                    .await
+            }
+            Command::SendTraceProbe {
+                dst,
+                timeout,
+                response_tx,
+            } => {
+                self.core
+                    .write()
+                    .await
+                    .send_trace_probe(dst, timeout, response_tx)
+                    .await
+            }
+            Command::SendRequest {
+                itinerary,
+                content,
+                timeout,
+                response_tx,
+            } => {
+                self.core
+                    .write()
+                    .await
+                    .send_request(itinerary, content, timeout, response_tx)
+                    .await
+            }
+            Command::SendResponse { handle, content } => {
+                self.core
+                    .write()
+                    .await
+                    .send_response(&handle, content)
+                    .await
+            }
             Command::ScheduleTimeout { duration, token } => Ok(self
                 .handle_schedule_timeout(duration, token)
                 .await
@@ -280,6 +460,7 @@ impl Dispatcher {
                 }
                 Ok(commands)
             }
+            Command::Leave(notifier) => self.core.write().await.leave(notifier),
         }
     }
 
@@ -289,6 +470,20 @@ impl Dispatcher {
         let _ = tokio::spawn(self.handle_commands(command));
     }
 
+    // Handles the queued commands of at most the given priority until terminated.
+    async fn run_worker(self: Arc<Self>, lowest: Priority) {
+        let mut cancel_rx = self.cancel_timer_rx.clone();
+
+        while !*cancel_rx.borrow() {
+            let command = tokio::select! {
+                command = self.queue.pop(lowest) => command,
+                _ = cancel_rx.changed() => break,
+            };
+
+            let _ = self.clone().handle_commands(command).await;
+        }
+    }
+
     async fn send_message(
         &self,
         recipients: &[(XorName, SocketAddr)],
@@ -326,6 +521,7 @@ impl Dispatcher {
                             recipient,
                             message
                         );
+                        let _ = self.core.write().await.remove_end_user(&recipient.1);
                         self.send_event(Event::ClientLost(recipient.1)).await;
                     }
                 }
@@ -374,10 +570,18 @@ impl Dispatcher {
         let joining = JoinAsRelocated::new(&self.comm, node, message_rx);
         let (node, section) = joining.run(bootstrap_addrs, genesis_key, details).await?;
 
+        self.comm.metrics().inc(Metric::Relocations, "self");
+
         let mut state = self.core.write().await;
         let event_tx = state.event_tx.clone();
+        let params = *state.params();
+        let bootstrap_cache = state.bootstrap_cache.take();
         let new_keypair = node.keypair.clone();
-        *state = Core::new(node, section, None, event_tx);
+        *state = Core::new(node, section, None, event_tx, params);
+        state.set_metrics(self.comm.metrics().clone());
+        state.set_section_members(self.comm.section_members());
+        state.bootstrap_cache = bootstrap_cache;
+        state.update_bootstrap_cache();
 
         state
             .send_event(Event::Relocated {
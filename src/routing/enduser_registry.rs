@@ -6,41 +6,152 @@
 // KIND, either express or implied. Please review the Licences for the specific language governing
 // permissions and limitations relating to use of the SAFE Network Software.
 
-use crate::error::{Error, Result};
+use super::socket_id::SocketIdKeys;
+use crate::{
+    error::{Error, Result},
+    messages::{ClientIdentity, ClientSignature},
+    rng,
+    section::SectionKeyShare,
+};
+use rand::Rng;
 use sn_messaging::EndUser;
 use std::{
-    collections::{btree_map::Entry, BTreeMap},
+    collections::{BTreeMap, BTreeSet},
     net::SocketAddr,
+    time::Duration,
 };
+use tokio::time::Instant;
 use xor_name::{Prefix, XorName};
 
+// How long a client session lasts without any message from the client.
+const SESSION_IDLE_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+// Maximum number of client sessions. Once reached, the least recently active session is dropped
+// to make room for every new one.
+const MAX_SESSIONS: usize = 10_000;
+// How long a client has to answer the challenge of its handshake.
+const CHALLENGE_TIMEOUT: Duration = Duration::from_secs(30);
+
 pub type SocketId = XorName;
+
+// The clients connected to us. Their socket ids are their encrypted addresses (see `socket_id`),
+// so routing a message to a client takes no state besides the keys, and any elder can do it.
+// The exception are the clients that authenticated with a `ClientHandshake`: their socket ids are
+// derived from their identity instead, to stay the same across their addresses, so only the
+// elder they authenticated with can route messages to them. The bindings aren't shared with the
+// rest of the section, the other elders relay the messages for such clients to all the elders
+// instead.
 pub(crate) struct EndUserRegistry {
-    clients: BTreeMap<SocketAddr, EndUser>,
-    socket_id_mapping: BTreeMap<SocketId, SocketAddr>,
+    keys: SocketIdKeys,
+    sessions: BTreeMap<SocketAddr, Session>,
+    // The sessions ordered by when they were last active, so the idle ones are found without
+    // going through all of them.
+    by_last_active: BTreeSet<(Instant, SocketAddr)>,
+    // Current addresses of the authenticated clients, by socket id.
+    identities: BTreeMap<SocketId, SocketAddr>,
+    // Nonces of the handshakes in progress, with when they were issued.
+    challenges: BTreeMap<SocketAddr, ([u8; 32], Instant)>,
+}
+
+struct Session {
+    end_user: EndUser,
+    identity: Option<ClientIdentity>,
+    last_active: Instant,
+}
+
+impl Session {
+    fn is_expired(&self, now: Instant) -> bool {
+        now.saturating_duration_since(self.last_active) >= SESSION_IDLE_TIMEOUT
+    }
 }
 
 impl EndUserRegistry {
     pub fn new() -> Self {
         Self {
-            clients: BTreeMap::default(),
-            socket_id_mapping: BTreeMap::default(),
+            keys: SocketIdKeys::default(),
+            sessions: BTreeMap::default(),
+            by_last_active: BTreeSet::default(),
+            identities: BTreeMap::default(),
+            challenges: BTreeMap::default(),
         }
     }
 
-    pub fn get_enduser_by_addr(&self, socketaddr: &SocketAddr) -> Option<&EndUser> {
-        self.clients.get(socketaddr)
+    pub fn get_enduser_by_addr(&self, socketaddr: &SocketAddr, now: Instant) -> Option<&EndUser> {
+        self.sessions
+            .get(socketaddr)
+            .filter(|session| !session.is_expired(now))
+            .map(|session| &session.end_user)
+    }
+
+    pub fn get_identity_by_addr(&self, addr: &SocketAddr, now: Instant) -> Option<ClientIdentity> {
+        self.sessions
+            .get(addr)
+            .filter(|session| !session.is_expired(now))
+            .and_then(|session| session.identity)
+    }
+
+    pub fn get_socket_addr(&self, socket_id: SocketId) -> Option<SocketAddr> {
+        self.identities
+            .get(&socket_id)
+            .copied()
+            .or_else(|| self.keys.decrypt(&socket_id))
     }
 
-    pub fn get_socket_addr(&self, socket_id: SocketId) -> Option<&SocketAddr> {
-        self.socket_id_mapping.get(&socket_id)
+    // Whether we started deriving the socket id key for the given section key already.
+    pub fn has_socket_id_key(&self, section_key: &bls::PublicKey) -> bool {
+        self.keys.has_started(section_key)
     }
 
-    pub fn try_add(&mut self, sender: SocketAddr, section_prefix: &Prefix) -> Result<EndUser> {
-        // create a unique socket id from client socket addr
-        let socket_id = XorName::from_content(&[
-            &bincode::serialize(&sender).map_err(|_| Error::FailedSignature)?
-        ]);
+    // Starts deriving the socket id key for our section key. Returns our share of it, to be sent
+    // to the other elders.
+    pub fn start_socket_id_key(&mut self, key_share: &SectionKeyShare) -> bls::SignatureShare {
+        self.keys.start(key_share)
+    }
+
+    pub fn add_socket_id_key_share(
+        &mut self,
+        section_key: bls::PublicKey,
+        index: usize,
+        share: bls::SignatureShare,
+    ) -> Result<()> {
+        self.keys.add_share(section_key, index, share)
+    }
+
+    // The signatures the socket id keys we keep were derived from, with their section keys.
+    pub fn socket_id_key_signatures(&self) -> Vec<(bls::PublicKey, bls::Signature)> {
+        self.keys.signatures()
+    }
+
+    // Adds the socket id keys derived from the given signatures, as older than ours.
+    pub fn insert_socket_id_key_signatures(
+        &mut self,
+        signatures: impl IntoIterator<Item = (bls::PublicKey, bls::Signature)>,
+    ) {
+        self.keys.insert_signatures(signatures)
+    }
+
+    // Starts a session for the client at `sender`, or refreshes its existing one.
+    pub fn try_add(
+        &mut self,
+        sender: SocketAddr,
+        section_prefix: &Prefix,
+        now: Instant,
+    ) -> Result<EndUser> {
+        if let Some(session) = self.sessions.get_mut(&sender) {
+            trace!(
+                "Message from client {}, socket id already exists: {:?}",
+                sender,
+                session.end_user
+            );
+            let _ = self.by_last_active.remove(&(session.last_active, sender));
+            let _ = self.by_last_active.insert((now, sender));
+            session.last_active = now;
+            return Ok(session.end_user);
+        }
+
+        debug!("First message from client {}, creating a socket id", sender);
+
+        // IPv6 addresses don't fit in a socket id, so those clients need a handshake.
+        let socket_id = self.keys.encrypt(&sender).ok_or(Error::InvalidState)?;
 
         // assign a XorName to the end user which belongs to this section's prefix
         // so messages directed to this end user are correctly routed back through us
@@ -49,21 +160,285 @@ impl EndUserRegistry {
         // TODO: we probably should remove the socket_id from the EndUser struct,
         // and pass the socket id separatelly as part of nodes' messages,
         // instead of it being part of the SrcLocation/DstLocation in nodes' messages.
-        // Currently each Elder cannot create a different socket id since that breaks
-        // aggregation of messages when sent to another section with aggregation AtDestination.
         let end_user = EndUser {
             xorname: user_xorname,
             socket_id,
         };
 
-        match self.socket_id_mapping.entry(socket_id) {
-            Entry::Vacant(entry) => {
-                let _ = self.clients.insert(sender, end_user);
-                let _ = entry.insert(sender);
+        self.insert_session(
+            sender,
+            Session {
+                end_user,
+                identity: None,
+                last_active: now,
+            },
+        );
+
+        Ok(end_user)
+    }
+
+    // Issues the challenge for the handshake of the client at `addr`.
+    pub fn start_handshake(&mut self, addr: SocketAddr, now: Instant) -> Result<[u8; 32]> {
+        self.challenges
+            .retain(|_, (_, issued)| now.saturating_duration_since(*issued) < CHALLENGE_TIMEOUT);
+        if self.challenges.len() >= MAX_SESSIONS && !self.challenges.contains_key(&addr) {
+            return Err(Error::InvalidState);
+        }
+
+        let nonce = rng::new().gen();
+        let _ = self.challenges.insert(addr, (nonce, now));
+        Ok(nonce)
+    }
+
+    // Verifies the proof of the client at `addr` and, if valid, starts an authenticated session
+    // for it, ending the one it had at another address, if any.
+    pub fn complete_handshake(
+        &mut self,
+        addr: SocketAddr,
+        identity: ClientIdentity,
+        signature: &ClientSignature,
+        our_name: &XorName,
+        section_prefix: &Prefix,
+        now: Instant,
+    ) -> Result<EndUser> {
+        let nonce = match self.challenges.remove(&addr) {
+            Some((nonce, issued)) if now.saturating_duration_since(issued) < CHALLENGE_TIMEOUT => {
+                nonce
+            }
+            _ => return Err(Error::InvalidState),
+        };
+
+        if !identity.verify(signature, &nonce, our_name) {
+            return Err(Error::FailedSignature);
+        }
+
+        let socket_id = identity.name();
+        let end_user = EndUser {
+            xorname: section_prefix.substituted_in(socket_id),
+            socket_id,
+        };
+
+        if let Some(old_addr) = self.identities.insert(socket_id, addr) {
+            if old_addr != addr {
+                debug!("Client {:?} moved from {} to {}", identity, old_addr, addr);
+                let _ = self.remove_session(&old_addr);
             }
-            Entry::Occupied(_) => (),
         }
+        self.insert_session(
+            addr,
+            Session {
+                end_user,
+                identity: Some(identity),
+                last_active: now,
+            },
+        );
 
         Ok(end_user)
     }
+
+    // Ends the session of the client at `addr`, e.g. because it can no longer be reached.
+    pub fn remove(&mut self, addr: &SocketAddr) -> Option<EndUser> {
+        let session = self.remove_session(addr)?;
+        if session.identity.is_some()
+            && self.identities.get(&session.end_user.socket_id) == Some(addr)
+        {
+            let _ = self.identities.remove(&session.end_user.socket_id);
+        }
+        Some(session.end_user)
+    }
+
+    // Removes the sessions that have been idle for too long and, if a new session for `incoming`
+    // would exceed `MAX_SESSIONS`, the least recently active one. Returns the removed sessions.
+    pub fn remove_expired(
+        &mut self,
+        incoming: &SocketAddr,
+        now: Instant,
+    ) -> Vec<(SocketAddr, EndUser)> {
+        let mut expired: Vec<_> = self
+            .by_last_active
+            .iter()
+            .take_while(|(last_active, _)| {
+                now.saturating_duration_since(*last_active) >= SESSION_IDLE_TIMEOUT
+            })
+            .map(|(_, addr)| *addr)
+            .collect();
+
+        if self.sessions.len() - expired.len() >= MAX_SESSIONS
+            && !self.sessions.contains_key(incoming)
+        {
+            if let Some((_, addr)) = self.by_last_active.iter().nth(expired.len()) {
+                expired.push(*addr);
+            }
+        }
+
+        expired
+            .into_iter()
+            .filter_map(|addr| self.remove(&addr).map(|end_user| (addr, end_user)))
+            .collect()
+    }
+
+    fn insert_session(&mut self, addr: SocketAddr, session: Session) {
+        let last_active = session.last_active;
+        if let Some(old) = self.sessions.insert(addr, session) {
+            let _ = self.by_last_active.remove(&(old.last_active, addr));
+        }
+        let _ = self.by_last_active.insert((last_active, addr));
+    }
+
+    fn remove_session(&mut self, addr: &SocketAddr) -> Option<Session> {
+        let session = self.sessions.remove(addr)?;
+        let _ = self.by_last_active.remove(&(session.last_active, *addr));
+        Some(session)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ed25519, messages::ClientHandshake, section::test_utils::gen_addr};
+
+    fn registry() -> EndUserRegistry {
+        let secret_key_set = bls::SecretKeySet::random(0, &mut rand::thread_rng());
+        let mut registry = EndUserRegistry::new();
+        let _ = registry.start_socket_id_key(&SectionKeyShare {
+            public_key_set: secret_key_set.public_keys(),
+            index: 0,
+            secret_key_share: secret_key_set.secret_key_share(0),
+        });
+        registry
+    }
+
+    #[test]
+    fn idle_expiry() -> Result<()> {
+        let mut registry = registry();
+        let prefix = Prefix::default();
+        let addr = gen_addr();
+        let now = Instant::now();
+
+        let end_user = registry.try_add(addr, &prefix, now)?;
+        assert_eq!(registry.get_socket_addr(end_user.socket_id), Some(addr));
+
+        // Activity keeps the session alive.
+        let now = now + SESSION_IDLE_TIMEOUT / 2;
+        assert_eq!(registry.try_add(addr, &prefix, now)?, end_user);
+
+        let now = now + SESSION_IDLE_TIMEOUT / 2;
+        assert!(registry.remove_expired(&gen_addr(), now).is_empty());
+
+        let now = now + SESSION_IDLE_TIMEOUT;
+        assert_eq!(
+            registry.remove_expired(&gen_addr(), now),
+            vec![(addr, end_user)]
+        );
+        assert!(registry.get_enduser_by_addr(&addr, now).is_none());
+
+        // The socket id stays valid, as it doesn't depend on the session.
+        assert_eq!(registry.get_socket_addr(end_user.socket_id), Some(addr));
+        assert_eq!(registry.try_add(addr, &prefix, now)?, end_user);
+
+        Ok(())
+    }
+
+    #[test]
+    fn remove() -> Result<()> {
+        let mut registry = registry();
+        let addr = gen_addr();
+        let now = Instant::now();
+
+        let end_user = registry.try_add(addr, &Prefix::default(), now)?;
+        assert_eq!(registry.remove(&addr), Some(end_user));
+        assert!(registry.get_enduser_by_addr(&addr, now).is_none());
+        assert_eq!(registry.remove(&addr), None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn evict_least_recently_active() -> Result<()> {
+        let mut registry = registry();
+        let prefix = Prefix::default();
+        let start = Instant::now();
+
+        let addrs: Vec<_> = (0..MAX_SESSIONS).map(|_| gen_addr()).collect();
+        for (index, addr) in addrs.iter().enumerate() {
+            let _ =
+                registry.try_add(*addr, &prefix, start + Duration::from_millis(index as u64))?;
+        }
+
+        // The oldest session becomes the most recently active one.
+        let now = start + Duration::from_secs(60);
+        let _ = registry.try_add(addrs[0], &prefix, now)?;
+        assert!(registry.remove_expired(&addrs[1], now).is_empty());
+
+        let evicted = registry.remove_expired(&gen_addr(), now);
+        assert_eq!(evicted.len(), 1);
+        assert_eq!(evicted[0].0, addrs[1]);
+        assert!(registry.get_enduser_by_addr(&addrs[0], now).is_some());
+
+        Ok(())
+    }
+
+    #[test]
+    fn handshake() -> Result<()> {
+        let mut registry = registry();
+        let prefix = Prefix::default();
+        let our_name = XorName::random();
+        let keypair = ed25519::Keypair::generate(&mut rand::thread_rng());
+        let now = Instant::now();
+
+        let prove = |nonce| match ClientHandshake::prove_ed25519(&keypair, &nonce, &our_name) {
+            ClientHandshake::Proof {
+                identity,
+                signature,
+            } => (identity, signature),
+            _ => unreachable!(),
+        };
+
+        let addr = gen_addr();
+        let (identity, signature) = prove(registry.start_handshake(addr, now)?);
+        let end_user =
+            registry.complete_handshake(addr, identity, &signature, &our_name, &prefix, now)?;
+        assert_eq!(registry.get_socket_addr(end_user.socket_id), Some(addr));
+        assert_eq!(registry.get_identity_by_addr(&addr, now), Some(identity));
+        assert_eq!(registry.try_add(addr, &prefix, now)?, end_user);
+
+        // The challenge can't be reused.
+        assert!(registry
+            .complete_handshake(addr, identity, &signature, &our_name, &prefix, now)
+            .is_err());
+
+        // Reconnecting from another address keeps the same user.
+        let new_addr = gen_addr();
+        let (identity, signature) = prove(registry.start_handshake(new_addr, now)?);
+        assert_eq!(
+            registry.complete_handshake(new_addr, identity, &signature, &our_name, &prefix, now)?,
+            end_user
+        );
+        assert_eq!(registry.get_socket_addr(end_user.socket_id), Some(new_addr));
+        assert!(registry.get_enduser_by_addr(&addr, now).is_none());
+
+        // A proof for another node's challenge is rejected.
+        let other_addr = gen_addr();
+        let nonce = registry.start_handshake(other_addr, now)?;
+        let proof = ClientHandshake::prove_ed25519(&keypair, &nonce, &XorName::random());
+        if let ClientHandshake::Proof {
+            identity,
+            signature,
+        } = proof
+        {
+            assert!(registry
+                .complete_handshake(other_addr, identity, &signature, &our_name, &prefix, now)
+                .is_err());
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn no_socket_ids_without_key() {
+        let mut registry = EndUserRegistry::new();
+        assert!(registry
+            .try_add(gen_addr(), &Prefix::default(), Instant::now())
+            .is_err());
+    }
 }
@@ -7,20 +7,119 @@
 // permissions and limitations relating to use of the SAFE Network Software.
 
 use crate::event::Event;
-use std::fmt::{self, Debug, Formatter};
-use tokio::sync::mpsc;
+use sn_messaging::DstLocation;
+use std::{
+    fmt::{self, Debug, Formatter},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex, PoisonError,
+    },
+};
+use tokio::sync::mpsc::{
+    self,
+    error::{TryRecvError, TrySendError},
+};
+
+/// Selects which events a subscriber receives. See `Routing::subscribe`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum EventFilter {
+    /// Every event.
+    All,
+    /// Changes to the membership of our section: `MemberJoined`, `MemberLeft`, `EldersChanged`,
+    /// `SectionSplit` and `AdultsChanged`.
+    Membership,
+    /// Our own relocation: `RelocationStarted` and `Relocated`.
+    Relocation,
+    /// Every `MessageReceived`, `RequestReceived` and `MessageDelivered`.
+    Messages,
+    /// `MessageReceived` and `RequestReceived` sent to the given destination only.
+    MessagesTo(DstLocation),
+    /// Messages from and connectivity of clients: `ClientMsgReceived`, `ClientLost` and
+    /// `ClientSessionExpired`.
+    Clients,
+    /// Events matching any of the given filters.
+    Any(Vec<EventFilter>),
+}
+
+impl EventFilter {
+    /// Returns whether the given event passes this filter.
+    pub fn matches(&self, event: &Event) -> bool {
+        match self {
+            Self::All => true,
+            Self::Membership => matches!(
+                event,
+                Event::MemberJoined { .. }
+                    | Event::MemberLeft { .. }
+                    | Event::EldersChanged { .. }
+                    | Event::SectionSplit { .. }
+                    | Event::AdultsChanged { .. }
+            ),
+            Self::Relocation => matches!(
+                event,
+                Event::RelocationStarted { .. } | Event::Relocated { .. }
+            ),
+            Self::Messages => matches!(
+                event,
+                Event::MessageReceived { .. }
+                    | Event::RequestReceived { .. }
+                    | Event::MessageDelivered { .. }
+            ),
+            Self::MessagesTo(location) => matches!(
+                event,
+                Event::MessageReceived { dst, .. } | Event::RequestReceived { dst, .. }
+                    if dst == location
+            ),
+            Self::Clients => matches!(
+                event,
+                Event::ClientMsgReceived { .. }
+                    | Event::ClientLost(_)
+                    | Event::ClientSessionExpired { .. }
+            ),
+            Self::Any(filters) => filters.iter().any(|filter| filter.matches(event)),
+        }
+    }
+}
+
+impl Default for EventFilter {
+    fn default() -> Self {
+        Self::All
+    }
+}
+
 /// Stream of routing node events
+///
+/// Every stream has its own buffer. If the consumer doesn't keep up and the buffer fills up, the
+/// routing core doesn't wait for it but drops the events instead. The stream then yields
+/// `Event::Lagged` with the number of dropped events in their place, i.e. after the events buffered
+/// before them and before the ones that came after them.
 pub struct EventStream {
     events_rx: mpsc::Receiver<Event>,
+    lagged: Arc<AtomicU64>,
+    subscribers: Arc<Mutex<Vec<Subscriber>>>,
 }
 
 impl EventStream {
-    pub(crate) fn new(events_rx: mpsc::Receiver<Event>) -> Self {
-        Self { events_rx }
-    }
-
     /// Returns next event
     pub async fn next(&mut self) -> Option<Event> {
+        {
+            // Hold the lock for the sender not to drop any event in between checking the buffer
+            // and the dropped events, which would then be reported ahead of buffered ones.
+            let _subscribers = self
+                .subscribers
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner);
+            match self.events_rx.try_recv() {
+                Ok(event) => return Some(event),
+                Err(TryRecvError::Disconnected) => return None,
+                Err(TryRecvError::Empty) => (),
+            }
+
+            let lagged = self.lagged.swap(0, Ordering::Relaxed);
+            if lagged > 0 {
+                return Some(Event::Lagged(lagged));
+            }
+        }
+
         self.events_rx.recv().await
     }
 }
@@ -30,3 +129,170 @@ impl Debug for EventStream {
         write!(f, "EventStream")
     }
 }
+
+// Creates an event sender together with a stream receiving all of its events. `buffer_size` is
+// also used for the streams subscribed later on.
+pub(crate) fn channel(buffer_size: usize) -> (EventSender, EventStream) {
+    let sender = EventSender {
+        subscribers: Arc::new(Mutex::new(Vec::new())),
+        buffer_size: buffer_size.max(1),
+    };
+    let stream = sender.subscribe(EventFilter::All);
+
+    (sender, stream)
+}
+
+// Sending half of the event streams. Hands every event over to all the subscribers whose filter
+// it matches, without ever waiting for them.
+#[derive(Clone)]
+pub(crate) struct EventSender {
+    subscribers: Arc<Mutex<Vec<Subscriber>>>,
+    buffer_size: usize,
+}
+
+impl EventSender {
+    pub fn subscribe(&self, filter: EventFilter) -> EventStream {
+        let (events_tx, events_rx) = mpsc::channel(self.buffer_size);
+        let lagged = Arc::new(AtomicU64::new(0));
+
+        self.subscribers
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push(Subscriber {
+                filter,
+                events_tx,
+                lagged: lagged.clone(),
+            });
+
+        EventStream {
+            events_rx,
+            lagged,
+            subscribers: self.subscribers.clone(),
+        }
+    }
+
+    // Sends the event to the matching subscribers. Returns `false` if there are no subscribers
+    // left.
+    pub fn send(&self, event: Event) -> bool {
+        let mut subscribers = self
+            .subscribers
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        subscribers.retain(|subscriber| !subscriber.events_tx.is_closed());
+
+        for subscriber in subscribers.iter() {
+            let matches = subscriber.filter.matches(&event);
+
+            // Report the events dropped before this one first, as soon as there's room for it.
+            let lagged = subscriber.lagged.load(Ordering::Relaxed);
+            if lagged > 0 {
+                match subscriber.events_tx.try_send(Event::Lagged(lagged)) {
+                    Ok(()) => {
+                        let _ = subscriber.lagged.fetch_sub(lagged, Ordering::Relaxed);
+                    }
+                    Err(TrySendError::Full(_)) => {
+                        if matches {
+                            let _ = subscriber.lagged.fetch_add(1, Ordering::Relaxed);
+                        }
+                        continue;
+                    }
+                    Err(TrySendError::Closed(_)) => continue,
+                }
+            }
+
+            if !matches {
+                continue;
+            }
+
+            match subscriber.events_tx.try_send(event.clone()) {
+                Ok(()) | Err(TrySendError::Closed(_)) => (),
+                Err(TrySendError::Full(_)) => {
+                    let _ = subscriber.lagged.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+        }
+
+        !subscribers.is_empty()
+    }
+}
+
+struct Subscriber {
+    filter: EventFilter,
+    events_tx: mpsc::Sender<Event>,
+    lagged: Arc<AtomicU64>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_matches::assert_matches;
+    use xor_name::XorName;
+
+    #[tokio::test]
+    async fn filtered_subscribers() {
+        let (sender, mut all) = channel(10);
+        let mut membership = sender.subscribe(EventFilter::Membership);
+        let dst = DstLocation::Section(XorName::random());
+        let mut messages = sender.subscribe(EventFilter::MessagesTo(dst));
+
+        assert!(sender.send(Event::MemberLeft {
+            name: XorName::random(),
+            age: 10,
+        }));
+        assert!(sender.send(Event::RestartRequired));
+
+        assert_matches!(all.next().await, Some(Event::MemberLeft { .. }));
+        assert_matches!(all.next().await, Some(Event::RestartRequired));
+        assert_matches!(membership.next().await, Some(Event::MemberLeft { .. }));
+        assert!(membership.events_rx.try_recv().is_err());
+        assert!(messages.events_rx.try_recv().is_err());
+
+        drop(all);
+        drop(membership);
+        drop(messages);
+        assert!(!sender.send(Event::RestartRequired));
+    }
+
+    #[tokio::test]
+    async fn slow_subscriber_lags() {
+        let (sender, mut stream) = channel(2);
+
+        for _ in 0..5 {
+            assert!(sender.send(Event::RestartRequired));
+        }
+
+        // The dropped events are reported after the ones buffered before them.
+        assert_matches!(stream.next().await, Some(Event::RestartRequired));
+        assert_matches!(stream.next().await, Some(Event::RestartRequired));
+        assert_matches!(stream.next().await, Some(Event::Lagged(3)));
+
+        assert!(sender.send(Event::RestartRequired));
+        assert_matches!(stream.next().await, Some(Event::RestartRequired));
+    }
+
+    #[tokio::test]
+    async fn lagged_in_order() {
+        let (sender, mut stream) = channel(2);
+
+        for age in 0..3 {
+            assert!(sender.send(member_left(age)));
+        }
+        assert_matches!(stream.next().await, Some(Event::MemberLeft { age: 0, .. }));
+
+        // Once there is room again, the dropped event is reported ahead of the next ones.
+        assert!(sender.send(member_left(3)));
+        assert!(sender.send(member_left(4)));
+
+        assert_matches!(stream.next().await, Some(Event::MemberLeft { age: 1, .. }));
+        assert_matches!(stream.next().await, Some(Event::Lagged(1)));
+        assert_matches!(stream.next().await, Some(Event::Lagged(2)));
+        assert!(stream.events_rx.try_recv().is_err());
+    }
+
+    fn member_left(age: u8) -> Event {
+        Event::MemberLeft {
+            name: XorName::random(),
+            age,
+        }
+    }
+}
@@ -10,49 +10,86 @@ pub(crate) mod command;
 
 mod bootstrap;
 mod comm;
+mod command_queue;
+mod compression;
 mod core;
+mod debug_snapshot;
 mod dispatcher;
 mod enduser_registry;
 mod event_stream;
+mod persistence;
+mod rate_limit;
+mod socket_id;
 mod split_barrier;
 #[cfg(test)]
 pub(crate) mod tests;
+mod topology;
+pub(crate) mod transfer;
+pub(crate) mod transport;
 
-pub use self::event_stream::EventStream;
+pub(crate) use self::core::{KEY_CACHE_SIZE, RESOURCE_PROOF_DATA_SIZE, RESOURCE_PROOF_DIFFICULTY};
 use self::{
+    bootstrap::BootstrapCache,
     comm::{Comm, ConnectionEvent},
     command::Command,
     core::Core,
     dispatcher::Dispatcher,
+    persistence::NodeSnapshot,
+    rate_limit::MessageClass,
+};
+pub use self::{
+    bootstrap::{JoinPolicy, JoinProgress, JoinResponseKind},
+    command_queue::CommandLimits,
+    compression::CompressionConfig,
+    debug_snapshot::{
+        DebugSnapshot, DkgSessionSnapshot, MemberSnapshot, PeerSnapshot, RelocateStatus,
+        SectionSnapshot,
+    },
+    event_stream::{EventFilter, EventStream},
+    rate_limit::{RateLimit, RateLimits},
+    topology::{SectionRelation, Topology, TopologySection},
+    transport::{MemoryNetwork, TransportBackend},
 };
 use crate::{
+    delivery::{Delivery, DeliveryPolicy},
     ed25519,
     error::Result,
     event::{Elders, Event, NodeElderChange},
-    messages::RoutingMsgUtils,
-    network::NetworkUtils,
+    messages::{ClientHandshake, RoutingMsgUtils, TraceHop},
+    metrics::{Metric, Metrics},
+    network::{NetworkStats, NetworkUtils},
     node::Node,
     peer::PeerUtils,
+    request::{RequestHandle, Response},
+    rng,
     section::{SectionAuthorityProviderUtils, SectionUtils},
-    Error, TransportConfig, MIN_ADULT_AGE,
+    Error, NetworkParams, TransportConfig,
 };
 use bytes::Bytes;
 use ed25519_dalek::{Keypair, PublicKey, Signature, Signer, KEYPAIR_LENGTH};
 use itertools::Itertools;
+use rand::Rng;
 use secured_linked_list::SecuredLinkedList;
 use sn_messaging::{
     client::ClientMsg,
-    node::{Peer, RoutingMsg},
-    DestInfo, DstLocation, EndUser, Itinerary, MessageType, SectionAuthorityProvider, WireMsg,
+    node::{Peer, RoutingMsg, Variant},
+    DestInfo, DstLocation, EndUser, Itinerary, MessageId, MessageKind, MessageType,
+    SectionAuthorityProvider, WireMsg,
 };
 use std::{
     collections::BTreeSet,
     fmt::{self, Debug, Formatter},
+    iter,
     net::SocketAddr,
+    path::{Path, PathBuf},
     sync::Arc,
+    time::Duration,
 };
 
-use tokio::{sync::mpsc, task};
+use tokio::{
+    sync::{mpsc, oneshot},
+    task, time,
+};
 use xor_name::{Prefix, XorName};
 
 /// Routing configuration.
@@ -64,6 +101,38 @@ pub struct Config {
     pub keypair: Option<Keypair>,
     /// Configuration for the underlying network transport.
     pub transport_config: TransportConfig,
+    /// Transport to communicate with the other nodes over. Of `transport_config`, only the
+    /// `local_port` and the `hard_coded_contacts` apply to transports other than QUIC.
+    pub transport: TransportBackend,
+    /// Path to a state snapshot previously written by `Routing::snapshot`. If set, the node
+    /// resumes from that state instead of joining as a new node, and `first` and `keypair` are
+    /// ignored.
+    pub restore_from: Option<PathBuf>,
+    /// Parameters of the network. Must be the same on every node of the network, so the joining
+    /// nodes need to use the parameters the genesis node was started with.
+    pub network_params: NetworkParams,
+    /// Number of events each event stream buffers for its consumer, 256 by default. Once a
+    /// stream's buffer is full, further events are dropped for that stream and it yields
+    /// `Event::Lagged` in their place. The events come in bursts on churn, e.g. a split raises
+    /// membership, elder and adult changes all at once, so consumers that don't handle their events
+    /// right away should raise it.
+    pub event_buffer_size: usize,
+    /// How long to keep trying to join the network and how often to retry.
+    pub join_policy: JoinPolicy,
+    /// If set, the steps of joining the network are reported to this channel, e.g. to find out
+    /// where joining gets stuck.
+    pub join_progress: Option<mpsc::UnboundedSender<JoinProgress>>,
+    /// Path to the bootstrap cache file. If set, the addresses of the elders this node learns
+    /// about are saved there, and the next time the node joins it tries them alongside the
+    /// hard-coded contacts of `transport_config`. The addresses are dropped once the node finds
+    /// itself in a network other than the one they were learned from.
+    pub bootstrap_cache: Option<PathBuf>,
+    /// Limits on the rate of the messages this node handles from any single peer.
+    pub rate_limits: RateLimits,
+    /// Limits on the commands this node queues and handles concurrently.
+    pub command_limits: CommandLimits,
+    /// Compression of the messages this node sends to other nodes.
+    pub compression: CompressionConfig,
 }
 
 impl Default for Config {
@@ -72,6 +141,16 @@ impl Default for Config {
             first: false,
             keypair: None,
             transport_config: TransportConfig::default(),
+            transport: TransportBackend::default(),
+            restore_from: None,
+            network_params: NetworkParams::default(),
+            event_buffer_size: EVENT_CHANNEL_SIZE,
+            join_policy: JoinPolicy::default(),
+            join_progress: None,
+            bootstrap_cache: None,
+            rate_limits: RateLimits::default(),
+            command_limits: CommandLimits::default(),
+            compression: CompressionConfig::default(),
         }
     }
 }
@@ -87,7 +166,13 @@ pub struct Routing {
     dispatcher: Arc<Dispatcher>,
 }
 
-static EVENT_CHANNEL_SIZE: usize = 20;
+static EVENT_CHANNEL_SIZE: usize = 256;
+
+// Maximum number of contacts to send the initial join requests to at once.
+const MAX_JOIN_CONTACTS: usize = 4;
+
+// How long to wait for our section to agree on our leave request.
+const LEAVE_TIMEOUT: Duration = Duration::from_secs(30);
 
 impl Routing {
     ////////////////////////////////////////////////////////////////////////////
@@ -96,28 +181,90 @@ impl Routing {
 
     /// Creates new node using the given config and bootstraps it to the network.
     ///
-    /// NOTE: It's not guaranteed this function ever returns. This can happen due to messages being
-    /// lost in transit during bootstrapping, or other reasons. It's the responsibility of the
-    /// caller to handle this case, for example by using a timeout.
+    /// NOTE: Unless `Config::join_policy` sets a deadline, it's not guaranteed this function ever
+    /// returns. This can happen due to the network never accepting this node, or other reasons.
+    /// With a deadline, `Error::BootstrapTimeout` is returned once it passes.
     pub async fn new(config: Config) -> Result<(Self, EventStream)> {
+        config.network_params.validate()?;
+        config.join_policy.validate()?;
+        config.rate_limits.validate()?;
+
+        let params = config.network_params;
         let keypair = config.keypair.unwrap_or_else(|| {
-            ed25519::gen_keypair(&Prefix::default().range_inclusive(), MIN_ADULT_AGE)
+            ed25519::gen_keypair(&Prefix::default().range_inclusive(), params.min_adult_age())
         });
         let node_name = ed25519::name(&keypair.public);
 
-        let (event_tx, event_rx) = mpsc::channel(EVENT_CHANNEL_SIZE);
+        let (event_tx, event_stream) = event_stream::channel(config.event_buffer_size);
         let (connection_event_tx, mut connection_event_rx) = mpsc::channel(1);
+        let mut bootstrap_cache = config.bootstrap_cache.as_deref().map(BootstrapCache::load);
+        if let Some(cache) = &mut bootstrap_cache {
+            cache.start_saving();
+        }
+
+        let (mut state, comm, backlog) = if let Some(path) = &config.restore_from {
+            let snapshot = NodeSnapshot::load(path)?;
+            let keypair = snapshot.keypair()?;
+            let node_name = ed25519::name(&keypair.public);
+
+            info!(
+                "{} Restoring node state from {}.",
+                node_name,
+                path.display()
+            );
 
-        let (state, comm, backlog) = if config.first {
+            // Try to come back on the same port, so the other nodes can still reach us.
+            let mut transport_config = config.transport_config;
+            if transport_config.local_port.is_none() {
+                transport_config.local_port = Some(snapshot.addr().port());
+            }
+
+            // The network parameters are fixed for the lifetime of the network, so the ones we ran
+            // with before take precedence over the configured ones.
+            let params = *snapshot.params();
+            if params != config.network_params {
+                warn!(
+                    "Configured network parameters differ from the restored ones, using {:?}",
+                    params
+                );
+            }
+
+            let joins_allowed = snapshot.joins_allowed();
+            let socket_id_keys = snapshot.socket_id_keys().to_vec();
+            let (section, network, key_shares) = snapshot.into_parts()?;
+
+            let comm = Comm::with_backend(&config.transport, transport_config, connection_event_tx)
+                .await?;
+            let node = Node::new(keypair, comm.our_connection_info());
+            let backlog =
+                persistence::revalidate(&node, &section, &comm, &mut connection_event_rx).await?;
+            let state = Core::restore(
+                node,
+                section,
+                network,
+                key_shares,
+                socket_id_keys,
+                joins_allowed,
+                event_tx,
+                params,
+            );
+
+            (state, comm, backlog)
+        } else if config.first {
             // Genesis node having a fix age of 255.
             let keypair = ed25519::gen_keypair(&Prefix::default().range_inclusive(), 255);
             let node_name = ed25519::name(&keypair.public);
 
             info!("{} Starting a new network as the genesis node.", node_name);
 
-            let comm = Comm::new(config.transport_config, connection_event_tx).await?;
+            let comm = Comm::with_backend(
+                &config.transport,
+                config.transport_config,
+                connection_event_tx,
+            )
+            .await?;
             let node = Node::new(keypair, comm.our_connection_info());
-            let state = Core::first_node(node, event_tx)?;
+            let state = Core::first_node(node, event_tx, params)?;
 
             let section = state.section();
 
@@ -139,20 +286,64 @@ impl Routing {
             (state, comm, vec![])
         } else {
             info!("{} Bootstrapping a new node.", node_name);
+
+            // Besides the one the transport bootstraps to, race our join requests to a few more of
+            // the known contacts, in case some of them are stale.
+            let mut transport_config = config.transport_config;
+            let mut contacts: Vec<_> = transport_config
+                .hard_coded_contacts
+                .iter()
+                .copied()
+                .collect();
+            if let Some(cache) = &bootstrap_cache {
+                contacts.extend(cache.contacts());
+                transport_config
+                    .hard_coded_contacts
+                    .extend(cache.contacts());
+            }
+
             let (comm, bootstrap_addr) =
-                Comm::bootstrap(config.transport_config, connection_event_tx).await?;
+                Comm::bootstrap(&config.transport, transport_config, connection_event_tx).await?;
+            let bootstrap_addrs = iter::once(bootstrap_addr)
+                .chain(contacts)
+                .unique()
+                .take(MAX_JOIN_CONTACTS)
+                .collect();
+
             let node = Node::new(keypair, comm.our_connection_info());
-            let (node, section, backlog) =
-                bootstrap::join(node, &comm, &mut connection_event_rx, bootstrap_addr).await?;
-            let state = Core::new(node, section, None, event_tx);
+            let (node, section, backlog) = bootstrap::join(
+                node,
+                &comm,
+                &mut connection_event_rx,
+                bootstrap_addrs,
+                &params,
+                &config.join_policy,
+                config.join_progress,
+            )
+            .await?;
+            let state = Core::new(node, section, None, event_tx, params);
 
             (state, comm, backlog)
         };
 
+        comm.set_rate_limits(config.rate_limits);
+        comm.set_compression(config.compression);
+        state.set_metrics(comm.metrics().clone());
+        state.set_section_members(comm.section_members());
+        state.bootstrap_cache = bootstrap_cache;
+        state.update_bootstrap_cache();
+        // A restored elder that didn't derive the socket id key of our current section key yet
+        // resumes doing so.
+        let initial_commands = state.share_socket_id_key()?;
+
         let dispatcher = Arc::new(Dispatcher::new(state, comm));
-        let event_stream = EventStream::new(event_rx);
+        dispatcher.start(config.command_limits);
         info!("{} Bootstrapped!", node_name);
 
+        for command in initial_commands {
+            dispatcher.clone().handle_commands(command).await?;
+        }
+
         // Process message backlog
         for (message, sender, dest_info) in backlog {
             dispatcher
@@ -176,6 +367,57 @@ impl Routing {
         Ok((routing, event_stream))
     }
 
+    /// Returns a new stream of the events passing the given filter, e.g. only the membership
+    /// changes or only the messages sent to a particular destination.
+    ///
+    /// The stream is independent of the one returned from `Routing::new` and of any other
+    /// subscription: it receives the events emitted from now on, has its own buffer of
+    /// `Config::event_buffer_size` events, and lagging behind affects neither the other streams nor
+    /// the routing itself.
+    pub async fn subscribe(&self, filter: EventFilter) -> EventStream {
+        self.dispatcher.core.read().await.event_tx.subscribe(filter)
+    }
+
+    /// Returns the metrics of this node: messages handled, send outcomes, duplicate messages,
+    /// DKG sessions, agreements, relocations and join rejections. The returned handle stays live,
+    /// see `Metrics::to_prometheus` to render it.
+    pub fn metrics(&self) -> Metrics {
+        self.dispatcher.comm.metrics().clone()
+    }
+
+    /// Returns statistics about the network as seen by this node: the sections and elders we know
+    /// of and estimates of the total number of elders and nodes.
+    pub async fn network_stats(&self) -> NetworkStats {
+        self.dispatcher.core.read().await.network_stats()
+    }
+
+    /// Returns a view of the current routing state - our section and its members, the other
+    /// sections we know of, the ongoing DKG sessions and splits, etc. - for diagnostics. It is
+    /// serialisable, so it can be dumped e.g. as JSON.
+    pub async fn debug_snapshot(&self) -> DebugSnapshot {
+        self.dispatcher.core.read().await.debug_snapshot()
+    }
+
+    /// Returns this node's view of the network: our section and all the other sections we know
+    /// of, with their elders and keys. It can be rendered with `Topology::to_dot` or
+    /// `Topology::to_json` for visualisation.
+    pub async fn topology(&self) -> Topology {
+        self.dispatcher.core.read().await.topology()
+    }
+
+    /// Writes the current routing state to `path`, so the node can later resume from it by
+    /// setting `Config::restore_from`.
+    ///
+    /// The snapshot contains the secret keys of this node, so it should be stored securely.
+    pub async fn snapshot(&self, path: &Path) -> Result<()> {
+        self.dispatcher
+            .core
+            .read()
+            .await
+            .persistent_state()
+            .save(path)
+    }
+
     /// Sets the JoinsAllowed flag.
     pub async fn set_joins_allowed(&self, joins_allowed: bool) -> Result<()> {
         let command = Command::SetJoinsAllowed(joins_allowed);
@@ -192,6 +434,29 @@ impl Routing {
         self.dispatcher.clone().handle_commands(command).await
     }
 
+    /// Leaves the network gracefully.
+    ///
+    /// Asks the elders of our section to vote this node offline and waits until they agree on it,
+    /// then shuts the node down. This way the section doesn't have to detect the departure via
+    /// lost connections. If there is no agreement within a timeout, the node is shut down anyway
+    /// and `Error::LeaveTimeout` is returned.
+    pub async fn leave(&self) -> Result<()> {
+        let (notifier, agreed) = oneshot::channel();
+        self.dispatcher
+            .clone()
+            .handle_commands(Command::Leave(notifier))
+            .await?;
+
+        let result = time::timeout(LEAVE_TIMEOUT, agreed).await;
+        self.dispatcher.terminate();
+
+        match result {
+            Ok(Ok(())) => Ok(()),
+            Ok(Err(_)) => Err(Error::InvalidState),
+            Err(_) => Err(Error::LeaveTimeout),
+        }
+    }
+
     /// Signals the Elders of our section to test connectivity to a node.
     pub async fn start_connectivity_test(&self, name: XorName) -> Result<()> {
         let command = Command::StartConnectivityTest(name);
@@ -297,12 +562,9 @@ impl Routing {
 
     /// Returns the information of all the current section adults.
     pub async fn our_adults(&self) -> Vec<Peer> {
-        self.dispatcher
-            .core
-            .read()
-            .await
-            .section()
-            .adults()
+        let core = self.dispatcher.core.read().await;
+        core.section()
+            .adults(core.params().min_age)
             .copied()
             .collect()
     }
@@ -366,13 +628,7 @@ impl Routing {
     ) -> Result<()> {
         if let DstLocation::EndUser(EndUser { socket_id, xorname }) = itinerary.dst {
             if self.our_prefix().await.matches(&xorname) {
-                let addr = self
-                    .dispatcher
-                    .core
-                    .read()
-                    .await
-                    .get_socket_addr(socket_id)
-                    .copied();
+                let addr = self.dispatcher.core.read().await.get_socket_addr(socket_id);
 
                 if let Some(socket_addr) = addr {
                     debug!("Sending client msg to {:?}", socket_addr);
@@ -399,6 +655,117 @@ impl Routing {
         self.dispatcher.clone().handle_commands(command).await
     }
 
+    /// Sends a message like `send_message`, asking the destination to acknowledge it with a
+    /// receipt, signed by the destination node or, once aggregated from the shares of its elders,
+    /// by the destination section. The message is sent again whenever its receipt doesn't arrive
+    /// in time, as set by `policy`. The destination hands it over to the upper layers only once.
+    ///
+    /// Once the receipt arrives, `Event::MessageDelivered` is raised and the returned `Delivery`
+    /// resolves.
+    ///
+    /// Only messages from this node, i.e. with `SrcLocation::Node`, are supported.
+    pub async fn send_message_with_receipt(
+        &self,
+        itinerary: Itinerary,
+        content: Bytes,
+        policy: DeliveryPolicy,
+    ) -> Result<Delivery> {
+        let msg_id = MessageId::from_content(&rng::new().gen::<[u8; 32]>())?;
+        let (receipt_tx, receipt_rx) = oneshot::channel();
+        let command = Command::SendMessageWithReceipt {
+            itinerary,
+            content,
+            policy,
+            msg_id,
+            receipt_tx,
+        };
+        self.dispatcher.clone().handle_commands(command).await?;
+
+        Ok(Delivery::new(msg_id, receipt_rx))
+    }
+
+    /// Sends a message of any size to the node `dst`, in chunks sent as separate messages, each
+    /// checked for integrity by the recipient, as is the whole content once reassembled. Only a
+    /// few chunks are in flight at any time, more are sent as the recipient acknowledges them.
+    /// Returns once all of them were acknowledged, or `Error::TransferTimeout` if the recipient
+    /// stopped acknowledging them.
+    ///
+    /// The recipient receives the content in a single `Event::MessageReceived`, from
+    /// `SrcLocation::Node` of this node.
+    pub async fn send_large_message(&self, dst: XorName, content: Bytes) -> Result<()> {
+        self.dispatcher
+            .clone()
+            .send_large_message(dst, content)
+            .await
+    }
+
+    /// Sends a message like `send_message`, but with every node relaying it on the way to the
+    /// destination appending a signed record of the hop: its name, section key, the time and the
+    /// nodes it relayed the message to. The destination receives the full path in the `trace` of
+    /// `Event::MessageReceived`.
+    pub async fn send_traced_message(&self, itinerary: Itinerary, content: Bytes) -> Result<()> {
+        let command = Command::SendTracedMessage { itinerary, content };
+        self.dispatcher.clone().handle_commands(command).await
+    }
+
+    /// Returns the route a message from this node to the node `dst` takes, as the hops recorded by
+    /// the nodes relaying it, starting with this one. The route is probed with a traced message
+    /// which `dst` answers. Returns `Error::RequestTimeout` if no answer arrives within `timeout`.
+    pub async fn trace_route(&self, dst: XorName, timeout: Duration) -> Result<Vec<TraceHop>> {
+        let (response_tx, response_rx) = oneshot::channel();
+        let command = Command::SendTraceProbe {
+            dst,
+            timeout,
+            response_tx,
+        };
+        self.dispatcher.clone().handle_commands(command).await?;
+
+        let response = match time::timeout(timeout, response_rx).await {
+            Ok(Ok(response)) => response,
+            Ok(Err(_)) => return Err(Error::InvalidState),
+            Err(_) => return Err(Error::RequestTimeout),
+        };
+
+        bincode::deserialize(&response.content).map_err(|_| Error::InvalidMessage)
+    }
+
+    /// Sends a request and waits for the response to it.
+    ///
+    /// The request is raised as `Event::RequestReceived` at the destination, where it's answered
+    /// with `Routing::respond`. A request sent to a section is answered by its elders jointly, and
+    /// the response is handed over once their signature shares have been aggregated. Returns
+    /// `Error::RequestTimeout` if no response arrives within `timeout`.
+    ///
+    /// Only requests from this node, i.e. with `SrcLocation::Node`, are supported.
+    pub async fn request(
+        &self,
+        itinerary: Itinerary,
+        content: Bytes,
+        timeout: Duration,
+    ) -> Result<Response> {
+        let (response_tx, response_rx) = oneshot::channel();
+        let command = Command::SendRequest {
+            itinerary,
+            content,
+            timeout,
+            response_tx,
+        };
+        self.dispatcher.clone().handle_commands(command).await?;
+
+        match time::timeout(timeout, response_rx).await {
+            Ok(Ok(response)) => Ok(response),
+            Ok(Err(_)) => Err(Error::InvalidState),
+            Err(_) => Err(Error::RequestTimeout),
+        }
+    }
+
+    /// Responds to a request received as `Event::RequestReceived`, with the handle taken out of the
+    /// event's `RequestSlot`.
+    pub async fn respond(&self, handle: RequestHandle, content: Bytes) -> Result<()> {
+        let command = Command::SendResponse { handle, content };
+        self.dispatcher.clone().handle_commands(command).await
+    }
+
     /// Send a message to a client peer.
     /// Messages sent to a client are not signed or validated as part of the
     /// routing library.
@@ -452,7 +819,10 @@ impl Debug for Routing {
     }
 }
 
-// Listen for incoming connection events and handle them.
+// Listen for incoming connection events and handle them. Handling a consensus message waits while
+// its queue is full, which in turn stops the transport from reading further messages off the
+// connections, as the channel of connection events is bounded. The other messages are shed
+// instead.
 async fn handle_connection_events(
     dispatcher: Arc<Dispatcher>,
     mut incoming_conns: mpsc::Receiver<ConnectionEvent>,
@@ -460,11 +830,17 @@ async fn handle_connection_events(
     while let Some(event) = incoming_conns.recv().await {
         match event {
             ConnectionEvent::Received((src, bytes)) => {
+                if dispatcher.comm.is_blocked(&src) {
+                    trace!("Ignoring message from blocked peer {}", src);
+                    continue;
+                }
+
                 trace!("New message ({} bytes) received from: {}", bytes.len(), src);
                 handle_message(dispatcher.clone(), bytes, src).await;
             }
             ConnectionEvent::Disconnected(addr) => {
                 trace!("Lost connection to {:?}", addr);
+                dispatcher.comm.forget_peer(&addr);
                 let _ = dispatcher
                     .clone()
                     .handle_commands(Command::HandleConnectionLost(addr))
@@ -475,6 +851,32 @@ async fn handle_connection_events(
 }
 
 async fn handle_message(dispatcher: Arc<Dispatcher>, bytes: Bytes, sender: SocketAddr) {
+    // Only nodes send compression frames. Rate limit them as such before decompressing them, so a
+    // peer can't make us decompress any number of them.
+    let admitted = if compression::is_frame(&bytes) {
+        if !dispatcher.comm.admit(sender, MessageClass::Routing) {
+            return;
+        }
+        Some(MessageClass::Routing)
+    } else {
+        None
+    };
+
+    let bytes = if let Some(bytes) = dispatcher.comm.receive(sender, bytes).await {
+        bytes
+    } else {
+        return;
+    };
+
+    if let Some(handshake) = ClientHandshake::decode(&bytes) {
+        if admitted != Some(MessageClass::Client)
+            && dispatcher.comm.admit(sender, MessageClass::Client)
+        {
+            handle_client_handshake(dispatcher, handshake, sender).await;
+        }
+        return;
+    }
+
     let wire_msg = match WireMsg::from(bytes) {
         Ok(wire_msg) => wire_msg,
         Err(error) => {
@@ -482,10 +884,29 @@ async fn handle_message(dispatcher: Arc<Dispatcher>, bytes: Bytes, sender: Socke
             return;
         }
     };
+
+    // Rate limit before deserialising the payload, the most expensive part of handling a message.
+    let class = match wire_msg.msg_kind() {
+        MessageKind::SectionInfo => MessageClass::SectionInfo,
+        MessageKind::Client => MessageClass::Client,
+        MessageKind::Routing | MessageKind::Node => MessageClass::Routing,
+    };
+    if class == MessageClass::Client && dispatcher.is_saturated() {
+        trace!("Shedding message from client {}", sender);
+        dispatcher
+            .comm
+            .metrics()
+            .inc(Metric::MessagesShed, "client");
+        return;
+    }
+    if admitted != Some(class) && !dispatcher.comm.admit(sender, class) {
+        return;
+    }
+
     let span = {
-        let mut state = dispatcher.core.write().await;
+        let state = dispatcher.core.read().await;
 
-        if !state.add_to_filter(&wire_msg).await {
+        if !state.add_to_filter(&wire_msg) {
             trace!(
                 "not handling message - already handled: {:?}",
                 wire_msg.msg_id()
@@ -516,7 +937,7 @@ async fn handle_message(dispatcher: Arc<Dispatcher>, bytes: Bytes, sender: Socke
                 message: msg,
                 dest_info,
             };
-            let _ = task::spawn(dispatcher.handle_commands(command));
+            dispatcher.handle_incoming(command).await;
         }
         MessageType::Routing { msg, dest_info } => {
             if let Err(err) = RoutingMsg::check_signature(&msg) {
@@ -527,12 +948,18 @@ async fn handle_message(dispatcher: Arc<Dispatcher>, bytes: Bytes, sender: Socke
                 return;
             }
 
+            // DKG messages are only told apart from the other routing messages once deserialised,
+            // so they are charged to both limits.
+            if is_dkg(&msg.variant) && !dispatcher.comm.admit(sender, MessageClass::Dkg) {
+                return;
+            }
+
             let command = Command::HandleMessage {
                 message: msg,
                 sender: Some(sender),
                 dest_info,
             };
-            let _ = task::spawn(dispatcher.handle_commands(command));
+            dispatcher.handle_incoming(command).await;
         }
         MessageType::Node {
             msg: _,
@@ -540,36 +967,17 @@ async fn handle_message(dispatcher: Arc<Dispatcher>, bytes: Bytes, sender: Socke
             src_section_pk: _,
         } => unimplemented!(),
         MessageType::Client { msg, .. } => {
-            let end_user = dispatcher
-                .core
-                .read()
-                .await
-                .get_enduser_by_addr(&sender)
-                .copied();
-
-            let end_user = match end_user {
-                Some(end_user) => {
-                    debug!(
-                        "Message from client {}, socket id already exists: {:?}",
-                        sender, end_user
-                    );
-                    end_user
-                }
-                None => {
-                    // this is the first time we receive a message from this client
-                    debug!("First message from client {}, creating a socket id", sender);
-
-                    // TODO: remove the enduser registry and simply encrypt socket addr with
-                    // this node's keypair and use that as the socket id
-                    match dispatcher.core.write().await.try_add(sender) {
-                        Ok(end_user) => end_user,
-                        Err(err) => {
-                            error!(
-                                "Failed to cache client socket address for message {:?}: {:?}",
-                                msg, err
-                            );
-                            return;
-                        }
+            // Every message from the client keeps its session alive.
+            let (end_user, identity) = {
+                let mut state = dispatcher.core.write().await;
+                match state.try_add(sender) {
+                    Ok(end_user) => (end_user, state.get_client_identity(&sender)),
+                    Err(err) => {
+                        error!(
+                            "Failed to cache client socket address for message {:?}: {:?}",
+                            msg, err
+                        );
+                        return;
                     }
                 }
             };
@@ -577,9 +985,74 @@ async fn handle_message(dispatcher: Arc<Dispatcher>, bytes: Bytes, sender: Socke
             let event = Event::ClientMsgReceived {
                 msg: Box::new(msg),
                 user: end_user,
+                identity,
             };
 
             dispatcher.send_event(event).await;
         }
     }
 }
+
+async fn handle_client_handshake(
+    dispatcher: Arc<Dispatcher>,
+    handshake: ClientHandshake,
+    sender: SocketAddr,
+) {
+    let response = {
+        let mut state = dispatcher.core.write().await;
+        match handshake {
+            ClientHandshake::Hello => match state.start_client_handshake(sender) {
+                Ok(challenge) => challenge,
+                Err(error) => {
+                    debug!("Cannot start handshake with client {}: {}", sender, error);
+                    ClientHandshake::Rejected
+                }
+            },
+            ClientHandshake::Proof {
+                identity,
+                signature,
+            } => match state.complete_client_handshake(sender, identity, &signature) {
+                Ok(user) => {
+                    debug!("Client {} authenticated as {:?}", sender, identity);
+                    ClientHandshake::Accepted { user }
+                }
+                Err(error) => {
+                    debug!("Failed to authenticate client {}: {}", sender, error);
+                    ClientHandshake::Rejected
+                }
+            },
+            ClientHandshake::Challenge { .. }
+            | ClientHandshake::Accepted { .. }
+            | ClientHandshake::Rejected => {
+                trace!("Ignoring unexpected handshake message from {}", sender);
+                return;
+            }
+        }
+    };
+
+    let bytes = match response.encode() {
+        Ok(bytes) => Bytes::from(bytes),
+        Err(error) => {
+            error!("Failed to serialize handshake message: {}", error);
+            return;
+        }
+    };
+
+    if let Err(error) = dispatcher
+        .comm
+        .send_bytes_on_existing_connection(sender, bytes)
+        .await
+    {
+        debug!("Failed to send handshake message to {}: {}", sender, error);
+    }
+}
+
+fn is_dkg(variant: &Variant) -> bool {
+    matches!(
+        variant,
+        Variant::DkgStart { .. }
+            | Variant::DkgMessage { .. }
+            | Variant::DkgFailureObservation { .. }
+            | Variant::DkgFailureAgreement(_)
+    )
+}
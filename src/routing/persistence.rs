@@ -0,0 +1,449 @@
+// Copyright 2021 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under The General Public License (GPL), version 3.
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied. Please review the Licences for the specific language governing
+// permissions and limitations relating to use of the SAFE Network Software.
+
+//! On-disk snapshots of the routing state, allowing a node to resume after a restart without
+//! having to rejoin the network as a new node.
+
+use super::{
+    comm::{Comm, ConnectionEvent},
+    compression,
+};
+use crate::{
+    error::{Error, Result},
+    node::Node,
+    peer::PeerUtils,
+    section::{SectionAuthorityProviderUtils, SectionKeyShare, SectionUtils},
+    NetworkParams,
+};
+use bls::serde_impl::SerdeSecret;
+use ed25519_dalek::Keypair;
+use serde::{Deserialize, Serialize};
+use sn_data_types::PublicKey;
+use sn_messaging::{
+    node::{Network, RoutingMsg, Section},
+    section_info::{GetSectionResponse, SectionInfoMsg},
+    DestInfo, MessageType, WireMsg,
+};
+use std::{collections::HashSet, fs, io::Write, net::SocketAddr, path::Path, time::Duration};
+use tokio::{sync::mpsc, time};
+use xor_name::XorName;
+
+// Version of the snapshot format. Bump whenever `NodeSnapshot` changes in an incompatible way.
+const SNAPSHOT_VERSION: u16 = 4;
+
+// How long to wait for our section to confirm a restored snapshot before giving up.
+const REVALIDATION_TIMEOUT: Duration = Duration::from_secs(30);
+
+// How many redirects to follow while looking for the current elders of our section.
+const MAX_REVALIDATION_REDIRECTS: usize = 3;
+
+// Outer envelope, so the version can be checked before attempting to decode the payload.
+#[derive(Serialize, Deserialize)]
+struct VersionedSnapshot {
+    version: u16,
+    payload: Vec<u8>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct StoredKeyShare {
+    public_key_set: bls::PublicKeySet,
+    index: usize,
+    secret_key_share: SerdeSecret<bls::SecretKeyShare>,
+}
+
+/// Persistent part of the routing state.
+#[derive(Serialize, Deserialize)]
+pub(crate) struct NodeSnapshot {
+    // The ed25519 keypair as bytes. The age of the node is encoded in its name.
+    keypair: Vec<u8>,
+    addr: SocketAddr,
+    section: Section,
+    network: Network,
+    // Section key shares, oldest first.
+    key_shares: Vec<StoredKeyShare>,
+    joins_allowed: bool,
+    params: NetworkParams,
+    // Signatures the socket ids of the clients are encrypted with the keys derived from, with
+    // their section keys, oldest first. Kept so the socket ids handed out stay valid.
+    socket_id_keys: Vec<(bls::PublicKey, bls::Signature)>,
+}
+
+impl NodeSnapshot {
+    pub fn new<'a>(
+        node: &Node,
+        section: &Section,
+        network: &Network,
+        key_shares: impl IntoIterator<Item = &'a SectionKeyShare>,
+        joins_allowed: bool,
+        params: NetworkParams,
+        socket_id_keys: Vec<(bls::PublicKey, bls::Signature)>,
+    ) -> Self {
+        let key_shares = key_shares
+            .into_iter()
+            .map(|share| StoredKeyShare {
+                public_key_set: share.public_key_set.clone(),
+                index: share.index,
+                secret_key_share: SerdeSecret(share.secret_key_share.clone()),
+            })
+            .collect();
+
+        Self {
+            keypair: node.keypair.to_bytes().to_vec(),
+            addr: node.addr,
+            section: section.clone(),
+            network: network.clone(),
+            key_shares,
+            joins_allowed,
+            params,
+            socket_id_keys,
+        }
+    }
+
+    pub fn keypair(&self) -> Result<Keypair> {
+        Keypair::from_bytes(&self.keypair)
+            .map_err(|err| Error::InvalidSnapshot(format!("invalid keypair: {}", err)))
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    pub fn joins_allowed(&self) -> bool {
+        self.joins_allowed
+    }
+
+    pub fn params(&self) -> &NetworkParams {
+        &self.params
+    }
+
+    pub fn socket_id_keys(&self) -> &[(bls::PublicKey, bls::Signature)] {
+        &self.socket_id_keys
+    }
+
+    // Decomposes the snapshot into the parts needed to recreate `Core`.
+    pub fn into_parts(self) -> Result<(Section, Network, Vec<SectionKeyShare>)> {
+        // Rebuild the section from scratch and merge the stored one into it. This re-verifies the
+        // authority provider and every member, in case the file was tampered with.
+        if !self.section.chain().self_verify() {
+            return Err(Error::InvalidSnapshot("invalid section chain".to_string()));
+        }
+
+        let mut section = Section::new(
+            *self.section.genesis_key(),
+            self.section.chain().clone(),
+            self.section.section_signed_authority_provider().clone(),
+        )
+        .map_err(|err| Error::InvalidSnapshot(format!("invalid section: {}", err)))?;
+        section
+            .merge(self.section)
+            .map_err(|err| Error::InvalidSnapshot(format!("invalid section: {}", err)))?;
+
+        let key_shares = self
+            .key_shares
+            .into_iter()
+            .map(|share| SectionKeyShare {
+                public_key_set: share.public_key_set,
+                index: share.index,
+                secret_key_share: share.secret_key_share.0,
+            })
+            .collect();
+
+        Ok((section, self.network, key_shares))
+    }
+
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        let payload = bincode::serialize(self)
+            .map_err(|err| Error::InvalidSnapshot(format!("failed to serialise: {}", err)))?;
+        bincode::serialize(&VersionedSnapshot {
+            version: SNAPSHOT_VERSION,
+            payload,
+        })
+        .map_err(|err| Error::InvalidSnapshot(format!("failed to serialise: {}", err)))
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let versioned: VersionedSnapshot = bincode::deserialize(bytes)
+            .map_err(|err| Error::InvalidSnapshot(format!("failed to deserialise: {}", err)))?;
+
+        if versioned.version != SNAPSHOT_VERSION {
+            return Err(Error::UnsupportedSnapshotVersion(versioned.version));
+        }
+
+        bincode::deserialize(&versioned.payload)
+            .map_err(|err| Error::InvalidSnapshot(format!("failed to deserialise: {}", err)))
+    }
+
+    // Writes the snapshot to `path`, see `write_atomically`.
+    pub fn save(&self, path: &Path) -> Result<()> {
+        write_atomically(path, &self.to_bytes()?)
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        let bytes = fs::read(path)?;
+        Self::from_bytes(&bytes)
+    }
+}
+
+// Writes `bytes` to the file at `path`. The file is first written to a temporary sibling and then
+// renamed, so a crash mid-write never leaves a truncated file behind.
+pub(crate) fn write_atomically(path: &Path, bytes: &[u8]) -> Result<()> {
+    let tmp_path = path.with_extension("tmp");
+
+    let mut file = fs::File::create(&tmp_path)?;
+    file.write_all(bytes)?;
+    file.sync_all()?;
+    fs::rename(&tmp_path, path)?;
+
+    Ok(())
+}
+
+/// Checks a restored section against the current view of the network, by asking the elders we
+/// knew about for the latest authority provider of our section.
+///
+/// Returns any routing messages received in the meantime, to be handled once the node is fully
+/// restored.
+pub(crate) async fn revalidate(
+    node: &Node,
+    section: &Section,
+    comm: &Comm,
+    incoming_conns: &mut mpsc::Receiver<ConnectionEvent>,
+) -> Result<Vec<(RoutingMsg, SocketAddr, DestInfo)>> {
+    let mut recipients: Vec<_> = section
+        .authority_provider()
+        .peers()
+        .filter(|peer| *peer.name() != node.name())
+        .map(|peer| (*peer.name(), *peer.addr()))
+        .collect();
+
+    if recipients.is_empty() {
+        // We were the only elder, there is nobody else to ask.
+        return Ok(vec![]);
+    }
+
+    let mut backlog = vec![];
+    let mut used_recipients = HashSet::new();
+
+    for _ in 0..=MAX_REVALIDATION_REDIRECTS {
+        used_recipients.extend(recipients.iter().map(|(_, addr)| *addr));
+        send_query(node, section, comm, &recipients).await;
+
+        let response = time::timeout(
+            REVALIDATION_TIMEOUT,
+            receive_response(incoming_conns, &mut backlog),
+        )
+        .await
+        .map_err(|_| Error::StaleSnapshot)??;
+
+        match response {
+            GetSectionResponse::Success(section_auth) => {
+                if !section_auth.prefix.matches(&node.name()) {
+                    warn!(
+                        "Section {:?} no longer covers our name - snapshot is stale",
+                        section_auth.prefix
+                    );
+                    return Err(Error::StaleSnapshot);
+                }
+
+                if section.chain().has_key(&section_auth.section_key()) {
+                    info!("Restored section state confirmed by {:?}", section_auth);
+                } else {
+                    // Our section moved on while we were away. The newer keys will be supplied
+                    // via anti-entropy once we resume.
+                    info!(
+                        "Restored section state is lagging behind {:?} - resuming anyway",
+                        section_auth
+                    );
+                }
+
+                return Ok(backlog);
+            }
+            GetSectionResponse::Redirect(section_auth) => {
+                if !section_auth.prefix.matches(&node.name()) {
+                    warn!(
+                        "Redirected to {:?} which doesn't cover our name - snapshot is stale",
+                        section_auth.prefix
+                    );
+                    return Err(Error::StaleSnapshot);
+                }
+
+                recipients = section_auth
+                    .elders
+                    .iter()
+                    .filter(|(_, addr)| !used_recipients.contains(*addr))
+                    .map(|(name, addr)| (*name, *addr))
+                    .collect();
+
+                if recipients.is_empty() {
+                    return Err(Error::StaleSnapshot);
+                }
+            }
+            GetSectionResponse::SectionInfoUpdate(error) => {
+                warn!("Section rejected our query: {:?}", error);
+                return Err(Error::StaleSnapshot);
+            }
+        }
+    }
+
+    Err(Error::StaleSnapshot)
+}
+
+async fn send_query(
+    node: &Node,
+    section: &Section,
+    comm: &Comm,
+    recipients: &[(XorName, SocketAddr)],
+) {
+    let message = MessageType::SectionInfo {
+        msg: SectionInfoMsg::GetSectionQuery(PublicKey::from(node.keypair.public)),
+        dest_info: DestInfo {
+            dest: node.name(),
+            dest_section_pk: *section.chain().last_key(),
+        },
+    };
+
+    super::bootstrap::send_message(comm, message, recipients.to_vec()).await
+}
+
+async fn receive_response(
+    incoming_conns: &mut mpsc::Receiver<ConnectionEvent>,
+    backlog: &mut Vec<(RoutingMsg, SocketAddr, DestInfo)>,
+) -> Result<GetSectionResponse> {
+    while let Some(event) = incoming_conns.recv().await {
+        let (sender, bytes) = match event {
+            ConnectionEvent::Received(received) => received,
+            ConnectionEvent::Disconnected(_) => continue,
+        };
+
+        if compression::is_frame(&bytes) {
+            trace!(
+                "Ignoring compression frame from {} while bootstrapping",
+                sender
+            );
+            continue;
+        }
+
+        match WireMsg::deserialize(bytes) {
+            Ok(MessageType::SectionInfo {
+                msg: SectionInfoMsg::GetSectionResponse(response),
+                ..
+            }) => return Ok(response),
+            Ok(MessageType::Routing { msg, dest_info }) => backlog.push((msg, sender, dest_info)),
+            Ok(_) => continue,
+            Err(error) => {
+                debug!("Failed to deserialize message: {}", error);
+                continue;
+            }
+        }
+    }
+
+    Err(Error::ConnectionClosed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        dkg::test_utils::section_signed,
+        section::{test_utils::gen_section_authority_provider, NodeStateUtils, SectionPeersUtils},
+        ELDER_SIZE,
+    };
+    use anyhow::Result;
+    use assert_matches::assert_matches;
+    use secured_linked_list::SecuredLinkedList;
+    use sn_messaging::node::NodeState;
+    use xor_name::Prefix;
+
+    #[test]
+    fn snapshot_roundtrip() -> Result<()> {
+        let (section, key_share, node) = create_section()?;
+        let network = Network::new();
+
+        let params = NetworkParams {
+            elder_size: 3,
+            recommended_section_size: 6,
+            ..Default::default()
+        };
+        let section_key = key_share.public_key_set.public_key();
+        let signature = bls::SecretKey::random().sign(b"socket id key");
+        let snapshot = NodeSnapshot::new(
+            &node,
+            &section,
+            &network,
+            Some(&key_share),
+            false,
+            params,
+            vec![(section_key, signature.clone())],
+        );
+        let bytes = snapshot.to_bytes()?;
+        let restored = NodeSnapshot::from_bytes(&bytes)?;
+
+        assert_eq!(
+            restored.keypair()?.to_bytes()[..],
+            node.keypair.to_bytes()[..]
+        );
+        assert_eq!(restored.addr(), node.addr);
+        assert!(!restored.joins_allowed());
+        assert_eq!(restored.params(), &params);
+        assert_eq!(restored.socket_id_keys(), &[(section_key, signature)]);
+
+        let (restored_section, _, restored_shares) = restored.into_parts()?;
+        assert_eq!(
+            restored_section.chain().last_key(),
+            section.chain().last_key()
+        );
+        assert_eq!(
+            restored_section.members().joined().count(),
+            section.members().joined().count()
+        );
+        assert_eq!(restored_shares.len(), 1);
+        assert_eq!(restored_shares[0].index, key_share.index);
+        assert_eq!(
+            restored_shares[0].public_key_set.public_key(),
+            key_share.public_key_set.public_key()
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn snapshot_with_unknown_version_is_rejected() -> Result<()> {
+        let bytes = bincode::serialize(&VersionedSnapshot {
+            version: SNAPSHOT_VERSION + 1,
+            payload: vec![],
+        })?;
+
+        assert_matches!(
+            NodeSnapshot::from_bytes(&bytes),
+            Err(Error::UnsupportedSnapshotVersion(version)) if version == SNAPSHOT_VERSION + 1
+        );
+
+        Ok(())
+    }
+
+    fn create_section() -> Result<(Section, SectionKeyShare, Node)> {
+        let (section_auth, mut nodes, sk_set) =
+            gen_section_authority_provider(Prefix::default(), ELDER_SIZE);
+        let chain = SecuredLinkedList::new(sk_set.secret_key().public_key());
+        let section_auth = section_signed(sk_set.secret_key(), section_auth)?;
+        let mut section = Section::new(*chain.root_key(), chain, section_auth)?;
+
+        for node in &nodes {
+            let node_state = NodeState::joined(node.peer());
+            let node_state = section_signed(sk_set.secret_key(), node_state)?;
+            let _ = section.update_member(node_state);
+        }
+
+        let key_share = SectionKeyShare {
+            public_key_set: sk_set.public_keys(),
+            index: 0,
+            secret_key_share: sk_set.secret_key_share(0),
+        };
+
+        Ok((section, key_share, nodes.remove(0)))
+    }
+}
@@ -0,0 +1,477 @@
+// Copyright 2021 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under The General Public License (GPL), version 3.
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied. Please review the Licences for the specific language governing
+// permissions and limitations relating to use of the SAFE Network Software.
+
+use crate::error::{Error, Result};
+use std::{
+    collections::{HashMap, HashSet},
+    net::SocketAddr,
+    sync::{Arc, PoisonError, RwLock},
+    time::Duration,
+};
+use tokio::time::Instant;
+
+// Number of (address, class) pairs tracked before the idle ones start being forgotten. Once that
+// many are busy, the messages from further addresses are dropped until some go idle.
+const MAX_TRACKED: usize = 10_000;
+
+/// Limits on the rate of incoming messages, applied per sender address and per message class.
+/// Messages over the limit are dropped unhandled, and addresses that keep exceeding the limits are
+/// blocked for a while. The members of our section are exempt, as the section relies on their
+/// messages, e.g. on churn, when they come in bursts.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RateLimits {
+    /// Limit on messages from clients. `None` means unlimited.
+    pub client: Option<RateLimit>,
+    /// Limit on section info queries, e.g. from bootstrapping nodes and clients.
+    pub section_info: Option<RateLimit>,
+    /// Limit on routing messages of all kinds, including DKG.
+    pub routing: Option<RateLimit>,
+    /// Additional limit on DKG messages. As those can only be told apart once deserialised, they
+    /// are charged to `routing` first.
+    pub dkg: Option<RateLimit>,
+    /// Number of messages from a single address to drop within `offence_window` before blocking
+    /// the address altogether.
+    pub block_threshold: u32,
+    /// Period the dropped messages are counted over for `block_threshold`.
+    pub offence_window: Duration,
+    /// How long the messages from a blocked address are ignored.
+    pub block_duration: Duration,
+}
+
+impl RateLimits {
+    // Checks every limit lets messages through.
+    pub(crate) fn validate(&self) -> Result<()> {
+        let limits = [self.client, self.section_info, self.routing, self.dkg];
+        if limits
+            .iter()
+            .flatten()
+            .any(|limit| limit.per_second == 0 || limit.burst == 0)
+        {
+            return Err(Error::InvalidRateLimits(
+                "per_second and burst must be positive",
+            ));
+        }
+
+        Ok(())
+    }
+
+    fn get(&self, class: MessageClass) -> Option<RateLimit> {
+        match class {
+            MessageClass::Client => self.client,
+            MessageClass::SectionInfo => self.section_info,
+            MessageClass::Routing => self.routing,
+            MessageClass::Dkg => self.dkg,
+        }
+    }
+}
+
+impl Default for RateLimits {
+    fn default() -> Self {
+        Self {
+            client: Some(RateLimit::new(100, 200)),
+            section_info: Some(RateLimit::new(10, 20)),
+            routing: Some(RateLimit::new(500, 1000)),
+            dkg: Some(RateLimit::new(200, 400)),
+            block_threshold: 100,
+            offence_window: Duration::from_secs(10),
+            block_duration: Duration::from_secs(60),
+        }
+    }
+}
+
+/// A token bucket: allows `per_second` messages on average and bursts of up to `burst` messages.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct RateLimit {
+    /// Rate the bucket refills at, in messages per second.
+    pub per_second: u32,
+    /// Capacity of the bucket.
+    pub burst: u32,
+}
+
+impl RateLimit {
+    /// Creates a limit of `per_second` messages with bursts of up to `burst` messages.
+    pub fn new(per_second: u32, burst: u32) -> Self {
+        Self { per_second, burst }
+    }
+}
+
+// Classes of incoming messages, each limited separately.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
+pub(crate) enum MessageClass {
+    Client,
+    SectionInfo,
+    Routing,
+    Dkg,
+}
+
+impl MessageClass {
+    pub fn label(self) -> &'static str {
+        match self {
+            Self::Client => "client",
+            Self::SectionInfo => "section_info",
+            Self::Routing => "routing",
+            Self::Dkg => "dkg",
+        }
+    }
+}
+
+#[derive(Debug, Eq, PartialEq)]
+pub(crate) enum Verdict {
+    Admit,
+    Drop,
+    // Drop, and the sender has just been blocked.
+    Block,
+}
+
+// Addresses of the members of our section, which are exempt from the rate limits. Shared between
+// the rate limiter and the node state, which keeps it up to date.
+#[derive(Clone, Default)]
+pub(crate) struct SectionMembers(Arc<RwLock<HashSet<SocketAddr>>>);
+
+impl SectionMembers {
+    pub fn set(&self, addrs: impl IntoIterator<Item = SocketAddr>) {
+        *self.0.write().unwrap_or_else(PoisonError::into_inner) = addrs.into_iter().collect();
+    }
+
+    pub fn contains(&self, addr: &SocketAddr) -> bool {
+        self.0
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .contains(addr)
+    }
+}
+
+pub(crate) struct RateLimiter {
+    limits: RateLimits,
+    members: SectionMembers,
+    buckets: HashMap<(SocketAddr, MessageClass), TokenBucket>,
+    offences: HashMap<SocketAddr, Offences>,
+    // Blocked addresses, with when they get unblocked.
+    blocked: HashMap<SocketAddr, Instant>,
+}
+
+impl RateLimiter {
+    pub fn new(limits: RateLimits) -> Self {
+        Self {
+            limits,
+            members: SectionMembers::default(),
+            buckets: HashMap::new(),
+            offences: HashMap::new(),
+            blocked: HashMap::new(),
+        }
+    }
+
+    pub fn members(&self) -> &SectionMembers {
+        &self.members
+    }
+
+    pub fn set_limits(&mut self, limits: RateLimits) {
+        self.limits = limits;
+        self.buckets.clear();
+    }
+
+    pub fn is_blocked(&mut self, addr: &SocketAddr, now: Instant) -> bool {
+        if self.members.contains(addr) {
+            return false;
+        }
+
+        match self.blocked.get(addr) {
+            Some(until) if *until > now => true,
+            Some(_) => {
+                let _ = self.blocked.remove(addr);
+                false
+            }
+            None => false,
+        }
+    }
+
+    // Takes a token for a message of `class` from `addr`.
+    pub fn check(&mut self, addr: SocketAddr, class: MessageClass, now: Instant) -> Verdict {
+        if self.members.contains(&addr) {
+            return Verdict::Admit;
+        }
+
+        if self.is_blocked(&addr, now) {
+            return Verdict::Drop;
+        }
+
+        let limit = if let Some(limit) = self.limits.get(class) {
+            limit
+        } else {
+            return Verdict::Admit;
+        };
+
+        if self.buckets.len() >= MAX_TRACKED && !self.buckets.contains_key(&(addr, class)) {
+            self.forget_idle(now);
+
+            // Not to be made to track any number of addresses.
+            if self.buckets.len() >= MAX_TRACKED {
+                return Verdict::Drop;
+            }
+        }
+
+        let bucket = self
+            .buckets
+            .entry((addr, class))
+            .or_insert_with(|| TokenBucket::new(&limit, now));
+        if bucket.take(&limit, now) {
+            return Verdict::Admit;
+        }
+
+        let offences = self.offences.entry(addr).or_insert(Offences {
+            count: 0,
+            since: now,
+        });
+        if now.saturating_duration_since(offences.since) > self.limits.offence_window {
+            offences.count = 0;
+            offences.since = now;
+        }
+        offences.count += 1;
+
+        if offences.count < self.limits.block_threshold {
+            return Verdict::Drop;
+        }
+
+        let _ = self.offences.remove(&addr);
+        self.buckets.retain(|(other, _), _| *other != addr);
+        let _ = self.blocked.insert(addr, now + self.limits.block_duration);
+
+        Verdict::Block
+    }
+
+    // Forgets the buckets that have refilled completely, as a fresh bucket would behave the same,
+    // together with the stale offences and blocks.
+    fn forget_idle(&mut self, now: Instant) {
+        let limits = self.limits;
+        self.buckets.retain(|(_, class), bucket| {
+            limits
+                .get(*class)
+                .map_or(false, |limit| !bucket.is_full(&limit, now))
+        });
+        self.offences.retain(|_, offences| {
+            now.saturating_duration_since(offences.since) <= limits.offence_window
+        });
+        self.blocked.retain(|_, until| *until > now);
+    }
+}
+
+struct TokenBucket {
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    fn new(limit: &RateLimit, now: Instant) -> Self {
+        Self {
+            tokens: limit.burst as f64,
+            last_refill: now,
+        }
+    }
+
+    fn take(&mut self, limit: &RateLimit, now: Instant) -> bool {
+        self.tokens = self.refilled(limit, now);
+        self.last_refill = now;
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn is_full(&self, limit: &RateLimit, now: Instant) -> bool {
+        self.refilled(limit, now) >= limit.burst as f64
+    }
+
+    fn refilled(&self, limit: &RateLimit, now: Instant) -> f64 {
+        let elapsed = now
+            .saturating_duration_since(self.last_refill)
+            .as_secs_f64();
+        (self.tokens + elapsed * limit.per_second as f64).min(limit.burst as f64)
+    }
+}
+
+struct Offences {
+    count: u32,
+    since: Instant,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::section::test_utils::gen_addr;
+    use assert_matches::assert_matches;
+
+    #[test]
+    fn token_bucket() {
+        let mut limiter = RateLimiter::new(RateLimits {
+            section_info: Some(RateLimit::new(2, 3)),
+            ..Default::default()
+        });
+        let addr = gen_addr();
+        let other = gen_addr();
+        let now = Instant::now();
+
+        for _ in 0..3 {
+            assert_eq!(
+                limiter.check(addr, MessageClass::SectionInfo, now),
+                Verdict::Admit
+            );
+        }
+        assert_eq!(
+            limiter.check(addr, MessageClass::SectionInfo, now),
+            Verdict::Drop
+        );
+
+        // Other senders and other classes have their own buckets.
+        assert_eq!(
+            limiter.check(other, MessageClass::SectionInfo, now),
+            Verdict::Admit
+        );
+        assert_eq!(
+            limiter.check(addr, MessageClass::Routing, now),
+            Verdict::Admit
+        );
+
+        let now = now + Duration::from_millis(500);
+        assert_eq!(
+            limiter.check(addr, MessageClass::SectionInfo, now),
+            Verdict::Admit
+        );
+        assert_eq!(
+            limiter.check(addr, MessageClass::SectionInfo, now),
+            Verdict::Drop
+        );
+    }
+
+    #[test]
+    fn block_repeat_offenders() {
+        let limits = RateLimits {
+            client: Some(RateLimit::new(1, 1)),
+            block_threshold: 3,
+            ..Default::default()
+        };
+        let mut limiter = RateLimiter::new(limits);
+        let addr = gen_addr();
+        let now = Instant::now();
+
+        assert_eq!(
+            limiter.check(addr, MessageClass::Client, now),
+            Verdict::Admit
+        );
+        assert_eq!(
+            limiter.check(addr, MessageClass::Client, now),
+            Verdict::Drop
+        );
+        assert_eq!(
+            limiter.check(addr, MessageClass::Client, now),
+            Verdict::Drop
+        );
+        assert_eq!(
+            limiter.check(addr, MessageClass::Client, now),
+            Verdict::Block
+        );
+        assert!(limiter.is_blocked(&addr, now));
+
+        // Blocked for every class, even the ones within their limits.
+        assert_eq!(
+            limiter.check(addr, MessageClass::Routing, now),
+            Verdict::Drop
+        );
+
+        let now = now + limits.block_duration;
+        assert!(!limiter.is_blocked(&addr, now));
+        assert_eq!(
+            limiter.check(addr, MessageClass::Client, now),
+            Verdict::Admit
+        );
+    }
+
+    #[test]
+    fn exempt_section_members() {
+        let mut limiter = RateLimiter::new(RateLimits {
+            routing: Some(RateLimit::new(1, 1)),
+            block_threshold: 1,
+            ..Default::default()
+        });
+        let member = gen_addr();
+        let other = gen_addr();
+        limiter.members().set(vec![member]);
+        let now = Instant::now();
+
+        for _ in 0..10 {
+            assert_eq!(
+                limiter.check(member, MessageClass::Routing, now),
+                Verdict::Admit
+            );
+        }
+
+        assert_eq!(
+            limiter.check(other, MessageClass::Routing, now),
+            Verdict::Admit
+        );
+        assert_eq!(
+            limiter.check(other, MessageClass::Routing, now),
+            Verdict::Block
+        );
+    }
+
+    #[test]
+    fn bounded_tracking() {
+        let mut limiter = RateLimiter::new(RateLimits {
+            section_info: Some(RateLimit::new(1, 2)),
+            ..Default::default()
+        });
+        let now = Instant::now();
+
+        // Busy addresses, with buckets that haven't refilled.
+        for _ in 0..MAX_TRACKED {
+            assert_eq!(
+                limiter.check(gen_addr(), MessageClass::SectionInfo, now),
+                Verdict::Admit
+            );
+        }
+        assert_eq!(
+            limiter.check(gen_addr(), MessageClass::SectionInfo, now),
+            Verdict::Drop
+        );
+        assert_eq!(limiter.buckets.len(), MAX_TRACKED);
+
+        // Room is made once they go idle.
+        let now = now + Duration::from_secs(1);
+        assert_eq!(
+            limiter.check(gen_addr(), MessageClass::SectionInfo, now),
+            Verdict::Admit
+        );
+        assert_eq!(limiter.buckets.len(), 1);
+    }
+
+    #[test]
+    fn validate() {
+        assert_matches!(RateLimits::default().validate(), Ok(()));
+
+        let limits = RateLimits {
+            client: Some(RateLimit::new(0, 10)),
+            ..Default::default()
+        };
+        assert_matches!(limits.validate(), Err(Error::InvalidRateLimits(_)));
+
+        let limits = RateLimits {
+            dkg: Some(RateLimit::new(10, 0)),
+            ..Default::default()
+        };
+        assert_matches!(limits.validate(), Err(Error::InvalidRateLimits(_)));
+
+        let limits = RateLimits {
+            routing: None,
+            ..Default::default()
+        };
+        assert_matches!(limits.validate(), Ok(()));
+    }
+}
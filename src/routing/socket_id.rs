@@ -0,0 +1,405 @@
+// Copyright 2021 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under The General Public License (GPL), version 3.
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied. Please review the Licences for the specific language governing
+// permissions and limitations relating to use of the SAFE Network Software.
+
+//! Socket ids of the clients are their socket addresses encrypted under a key shared by the
+//! elders of our section, so any of them can tell where to send a message to a client, and they
+//! all assign the same id to a client, as the aggregation of the messages to it requires.
+//!
+//! The key is derived from the section signature of a fixed message, which the elders obtain by
+//! exchanging their signature shares of it with each other. The signature is unique to the section
+//! key and can't be produced by anyone but a quorum of its elders.
+//!
+//! The encryption is AES-SIV (RFC 5297), deterministic and authenticated: the 16-byte synthetic
+//! IV, a MAC of the address, is also the IV the address is encrypted with. That leaves 16 bytes of
+//! the 32-byte id for the address, enough for IPv4 addresses and their port but not for IPv6
+//! ones, so the clients connecting over IPv6 have to authenticate with a `ClientHandshake` to be
+//! given an identity-based socket id instead.
+
+use crate::{
+    error::{Error, Result},
+    section::SectionKeyShare,
+};
+use aes_siv::{aead::generic_array::GenericArray, siv::Aes128Siv};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    iter,
+    net::{Ipv4Addr, SocketAddr, SocketAddrV4},
+    time::Duration,
+};
+use tiny_keccak::{Hasher, Sha3};
+use xor_name::XorName;
+
+const KEY_DOMAIN: &[u8] = b"sn_routing socket id key";
+// Associated data of the encrypted addresses, binding them to their use.
+const ASSOCIATED_DATA: &[u8] = b"sn_routing socket id";
+
+const IV_LEN: usize = 16;
+const ADDR_LEN: usize = 32 - IV_LEN;
+
+// Number of the most recent keys kept to decrypt socket ids with, so the ids handed out before an
+// elder change stay valid for a while after it.
+const KEYS_KEPT: usize = 2;
+// Number of section keys to keep the shares of other elders for, until we have ours.
+const MAX_PENDING_KEYS: usize = 4;
+// How long to wait for the other elders to answer our share before sending it again.
+pub(crate) const SHARE_RETRY_INTERVAL: Duration = Duration::from_secs(10);
+
+// Keys to encrypt the addresses of the clients into their socket ids with.
+#[derive(Default)]
+pub(crate) struct SocketIdKeys {
+    // Derived keys with the section keys they belong to and the signatures they were derived from,
+    // the most recent last.
+    keys: Vec<(bls::PublicKey, bls::Signature, SocketIdKey)>,
+    // Shares of the signature the next key is derived from, collected so far.
+    collecting: Option<Collecting>,
+    // Signature shares of other elders for section keys we don't have our share of yet. They
+    // aren't verified until we do.
+    pending: BTreeMap<bls::PublicKey, BTreeMap<usize, bls::SignatureShare>>,
+}
+
+struct Collecting {
+    public_key_set: bls::PublicKeySet,
+    shares: BTreeMap<usize, bls::SignatureShare>,
+}
+
+// Our share of the key for `section_key`, being sent to the other elders until they answer with
+// theirs.
+pub(crate) struct PendingShare {
+    pub section_key: bls::PublicKey,
+    // Elders that didn't answer yet.
+    pub unacknowledged: BTreeSet<XorName>,
+    // Token of the timeout to send the share again on.
+    pub timer_token: u64,
+}
+
+impl SocketIdKeys {
+    // Whether we started deriving the key for the given section key already.
+    pub fn has_started(&self, section_key: &bls::PublicKey) -> bool {
+        self.has_key(section_key)
+            || self
+                .collecting
+                .as_ref()
+                .map(|collecting| collecting.public_key_set.public_key() == *section_key)
+                .unwrap_or(false)
+    }
+
+    // Starts deriving the key for the section key of `key_share`. Returns our signature share,
+    // to be sent to the other elders.
+    pub fn start(&mut self, key_share: &SectionKeyShare) -> bls::SignatureShare {
+        let section_key = key_share.public_key_set.public_key();
+        let signable = signable_bytes(&section_key);
+        let our_share = sign_share(key_share);
+
+        let mut shares: BTreeMap<_, _> = self
+            .pending
+            .remove(&section_key)
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|(index, share)| {
+                key_share
+                    .public_key_set
+                    .public_key_share(*index)
+                    .verify(share, &signable)
+            })
+            .collect();
+        let _ = shares.insert(key_share.index, our_share.clone());
+
+        self.collecting = Some(Collecting {
+            public_key_set: key_share.public_key_set.clone(),
+            shares,
+        });
+        self.try_derive();
+
+        our_share
+    }
+
+    // Adds the signature share of the elder with the given index.
+    pub fn add_share(
+        &mut self,
+        section_key: bls::PublicKey,
+        index: usize,
+        share: bls::SignatureShare,
+    ) -> Result<()> {
+        if self.has_key(&section_key) {
+            return Ok(());
+        }
+
+        match &mut self.collecting {
+            Some(collecting) if collecting.public_key_set.public_key() == section_key => {
+                if !collecting
+                    .public_key_set
+                    .public_key_share(index)
+                    .verify(&share, &signable_bytes(&section_key))
+                {
+                    return Err(Error::InvalidSignatureShare);
+                }
+
+                let _ = collecting.shares.insert(index, share);
+                self.try_derive();
+            }
+            _ => {
+                let _ = self
+                    .pending
+                    .entry(section_key)
+                    .or_default()
+                    .insert(index, share);
+
+                while self.pending.len() > MAX_PENDING_KEYS {
+                    let key = *self.pending.keys().next().expect("pending is not empty");
+                    let _ = self.pending.remove(&key);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    // Encrypts `addr` under the most recent key. Returns `None` if we don't have any key yet, or
+    // if `addr` is an IPv6 address.
+    pub fn encrypt(&self, addr: &SocketAddr) -> Option<XorName> {
+        self.keys.last().and_then(|(_, _, key)| key.encrypt(addr))
+    }
+
+    // Decrypts the socket id under any of the keys we keep.
+    pub fn decrypt(&self, socket_id: &XorName) -> Option<SocketAddr> {
+        self.keys
+            .iter()
+            .rev()
+            .find_map(|(_, _, key)| key.decrypt(socket_id))
+    }
+
+    // The signatures the keys we keep were derived from, with their section keys, oldest first.
+    pub fn signatures(&self) -> Vec<(bls::PublicKey, bls::Signature)> {
+        self.keys
+            .iter()
+            .map(|(section_key, signature, _)| (*section_key, signature.clone()))
+            .collect()
+    }
+
+    // Adds the keys derived from the given signatures, e.g. persisted before a restart, as older
+    // than the ones we derived ourselves. The signatures not of the fixed message by the section
+    // key they come with are skipped.
+    pub fn insert_signatures(
+        &mut self,
+        signatures: impl IntoIterator<Item = (bls::PublicKey, bls::Signature)>,
+    ) {
+        let mut keys = vec![];
+        for (section_key, signature) in signatures {
+            if self.has_key(&section_key)
+                || keys.iter().any(|(key, _, _)| *key == section_key)
+                || !section_key.verify(&signature, signable_bytes(&section_key))
+            {
+                continue;
+            }
+
+            let key = SocketIdKey::from_signature(&signature);
+            keys.push((section_key, signature, key));
+        }
+
+        keys.append(&mut self.keys);
+        let excess = keys.len().saturating_sub(KEYS_KEPT);
+        self.keys = keys.split_off(excess);
+
+        if let Some(collecting) = &self.collecting {
+            if self.has_key(&collecting.public_key_set.public_key()) {
+                self.collecting = None;
+            }
+        }
+    }
+
+    fn has_key(&self, section_key: &bls::PublicKey) -> bool {
+        self.keys.iter().any(|(key, _, _)| key == section_key)
+    }
+
+    fn try_derive(&mut self) {
+        let collecting = if let Some(collecting) = &self.collecting {
+            collecting
+        } else {
+            return;
+        };
+
+        if collecting.shares.len() <= collecting.public_key_set.threshold() {
+            return;
+        }
+
+        let signature = match collecting
+            .public_key_set
+            .combine_signatures(collecting.shares.iter())
+        {
+            Ok(signature) => signature,
+            Err(error) => {
+                error!("Failed to combine socket id key shares: {:?}", error);
+                return;
+            }
+        };
+
+        let section_key = collecting.public_key_set.public_key();
+        trace!("Derived socket id key for {:?}", section_key);
+
+        let key = SocketIdKey::from_signature(&signature);
+        self.keys.push((section_key, signature, key));
+        if self.keys.len() > KEYS_KEPT {
+            let _ = self.keys.remove(0);
+        }
+        self.collecting = None;
+    }
+}
+
+// Our share of the signature the key for the section key of `key_share` is derived from.
+pub(crate) fn sign_share(key_share: &SectionKeyShare) -> bls::SignatureShare {
+    let section_key = key_share.public_key_set.public_key();
+    key_share
+        .secret_key_share
+        .sign(&signable_bytes(&section_key))
+}
+
+fn signable_bytes(section_key: &bls::PublicKey) -> Vec<u8> {
+    [KEY_DOMAIN, &section_key.to_bytes()[..]].concat()
+}
+
+struct SocketIdKey([u8; 32]);
+
+impl SocketIdKey {
+    fn from_signature(signature: &bls::Signature) -> Self {
+        let mut key = [0; 32];
+        let mut hasher = Sha3::v256();
+        hasher.update(KEY_DOMAIN);
+        hasher.update(&signature.to_bytes());
+        hasher.finalize(&mut key);
+        Self(key)
+    }
+
+    fn encrypt(&self, addr: &SocketAddr) -> Option<XorName> {
+        let plaintext = encode_addr(addr)?;
+        let ciphertext = self
+            .cipher()
+            .encrypt(iter::once(ASSOCIATED_DATA), &plaintext)
+            .ok()?;
+
+        let mut id = [0; 32];
+        id.copy_from_slice(&ciphertext);
+        Some(XorName(id))
+    }
+
+    fn decrypt(&self, socket_id: &XorName) -> Option<SocketAddr> {
+        let plaintext = self
+            .cipher()
+            .decrypt(iter::once(ASSOCIATED_DATA), &socket_id.0)
+            .ok()?;
+
+        decode_addr(&plaintext)
+    }
+
+    fn cipher(&self) -> Aes128Siv {
+        Aes128Siv::new(GenericArray::clone_from_slice(&self.0))
+    }
+}
+
+// IPv4 address and port, padded with zeros.
+fn encode_addr(addr: &SocketAddr) -> Option<[u8; ADDR_LEN]> {
+    let addr = match addr {
+        SocketAddr::V4(addr) => addr,
+        SocketAddr::V6(_) => return None,
+    };
+
+    let mut bytes = [0; ADDR_LEN];
+    bytes[..4].copy_from_slice(&addr.ip().octets());
+    bytes[4..6].copy_from_slice(&addr.port().to_be_bytes());
+    Some(bytes)
+}
+
+fn decode_addr(bytes: &[u8]) -> Option<SocketAddr> {
+    match bytes {
+        [a, b, c, d, port0, port1, padding @ ..]
+            if padding.len() == ADDR_LEN - 6 && padding.iter().all(|byte| *byte == 0) =>
+        {
+            let ip = Ipv4Addr::new(*a, *b, *c, *d);
+            let port = u16::from_be_bytes([*port0, *port1]);
+            Some(SocketAddr::V4(SocketAddrV4::new(ip, port)))
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::section::test_utils::gen_addr;
+
+    #[test]
+    fn encrypt_decrypt() {
+        let key = SocketIdKey([1; 32]);
+        let other_key = SocketIdKey([2; 32]);
+
+        let addr = gen_addr();
+        let socket_id = key.encrypt(&addr).expect("IPv4 address");
+        assert_eq!(key.encrypt(&addr), Some(socket_id));
+        assert_eq!(key.decrypt(&socket_id), Some(addr));
+        assert_eq!(other_key.decrypt(&socket_id), None);
+
+        // Any change to the id is detected, be it to the IV or to the encrypted address.
+        for index in &[0, 31] {
+            let mut tampered = socket_id;
+            tampered.0[*index] ^= 1;
+            assert_eq!(key.decrypt(&tampered), None);
+        }
+
+        // IPv6 addresses don't fit in the id.
+        assert_eq!(key.encrypt(&"[2001:db8::1]:12000".parse().unwrap()), None);
+    }
+
+    #[test]
+    fn derive_key_from_shares() -> Result<()> {
+        let secret_key_set = bls::SecretKeySet::random(1, &mut rand::thread_rng());
+        let key_share = |index| SectionKeyShare {
+            public_key_set: secret_key_set.public_keys(),
+            index,
+            secret_key_share: secret_key_set.secret_key_share(index),
+        };
+        let section_key = secret_key_set.public_keys().public_key();
+
+        let mut keys0 = SocketIdKeys::default();
+        let mut keys1 = SocketIdKeys::default();
+
+        // A share arriving before we have ours is kept until we do.
+        let share1 = SocketIdKeys::default().start(&key_share(1));
+        keys0.add_share(section_key, 1, share1)?;
+        let share0 = keys0.start(&key_share(0));
+        assert!(keys0.has_started(&section_key));
+
+        let addr = gen_addr();
+        let socket_id = keys0.encrypt(&addr).expect("key derived");
+
+        assert!(keys1.encrypt(&addr).is_none());
+        let _ = keys1.start(&key_share(1));
+        assert!(keys1.encrypt(&addr).is_none());
+        keys1.add_share(section_key, 0, share0)?;
+        assert_eq!(keys1.encrypt(&addr), Some(socket_id));
+        assert_eq!(keys1.decrypt(&socket_id), Some(addr));
+
+        // The key can be restored from the signature it was derived from.
+        let mut restored = SocketIdKeys::default();
+        restored.insert_signatures(keys0.signatures());
+        assert_eq!(restored.decrypt(&socket_id), Some(addr));
+        assert_eq!(restored.encrypt(&addr), Some(socket_id));
+
+        let forged = (section_key, bls::SecretKey::random().sign(KEY_DOMAIN));
+        let mut restored = SocketIdKeys::default();
+        restored.insert_signatures(iter::once(forged));
+        assert!(restored.encrypt(&addr).is_none());
+
+        // Invalid shares are rejected.
+        let mut keys2 = SocketIdKeys::default();
+        let _ = keys2.start(&key_share(2));
+        let forged = key_share(0).secret_key_share.sign(b"something else");
+        assert!(keys2.add_share(section_key, 0, forged).is_err());
+        assert!(keys2.encrypt(&addr).is_none());
+
+        Ok(())
+    }
+}
@@ -23,6 +23,11 @@ impl SplitBarrier {
         Self(Vec::new())
     }
 
+    // Cached agreements, waiting for their sibling.
+    pub fn pending(&self) -> impl Iterator<Item = &SectionAuthorityProvider> {
+        self.0.iter().map(|(section_auth, _)| &section_auth.value)
+    }
+
     // Pass an aggreed-on proposal for `OurElders` through this function. If there is no split, it
     // returns it unchanged. If there is a split and we've seen the aggreement for only one
     // subsection so far, it caches it and returns nothing. Otherwise it returns both proposals.
@@ -6,7 +6,10 @@
 // KIND, either express or implied. Please review the Licences for the specific language governing
 // permissions and limitations relating to use of the SAFE Network Software.
 
-use super::{Comm, Command, Core, Dispatcher};
+use super::{
+    event_stream, socket_id, Comm, Command, CommandLimits, Core, Dispatcher, MemoryNetwork,
+    TransportBackend,
+};
 use crate::{
     dkg::{
         test_utils::{prove, section_signed},
@@ -14,18 +17,22 @@ use crate::{
     },
     ed25519,
     event::Event,
-    messages::{PlainMessageUtils, RoutingMsgUtils, SrcAuthorityUtils, VerifyStatus},
+    messages::{
+        ClientHandshake, Envelope, PlainMessageUtils, RoutingMsgUtils, SrcAuthorityUtils,
+        VerifyStatus,
+    },
     network::NetworkUtils,
     node::Node,
     peer::PeerUtils,
     relocation::{self, RelocatePayloadUtils, SignedRelocateDetailsUtils},
+    request::RequestId,
     routing::core::{RESOURCE_PROOF_DATA_SIZE, RESOURCE_PROOF_DIFFICULTY},
     section::{
         test_utils::*, ElderCandidatesUtils, NodeStateUtils, SectionAuthorityProviderUtils,
         SectionKeyShare, SectionPeersUtils, SectionUtils, FIRST_SECTION_MIN_AGE, MIN_ADULT_AGE,
         MIN_AGE,
     },
-    supermajority, ELDER_SIZE,
+    supermajority, DeliveryPolicy, NetworkParams, ELDER_SIZE,
 };
 use anyhow::Result;
 use assert_matches::assert_matches;
@@ -34,6 +41,7 @@ use resource_proof::ResourceProof;
 use secured_linked_list::SecuredLinkedList;
 use sn_data_types::{Keypair, PublicKey};
 use sn_messaging::{
+    client::{ClientMsg, ClientSigned, ProcessMsg, Query, TransferQuery},
     location::{Aggregation, Itinerary},
     node::{
         JoinAsRelocatedRequest, JoinRequest, JoinResponse, MembershipState, Network, NodeState,
@@ -41,16 +49,19 @@ use sn_messaging::{
         RoutingMsg, Section, SectionSigned, Signed, SignedRelocateDetails, Variant,
     },
     section_info::{GetSectionResponse, SectionInfoMsg},
-    DestInfo, DstLocation, MessageType, SectionAuthorityProvider, SrcLocation,
+    DestInfo, DstLocation, EndUser, MessageId, MessageType, SectionAuthorityProvider, SrcLocation,
 };
 use std::{
     collections::{BTreeSet, HashSet},
     iter,
-    net::Ipv4Addr,
     ops::Deref,
+    sync::Arc,
 };
 use tokio::{
-    sync::mpsc,
+    sync::{
+        mpsc,
+        oneshot::{self, error::TryRecvError},
+    },
     time::{timeout, Duration},
 };
 use xor_name::{Prefix, XorName};
@@ -60,7 +71,11 @@ static TEST_EVENT_CHANNEL_SIZE: usize = 20;
 #[tokio::test]
 async fn receive_matching_get_section_request_as_elder() -> Result<()> {
     let node = create_node(MIN_ADULT_AGE);
-    let state = Core::first_node(node, mpsc::channel(TEST_EVENT_CHANNEL_SIZE).0)?;
+    let state = Core::first_node(
+        node,
+        event_stream::channel(TEST_EVENT_CHANNEL_SIZE).0,
+        NetworkParams::default(),
+    )?;
     let dispatcher = Dispatcher::new(state, create_comm().await?);
 
     let new_node_comm = create_comm().await?;
@@ -115,7 +130,8 @@ async fn receive_mismatching_get_section_request_as_adult() -> Result<()> {
         node,
         section,
         None,
-        mpsc::channel(TEST_EVENT_CHANNEL_SIZE).0,
+        event_stream::channel(TEST_EVENT_CHANNEL_SIZE).0,
+        NetworkParams::default(),
     );
     let dispatcher = Dispatcher::new(state, create_comm().await?);
 
@@ -174,7 +190,11 @@ async fn receive_mismatching_get_section_request_as_adult() -> Result<()> {
 async fn receive_join_request_without_resource_proof_response() -> Result<()> {
     let node = create_node(FIRST_SECTION_MIN_AGE);
     let node_name = node.name();
-    let state = Core::first_node(node, mpsc::channel(TEST_EVENT_CHANNEL_SIZE).0)?;
+    let state = Core::first_node(
+        node,
+        event_stream::channel(TEST_EVENT_CHANNEL_SIZE).0,
+        NetworkParams::default(),
+    )?;
     let dispatcher = Dispatcher::new(state, create_comm().await?);
 
     let new_node_comm = create_comm().await?;
@@ -226,9 +246,32 @@ async fn receive_join_request_without_resource_proof_response() -> Result<()> {
 
 #[tokio::test]
 async fn receive_join_request_with_resource_proof_response() -> Result<()> {
+    assert!(receive_join_request_solved_with(NetworkParams::default()).await?);
+    Ok(())
+}
+
+#[tokio::test]
+async fn reject_join_request_with_mismatching_network_params() -> Result<()> {
+    let params = NetworkParams {
+        elder_size: 3,
+        recommended_section_size: 6,
+        ..Default::default()
+    };
+    assert!(!receive_join_request_solved_with(params).await?);
+    Ok(())
+}
+
+// Sends a join request with the resource proof solved by a node using the given network
+// parameters to an elder using the default ones. Returns whether the elder proposed the node
+// online.
+async fn receive_join_request_solved_with(params: NetworkParams) -> Result<bool> {
     let node = create_node(FIRST_SECTION_MIN_AGE);
     let node_name = node.name();
-    let state = Core::first_node(node, mpsc::channel(TEST_EVENT_CHANNEL_SIZE).0)?;
+    let state = Core::first_node(
+        node,
+        event_stream::channel(TEST_EVENT_CHANNEL_SIZE).0,
+        NetworkParams::default(),
+    )?;
     let dispatcher = Dispatcher::new(state, create_comm().await?);
 
     let new_node = Node::new(
@@ -242,7 +285,7 @@ async fn receive_join_request_with_resource_proof_response() -> Result<()> {
     let nonce_signature = ed25519::sign(&serialized, &dispatcher.core.read().await.node().keypair);
 
     let rp = ResourceProof::new(RESOURCE_PROOF_DATA_SIZE, RESOURCE_PROOF_DIFFICULTY);
-    let data = rp.create_proof_data(&nonce);
+    let data = rp.create_proof_data(&params.bind_nonce(&nonce));
     let mut prover = rp.create_prover(data.clone());
     let solution = prover.solve();
 
@@ -291,9 +334,7 @@ async fn receive_join_request_with_resource_proof_response() -> Result<()> {
         }
     }
 
-    assert!(test_connectivity);
-
-    Ok(())
+    Ok(test_connectivity)
 }
 
 #[tokio::test]
@@ -311,7 +352,8 @@ async fn receive_join_request_from_relocated_node() -> Result<()> {
         node,
         section,
         Some(section_key_share),
-        mpsc::channel(TEST_EVENT_CHANNEL_SIZE).0,
+        event_stream::channel(TEST_EVENT_CHANNEL_SIZE).0,
+        NetworkParams::default(),
     );
     let dispatcher = Dispatcher::new(state, create_comm().await?);
 
@@ -408,7 +450,8 @@ async fn aggregate_proposals() -> Result<()> {
         nodes[0].clone(),
         section.clone(),
         Some(section_key_share),
-        mpsc::channel(TEST_EVENT_CHANNEL_SIZE).0,
+        event_stream::channel(TEST_EVENT_CHANNEL_SIZE).0,
+        NetworkParams::default(),
     );
     let dispatcher = Dispatcher::new(state, create_comm().await?);
 
@@ -484,7 +527,7 @@ async fn aggregate_proposals() -> Result<()> {
 
 #[tokio::test]
 async fn handle_agreement_on_online() -> Result<()> {
-    let (event_tx, mut event_rx) = mpsc::channel(TEST_EVENT_CHANNEL_SIZE);
+    let (event_tx, mut event_rx) = event_stream::channel(TEST_EVENT_CHANNEL_SIZE);
 
     let prefix = Prefix::default();
 
@@ -492,7 +535,13 @@ async fn handle_agreement_on_online() -> Result<()> {
     let sk_set = SecretKeySet::random();
     let (section, section_key_share) = create_section(&sk_set, &section_auth)?;
     let node = nodes.remove(0);
-    let state = Core::new(node, section, Some(section_key_share), event_tx);
+    let state = Core::new(
+        node,
+        section,
+        Some(section_key_share),
+        event_tx,
+        NetworkParams::default(),
+    );
     let dispatcher = Dispatcher::new(state, create_comm().await?);
 
     let new_peer = create_peer(MIN_AGE);
@@ -500,11 +549,22 @@ async fn handle_agreement_on_online() -> Result<()> {
     let status = handle_online_command(&new_peer, &sk_set, &dispatcher, &section_auth).await?;
     assert!(status.node_approval_sent);
 
-    assert_matches!(event_rx.recv().await, Some(Event::MemberJoined { name, age, .. }) => {
+    assert_matches!(event_rx.next().await, Some(Event::MemberJoined { name, age, .. }) => {
         assert_eq!(name, *new_peer.name());
         assert_eq!(age, MIN_AGE);
     });
 
+    // Our section is the whole network, so the estimate is exact.
+    assert_matches!(event_rx.next().await, Some(Event::NetworkStatsChanged(stats)) => {
+        assert_eq!(stats.known_sections, 1);
+        assert_eq!(stats.estimated_nodes, ELDER_SIZE as u64 + 1);
+        assert!(stats.confident);
+    });
+    assert_eq!(
+        dispatcher.core.read().await.network_stats().estimated_nodes,
+        ELDER_SIZE as u64 + 1
+    );
+
     Ok(())
 }
 
@@ -547,7 +607,8 @@ async fn handle_agreement_on_online_of_elder_candidate() -> Result<()> {
         node,
         section,
         Some(section_key_share),
-        mpsc::channel(TEST_EVENT_CHANNEL_SIZE).0,
+        event_stream::channel(TEST_EVENT_CHANNEL_SIZE).0,
+        NetworkParams::default(),
     );
     let dispatcher = Dispatcher::new(state, create_comm().await?);
 
@@ -694,9 +755,15 @@ async fn handle_agreement_on_online_of_rejoined_node(phase: NetworkPhase, age: u
     let _ = section.update_member(node_state);
 
     // Make a Node
-    let (event_tx, _event_rx) = mpsc::channel(TEST_EVENT_CHANNEL_SIZE);
+    let (event_tx, _event_rx) = event_stream::channel(TEST_EVENT_CHANNEL_SIZE);
     let node = nodes.remove(0);
-    let state = Core::new(node, section, Some(section_key_share), event_tx);
+    let state = Core::new(
+        node,
+        section,
+        Some(section_key_share),
+        event_tx,
+        NetworkParams::default(),
+    );
     let dispatcher = Dispatcher::new(state, create_comm().await?);
 
     // Simulate peer with the same name is rejoin and verify resulted behaviours.
@@ -750,9 +817,15 @@ async fn handle_agreement_on_offline_of_non_elder() -> Result<()> {
     let node_state = section_signed(sk_set.secret_key(), node_state)?;
     let _ = section.update_member(node_state);
 
-    let (event_tx, mut event_rx) = mpsc::channel(TEST_EVENT_CHANNEL_SIZE);
+    let (event_tx, mut event_rx) = event_stream::channel(TEST_EVENT_CHANNEL_SIZE);
     let node = nodes.remove(0);
-    let state = Core::new(node, section, Some(section_key_share), event_tx);
+    let state = Core::new(
+        node,
+        section,
+        Some(section_key_share),
+        event_tx,
+        NetworkParams::default(),
+    );
     let dispatcher = Dispatcher::new(state, create_comm().await?);
 
     let node_state = NodeState {
@@ -766,7 +839,7 @@ async fn handle_agreement_on_offline_of_non_elder() -> Result<()> {
         .handle_command(Command::HandleAgreement { proposal, signed })
         .await?;
 
-    assert_matches!(event_rx.recv().await, Some(Event::MemberLeft { name, age, }) => {
+    assert_matches!(event_rx.next().await, Some(Event::MemberLeft { name, age, }) => {
         assert_eq!(name, *existing_peer.name());
         assert_eq!(age, MIN_AGE);
     });
@@ -774,6 +847,56 @@ async fn handle_agreement_on_offline_of_non_elder() -> Result<()> {
     Ok(())
 }
 
+#[test]
+fn debug_snapshot() -> Result<()> {
+    let (section_auth, mut nodes) = create_section_auth();
+    let sk_set = SecretKeySet::random();
+
+    let (mut section, section_key_share) = create_section(&sk_set, &section_auth)?;
+
+    let existing_peer = create_peer(MIN_AGE);
+    let node_state = NodeState::joined(existing_peer);
+    let node_state = section_signed(sk_set.secret_key(), node_state)?;
+    let _ = section.update_member(node_state);
+
+    let (event_tx, _event_rx) = event_stream::channel(TEST_EVENT_CHANNEL_SIZE);
+    let node = nodes.remove(0);
+    let state = Core::new(
+        node,
+        section,
+        Some(section_key_share),
+        event_tx,
+        NetworkParams::default(),
+    );
+
+    let snapshot = state.debug_snapshot();
+    assert!(snapshot.is_elder);
+    assert_eq!(snapshot.prefix, section_auth.prefix);
+    assert_eq!(
+        snapshot.section_chain_keys.last(),
+        Some(&sk_set.secret_key().public_key())
+    );
+    itertools::assert_equal(
+        snapshot.elders.iter().map(|peer| peer.name),
+        section_auth.names(),
+    );
+    assert!(snapshot
+        .members
+        .iter()
+        .any(|member| member.name == *existing_peer.name()
+            && member.age == MIN_AGE
+            && member.state == MembershipState::Joined));
+    assert!(snapshot.network.is_empty());
+    assert!(snapshot.dkg_sessions.is_empty());
+    assert!(snapshot.split_barrier.is_empty());
+    assert_eq!(snapshot.relocate_state, None);
+    assert!(snapshot.joins_allowed);
+
+    let _ = bincode::serialize(&snapshot)?;
+
+    Ok(())
+}
+
 #[tokio::test]
 async fn handle_agreement_on_offline_of_elder() -> Result<()> {
     let (section_auth, mut nodes) = create_section_auth();
@@ -796,10 +919,16 @@ async fn handle_agreement_on_offline_of_elder() -> Result<()> {
         .leave()?;
 
     // Create our node
-    let (event_tx, mut event_rx) = mpsc::channel(TEST_EVENT_CHANNEL_SIZE);
+    let (event_tx, mut event_rx) = event_stream::channel(TEST_EVENT_CHANNEL_SIZE);
     let node = nodes.remove(0);
     let node_name = node.name();
-    let state = Core::new(node, section, Some(section_key_share), event_tx);
+    let state = Core::new(
+        node,
+        section,
+        Some(section_key_share),
+        event_tx,
+        NetworkParams::default(),
+    );
     let dispatcher = Dispatcher::new(state, create_comm().await?);
 
     // Handle agreement on the Offline proposal
@@ -849,7 +978,7 @@ async fn handle_agreement_on_offline_of_elder() -> Result<()> {
 
     assert!(dkg_start_sent);
 
-    assert_matches!(event_rx.recv().await, Some(Event::MemberLeft { name, .. }) => {
+    assert_matches!(event_rx.next().await, Some(Event::MemberLeft { name, .. }) => {
         assert_eq!(name, *remove_peer.name());
     });
 
@@ -865,6 +994,600 @@ async fn handle_agreement_on_offline_of_elder() -> Result<()> {
     Ok(())
 }
 
+#[tokio::test]
+async fn handle_leave_request() -> Result<()> {
+    let (section_auth, mut nodes) = create_section_auth();
+    let sk_set = SecretKeySet::random();
+
+    let (mut section, section_key_share) = create_section(&sk_set, &section_auth)?;
+
+    let leaving_node = create_node(MIN_AGE);
+    let leaving_peer = leaving_node.peer();
+    let node_state = NodeState::joined(leaving_peer);
+    let node_state = section_signed(sk_set.secret_key(), node_state)?;
+    let _ = section.update_member(node_state);
+
+    let (event_tx, mut event_rx) = event_stream::channel(TEST_EVENT_CHANNEL_SIZE);
+    let node = nodes.remove(0);
+    let node_name = node.name();
+    let section_key = *section.chain().last_key();
+    let state = Core::new(
+        node,
+        section,
+        Some(section_key_share),
+        event_tx,
+        NetworkParams::default(),
+    );
+    let dispatcher = Dispatcher::new(state, create_comm().await?);
+
+    // Receive the leave request.
+    let message = RoutingMsg::single_src(
+        &leaving_node,
+        DstLocation::DirectAndUnrouted,
+        Variant::UserMessage(Envelope::Leave.encode()?),
+        section_key,
+    )?;
+
+    let commands = dispatcher
+        .handle_command(Command::HandleMessage {
+            sender: Some(leaving_node.addr),
+            message,
+            dest_info: DestInfo {
+                dest: node_name,
+                dest_section_pk: section_key,
+            },
+        })
+        .await?;
+
+    // Verify we proposed the leaving node offline.
+    let offline_proposed = commands.into_iter().any(|command| {
+        let message = match command {
+            Command::HandleMessage { message, .. } => message,
+            Command::SendMessage {
+                message: MessageType::Routing { msg, .. },
+                ..
+            } => msg,
+            _ => return false,
+        };
+
+        matches!(
+            message.variant,
+            Variant::Propose {
+                content: Proposal::Offline(node_state),
+                ..
+            } if node_state.peer.name() == leaving_peer.name()
+                && node_state.has_left_voluntarily()
+        )
+    });
+    assert!(offline_proposed);
+
+    // Handle the agreement on it and verify the leaving node gets notified.
+    let proposal = Proposal::Offline(NodeState::joined(leaving_peer).leave_voluntarily()?);
+    let signed = prove(sk_set.secret_key(), &proposal.as_signable())?;
+
+    let commands = dispatcher
+        .handle_command(Command::HandleAgreement { proposal, signed })
+        .await?;
+
+    let sync_sent = commands.into_iter().any(|command| match command {
+        Command::SendMessage {
+            recipients,
+            message: MessageType::Routing { msg, .. },
+            ..
+        } => {
+            recipients == [(*leaving_peer.name(), *leaving_peer.addr())]
+                && matches!(msg.variant, Variant::Sync { .. })
+        }
+        _ => false,
+    });
+    assert!(sync_sent);
+
+    assert_matches!(event_rx.next().await, Some(Event::MemberLeft { name, .. }) => {
+        assert_eq!(name, *leaving_peer.name());
+    });
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn handle_aggregated_response_from_section() -> Result<()> {
+    let (section_auth, _) = create_section_auth();
+    let sk_set = SecretKeySet::random();
+    let (section, _) = create_section(&sk_set, &section_auth)?;
+    let section_key = *section.chain().last_key();
+    let section_name = section_auth.prefix().name();
+
+    let node = create_node(MIN_ADULT_AGE);
+    let node_name = node.name();
+    let state = Core::new(
+        node,
+        section,
+        None,
+        event_stream::channel(TEST_EVENT_CHANNEL_SIZE).0,
+        NetworkParams::default(),
+    );
+    let dispatcher = Dispatcher::new(state, create_comm().await?);
+
+    let (response_tx, mut response_rx) = oneshot::channel();
+    let commands = dispatcher
+        .handle_command(Command::SendRequest {
+            itinerary: Itinerary {
+                src: SrcLocation::Node(node_name),
+                dst: DstLocation::Section(section_name),
+                aggregation: Aggregation::None,
+            },
+            content: Bytes::from_static(b"request"),
+            timeout: Duration::from_secs(10),
+            response_tx,
+        })
+        .await?;
+    let id = sent_request_id(commands).expect("request not sent");
+
+    let response = PlainMessage {
+        src: section_name,
+        dst: DstLocation::Node(node_name),
+        dst_key: section_key,
+        variant: Variant::UserMessage(
+            Envelope::Response {
+                id,
+                content: b"response".to_vec(),
+            }
+            .encode()?,
+        ),
+    };
+    let signed = prove(sk_set.secret_key(), &response.as_signable())?;
+    let message = RoutingMsg::section_src(response, signed, SecuredLinkedList::new(section_key))?;
+
+    let _ = dispatcher
+        .handle_command(Command::HandleMessage {
+            sender: None,
+            message,
+            dest_info: DestInfo {
+                dest: node_name,
+                dest_section_pk: section_key,
+            },
+        })
+        .await?;
+
+    let response = response_rx.try_recv()?;
+    assert_eq!(response.content, Bytes::from_static(b"response"));
+    assert_eq!(response.src, SrcLocation::Section(section_name));
+    assert!(response.signed.is_some());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn reject_spoofed_response() -> Result<()> {
+    let (section_auth, elders) = create_section_auth();
+    let sk_set = SecretKeySet::random();
+    let (section, _) = create_section(&sk_set, &section_auth)?;
+    let section_key = *section.chain().last_key();
+    let section_name = section_auth.prefix().name();
+
+    let node = create_node(MIN_ADULT_AGE);
+    let node_name = node.name();
+    let state = Core::new(
+        node,
+        section,
+        None,
+        event_stream::channel(TEST_EVENT_CHANNEL_SIZE).0,
+        NetworkParams::default(),
+    );
+    let dispatcher = Dispatcher::new(state, create_comm().await?);
+
+    let (response_tx, mut response_rx) = oneshot::channel();
+    let commands = dispatcher
+        .handle_command(Command::SendRequest {
+            itinerary: Itinerary {
+                src: SrcLocation::Node(node_name),
+                dst: DstLocation::Section(section_name),
+                aggregation: Aggregation::None,
+            },
+            content: Bytes::from_static(b"request"),
+            timeout: Duration::from_secs(10),
+            response_tx,
+        })
+        .await?;
+    let id = sent_request_id(commands).expect("request not sent");
+
+    // Responses from a single node, be it one of the elders of the section or some other node,
+    // don't answer a request to the section.
+    for sender in &[elders[0].clone(), create_node(MIN_ADULT_AGE)] {
+        let message = RoutingMsg::single_src(
+            sender,
+            DstLocation::Node(node_name),
+            Variant::UserMessage(
+                Envelope::Response {
+                    id,
+                    content: b"spoofed".to_vec(),
+                }
+                .encode()?,
+            ),
+            section_key,
+        )?;
+
+        let _ = dispatcher
+            .handle_command(Command::HandleMessage {
+                sender: Some(sender.addr),
+                message,
+                dest_info: DestInfo {
+                    dest: node_name,
+                    dest_section_pk: section_key,
+                },
+            })
+            .await;
+
+        assert_matches!(response_rx.try_recv(), Err(TryRecvError::Empty));
+    }
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn reject_spoofed_receipt() -> Result<()> {
+    let (section_auth, elders) = create_section_auth();
+    let sk_set = SecretKeySet::random();
+    let (section, _) = create_section(&sk_set, &section_auth)?;
+    let section_key = *section.chain().last_key();
+    let section_name = section_auth.prefix().name();
+
+    let node = create_node(MIN_ADULT_AGE);
+    let node_name = node.name();
+    let state = Core::new(
+        node,
+        section,
+        None,
+        event_stream::channel(TEST_EVENT_CHANNEL_SIZE).0,
+        NetworkParams::default(),
+    );
+    let dispatcher = Dispatcher::new(state, create_comm().await?);
+
+    let msg_id = MessageId::new();
+    let (receipt_tx, mut receipt_rx) = oneshot::channel();
+    let _ = dispatcher
+        .handle_command(Command::SendMessageWithReceipt {
+            itinerary: Itinerary {
+                src: SrcLocation::Node(node_name),
+                dst: DstLocation::Section(section_name),
+                aggregation: Aggregation::None,
+            },
+            content: Bytes::from_static(b"hello"),
+            policy: DeliveryPolicy::default(),
+            msg_id,
+            receipt_tx,
+        })
+        .await?;
+
+    // A receipt from a single elder doesn't confirm the delivery to the whole section.
+    let sender = &elders[0];
+    let message = RoutingMsg::single_src(
+        sender,
+        DstLocation::Node(node_name),
+        Variant::UserMessage(Envelope::Receipt { id: msg_id }.encode()?),
+        section_key,
+    )?;
+    let _ = dispatcher
+        .handle_command(Command::HandleMessage {
+            sender: Some(sender.addr),
+            message,
+            dest_info: DestInfo {
+                dest: node_name,
+                dest_section_pk: section_key,
+            },
+        })
+        .await;
+
+    assert_matches!(receipt_rx.try_recv(), Err(TryRecvError::Empty));
+
+    Ok(())
+}
+
+// Returns the id of the request among the messages sent by `commands`.
+fn sent_request_id(commands: Vec<Command>) -> Option<RequestId> {
+    commands.into_iter().find_map(|command| {
+        let message = match command {
+            Command::SendMessage {
+                message: MessageType::Routing { msg, .. },
+                ..
+            } => msg,
+            _ => return None,
+        };
+
+        match message.variant {
+            Variant::UserMessage(content) => match Envelope::decode(&content).ok()? {
+                Envelope::Request { id, .. } => Some(id),
+                _ => None,
+            },
+            _ => None,
+        }
+    })
+}
+
+#[tokio::test]
+async fn share_socket_id_key_until_answered() -> Result<()> {
+    let (section_auth, nodes) = create_section_auth();
+    let sk_set = SecretKeySet::random();
+    let (section, section_key_share) = create_section(&sk_set, &section_auth)?;
+    let section_key = *section.chain().last_key();
+    let state = Core::new(
+        nodes[0].clone(),
+        section,
+        Some(section_key_share),
+        event_stream::channel(TEST_EVENT_CHANNEL_SIZE).0,
+        NetworkParams::default(),
+    );
+    let dispatcher = Dispatcher::new(state, create_comm().await?);
+
+    // Our share goes to every other elder, to be sent again on the timeout.
+    let commands = dispatcher.core.write().await.share_socket_id_key()?;
+    let token = assert_matches!(
+        &commands[..],
+        [
+            Command::SendMessage { recipients, message: MessageType::Routing { msg, .. }, .. },
+            Command::ScheduleTimeout { token, .. },
+        ] => {
+            assert_eq!(recipients.len(), nodes.len() - 1);
+            assert_eq!(socket_id_key_share(msg), Some((0, false)));
+            *token
+        }
+    );
+
+    // One of them answers with its own share.
+    let answering = &nodes[1];
+    let envelope = Envelope::SocketIdKeyShare {
+        section_key,
+        index: 1,
+        share: socket_id::sign_share(&create_section_key_share(&sk_set, 1)),
+        reply: true,
+    };
+    let message = RoutingMsg::single_src(
+        answering,
+        DstLocation::DirectAndUnrouted,
+        Variant::UserMessage(envelope.encode()?),
+        section_key,
+    )?;
+    let _ = dispatcher
+        .handle_command(Command::HandleMessage {
+            sender: Some(answering.addr),
+            message,
+            dest_info: DestInfo {
+                dest: nodes[0].name(),
+                dest_section_pk: section_key,
+            },
+        })
+        .await?;
+
+    // The share is sent again only to the elders that didn't answer.
+    let commands = dispatcher
+        .handle_command(Command::HandleTimeout(token))
+        .await?;
+    assert_matches!(
+        &commands[..],
+        [Command::SendMessage { recipients, .. }, Command::ScheduleTimeout { .. }] => {
+            assert_eq!(recipients.len(), nodes.len() - 2);
+            assert!(recipients.iter().all(|(name, _)| *name != answering.name()));
+        }
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn answer_socket_id_key_share() -> Result<()> {
+    let (section_auth, nodes) = create_section_auth();
+    let sk_set = SecretKeySet::random();
+    let (section, section_key_share) = create_section(&sk_set, &section_auth)?;
+    let section_key = *section.chain().last_key();
+    let state = Core::new(
+        nodes[0].clone(),
+        section,
+        Some(section_key_share),
+        event_stream::channel(TEST_EVENT_CHANNEL_SIZE).0,
+        NetworkParams::default(),
+    );
+    let dispatcher = Dispatcher::new(state, create_comm().await?);
+
+    // E.g. an elder that restarted before deriving the key.
+    let sender = &nodes[1];
+    let envelope = Envelope::SocketIdKeyShare {
+        section_key,
+        index: 1,
+        share: socket_id::sign_share(&create_section_key_share(&sk_set, 1)),
+        reply: false,
+    };
+    let message = RoutingMsg::single_src(
+        sender,
+        DstLocation::DirectAndUnrouted,
+        Variant::UserMessage(envelope.encode()?),
+        section_key,
+    )?;
+    let commands = dispatcher
+        .handle_command(Command::HandleMessage {
+            sender: Some(sender.addr),
+            message,
+            dest_info: DestInfo {
+                dest: nodes[0].name(),
+                dest_section_pk: section_key,
+            },
+        })
+        .await?;
+
+    let answered = commands.iter().any(|command| match command {
+        Command::SendMessage {
+            recipients,
+            message: MessageType::Routing { msg, .. },
+            ..
+        } => {
+            recipients == &[(sender.name(), sender.addr)]
+                && socket_id_key_share(msg) == Some((0, true))
+        }
+        _ => false,
+    });
+    assert!(answered);
+
+    Ok(())
+}
+
+// Returns the index of the sender and whether it's a reply, if `msg` carries a socket id key
+// share.
+fn socket_id_key_share(msg: &RoutingMsg) -> Option<(usize, bool)> {
+    match &msg.variant {
+        Variant::UserMessage(content) => match Envelope::decode(content).ok()? {
+            Envelope::SocketIdKeyShare { index, reply, .. } => Some((index, reply)),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+#[tokio::test]
+async fn route_to_authenticated_client_through_its_elder() -> Result<()> {
+    let (section_auth, nodes) = create_section_auth();
+    let sk_set = SecretKeySet::random();
+    let (section, _) = create_section(&sk_set, &section_auth)?;
+    let section_key = *section.chain().last_key();
+
+    let create_elder = |node: Node, comm: Comm| {
+        let state = Core::new(
+            node,
+            section.clone(),
+            None,
+            event_stream::channel(TEST_EVENT_CHANNEL_SIZE).0,
+            NetworkParams::default(),
+        );
+        Dispatcher::new(state, comm)
+    };
+    let other_elder = create_elder(nodes[0].clone(), create_comm().await?);
+    let client_elder = create_elder(nodes[1].clone(), create_comm().await?);
+
+    // The client authenticates with one of the elders only.
+    let client_addr = gen_addr();
+    let keypair = ed25519::Keypair::generate(&mut rand::thread_rng());
+    let challenge = client_elder
+        .core
+        .write()
+        .await
+        .start_client_handshake(client_addr)?;
+    let (identity, signature) = match challenge {
+        ClientHandshake::Challenge { nonce, node } => {
+            match ClientHandshake::prove_ed25519(&keypair, &nonce, &node) {
+                ClientHandshake::Proof {
+                    identity,
+                    signature,
+                } => (identity, signature),
+                _ => unreachable!(),
+            }
+        }
+        _ => unreachable!(),
+    };
+    let end_user = client_elder.core.write().await.complete_client_handshake(
+        client_addr,
+        identity,
+        &signature,
+    )?;
+
+    let message = client_message(&nodes[2], end_user, section_key)?;
+    let handle = |dispatcher: &Dispatcher| {
+        dispatcher.handle_command(Command::HandleMessage {
+            sender: Some(nodes[2].addr),
+            message: message.clone(),
+            dest_info: DestInfo {
+                dest: end_user.xorname,
+                dest_section_pk: section_key,
+            },
+        })
+    };
+
+    // The other elder doesn't know the client, so relays the message to the elder it
+    // authenticated with, among others.
+    let commands = handle(&other_elder).await?;
+    let relayed = commands.iter().any(|command| match command {
+        Command::SendMessage {
+            recipients,
+            message: MessageType::Routing { .. },
+            ..
+        } => recipients.contains(&(nodes[1].name(), nodes[1].addr)),
+        _ => false,
+    });
+    assert!(relayed);
+
+    // Which sends it on to the client.
+    let commands = handle(&client_elder).await?;
+    assert_matches!(&commands[..], [Command::SendMessage {
+        recipients,
+        message: MessageType::Client { .. },
+        ..
+    }] => {
+        assert_eq!(recipients, &[(end_user.xorname, client_addr)]);
+    });
+
+    Ok(())
+}
+
+// Creates a message from `sender` carrying a client message to `end_user`.
+fn client_message(
+    sender: &Node,
+    end_user: EndUser,
+    section_key: bls::PublicKey,
+) -> Result<RoutingMsg> {
+    let keypair = Keypair::new_ed25519(&mut rand::thread_rng());
+    let client_msg = ClientMsg::Process(ProcessMsg::Query {
+        id: MessageId::new(),
+        query: Query::Transfer(TransferQuery::GetBalance(keypair.public_key())),
+        client_signed: ClientSigned {
+            public_key: keypair.public_key(),
+            signature: keypair.sign(b"the msg"),
+        },
+    });
+    let content = client_msg.serialize(end_user.xorname, section_key)?;
+
+    Ok(RoutingMsg::single_src(
+        sender,
+        DstLocation::EndUser(end_user),
+        Variant::UserMessage(Envelope::user(content).encode()?),
+        section_key,
+    )?)
+}
+
+#[tokio::test]
+async fn ignore_direct_user_message_as_adult() -> Result<()> {
+    let (section_auth, nodes) = create_section_auth();
+    let sk_set = SecretKeySet::random();
+    let (section, _) = create_section(&sk_set, &section_auth)?;
+    let section_key = *section.chain().last_key();
+    let node = create_node(MIN_ADULT_AGE);
+    let node_name = node.name();
+    let (event_tx, mut event_rx) = event_stream::channel(TEST_EVENT_CHANNEL_SIZE);
+    let state = Core::new(node, section, None, event_tx, NetworkParams::default());
+    let dispatcher = Dispatcher::new(state, create_comm().await?);
+
+    let sender = &nodes[0];
+    let message = RoutingMsg::single_src(
+        sender,
+        DstLocation::DirectAndUnrouted,
+        Variant::UserMessage(Envelope::user(Bytes::from_static(b"hello")).encode()?),
+        section_key,
+    )?;
+    let commands = dispatcher
+        .handle_command(Command::HandleMessage {
+            sender: Some(sender.addr),
+            message,
+            dest_info: DestInfo {
+                dest: node_name,
+                dest_section_pk: section_key,
+            },
+        })
+        .await?;
+
+    assert!(commands.is_empty());
+    assert!(timeout(Duration::from_millis(10), event_rx.next())
+        .await
+        .is_err());
+
+    Ok(())
+}
+
 #[tokio::test]
 async fn handle_untrusted_message_from_peer() -> Result<()> {
     handle_untrusted_message(UntrustedMessageSource::Peer).await
@@ -913,7 +1636,8 @@ async fn handle_untrusted_message(source: UntrustedMessageSource) -> Result<()>
         node,
         section.clone(),
         None,
-        mpsc::channel(TEST_EVENT_CHANNEL_SIZE).0,
+        event_stream::channel(TEST_EVENT_CHANNEL_SIZE).0,
+        NetworkParams::default(),
     );
     let dispatcher = Dispatcher::new(state, create_comm().await?);
 
@@ -1035,7 +1759,8 @@ async fn handle_bounced_untrusted_message() -> Result<()> {
         node,
         section.clone(),
         Some(section_key_share),
-        mpsc::channel(TEST_EVENT_CHANNEL_SIZE).0,
+        event_stream::channel(TEST_EVENT_CHANNEL_SIZE).0,
+        NetworkParams::default(),
     );
     let dispatcher = Dispatcher::new(state, create_comm().await?);
 
@@ -1109,11 +1834,17 @@ async fn handle_sync() -> Result<()> {
     let old_section = Section::new(pk0, chain.clone(), section_signed_old_section_auth)?;
 
     // Create our node
-    let (event_tx, mut event_rx) = mpsc::channel(TEST_EVENT_CHANNEL_SIZE);
+    let (event_tx, mut event_rx) = event_stream::channel(TEST_EVENT_CHANNEL_SIZE);
     let section_key_share = create_section_key_share(&sk1_set, 0);
     let node = nodes.remove(0);
     let node_name = node.name();
-    let state = Core::new(node, old_section, Some(section_key_share), event_tx);
+    let state = Core::new(
+        node,
+        old_section,
+        Some(section_key_share),
+        event_tx,
+        NetworkParams::default(),
+    );
     let dispatcher = Dispatcher::new(state, create_comm().await?);
 
     // Create new `Section` as a successor to the previous one.
@@ -1164,7 +1895,7 @@ async fn handle_sync() -> Result<()> {
 
     // Verify our `Section` got updated.
     assert_matches!(
-        event_rx.recv().await,
+        event_rx.next().await,
         Some(Event::EldersChanged { elders, .. }) => {
             assert_eq!(elders.key, pk2);
             assert!(elders.added.iter().all(|a| new_section_elders.contains(a)));
@@ -1205,10 +1936,10 @@ async fn handle_untrusted_sync() -> Result<()> {
     let section_signed_new_section_auth = section_signed(&sk2, new_section_auth.clone())?;
     let new_section = Section::new(pk0, chain.truncate(2), section_signed_new_section_auth)?;
 
-    let (event_tx, mut event_rx) = mpsc::channel(TEST_EVENT_CHANNEL_SIZE);
+    let (event_tx, mut event_rx) = event_stream::channel(TEST_EVENT_CHANNEL_SIZE);
     let node = create_node(MIN_ADULT_AGE);
     let node_name = node.name();
-    let state = Core::new(node, old_section, None, event_tx);
+    let state = Core::new(node, old_section, None, event_tx, NetworkParams::default());
     let dispatcher = Dispatcher::new(state, create_comm().await?);
 
     let sender = create_node(MIN_ADULT_AGE);
@@ -1256,7 +1987,7 @@ async fn handle_untrusted_sync() -> Result<()> {
     }
 
     assert!(bounce_sent);
-    assert!(timeout(Duration::from_secs(5), event_rx.recv())
+    assert!(timeout(Duration::from_secs(5), event_rx.next())
         .await
         .is_err());
 
@@ -1285,7 +2016,7 @@ async fn handle_bounced_untrusted_sync() -> Result<()> {
     let section_signed_section_auth = section_signed(sk2, section_auth.clone())?;
     let section_full = Section::new(pk0, chain, section_signed_section_auth)?;
 
-    let (event_tx, _) = mpsc::channel(TEST_EVENT_CHANNEL_SIZE);
+    let (event_tx, _) = event_stream::channel(TEST_EVENT_CHANNEL_SIZE);
     let node = nodes.remove(0);
     let node_name = node.name();
     let section_key_share = create_section_key_share(&sk2_set, 0);
@@ -1294,6 +2025,7 @@ async fn handle_bounced_untrusted_sync() -> Result<()> {
         section_full.clone(),
         Some(section_key_share),
         event_tx,
+        NetworkParams::default(),
     );
     let dispatcher = Dispatcher::new(state, create_comm().await?);
 
@@ -1388,7 +2120,8 @@ async fn relocation(relocated_peer_role: RelocatedPeerRole) -> Result<()> {
         node,
         section,
         Some(section_key_share),
-        mpsc::channel(TEST_EVENT_CHANNEL_SIZE).0,
+        event_stream::channel(TEST_EVENT_CHANNEL_SIZE).0,
+        NetworkParams::default(),
     );
     let dispatcher = Dispatcher::new(state, create_comm().await?);
 
@@ -1468,7 +2201,11 @@ enum MessageDst {
 async fn message_to_self(dst: MessageDst) -> Result<()> {
     let node = create_node(MIN_ADULT_AGE);
     let peer = node.peer();
-    let state = Core::first_node(node, mpsc::channel(TEST_EVENT_CHANNEL_SIZE).0)?;
+    let state = Core::first_node(
+        node,
+        event_stream::channel(TEST_EVENT_CHANNEL_SIZE).0,
+        NetworkParams::default(),
+    )?;
     let dispatcher = Dispatcher::new(state, create_comm().await?);
     let section_name = XorName::random();
 
@@ -1498,7 +2235,8 @@ async fn message_to_self(dst: MessageDst) -> Result<()> {
         assert_eq!(dest_info.dest, dst_name);
         assert_matches!(
             &message.variant,
-            Variant::UserMessage(actual_content) if actual_content.clone() == content
+            Variant::UserMessage(actual_content)
+                if Envelope::decode(actual_content)? == Envelope::user(content)
         );
     });
 
@@ -1558,8 +2296,14 @@ async fn handle_elders_update() -> Result<()> {
         public_key: pk0,
     };
 
-    let (event_tx, mut event_rx) = mpsc::channel(TEST_EVENT_CHANNEL_SIZE);
-    let state = Core::new(node, section0.clone(), Some(section_key_share), event_tx);
+    let (event_tx, mut event_rx) = event_stream::channel(TEST_EVENT_CHANNEL_SIZE);
+    let state = Core::new(
+        node,
+        section0.clone(),
+        Some(section_key_share),
+        event_tx,
+        NetworkParams::default(),
+    );
     let dispatcher = Dispatcher::new(state, create_comm().await?);
 
     let commands = dispatcher
@@ -1605,7 +2349,7 @@ async fn handle_elders_update() -> Result<()> {
     assert_eq!(sync_actual_recipients, sync_expected_recipients);
 
     assert_matches!(
-        event_rx.recv().await,
+        event_rx.next().await,
         Some(Event::EldersChanged { elders, .. }) => {
             assert_eq!(elders.key, pk1);
             assert_eq!(elder_names1, elders.added.union(&elders.remaining).copied().collect());
@@ -1653,8 +2397,14 @@ async fn handle_demote_during_split() -> Result<()> {
         assert!(section.update_member(node_state));
     }
 
-    let (event_tx, _) = mpsc::channel(TEST_EVENT_CHANNEL_SIZE);
-    let state = Core::new(node, section, Some(section_key_share), event_tx);
+    let (event_tx, _) = event_stream::channel(TEST_EVENT_CHANNEL_SIZE);
+    let state = Core::new(
+        node,
+        section,
+        Some(section_key_share),
+        event_tx,
+        NetworkParams::default(),
+    );
     let dispatcher = Dispatcher::new(state, create_comm().await?);
 
     let sk_set_v1_p0 = SecretKeySet::random();
@@ -1737,6 +2487,46 @@ pub fn init_log() {
         .init()
 }
 
+#[tokio::test]
+async fn handle_incoming_consensus_message_once_queue_drained() -> Result<()> {
+    let state = Core::first_node(
+        create_node(MIN_ADULT_AGE),
+        event_stream::channel(TEST_EVENT_CHANNEL_SIZE).0,
+        NetworkParams::default(),
+    )?;
+    let dispatcher = Arc::new(Dispatcher::new(state, create_comm().await?));
+
+    // The worker only runs once the test yields, so the first message fills the queue.
+    dispatcher.start(CommandLimits {
+        consensus: 1,
+        workers: 1,
+        consensus_workers: 0,
+        ..Default::default()
+    });
+    dispatcher
+        .handle_incoming(Command::SetJoinsAllowed(false))
+        .await;
+    assert!(dispatcher.is_saturated());
+
+    // The next ones wait for the worker to make room, then get through.
+    for joins_allowed in &[true, false] {
+        timeout(
+            Duration::from_secs(5),
+            dispatcher.handle_incoming(Command::SetJoinsAllowed(*joins_allowed)),
+        )
+        .await?;
+    }
+
+    timeout(Duration::from_secs(5), async {
+        while dispatcher.is_saturated() {
+            tokio::task::yield_now().await
+        }
+    })
+    .await?;
+
+    Ok(())
+}
+
 fn create_peer(age: u8) -> Peer {
     let name = ed25519::gen_name_with_age(age);
     let mut peer = Peer::new(name, gen_addr());
@@ -1758,16 +2548,16 @@ fn create_node(age: u8) -> Node {
     )
 }
 
+thread_local! {
+    // Network the comms of a test talk to each other over. Every test runs on its own thread.
+    static NETWORK: MemoryNetwork = MemoryNetwork::new();
+}
+
+// Creates a comm on the in-memory network of the current test, so the tests don't open sockets.
 async fn create_comm() -> Result<Comm> {
     let (tx, _rx) = mpsc::channel(TEST_EVENT_CHANNEL_SIZE);
-    Ok(Comm::new(
-        qp2p::Config {
-            local_ip: Some(Ipv4Addr::LOCALHOST.into()),
-            ..Default::default()
-        },
-        tx,
-    )
-    .await?)
+    let backend = TransportBackend::Memory(NETWORK.with(MemoryNetwork::clone));
+    Ok(Comm::with_backend(&backend, qp2p::Config::default(), tx).await?)
 }
 
 // Generate random SectionAuthorityProvider and the corresponding Nodes.
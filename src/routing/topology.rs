@@ -0,0 +1,292 @@
+// Copyright 2021 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under The General Public License (GPL), version 3.
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied. Please review the Licences for the specific language governing
+// permissions and limitations relating to use of the SAFE Network Software.
+
+use super::debug_snapshot::PeerSnapshot;
+use hex_fmt::HexFmt;
+use itertools::Itertools;
+use serde::Serialize;
+use std::{
+    collections::BTreeSet,
+    fmt::{self, Display, Formatter, Write},
+};
+use xor_name::{Prefix, XorName};
+
+/// A node's view of the network prefix tree: its own section and all the other sections it knows
+/// of. See `Routing::topology`.
+#[derive(Clone, Debug, Serialize)]
+pub struct Topology {
+    /// Name of the node whose view this is.
+    pub name: XorName,
+    /// The known sections, ordered by prefix. Ours is among them.
+    pub sections: Vec<TopologySection>,
+}
+
+/// A section in a `Topology`.
+#[derive(Clone, Debug, Serialize)]
+pub struct TopologySection {
+    /// Prefix of the section.
+    pub prefix: Prefix,
+    /// How the section relates to ours.
+    pub relation: SectionRelation,
+    /// The latest key of the section we know of.
+    pub key: bls::PublicKey,
+    /// Generation of the key, as the position in our section chain. For our section, this is the
+    /// position of our current key. For the other sections, it is the position of the key of ours
+    /// their info is vouched for with, so it tells how recently we learned about them. `None` if
+    /// that key is not in our chain (anymore).
+    pub generation: Option<u64>,
+    /// Elders of the section.
+    pub elders: Vec<PeerSnapshot>,
+}
+
+/// How a section relates to ours.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize)]
+pub enum SectionRelation {
+    /// Our own section.
+    Our,
+    /// The sibling of our section.
+    Sibling,
+    /// A section whose prefix differs from ours in exactly one bit.
+    Neighbour,
+    /// Any other section.
+    Other,
+}
+
+impl SectionRelation {
+    pub(crate) fn new(our: &Prefix, other: &Prefix) -> Self {
+        if other == our {
+            Self::Our
+        } else if *other == our.sibling() {
+            Self::Sibling
+        } else if other.is_neighbour(our) {
+            Self::Neighbour
+        } else {
+            Self::Other
+        }
+    }
+
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Our => "our",
+            Self::Sibling => "sibling",
+            Self::Neighbour => "neighbour",
+            Self::Other => "other",
+        }
+    }
+
+    fn fill_color(self) -> &'static str {
+        match self {
+            Self::Our => "palegreen",
+            Self::Sibling => "lightblue",
+            Self::Neighbour => "lightyellow",
+            Self::Other => "white",
+        }
+    }
+}
+
+impl Topology {
+    /// Renders the prefix tree as a Graphviz DOT digraph. Every known section is a box labelled
+    /// with its prefix, key, key generation and elders, coloured by its relation to our section.
+    /// The prefixes in between are drawn as points, to show the shape of the tree.
+    pub fn to_dot(&self) -> String {
+        Self::dot_tree(
+            &self.name.to_string(),
+            self.sections.iter().map(|section| {
+                let label = format!(
+                    "key: {:<8}\\ngeneration: {}\\nelders: {}",
+                    HexFmt(&section.key.to_bytes()),
+                    GenerationLabel(section.generation),
+                    section.elders.iter().map(|elder| elder.name).format(", ")
+                );
+                (section.prefix, section.relation.fill_color(), label)
+            }),
+        )
+    }
+
+    /// Renders a prefix tree as a Graphviz DOT digraph titled `title`, the way `to_dot` does, for
+    /// views of the network other than a single node's. Every section is given as its prefix, its
+    /// fill colour and the lines of its label below the prefix, separated by DOT's `\n`.
+    pub fn dot_tree<'a>(
+        title: &str,
+        sections: impl IntoIterator<Item = (Prefix, &'a str, String)>,
+    ) -> String {
+        let mut output = String::new();
+        let _ = writeln!(output, "digraph \"{}\" {{", title);
+        let _ = writeln!(
+            output,
+            "    node [shape=box, style=filled, fontname=monospace];"
+        );
+
+        let sections: Vec<_> = sections.into_iter().collect();
+        let known: BTreeSet<_> = sections.iter().map(|(prefix, ..)| *prefix).collect();
+        for prefix in tree_prefixes(known.iter()) {
+            if !known.contains(&prefix) {
+                let _ = writeln!(
+                    output,
+                    "    {} [shape=point, label=\"\", xlabel=\"{}\"];",
+                    dot_id(&prefix),
+                    PrefixLabel(&prefix)
+                );
+            }
+            if prefix.bit_count() > 0 {
+                let _ = writeln!(
+                    output,
+                    "    {} -> {};",
+                    dot_id(&prefix.popped()),
+                    dot_id(&prefix)
+                );
+            }
+        }
+
+        for (prefix, fill_color, label) in &sections {
+            let _ = writeln!(
+                output,
+                "    {} [fillcolor={}, label=\"{}\\n{}\"];",
+                dot_id(prefix),
+                fill_color,
+                PrefixLabel(prefix),
+                label
+            );
+        }
+
+        output.push_str("}\n");
+        output
+    }
+
+    /// Renders the view as JSON.
+    pub fn to_json(&self) -> String {
+        let view = JsonTopology {
+            name: HexFmt(&self.name.0).to_string(),
+            sections: self
+                .sections
+                .iter()
+                .map(|section| JsonSection {
+                    prefix: format!("{:b}", section.prefix),
+                    relation: section.relation.as_str(),
+                    key: HexFmt(&section.key.to_bytes()).to_string(),
+                    generation: section.generation,
+                    elders: section
+                        .elders
+                        .iter()
+                        .map(|elder| JsonPeer {
+                            name: HexFmt(&elder.name.0).to_string(),
+                            addr: elder.addr.to_string(),
+                        })
+                        .collect(),
+                })
+                .collect(),
+        };
+
+        serde_json::to_string(&view).expect("topology serialises to JSON")
+    }
+}
+
+// JSON rendering of a `Topology`, with the names and keys in hex and the prefixes as bit strings,
+// the way they are displayed elsewhere.
+#[derive(Serialize)]
+struct JsonTopology {
+    name: String,
+    sections: Vec<JsonSection>,
+}
+
+#[derive(Serialize)]
+struct JsonSection {
+    prefix: String,
+    relation: &'static str,
+    key: String,
+    generation: Option<u64>,
+    elders: Vec<JsonPeer>,
+}
+
+#[derive(Serialize)]
+struct JsonPeer {
+    name: String,
+    addr: String,
+}
+
+// Returns the given prefixes together with all their ancestors.
+fn tree_prefixes<'a>(prefixes: impl IntoIterator<Item = &'a Prefix>) -> BTreeSet<Prefix> {
+    let mut all = BTreeSet::new();
+    for prefix in prefixes {
+        let mut prefix = *prefix;
+        while all.insert(prefix) && prefix.bit_count() > 0 {
+            prefix = prefix.popped();
+        }
+    }
+    all
+}
+
+// Identifier of the node representing `prefix` in a DOT graph.
+fn dot_id(prefix: &Prefix) -> String {
+    format!("\"p{:b}\"", prefix)
+}
+
+// Displays a prefix the way it's labelled in a DOT graph, e.g. `(01)`.
+struct PrefixLabel<'a>(&'a Prefix);
+
+impl Display for PrefixLabel<'_> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "({:b})", self.0)
+    }
+}
+
+struct GenerationLabel(Option<u64>);
+
+impl Display for GenerationLabel {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self.0 {
+            Some(generation) => write!(f, "{}", generation),
+            None => write!(f, "?"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::section::test_utils::gen_addr;
+
+    #[test]
+    fn render() {
+        let our: Prefix = "01".parse().unwrap();
+        let sections: Vec<_> = ["01", "00", "1"]
+            .iter()
+            .map(|prefix| {
+                let prefix: Prefix = prefix.parse().unwrap();
+                TopologySection {
+                    prefix,
+                    relation: SectionRelation::new(&our, &prefix),
+                    key: bls::SecretKey::random().public_key(),
+                    generation: Some(1),
+                    elders: vec![PeerSnapshot {
+                        name: prefix.substituted_in(XorName::random()),
+                        addr: gen_addr(),
+                    }],
+                }
+            })
+            .collect();
+        assert_eq!(sections[1].relation, SectionRelation::Sibling);
+        assert_eq!(sections[2].relation, SectionRelation::Neighbour);
+
+        let topology = Topology {
+            name: XorName::random(),
+            sections,
+        };
+
+        let dot = topology.to_dot();
+        assert!(dot.contains("\"p\" -> \"p0\";"));
+        assert!(dot.contains("\"p0\" -> \"p01\";"));
+        assert!(dot.contains("\"p\" -> \"p1\";"));
+        assert!(dot.contains("\"p0\" [shape=point"));
+        assert!(dot.contains("\"p01\" [fillcolor=palegreen"));
+
+        let json = topology.to_json();
+        assert!(json.contains("\"prefix\":\"00\",\"relation\":\"sibling\""));
+        assert_eq!(json.matches("\"elders\":[{").count(), 3);
+    }
+}
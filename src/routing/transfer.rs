@@ -0,0 +1,452 @@
+// Copyright 2021 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under The General Public License (GPL), version 3.
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied. Please review the Licences for the specific language governing
+// permissions and limitations relating to use of the SAFE Network Software.
+
+//! Transfers of large messages between nodes, see `Routing::send_large_message`.
+//!
+//! The content is split into chunks sent as separate messages, each carrying the hash of its own
+//! content and of the whole content. The receiver acknowledges the chunks it has received so far
+//! in order, and the sender keeps at most `WINDOW` chunks beyond that in flight, going back to the
+//! first unacknowledged chunk when the acknowledgements stop coming. The sender serialises one
+//! chunk at a time instead of the whole content for every recipient, and the receiver limits the
+//! size and number of the transfers it accepts at once, in total and per sender. The receiver
+//! remembers the transfers it completed for a while, to acknowledge their chunks again if the
+//! last acknowledgement got lost, rather than receiving the message twice.
+//!
+//! The chunks and their acknowledgements are routed messages like any other, rather than being
+//! written to a qp2p stream: they reach the nodes we aren't connected to directly, they are signed
+//! by their sender, and qp2p reads every incoming stream itself anyway, handing over each message
+//! on it separately. So the chunks are kept small enough to be sent as messages, and they are
+//! queued with the network commands, which are never shed, rather than with the user messages.
+
+use crate::rng;
+use bytes::{Bytes, BytesMut};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashMap},
+    time::Duration,
+};
+use tiny_keccak::{Hasher, Sha3};
+use tokio::time::Instant;
+use xor_name::XorName;
+
+/// Size of the chunks.
+pub(crate) const CHUNK_SIZE: usize = 256 * 1024;
+/// Number of chunks sent ahead of the acknowledged ones.
+pub(crate) const WINDOW: u32 = 8;
+/// How long the sender waits for an acknowledgement before sending the unacknowledged chunks
+/// again.
+pub(crate) const ACK_TIMEOUT: Duration = Duration::from_secs(10);
+/// How many times in a row the sender sends the unacknowledged chunks again before giving up.
+pub(crate) const MAX_RETRIES: u32 = 3;
+
+// Largest content we accept.
+const MAX_SIZE: u64 = 64 * 1024 * 1024;
+// Number of transfers we receive at once at most.
+const MAX_INCOMING: usize = 16;
+// Total size of the content of the transfers we receive at once at most, and of those from any
+// single sender.
+const MAX_BUFFERED: u64 = 4 * MAX_SIZE;
+const MAX_BUFFERED_PER_SENDER: u64 = 2 * MAX_SIZE;
+// How long a transfer can go without a new chunk before we forget it, and how long we remember
+// a completed transfer for.
+const INCOMING_EXPIRY: Duration = Duration::from_secs(60);
+// Number of completed transfers we remember at most.
+const MAX_COMPLETED: usize = 1024;
+
+// Chunk of the content of a large message.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub(crate) struct Chunk {
+    pub transfer_id: u64,
+    pub index: u32,
+    pub count: u32,
+    // Size of the whole content.
+    pub size: u64,
+    // Hash of the whole content.
+    pub digest: [u8; 32],
+    // Hash of the content of this chunk.
+    pub hash: [u8; 32],
+    pub content: Vec<u8>,
+}
+
+// Large message being sent.
+pub(crate) struct OutgoingTransfer {
+    id: u64,
+    digest: [u8; 32],
+    content: Bytes,
+}
+
+impl OutgoingTransfer {
+    pub fn new(content: Bytes) -> Self {
+        Self {
+            id: rng::new().gen(),
+            digest: hash(&content),
+            content,
+        }
+    }
+
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    // Number of chunks. Empty content still takes one (empty) chunk.
+    pub fn count(&self) -> u32 {
+        chunk_count(self.content.len() as u64)
+    }
+
+    pub fn chunk(&self, index: u32) -> Chunk {
+        let start = (index as usize * CHUNK_SIZE).min(self.content.len());
+        let end = (start + CHUNK_SIZE).min(self.content.len());
+        let content = &self.content[start..end];
+
+        Chunk {
+            transfer_id: self.id,
+            index,
+            count: self.count(),
+            size: self.content.len() as u64,
+            digest: self.digest,
+            hash: hash(content),
+            content: content.to_vec(),
+        }
+    }
+}
+
+// Large messages being received, by their sender and transfer id.
+#[derive(Default)]
+pub(crate) struct IncomingTransfers {
+    transfers: HashMap<(XorName, u64), IncomingTransfer>,
+    // Transfers received in full, with their number of chunks and when they completed.
+    completed: HashMap<(XorName, u64), (u32, Instant)>,
+}
+
+struct IncomingTransfer {
+    count: u32,
+    size: u64,
+    digest: [u8; 32],
+    chunks: BTreeMap<u32, Bytes>,
+    last_active: Instant,
+}
+
+impl IncomingTransfer {
+    // Number of the chunks received in order, from the first one.
+    fn received(&self) -> u32 {
+        self.chunks
+            .keys()
+            .zip(0..)
+            .take_while(|(index, expected)| **index == *expected)
+            .count() as u32
+    }
+}
+
+// Outcome of receiving a chunk.
+#[derive(Debug, Eq, PartialEq)]
+pub(crate) enum Received {
+    // More chunks to come. Acknowledge this many.
+    Partial(u32),
+    // That was the last chunk, this is the content.
+    Complete(Bytes),
+    // Chunk of a transfer completed already, whose last acknowledgement may have been lost.
+    // Acknowledge this many again.
+    Duplicate(u32),
+    // The chunk is invalid or over the limits and was dropped.
+    Rejected,
+}
+
+impl IncomingTransfers {
+    pub fn receive(&mut self, sender: XorName, chunk: Chunk, now: Instant) -> Received {
+        if chunk.size > MAX_SIZE
+            || chunk.count != chunk_count(chunk.size)
+            || chunk.index >= chunk.count
+            || chunk.content.len() as u64 != chunk_len(chunk.size, chunk.index)
+            || hash(&chunk.content) != chunk.hash
+        {
+            return Received::Rejected;
+        }
+
+        self.transfers.retain(|_, transfer| {
+            now.saturating_duration_since(transfer.last_active) < INCOMING_EXPIRY
+        });
+        self.completed.retain(|_, (_, completed_at)| {
+            now.saturating_duration_since(*completed_at) < INCOMING_EXPIRY
+        });
+
+        let key = (sender, chunk.transfer_id);
+        if let Some((count, _)) = self.completed.get(&key) {
+            return Received::Duplicate(*count);
+        }
+
+        if !self.transfers.contains_key(&key) && !self.has_room_for(&sender, chunk.size) {
+            return Received::Rejected;
+        }
+
+        let transfer = self
+            .transfers
+            .entry(key)
+            .or_insert_with(|| IncomingTransfer {
+                count: chunk.count,
+                size: chunk.size,
+                digest: chunk.digest,
+                chunks: BTreeMap::new(),
+                last_active: now,
+            });
+        if (transfer.count, transfer.size, transfer.digest)
+            != (chunk.count, chunk.size, chunk.digest)
+        {
+            return Received::Rejected;
+        }
+
+        transfer.last_active = now;
+        let _ = transfer
+            .chunks
+            .insert(chunk.index, Bytes::from(chunk.content));
+
+        let received = transfer.received();
+        if received < transfer.count {
+            return Received::Partial(received);
+        }
+
+        let transfer = if let Some(transfer) = self.transfers.remove(&key) {
+            transfer
+        } else {
+            return Received::Rejected;
+        };
+        self.complete(key, transfer.count, now);
+
+        let mut content = BytesMut::with_capacity(transfer.size as usize);
+        for chunk in transfer.chunks.values() {
+            content.extend_from_slice(chunk);
+        }
+
+        if content.len() as u64 != transfer.size || hash(&content) != transfer.digest {
+            return Received::Rejected;
+        }
+
+        Received::Complete(content.freeze())
+    }
+
+    // Whether a new transfer of `size` bytes from `sender` stays within the limits.
+    fn has_room_for(&self, sender: &XorName, size: u64) -> bool {
+        if self.transfers.len() >= MAX_INCOMING {
+            return false;
+        }
+
+        let (total, from_sender) = self.transfers.iter().fold(
+            (size, size),
+            |(total, from_sender), ((transfer_sender, _), transfer)| {
+                if transfer_sender == sender {
+                    (total + transfer.size, from_sender + transfer.size)
+                } else {
+                    (total + transfer.size, from_sender)
+                }
+            },
+        );
+
+        total <= MAX_BUFFERED && from_sender <= MAX_BUFFERED_PER_SENDER
+    }
+
+    fn complete(&mut self, key: (XorName, u64), count: u32, now: Instant) {
+        if self.completed.len() >= MAX_COMPLETED {
+            let oldest = self
+                .completed
+                .iter()
+                .min_by_key(|(_, (_, completed_at))| *completed_at)
+                .map(|(key, _)| *key);
+            if let Some(oldest) = oldest {
+                let _ = self.completed.remove(&oldest);
+            }
+        }
+
+        let _ = self.completed.insert(key, (count, now));
+    }
+}
+
+// Number of chunks content of `size` bytes is split into. Empty content still takes one (empty)
+// chunk.
+fn chunk_count(size: u64) -> u32 {
+    ((size + CHUNK_SIZE as u64 - 1) / CHUNK_SIZE as u64).max(1) as u32
+}
+
+// Size of the chunk with the given index of content of `size` bytes.
+fn chunk_len(size: u64, index: u32) -> u64 {
+    let start = (u64::from(index) * CHUNK_SIZE as u64).min(size);
+    (size - start).min(CHUNK_SIZE as u64)
+}
+
+fn hash(bytes: &[u8]) -> [u8; 32] {
+    let mut output = [0; 32];
+    let mut hasher = Sha3::v256();
+    hasher.update(bytes);
+    hasher.finalize(&mut output);
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_matches::assert_matches;
+    use rand::seq::SliceRandom;
+
+    #[test]
+    fn split_and_reassemble() {
+        let content: Vec<u8> = (0..(2 * CHUNK_SIZE + 100)).map(|i| i as u8).collect();
+        let transfer = OutgoingTransfer::new(Bytes::from(content.clone()));
+        assert_eq!(transfer.count(), 3);
+
+        let mut indices: Vec<_> = (0..transfer.count()).collect();
+        indices.shuffle(&mut rand::thread_rng());
+
+        let sender = XorName::random();
+        let now = Instant::now();
+        let mut incoming = IncomingTransfers::default();
+        let mut outcomes: Vec<_> = indices
+            .iter()
+            .map(|index| incoming.receive(sender, transfer.chunk(*index), now))
+            .collect();
+
+        assert_eq!(
+            outcomes.pop(),
+            Some(Received::Complete(Bytes::from(content)))
+        );
+        assert!(outcomes
+            .iter()
+            .all(|outcome| matches!(outcome, Received::Partial(_))));
+    }
+
+    #[test]
+    fn acknowledge_in_order() {
+        let transfer = OutgoingTransfer::new(Bytes::from(vec![7; 3 * CHUNK_SIZE]));
+        let sender = XorName::random();
+        let now = Instant::now();
+        let mut incoming = IncomingTransfers::default();
+
+        assert_eq!(
+            incoming.receive(sender, transfer.chunk(1), now),
+            Received::Partial(0)
+        );
+        assert_eq!(
+            incoming.receive(sender, transfer.chunk(0), now),
+            Received::Partial(2)
+        );
+        // A chunk received again changes nothing.
+        assert_eq!(
+            incoming.receive(sender, transfer.chunk(0), now),
+            Received::Partial(2)
+        );
+    }
+
+    #[test]
+    fn reject_corrupted_chunk() {
+        let transfer = OutgoingTransfer::new(Bytes::from(vec![7; 2 * CHUNK_SIZE]));
+        let sender = XorName::random();
+        let now = Instant::now();
+        let mut incoming = IncomingTransfers::default();
+
+        let mut chunk = transfer.chunk(0);
+        chunk.content[0] ^= 1;
+        assert_eq!(incoming.receive(sender, chunk, now), Received::Rejected);
+
+        // A consistent chunk of other content doesn't pass the check of the whole content.
+        let other = OutgoingTransfer::new(Bytes::from(vec![8; 2 * CHUNK_SIZE]));
+        let mut chunk = other.chunk(1);
+        chunk.transfer_id = transfer.id();
+        chunk.digest = transfer.digest;
+        assert_eq!(
+            incoming.receive(sender, transfer.chunk(0), now),
+            Received::Partial(1)
+        );
+        assert_eq!(incoming.receive(sender, chunk, now), Received::Rejected);
+    }
+
+    #[test]
+    fn acknowledge_completed_transfer_again() {
+        let transfer = OutgoingTransfer::new(Bytes::from(vec![7; 2 * CHUNK_SIZE]));
+        let sender = XorName::random();
+        let now = Instant::now();
+        let mut incoming = IncomingTransfers::default();
+
+        assert_eq!(
+            incoming.receive(sender, transfer.chunk(0), now),
+            Received::Partial(1)
+        );
+        assert_matches!(
+            incoming.receive(sender, transfer.chunk(1), now),
+            Received::Complete(_)
+        );
+
+        // The sender didn't get the last acknowledgement and sends the chunks again.
+        assert_eq!(
+            incoming.receive(sender, transfer.chunk(1), now),
+            Received::Duplicate(2)
+        );
+
+        // Until it's forgotten, long after the sender gave up.
+        let later = now + INCOMING_EXPIRY;
+        assert_eq!(
+            incoming.receive(sender, transfer.chunk(1), later),
+            Received::Partial(0)
+        );
+    }
+
+    #[test]
+    fn reject_chunks_over_limits() {
+        let transfer = OutgoingTransfer::new(Bytes::from(vec![7; 2 * CHUNK_SIZE + 1]));
+        let sender = XorName::random();
+        let now = Instant::now();
+        let mut incoming = IncomingTransfers::default();
+
+        // A count not matching the size.
+        let mut chunk = transfer.chunk(0);
+        chunk.count = 100;
+        assert_eq!(incoming.receive(sender, chunk, now), Received::Rejected);
+
+        // A chunk shorter than its index implies.
+        let mut chunk = transfer.chunk(0);
+        chunk.content.truncate(1);
+        chunk.hash = hash(&chunk.content);
+        assert_eq!(incoming.receive(sender, chunk, now), Received::Rejected);
+
+        // Transfers of a single sender over its share of the buffer.
+        let first_chunk = |transfer_id| {
+            let size = MAX_SIZE;
+            let content = vec![0; CHUNK_SIZE];
+            Chunk {
+                transfer_id,
+                index: 0,
+                count: chunk_count(size),
+                size,
+                digest: [0; 32],
+                hash: hash(&content),
+                content,
+            }
+        };
+        let per_sender = MAX_BUFFERED_PER_SENDER / MAX_SIZE;
+        for transfer_id in 0..per_sender {
+            assert_eq!(
+                incoming.receive(sender, first_chunk(transfer_id), now),
+                Received::Partial(1)
+            );
+        }
+        assert_eq!(
+            incoming.receive(sender, first_chunk(per_sender), now),
+            Received::Rejected
+        );
+
+        // And of all senders over the whole buffer.
+        let total = MAX_BUFFERED / MAX_SIZE;
+        for transfer_id in per_sender..total {
+            assert_eq!(
+                incoming.receive(XorName::random(), first_chunk(transfer_id), now),
+                Received::Partial(1)
+            );
+        }
+        assert_eq!(
+            incoming.receive(XorName::random(), first_chunk(total), now),
+            Received::Rejected
+        );
+    }
+}
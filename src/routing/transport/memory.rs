@@ -0,0 +1,410 @@
+// Copyright 2021 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under The General Public License (GPL), version 3.
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied. Please review the Licences for the specific language governing
+// permissions and limitations relating to use of the SAFE Network Software.
+
+use super::{Scheduler, Transport};
+use crate::{
+    error::{Error, Result},
+    routing::comm::ConnectionEvent,
+};
+use bytes::Bytes;
+use futures::{future::BoxFuture, FutureExt};
+use std::{
+    collections::{BTreeSet, HashMap},
+    fmt::{self, Debug, Formatter},
+    net::{Ipv4Addr, SocketAddr},
+    sync::{Arc, Mutex, MutexGuard},
+};
+use tokio::sync::mpsc;
+
+// Port the automatically assigned addresses start at.
+const FIRST_PORT: u16 = 1;
+
+/// In-process network connecting nodes that use `TransportBackend::Memory`.
+///
+/// Cloning it yields a handle to the same network. Every node gets a distinct address on it, so
+/// many nodes can run in a single process without opening any sockets.
+#[derive(Clone, Default)]
+pub struct MemoryNetwork {
+    inner: Arc<Mutex<Inner>>,
+    // Delays and orders the delivery of the messages, if set. Otherwise they are delivered
+    // immediately.
+    scheduler: Option<Arc<Scheduler>>,
+}
+
+#[derive(Default)]
+struct Inner {
+    next_port: u16,
+    // Senders for the connection events of the nodes currently on the network.
+    nodes: HashMap<SocketAddr, mpsc::Sender<ConnectionEvent>>,
+    // Established connections, each stored as an ordered pair of addresses.
+    connections: BTreeSet<(SocketAddr, SocketAddr)>,
+}
+
+impl MemoryNetwork {
+    /// Creates a new, empty network.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Creates a new, empty network whose messages are delivered by the given scheduler.
+    pub(crate) fn with_scheduler(scheduler: Arc<Scheduler>) -> Self {
+        Self {
+            inner: Arc::default(),
+            scheduler: Some(scheduler),
+        }
+    }
+
+    /// Returns the number of nodes currently on the network.
+    pub fn len(&self) -> usize {
+        self.lock().nodes.len()
+    }
+
+    /// Returns whether there are no nodes on the network.
+    pub fn is_empty(&self) -> bool {
+        self.lock().nodes.is_empty()
+    }
+
+    fn lock(&self) -> MutexGuard<Inner> {
+        self.inner.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    // Adds a node to the network, on the given port if any (and non-zero), otherwise on the next
+    // free one.
+    fn register(
+        &self,
+        port: Option<u16>,
+        event_tx: mpsc::Sender<ConnectionEvent>,
+    ) -> Result<SocketAddr> {
+        let mut inner = self.lock();
+
+        let addr = match port {
+            Some(port) if port != 0 => {
+                let addr = local_addr(port);
+                if inner.nodes.contains_key(&addr) {
+                    return Err(Error::AddressInUse(addr));
+                }
+                addr
+            }
+            _ => loop {
+                inner.next_port = inner.next_port.max(FIRST_PORT);
+                let addr = local_addr(inner.next_port);
+                inner.next_port = inner
+                    .next_port
+                    .checked_add(1)
+                    .ok_or(Error::AddressInUse(addr))?;
+
+                if !inner.nodes.contains_key(&addr) {
+                    break addr;
+                }
+            },
+        };
+
+        let _ = inner.nodes.insert(addr, event_tx);
+        Ok(addr)
+    }
+
+    // Removes the node from the network, notifying its peers of the disconnection.
+    fn unregister(&self, addr: &SocketAddr) {
+        let mut inner = self.lock();
+
+        if inner.nodes.remove(addr).is_none() {
+            return;
+        }
+
+        let peers: Vec<_> = inner
+            .connections
+            .iter()
+            .filter_map(|(a, b)| {
+                if a == addr {
+                    Some(*b)
+                } else if b == addr {
+                    Some(*a)
+                } else {
+                    None
+                }
+            })
+            .collect();
+
+        for peer in peers {
+            let _ = inner.connections.remove(&ordered(*addr, peer));
+
+            if let Some(tx) = inner.nodes.get(&peer) {
+                // Disconnections are not worth blocking on, drop them if the peer is lagging.
+                let _ = tx.try_send(ConnectionEvent::Disconnected(*addr));
+            }
+        }
+    }
+
+    // Delivers the message from `src` to `dst`, connecting them first if `connect` is true.
+    async fn deliver(
+        &self,
+        src: SocketAddr,
+        dst: SocketAddr,
+        msg: Bytes,
+        connect: bool,
+    ) -> Result<()> {
+        let tx = {
+            let mut inner = self.lock();
+
+            if !inner.nodes.contains_key(&src) {
+                return Err(Error::ConnectionClosed);
+            }
+
+            let tx = inner
+                .nodes
+                .get(&dst)
+                .cloned()
+                .ok_or(Error::PeerUnreachable(dst))?;
+
+            let connection = ordered(src, dst);
+            if connect {
+                let _ = inner.connections.insert(connection);
+            } else if !inner.connections.contains(&connection) {
+                return Err(Error::PeerUnreachable(dst));
+            }
+
+            tx
+        };
+
+        let event = ConnectionEvent::Received((src, msg));
+
+        if let Some(scheduler) = &self.scheduler {
+            scheduler.schedule(tx, event);
+            Ok(())
+        } else {
+            tx.send(event)
+                .await
+                .map_err(|_| Error::PeerUnreachable(dst))
+        }
+    }
+
+    fn contains(&self, addr: &SocketAddr) -> bool {
+        self.lock().nodes.contains_key(addr)
+    }
+}
+
+impl Debug for MemoryNetwork {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_struct("MemoryNetwork")
+            .field("nodes", &self.len())
+            .finish()
+    }
+}
+
+// Transport over a `MemoryNetwork`.
+pub(crate) struct MemoryTransport {
+    network: MemoryNetwork,
+    addr: SocketAddr,
+}
+
+impl MemoryTransport {
+    pub fn new(
+        network: &MemoryNetwork,
+        port: Option<u16>,
+        event_tx: mpsc::Sender<ConnectionEvent>,
+    ) -> Result<Self> {
+        let addr = network.register(port, event_tx)?;
+
+        Ok(Self {
+            network: network.clone(),
+            addr,
+        })
+    }
+
+    // Connects to the first of the contacts that is on the network and returns its address.
+    pub fn bootstrap<'a>(
+        &self,
+        contacts: impl IntoIterator<Item = &'a SocketAddr>,
+    ) -> Result<SocketAddr> {
+        let mut inner = self.network.lock();
+
+        let contact = contacts
+            .into_iter()
+            .find(|addr| **addr != self.addr && inner.nodes.contains_key(addr))
+            .copied()
+            .ok_or(Error::BootstrapFailed)?;
+        let _ = inner.connections.insert(ordered(self.addr, contact));
+
+        Ok(contact)
+    }
+}
+
+impl Transport for MemoryTransport {
+    fn local_addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    fn send(&self, recipient: SocketAddr, msg: Bytes) -> BoxFuture<'_, Result<()>> {
+        self.network
+            .deliver(self.addr, recipient, msg, true)
+            .boxed()
+    }
+
+    fn send_on_existing_connection(
+        &self,
+        recipient: SocketAddr,
+        msg: Bytes,
+    ) -> BoxFuture<'_, Result<()>> {
+        self.network
+            .deliver(self.addr, recipient, msg, false)
+            .boxed()
+    }
+
+    fn is_reachable(&self, peer: SocketAddr) -> BoxFuture<'_, Result<()>> {
+        let result = if self.network.contains(&peer) {
+            Ok(())
+        } else {
+            Err(Error::PeerUnreachable(peer))
+        };
+
+        async move { result }.boxed()
+    }
+
+    fn close(&self) {
+        self.network.unregister(&self.addr)
+    }
+}
+
+impl Drop for MemoryTransport {
+    fn drop(&mut self) {
+        self.close()
+    }
+}
+
+fn local_addr(port: u16) -> SocketAddr {
+    SocketAddr::from((Ipv4Addr::LOCALHOST, port))
+}
+
+fn ordered(a: SocketAddr, b: SocketAddr) -> (SocketAddr, SocketAddr) {
+    if a <= b {
+        (a, b)
+    } else {
+        (b, a)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+    use assert_matches::assert_matches;
+
+    #[tokio::test]
+    async fn send_and_receive() -> Result<()> {
+        let network = MemoryNetwork::new();
+
+        let (tx0, _rx0) = mpsc::channel(1);
+        let transport0 = MemoryTransport::new(&network, None, tx0)?;
+
+        let (tx1, mut rx1) = mpsc::channel(1);
+        let transport1 = MemoryTransport::new(&network, None, tx1)?;
+
+        assert_ne!(transport0.local_addr(), transport1.local_addr());
+        assert_eq!(network.len(), 2);
+
+        let msg = Bytes::from_static(b"hello");
+        transport0
+            .send(transport1.local_addr(), msg.clone())
+            .await?;
+
+        assert_matches!(
+            rx1.recv().await,
+            Some(ConnectionEvent::Received((src, received))) => {
+                assert_eq!(src, transport0.local_addr());
+                assert_eq!(received, msg);
+            }
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn send_on_existing_connection_requires_connection() -> Result<()> {
+        let network = MemoryNetwork::new();
+
+        let (tx0, _rx0) = mpsc::channel(1);
+        let transport0 = MemoryTransport::new(&network, None, tx0)?;
+
+        let (tx1, mut rx1) = mpsc::channel(1);
+        let transport1 = MemoryTransport::new(&network, None, tx1)?;
+
+        let msg = Bytes::from_static(b"hello");
+        assert_matches!(
+            transport0
+                .send_on_existing_connection(transport1.local_addr(), msg.clone())
+                .await,
+            Err(Error::PeerUnreachable(_))
+        );
+
+        // Connection established by the other side can be reused.
+        transport1
+            .send(transport0.local_addr(), msg.clone())
+            .await?;
+        transport0
+            .send_on_existing_connection(transport1.local_addr(), msg)
+            .await?;
+        assert_matches!(rx1.recv().await, Some(ConnectionEvent::Received(_)));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn disconnect_on_drop() -> Result<()> {
+        let network = MemoryNetwork::new();
+
+        let (tx0, mut rx0) = mpsc::channel(1);
+        let transport0 = MemoryTransport::new(&network, None, tx0)?;
+
+        let (tx1, _rx1) = mpsc::channel(1);
+        let transport1 = MemoryTransport::new(&network, None, tx1)?;
+        let addr1 = transport1.local_addr();
+
+        transport0.send(addr1, Bytes::from_static(b"hello")).await?;
+        drop(transport1);
+
+        assert_matches!(
+            rx0.recv().await,
+            Some(ConnectionEvent::Disconnected(addr)) => assert_eq!(addr, addr1)
+        );
+        assert_matches!(
+            transport0.is_reachable(addr1).await,
+            Err(Error::PeerUnreachable(_))
+        );
+        assert_eq!(network.len(), 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn bootstrap_to_first_available_contact() -> Result<()> {
+        let network = MemoryNetwork::new();
+
+        let (tx0, _rx0) = mpsc::channel(1);
+        let transport0 = MemoryTransport::new(&network, Some(5000), tx0)?;
+        let (tx1, _rx1) = mpsc::channel(1);
+        let transport1 = MemoryTransport::new(&network, None, tx1)?;
+
+        let missing = local_addr(6000);
+        assert_eq!(
+            transport1.bootstrap(&[missing, transport0.local_addr()])?,
+            transport0.local_addr()
+        );
+        assert_matches!(
+            transport1.bootstrap(&[missing]),
+            Err(Error::BootstrapFailed)
+        );
+
+        let (tx2, _rx2) = mpsc::channel(1);
+        assert_matches!(
+            MemoryTransport::new(&network, Some(5000), tx2),
+            Err(Error::AddressInUse(_))
+        );
+
+        Ok(())
+    }
+}
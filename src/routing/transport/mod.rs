@@ -0,0 +1,103 @@
+// Copyright 2021 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under The General Public License (GPL), version 3.
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied. Please review the Licences for the specific language governing
+// permissions and limitations relating to use of the SAFE Network Software.
+
+//! Network transports the node can run on.
+
+mod memory;
+mod quic;
+mod scheduler;
+
+pub use self::memory::MemoryNetwork;
+pub(crate) use self::scheduler::Scheduler;
+
+use self::{memory::MemoryTransport, quic::QuicTransport};
+use super::comm::ConnectionEvent;
+use crate::{error::Result, TransportConfig};
+use bytes::Bytes;
+use futures::future::BoxFuture;
+use std::net::SocketAddr;
+use tokio::sync::mpsc;
+
+/// Selects the transport the node uses to talk to the other nodes.
+#[derive(Clone, Debug)]
+pub enum TransportBackend {
+    /// QUIC over UDP, configured by `Config::transport_config`.
+    Quic,
+    /// In-process transport which delivers the messages over channels, without opening any
+    /// sockets. All the nodes that need to talk to each other must share the same `MemoryNetwork`.
+    Memory(MemoryNetwork),
+}
+
+impl Default for TransportBackend {
+    fn default() -> Self {
+        Self::Quic
+    }
+}
+
+// Low-level transport of serialised messages between nodes.
+//
+// Incoming messages and disconnections of peers are reported as `ConnectionEvent`s through the
+// sender the transport was created with.
+pub(crate) trait Transport: Send + Sync {
+    // Address the other nodes can reach us at.
+    fn local_addr(&self) -> SocketAddr;
+
+    // Sends the message to the recipient, connecting to it first if not already connected.
+    fn send(&self, recipient: SocketAddr, msg: Bytes) -> BoxFuture<'_, Result<()>>;
+
+    // Sends the message to the recipient only if we are already connected to it.
+    fn send_on_existing_connection(
+        &self,
+        recipient: SocketAddr,
+        msg: Bytes,
+    ) -> BoxFuture<'_, Result<()>>;
+
+    // Tests whether the peer can be reached by a fresh connection.
+    fn is_reachable(&self, peer: SocketAddr) -> BoxFuture<'_, Result<()>>;
+
+    // Closes all the connections and stops accepting new ones.
+    fn close(&self);
+}
+
+// Creates a transport listening for incoming connections.
+pub(crate) async fn bind(
+    backend: &TransportBackend,
+    transport_config: TransportConfig,
+    event_tx: mpsc::Sender<ConnectionEvent>,
+) -> Result<Box<dyn Transport>> {
+    match backend {
+        TransportBackend::Quic => Ok(Box::new(
+            QuicTransport::new(transport_config, event_tx).await?,
+        )),
+        TransportBackend::Memory(network) => Ok(Box::new(MemoryTransport::new(
+            network,
+            transport_config.local_port,
+            event_tx,
+        )?)),
+    }
+}
+
+// Creates a transport connected to one of the hard-coded contacts of `transport_config`. Returns
+// the transport along with the address of the contact.
+pub(crate) async fn bootstrap(
+    backend: &TransportBackend,
+    transport_config: TransportConfig,
+    event_tx: mpsc::Sender<ConnectionEvent>,
+) -> Result<(Box<dyn Transport>, SocketAddr)> {
+    match backend {
+        TransportBackend::Quic => {
+            let (transport, addr) = QuicTransport::bootstrap(transport_config, event_tx).await?;
+            Ok((Box::new(transport), addr))
+        }
+        TransportBackend::Memory(network) => {
+            let transport = MemoryTransport::new(network, transport_config.local_port, event_tx)?;
+            let addr = transport.bootstrap(&transport_config.hard_coded_contacts)?;
+            Ok((Box::new(transport), addr))
+        }
+    }
+}
@@ -0,0 +1,180 @@
+// Copyright 2021 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under The General Public License (GPL), version 3.
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied. Please review the Licences for the specific language governing
+// permissions and limitations relating to use of the SAFE Network Software.
+
+use super::Transport;
+use crate::{
+    error::{Error, Result},
+    routing::comm::ConnectionEvent,
+};
+use bytes::Bytes;
+use futures::{future::BoxFuture, stream::StreamExt, FutureExt};
+use qp2p::{Endpoint, QuicP2p};
+use std::net::SocketAddr;
+use tokio::{sync::mpsc, task};
+
+// Transport over QUIC, backed by `qp2p`.
+pub(crate) struct QuicTransport {
+    _quic_p2p: QuicP2p,
+    endpoint: Endpoint,
+}
+
+impl QuicTransport {
+    pub async fn new(
+        transport_config: qp2p::Config,
+        event_tx: mpsc::Sender<ConnectionEvent>,
+    ) -> Result<Self> {
+        let quic_p2p = QuicP2p::with_config(Some(transport_config), &[], true)
+            .map_err(|err| Error::InvalidConfig { err })?;
+
+        // Don't bootstrap, just create an endpoint to listen to
+        // the incoming messages from other nodes.
+        // This also returns the a channel where we can listen for
+        // disconnection events.
+        let (endpoint, _incoming_connections, incoming_messages, disconnections) = quic_p2p
+            .new_endpoint()
+            .await
+            .map_err(|err| Error::CannotConnectEndpoint { err })?;
+
+        let _ = task::spawn(handle_incoming_messages(
+            incoming_messages,
+            event_tx.clone(),
+        ));
+
+        let _ = task::spawn(handle_disconnection_events(disconnections, event_tx));
+
+        Ok(Self {
+            _quic_p2p: quic_p2p,
+            endpoint,
+        })
+    }
+
+    pub async fn bootstrap(
+        transport_config: qp2p::Config,
+        event_tx: mpsc::Sender<ConnectionEvent>,
+    ) -> Result<(Self, SocketAddr)> {
+        let quic_p2p = QuicP2p::with_config(Some(transport_config), &[], true)
+            .map_err(|err| Error::InvalidConfig { err })?;
+
+        // Bootstrap to the network returning the connection to a node.
+        // We can use the returned channels to listen for incoming messages and disconnection events
+        let (endpoint, _incoming_connections, incoming_messages, disconnections, bootstrap_addr) =
+            quic_p2p
+                .bootstrap()
+                .await
+                .map_err(|err| Error::CannotConnectEndpoint { err })?;
+
+        let _ = task::spawn(handle_incoming_messages(
+            incoming_messages,
+            event_tx.clone(),
+        ));
+
+        let _ = task::spawn(handle_disconnection_events(disconnections, event_tx));
+
+        Ok((
+            Self {
+                _quic_p2p: quic_p2p,
+                endpoint,
+            },
+            bootstrap_addr,
+        ))
+    }
+
+    async fn is_reachable_impl(&self, peer: SocketAddr) -> Result<()> {
+        let qp2p_config = qp2p::Config {
+            local_ip: Some(self.endpoint.local_addr().ip()),
+            local_port: Some(0),
+            forward_port: false,
+            ..Default::default()
+        };
+
+        let qp2p = QuicP2p::with_config(Some(qp2p_config), &[], false)
+            .map_err(|err| Error::InvalidConfig { err })?;
+        let (connectivity_endpoint, _, _, _) = qp2p
+            .new_endpoint()
+            .await
+            .map_err(|err| Error::CannotConnectEndpoint { err })?;
+
+        connectivity_endpoint
+            .is_reachable(&peer)
+            .await
+            .map_err(|err| Error::AddressNotReachable { err })
+    }
+}
+
+impl Transport for QuicTransport {
+    fn local_addr(&self) -> SocketAddr {
+        self.endpoint.socket_addr()
+    }
+
+    fn send(&self, recipient: SocketAddr, msg: Bytes) -> BoxFuture<'_, Result<()>> {
+        async move {
+            trace!("Low level send for msg over qp2p");
+            self.endpoint
+                .send_message(msg, &recipient)
+                .await
+                .map_err(|err| match err {
+                    qp2p::Error::Connection(qp2p::ConnectionError::LocallyClosed) => {
+                        Error::ConnectionClosed
+                    }
+                    _ => {
+                        trace!("during sending, received error {:?}", err);
+                        Error::AddressNotReachable { err }
+                    }
+                })
+        }
+        .boxed()
+    }
+
+    fn send_on_existing_connection(
+        &self,
+        recipient: SocketAddr,
+        msg: Bytes,
+    ) -> BoxFuture<'_, Result<()>> {
+        async move {
+            self.endpoint
+                .try_send_message(msg, &recipient)
+                .await
+                .map_err(|err| Error::AddressNotReachable { err })
+        }
+        .boxed()
+    }
+
+    fn is_reachable(&self, peer: SocketAddr) -> BoxFuture<'_, Result<()>> {
+        self.is_reachable_impl(peer).boxed()
+    }
+
+    fn close(&self) {
+        self.endpoint.close()
+    }
+}
+
+impl Drop for QuicTransport {
+    fn drop(&mut self) {
+        self.endpoint.close()
+    }
+}
+
+async fn handle_disconnection_events(
+    mut disconnections: qp2p::DisconnectionEvents,
+    event_tx: mpsc::Sender<ConnectionEvent>,
+) {
+    while let Some(peer_addr) = disconnections.next().await {
+        let _ = event_tx
+            .send(ConnectionEvent::Disconnected(peer_addr))
+            .await;
+    }
+}
+
+async fn handle_incoming_messages(
+    mut incoming_msgs: qp2p::IncomingMessages,
+    event_tx: mpsc::Sender<ConnectionEvent>,
+) {
+    while let Some((src, msg)) = incoming_msgs.next().await {
+        let _ = event_tx.send(ConnectionEvent::Received((src, msg))).await;
+    }
+}
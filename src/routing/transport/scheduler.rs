@@ -0,0 +1,173 @@
+// Copyright 2021 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under The General Public License (GPL), version 3.
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied. Please review the Licences for the specific language governing
+// permissions and limitations relating to use of the SAFE Network Software.
+
+use crate::{
+    rng::{self, MainRng},
+    routing::comm::ConnectionEvent,
+};
+use rand::Rng;
+use std::{
+    cmp::{Ordering, Reverse},
+    collections::BinaryHeap,
+    ops::Range,
+    sync::{Mutex, MutexGuard},
+    time::Duration,
+};
+use tokio::{
+    sync::{mpsc, Notify},
+    time::{self, Instant},
+};
+
+// Controls the delivery of the messages sent over a `MemoryNetwork`: every message is delayed by
+// a latency drawn from a (possibly seeded) random generator and the messages are delivered one at
+// a time in the order of their delivery times. Ties are broken by the order of sending, so the
+// delivery order depends only on the generator and on the clock.
+pub(crate) struct Scheduler {
+    state: Mutex<State>,
+    notify: Notify,
+}
+
+struct State {
+    rng: MainRng,
+    latency: Range<Duration>,
+    next_seq: u64,
+    queue: BinaryHeap<Reverse<Pending>>,
+}
+
+impl Scheduler {
+    pub fn new(latency: Range<Duration>) -> Self {
+        Self {
+            state: Mutex::new(State {
+                rng: rng::new(),
+                latency,
+                next_seq: 0,
+                queue: BinaryHeap::new(),
+            }),
+            notify: Notify::new(),
+        }
+    }
+
+    // Queues the event for delivery to `tx`.
+    pub fn schedule(&self, tx: mpsc::Sender<ConnectionEvent>, event: ConnectionEvent) {
+        let mut state = self.lock();
+
+        let latency = if state.latency.start < state.latency.end {
+            let start = state.latency.start.as_micros() as u64;
+            let end = state.latency.end.as_micros() as u64;
+            Duration::from_micros(state.rng.gen_range(start, end))
+        } else {
+            state.latency.start
+        };
+
+        let seq = state.next_seq;
+        state.next_seq += 1;
+        state.queue.push(Reverse(Pending {
+            at: Instant::now() + latency,
+            seq,
+            tx,
+            event,
+        }));
+
+        self.notify.notify_one();
+    }
+
+    // Delivers the queued events as they become due. Never returns.
+    pub async fn run(&self) {
+        loop {
+            let next = self.lock().queue.peek().map(|Reverse(pending)| pending.at);
+
+            match next {
+                Some(at) if at <= Instant::now() => {
+                    let pending = self.lock().queue.pop();
+                    if let Some(Reverse(pending)) = pending {
+                        // The recipient might have left the network meanwhile, which is fine.
+                        let _ = pending.tx.send(pending.event).await;
+                    }
+                }
+                Some(at) => {
+                    tokio::select! {
+                        _ = time::sleep_until(at) => {}
+                        _ = self.notify.notified() => {}
+                    }
+                }
+                None => self.notify.notified().await,
+            }
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<State> {
+        self.state.lock().unwrap_or_else(|err| err.into_inner())
+    }
+}
+
+struct Pending {
+    at: Instant,
+    seq: u64,
+    tx: mpsc::Sender<ConnectionEvent>,
+    event: ConnectionEvent,
+}
+
+impl PartialEq for Pending {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Pending {}
+
+impl PartialOrd for Pending {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Pending {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (self.at, self.seq).cmp(&(other.at, other.seq))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_matches::assert_matches;
+    use bytes::Bytes;
+    use std::{net::SocketAddr, sync::Arc};
+    use tokio::task;
+
+    #[tokio::test]
+    async fn delivers_in_order_of_delivery_time() {
+        time::pause();
+
+        let scheduler = Arc::new(Scheduler::new(
+            Duration::from_millis(10)..Duration::from_millis(10),
+        ));
+        let driver = task::spawn({
+            let scheduler = scheduler.clone();
+            async move { scheduler.run().await }
+        });
+
+        let (tx, mut rx) = mpsc::channel(10);
+        let addr0: SocketAddr = ([127, 0, 0, 1], 1).into();
+        let addr1: SocketAddr = ([127, 0, 0, 1], 2).into();
+
+        scheduler.schedule(tx.clone(), ConnectionEvent::Received((addr0, Bytes::new())));
+        scheduler.schedule(tx, ConnectionEvent::Received((addr1, Bytes::new())));
+
+        let start = Instant::now();
+        assert_matches!(rx.recv().await, Some(ConnectionEvent::Received((addr, _))) => {
+            assert_eq!(addr, addr0)
+        });
+        assert_matches!(rx.recv().await, Some(ConnectionEvent::Received((addr, _))) => {
+            assert_eq!(addr, addr1)
+        });
+        assert!(Instant::now() - start >= Duration::from_millis(10));
+
+        driver.abort();
+    }
+}
@@ -27,7 +27,7 @@ use crate::{
     dkg::SectionSignedUtils,
     error::{Error, Result},
     peer::PeerUtils,
-    ELDER_SIZE, RECOMMENDED_SECTION_SIZE,
+    rng, NetworkParams,
 };
 use secured_linked_list::{error::Error as SecuredLinkedListError, SecuredLinkedList};
 use serde::Serialize;
@@ -87,7 +87,11 @@ pub trait SectionUtils {
 
     /// Generate a new section info(s) based on the current set of members.
     /// Returns a set of candidate SectionAuthorityProviders.
-    fn promote_and_demote_elders(&self, our_name: &XorName) -> Vec<ElderCandidates>;
+    fn promote_and_demote_elders(
+        &self,
+        our_name: &XorName,
+        params: &NetworkParams,
+    ) -> Vec<ElderCandidates>;
 
     // Prefix of our section.
     fn prefix(&self) -> &Prefix;
@@ -98,7 +102,7 @@ pub trait SectionUtils {
     fn active_members(&self) -> Box<dyn Iterator<Item = &Peer> + '_>;
 
     /// Returns adults from our section.
-    fn adults(&self) -> Box<dyn Iterator<Item = &Peer> + '_>;
+    fn adults(&self, min_age: u8) -> Box<dyn Iterator<Item = &Peer> + '_>;
 
     /// Returns live adults from our section.
     fn live_adults(&self) -> Box<dyn Iterator<Item = &Peer> + '_>;
@@ -108,7 +112,11 @@ pub trait SectionUtils {
     // Tries to split our section.
     // If we have enough mature nodes for both subsections, returns the SectionAuthorityProviders
     // of the two subsections. Otherwise returns `None`.
-    fn try_split(&self, our_name: &XorName) -> Option<(ElderCandidates, ElderCandidates)>;
+    fn try_split(
+        &self,
+        our_name: &XorName,
+        params: &NetworkParams,
+    ) -> Option<(ElderCandidates, ElderCandidates)>;
 
     // Returns the candidates for elders out of all the nodes in the section, even out of the
     // relocating nodes if there would not be enough instead.
@@ -141,7 +149,7 @@ impl SectionUtils for Section {
 
     /// Creates `Section` for the first node in the network
     fn first_node(peer: Peer) -> Result<(Section, SectionKeyShare)> {
-        let secret_key_set = bls::SecretKeySet::random(0, &mut rand::thread_rng());
+        let secret_key_set = bls::SecretKeySet::random(0, &mut rng::new());
         let public_key_set = secret_key_set.public_keys();
         let secret_key_share = secret_key_set.secret_key_share(0);
 
@@ -298,12 +306,18 @@ impl SectionUtils for Section {
 
     /// Generate a new section info(s) based on the current set of members.
     /// Returns a set of candidate SectionAuthorityProviders.
-    fn promote_and_demote_elders(&self, our_name: &XorName) -> Vec<ElderCandidates> {
-        if let Some((our_elder_candidates, other_elder_candidates)) = self.try_split(our_name) {
+    fn promote_and_demote_elders(
+        &self,
+        our_name: &XorName,
+        params: &NetworkParams,
+    ) -> Vec<ElderCandidates> {
+        if let Some((our_elder_candidates, other_elder_candidates)) =
+            self.try_split(our_name, params)
+        {
             return vec![our_elder_candidates, other_elder_candidates];
         }
 
-        let expected_peers = self.elder_candidates(ELDER_SIZE);
+        let expected_peers = self.elder_candidates(params.elder_size);
         let expected_names: BTreeSet<_> = expected_peers.iter().map(Peer::name).cloned().collect();
         let current_names: BTreeSet<_> = self.authority_provider().names();
 
@@ -341,10 +355,10 @@ impl SectionUtils for Section {
     }
 
     /// Returns adults from our section.
-    fn adults(&self) -> Box<dyn Iterator<Item = &Peer> + '_> {
+    fn adults(&self, min_age: u8) -> Box<dyn Iterator<Item = &Peer> + '_> {
         Box::new(
             self.members
-                .mature()
+                .mature(min_age)
                 .filter(move |peer| !self.is_elder(peer.name())),
         )
     }
@@ -370,7 +384,11 @@ impl SectionUtils for Section {
     // Tries to split our section.
     // If we have enough mature nodes for both subsections, returns the SectionAuthorityProviders
     // of the two subsections. Otherwise returns `None`.
-    fn try_split(&self, our_name: &XorName) -> Option<(ElderCandidates, ElderCandidates)> {
+    fn try_split(
+        &self,
+        our_name: &XorName,
+        params: &NetworkParams,
+    ) -> Option<(ElderCandidates, ElderCandidates)> {
         let next_bit_index = if let Ok(index) = self.prefix().bit_count().try_into() {
             index
         } else {
@@ -382,7 +400,7 @@ impl SectionUtils for Section {
 
         let (our_new_size, sibling_new_size) = self
             .members
-            .mature()
+            .mature(params.min_age)
             .map(|peer| peer.name().bit(next_bit_index) == next_bit)
             .fold((0, 0), |(ours, siblings), is_our_prefix| {
                 if is_our_prefix {
@@ -393,7 +411,9 @@ impl SectionUtils for Section {
             });
 
         // If none of the two new sections would contain enough entries, return `None`.
-        if our_new_size < RECOMMENDED_SECTION_SIZE || sibling_new_size < RECOMMENDED_SECTION_SIZE {
+        if our_new_size < params.recommended_section_size
+            || sibling_new_size < params.recommended_section_size
+        {
             return None;
         }
 
@@ -402,12 +422,12 @@ impl SectionUtils for Section {
 
         let our_elders = self.members.elder_candidates_matching_prefix(
             &our_prefix,
-            ELDER_SIZE,
+            params.elder_size,
             self.authority_provider(),
         );
         let other_elders = self.members.elder_candidates_matching_prefix(
             &other_prefix,
-            ELDER_SIZE,
+            params.elder_size,
             self.authority_provider(),
         );
 
@@ -29,11 +29,19 @@ pub trait NodeStateUtils {
     // Creates a `NodeState` in the `Joined` state.
     fn joined(peer: Peer) -> NodeState;
 
-    // Is the age > `MIN_AGE`?
-    fn is_mature(&self) -> bool;
+    // Is the age > `min_age`?
+    fn is_mature(&self, min_age: u8) -> bool;
 
     fn leave(self) -> Result<NodeState, Error>;
 
+    // Like `leave`, but for a node that asked to leave. `NodeState` has no room for the reason, so
+    // the peer is marked unreachable instead, as it won't accept connections any more. Being part
+    // of the `Offline` proposal, the reason is agreed on by the elders together with the rest.
+    fn leave_voluntarily(self) -> Result<NodeState, Error>;
+
+    // Whether this is the state of a node that left because it asked to.
+    fn has_left_voluntarily(&self) -> bool;
+
     // Convert this info into one with the state changed to `Relocated`.
     fn relocate(self, destination: XorName) -> NodeState;
 }
@@ -47,9 +55,9 @@ impl NodeStateUtils for NodeState {
         }
     }
 
-    // Is the age > `MIN_AGE`?
-    fn is_mature(&self) -> bool {
-        self.peer.age() > MIN_AGE
+    // Is the age > `min_age`?
+    fn is_mature(&self, min_age: u8) -> bool {
+        self.peer.age() > min_age
     }
 
     fn leave(self) -> Result<NodeState, Error> {
@@ -64,6 +72,16 @@ impl NodeStateUtils for NodeState {
         })
     }
 
+    fn leave_voluntarily(self) -> Result<NodeState, Error> {
+        let mut state = self.leave()?;
+        state.peer.set_reachable(false);
+        Ok(state)
+    }
+
+    fn has_left_voluntarily(&self) -> bool {
+        self.state == MembershipState::Left && !self.peer.is_reachable()
+    }
+
     // Convert this info into one with the state changed to `Relocated`.
     fn relocate(self, destination: XorName) -> NodeState {
         NodeState {
@@ -76,6 +76,11 @@ impl SectionKeysProvider {
         self.cache.has_key_share()
     }
 
+    /// Returns all the finalised key shares, oldest first.
+    pub fn key_shares(&self) -> impl Iterator<Item = &SectionKeyShare> {
+        self.cache.iter()
+    }
+
     pub fn insert_dkg_outcome(&mut self, share: SectionKeyShare) {
         let public_key = share.public_key_set.public_key();
         let _ = self.pending.insert(public_key, share);
@@ -110,6 +115,11 @@ impl MiniKeyCache {
         !self.list.is_empty()
     }
 
+    /// Returns all the cached key shares, oldest first.
+    pub fn iter(&self) -> impl Iterator<Item = &SectionKeyShare> {
+        self.list.iter().map(|(_, share)| share)
+    }
+
     /// Returns the most recently added key.
     pub fn get_most_recent(&self) -> Result<&SectionKeyShare> {
         if let Some((_, share)) = self.list.back() {
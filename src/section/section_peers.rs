@@ -28,8 +28,8 @@ pub trait SectionPeersUtils {
     /// Returns an iterator over the members that have state == `Joined`.
     fn joined(&self) -> Box<dyn Iterator<Item = &NodeState> + '_>;
 
-    /// Returns joined nodes from our section with age greater than `MIN_AGE`
-    fn mature(&self) -> Box<dyn Iterator<Item = &Peer> + '_>;
+    /// Returns joined nodes from our section with age greater than `min_age`
+    fn mature(&self, min_age: u8) -> Box<dyn Iterator<Item = &Peer> + '_>;
 
     /// Get info for the member with the given name.
     fn get(&self, name: &XorName) -> Option<&NodeState>;
@@ -79,11 +79,11 @@ impl SectionPeersUtils for SectionPeers {
         )
     }
 
-    /// Returns joined nodes from our section with age greater than `MIN_AGE`
-    fn mature(&self) -> Box<dyn Iterator<Item = &Peer> + '_> {
+    /// Returns joined nodes from our section with age greater than `min_age`
+    fn mature(&self, min_age: u8) -> Box<dyn Iterator<Item = &Peer> + '_> {
         Box::new(
             self.joined()
-                .filter(|info| info.is_mature())
+                .filter(move |info| info.is_mature(min_age))
                 .map(|info| &info.peer),
         )
     }
@@ -234,7 +234,7 @@ fn cmp_elder_candidates(
 // Compare candidates for the next elders according to their peer state. The one comparing `Less`
 // wins. `Joined` is preferred over `Relocated` which is preferred over `Left`.
 // NOTE: we only consider `Relocated` peers as elder candidates if we don't have enough `Joined`
-// members to reach `ELDER_SIZE`.
+// members to reach `elder_size`.
 fn cmp_elder_candidates_by_membership_state(
     lhs: &MembershipState,
     rhs: &MembershipState,
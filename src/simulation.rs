@@ -0,0 +1,138 @@
+// Copyright 2021 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under The General Public License (GPL), version 3.
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied. Please review the Licences for the specific language governing
+// permissions and limitations relating to use of the SAFE Network Software.
+
+use crate::{
+    rng,
+    routing::{transport::Scheduler, Config, MemoryNetwork, TransportBackend},
+};
+use std::{ops::Range, sync::Arc, time::Duration};
+use tokio::{
+    task::{self, JoinHandle},
+    time,
+};
+
+// Latency of the messages, unless specified otherwise.
+const DEFAULT_LATENCY: Range<Duration> = Duration::from_millis(1)..Duration::from_millis(50);
+
+/// Deterministic simulation of a network of nodes running in a single process.
+///
+/// Creating a `Simulation` seeds all the randomness used by routing on the current thread (keys,
+/// node names, DKG, ...). The nodes created with `Simulation::config` talk to each other over an
+/// in-memory network where each message is delayed by a latency drawn from the same seed, and are
+/// delivered in the order of their delivery times. Provided all the timers run on virtual time,
+/// this makes a simulation fully reproducible from its seed, so a failing scenario can be replayed
+/// (and shrunk) by running it again with the same seed.
+///
+/// A failing scenario can also be shrunk with `Simulation::shrink`, to the smallest size (number of
+/// nodes, of churn events, ...) that still fails with the same seed.
+///
+/// Therefore the simulation must run on a current-thread tokio runtime with the clock paused
+/// (`tokio::time::pause`, which requires tokio's `test-util` feature), and should be the only thing
+/// running on that runtime.
+#[derive(Debug)]
+pub struct Simulation {
+    seed: u64,
+    network: MemoryNetwork,
+    driver: JoinHandle<()>,
+}
+
+impl Simulation {
+    /// Starts a new simulation with the given seed.
+    pub fn new(seed: u64) -> Self {
+        Self::with_latency(seed, DEFAULT_LATENCY)
+    }
+
+    /// Starts a new simulation with the given seed, with message latencies in the given range.
+    pub fn with_latency(seed: u64, latency: Range<Duration>) -> Self {
+        rng::seed(seed);
+
+        let scheduler = Arc::new(Scheduler::new(latency));
+        let driver = task::spawn({
+            let scheduler = scheduler.clone();
+            async move { scheduler.run().await }
+        });
+
+        Self {
+            seed,
+            network: MemoryNetwork::with_scheduler(scheduler),
+            driver,
+        }
+    }
+
+    /// Seed of this simulation.
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// The simulated network.
+    pub fn network(&self) -> &MemoryNetwork {
+        &self.network
+    }
+
+    /// Returns the config for a node taking part in this simulation.
+    pub fn config(&self) -> Config {
+        Config {
+            transport: TransportBackend::Memory(self.network.clone()),
+            ..Default::default()
+        }
+    }
+
+    /// Lets the simulation run for the given (virtual) duration.
+    pub async fn run_for(&self, duration: Duration) {
+        time::sleep(duration).await
+    }
+
+    /// Shrinks a scenario that fails with `seed` at the given `size`. `fails(seed, size)` runs the
+    /// scenario of the given size, each time in a fresh simulation (and runtime), and returns
+    /// whether it failed. Returns the smallest size found to still fail. Unless that is 1, the
+    /// scenario passes at one smaller.
+    pub fn shrink<F>(seed: u64, size: usize, mut fails: F) -> usize
+    where
+        F: FnMut(u64, usize) -> bool,
+    {
+        let mut size = size;
+        let mut step = size / 2;
+
+        // Take the largest steps down that still fail, then smaller ones once they pass. As the
+        // failures needn't be monotonic in the size, a step that failed is tried again.
+        while step > 0 {
+            if fails(seed, size - step) {
+                size -= step;
+                step = step.min(size / 2);
+            } else {
+                step /= 2;
+            }
+        }
+
+        size
+    }
+}
+
+impl Drop for Simulation {
+    fn drop(&mut self) {
+        self.driver.abort()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shrink() {
+        assert_eq!(Simulation::shrink(7, 100, |_, size| size >= 13), 13);
+        assert_eq!(Simulation::shrink(7, 100, |_, size| size > 0), 1);
+        assert_eq!(Simulation::shrink(7, 100, |_, _| false), 100);
+
+        // The seed is kept while shrinking.
+        let _ = Simulation::shrink(7, 100, |seed, _| {
+            assert_eq!(seed, 7);
+            true
+        });
+    }
+}
@@ -11,8 +11,8 @@ mod utils;
 use anyhow::{Error, Result};
 use ed25519_dalek::Keypair;
 use futures::future;
-use sn_routing::{Config, Event, NodeElderChange, ELDER_SIZE};
-use std::collections::HashSet;
+use sn_routing::{Config, Event, MemoryNetwork, NodeElderChange, TransportBackend, ELDER_SIZE};
+use std::{collections::HashSet, env, fs};
 use tokio::time;
 use utils::*;
 use xor_name::XOR_NAME_LEN;
@@ -36,6 +36,38 @@ async fn test_genesis_node() -> Result<()> {
     Ok(())
 }
 
+#[tokio::test]
+async fn test_genesis_node_restore() -> Result<()> {
+    let (node, mut event_stream) = create_node(Config {
+        first: true,
+        ..Default::default()
+    })
+    .await?;
+
+    assert_next_event!(event_stream, Event::EldersChanged { .. });
+
+    let path = env::temp_dir().join(format!("sn_routing_snapshot_{}", node.name().await));
+    node.snapshot(&path).await?;
+
+    let mut config = Config {
+        restore_from: Some(path.clone()),
+        ..Default::default()
+    };
+    // The original node is still bound to its port, so let the restored one pick another.
+    config.transport_config.local_port = Some(0);
+    let (restored, _event_stream) = create_node(config).await?;
+    let _ = fs::remove_file(&path);
+
+    assert_eq!(restored.name().await, node.name().await);
+    assert_eq!(
+        restored.section_chain().await.last_key(),
+        node.section_chain().await.last_key()
+    );
+    assert!(restored.is_elder().await);
+
+    Ok(())
+}
+
 #[tokio::test]
 async fn test_node_bootstrapping() -> Result<()> {
     let (genesis_node, mut event_stream) = create_node(Config {
@@ -64,6 +96,40 @@ async fn test_node_bootstrapping() -> Result<()> {
     Ok(())
 }
 
+#[tokio::test]
+async fn test_node_bootstrapping_over_memory_transport() -> Result<()> {
+    let network = MemoryNetwork::new();
+
+    let (genesis_node, mut event_stream) = create_node(Config {
+        first: true,
+        transport: TransportBackend::Memory(network.clone()),
+        ..Default::default()
+    })
+    .await?;
+
+    let genesis_handler = tokio::spawn(async move {
+        assert_next_event!(event_stream, Event::EldersChanged { .. });
+        assert_next_event!(event_stream, Event::MemberJoined { .. });
+    });
+
+    let genesis_contact = genesis_node.our_connection_info();
+    let (node1, _event_stream) = create_node(Config {
+        transport: TransportBackend::Memory(network.clone()),
+        ..config_with_contact(genesis_contact)
+    })
+    .await?;
+
+    genesis_handler.await?;
+
+    assert_eq!(network.len(), 2);
+
+    let elder_size = 2;
+    verify_invariants_for_node(&genesis_node, elder_size).await?;
+    verify_invariants_for_node(&node1, elder_size).await?;
+
+    Ok(())
+}
+
 #[tokio::test]
 async fn test_startup_section_bootstrapping() -> Result<()> {
     // Create the genesis node.
@@ -137,3 +203,32 @@ async fn test_startup_elders() -> Result<()> {
 
     Ok(())
 }
+
+#[tokio::test]
+async fn test_startup_elders_over_memory_transport() -> Result<()> {
+    let network = MemoryNetwork::new();
+    let mut nodes =
+        create_connected_nodes_with(ELDER_SIZE, TransportBackend::Memory(network.clone())).await?;
+    assert_eq!(network.len(), ELDER_SIZE);
+
+    future::join_all(nodes.iter_mut().map(|(node, stream)| async move {
+        if node.is_elder().await {
+            return;
+        }
+
+        assert_event!(
+            stream,
+            Event::EldersChanged {
+                self_status_change: NodeElderChange::Promoted,
+                ..
+            }
+        )
+    }))
+    .await;
+
+    for (node, _) in &nodes {
+        verify_invariants_for_node(node, ELDER_SIZE).await?;
+    }
+
+    Ok(())
+}
@@ -9,8 +9,9 @@
 mod utils;
 
 use anyhow::{anyhow, format_err, Result};
+use assert_matches::assert_matches;
 use bytes::Bytes;
-use qp2p::QuicP2p;
+use qp2p::{Endpoint, IncomingMessages, QuicP2p};
 use sn_data_types::Keypair;
 use sn_messaging::client::ProcessMsg;
 use sn_messaging::{
@@ -18,8 +19,15 @@ use sn_messaging::{
     location::{Aggregation, Itinerary},
     DstLocation, MessageId, SrcLocation,
 };
-use sn_routing::{Config, Error, Event, NodeElderChange};
-use std::net::{IpAddr, Ipv4Addr};
+use sn_routing::{
+    ClientHandshake, ClientIdentity, Config, DeliveryPolicy, Error, Event, EventFilter,
+    NodeElderChange,
+};
+use std::{
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    sync::Arc,
+    time::Duration,
+};
 use utils::*;
 use xor_name::XorName;
 
@@ -67,7 +75,7 @@ async fn test_messages_client_node() -> Result<()> {
     let node_handler = tokio::spawn(async move {
         while let Some(event) = event_stream.next().await {
             match event {
-                Event::ClientMsgReceived { msg, user } => {
+                Event::ClientMsgReceived { msg, user, .. } => {
                     assert_eq!(*msg, query_clone.clone());
                     node.send_message(
                         Itinerary {
@@ -81,12 +89,12 @@ async fn test_messages_client_node() -> Result<()> {
                         None,
                     )
                     .await?;
-                    break;
+                    return Ok(user);
                 }
                 other => println!("Ignoring msg: {:?}", other),
             }
         }
-        Ok::<(), Error>(())
+        Err(anyhow!("Event stream closed"))
     });
 
     let query_bytes = query.serialize(XorName::from(pk), section_key)?;
@@ -95,16 +103,14 @@ async fn test_messages_client_node() -> Result<()> {
         .await?;
 
     // just await for node to respond to client
-    node_handler.await??;
+    let user = node_handler.await??;
 
     if let Some((_, resp)) = incoming_messages.next().await {
-        // the xorname assigned to each end user is computed from
-        // the client socket addr plus the client section prefix
-        let socket_id =
-            XorName::from_content(&[&bincode::serialize(&client_endpoint.socket_addr())?]);
-        let user_xorname = section_prefix.substituted_in(socket_id);
+        // the xorname assigned to each end user is within the section prefix, and its socket id
+        // is the encrypted client socket addr
+        assert!(section_prefix.matches(&user.xorname));
 
-        let expected_bytes = query.serialize(user_xorname, section_key)?;
+        let expected_bytes = query.serialize(user.xorname, section_key)?;
 
         assert_eq!(resp, expected_bytes);
 
@@ -117,6 +123,113 @@ async fn test_messages_client_node() -> Result<()> {
     }
 }
 
+#[tokio::test]
+async fn test_client_handshake() -> Result<()> {
+    let (node, mut event_stream) = create_node(Config {
+        first: true,
+        ..Default::default()
+    })
+    .await?;
+
+    let config = sn_routing::TransportConfig {
+        local_ip: Some(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1))),
+        ..Default::default()
+    };
+    let node_addr = node.our_connection_info();
+    let section_key = *node.section_chain().await.last_key();
+
+    let client = QuicP2p::with_config(Some(config), &[node_addr], false)?;
+    let (client_endpoint, _, mut incoming_messages, _) = client.new_endpoint().await?;
+    client_endpoint.connect_to(&node_addr).await?;
+
+    let secret_key = bls::SecretKey::random();
+    let (nonce, node_name) = match exchange(
+        &client_endpoint,
+        &mut incoming_messages,
+        node_addr,
+        ClientHandshake::Hello,
+    )
+    .await?
+    {
+        ClientHandshake::Challenge { nonce, node } => (nonce, node),
+        other => return Err(format_err!("unexpected response: {:?}", other)),
+    };
+    assert_eq!(node_name, node.name().await);
+
+    let user = match exchange(
+        &client_endpoint,
+        &mut incoming_messages,
+        node_addr,
+        ClientHandshake::prove_bls(&secret_key, &nonce, &node_name),
+    )
+    .await?
+    {
+        ClientHandshake::Accepted { user } => user,
+        other => return Err(format_err!("unexpected response: {:?}", other)),
+    };
+
+    // Replaying the proof fails, as the challenge is used up.
+    assert_eq!(
+        exchange(
+            &client_endpoint,
+            &mut incoming_messages,
+            node_addr,
+            ClientHandshake::prove_bls(&secret_key, &nonce, &node_name),
+        )
+        .await?,
+        ClientHandshake::Rejected
+    );
+
+    // The messages from the client carry its identity.
+    let keypair = Keypair::new_ed25519(&mut rand::thread_rng());
+    let query = ClientMsg::Process(ProcessMsg::Query {
+        id: MessageId::new(),
+        query: Query::Transfer(TransferQuery::GetBalance(keypair.public_key())),
+        client_signed: ClientSigned {
+            public_key: keypair.public_key(),
+            signature: keypair.sign(b"the msg"),
+        },
+    });
+    client_endpoint
+        .send_message(
+            query.serialize(XorName::from(keypair.public_key()), section_key)?,
+            &node_addr,
+        )
+        .await?;
+
+    while let Some(event) = event_stream.next().await {
+        if let Event::ClientMsgReceived {
+            user: received_user,
+            identity,
+            ..
+        } = event
+        {
+            assert_eq!(received_user, user);
+            assert_eq!(identity, Some(ClientIdentity::Bls(secret_key.public_key())));
+            return Ok(());
+        }
+    }
+
+    Err(format_err!("event stream closed"))
+}
+
+// Sends a handshake message from the client and returns the response of the node.
+async fn exchange(
+    endpoint: &Endpoint,
+    incoming_messages: &mut IncomingMessages,
+    node_addr: SocketAddr,
+    msg: ClientHandshake,
+) -> Result<ClientHandshake> {
+    endpoint
+        .send_message(Bytes::from(msg.encode()?), &node_addr)
+        .await?;
+    let (_, bytes) = incoming_messages
+        .next()
+        .await
+        .ok_or_else(|| format_err!("connection closed"))?;
+    ClientHandshake::decode(&bytes).ok_or_else(|| format_err!("not a handshake message"))
+}
+
 #[tokio::test]
 async fn test_messages_between_nodes() -> Result<()> {
     let msg = b"hello!";
@@ -207,3 +320,261 @@ async fn test_messages_between_nodes() -> Result<()> {
 
     Err(format_err!("message not received"))
 }
+
+#[tokio::test]
+async fn test_subscribe_to_messages() -> Result<()> {
+    let msg = b"hello!";
+
+    let (node1, _event_stream) = create_node(Config {
+        first: true,
+        ..Default::default()
+    })
+    .await?;
+    let node1_name = node1.name().await;
+
+    let mut membership_stream = node1.subscribe(EventFilter::Membership).await;
+    let mut message_stream = node1
+        .subscribe(EventFilter::MessagesTo(DstLocation::Node(node1_name)))
+        .await;
+
+    let (node2, _event_stream) =
+        create_node(config_with_contact(node1.our_connection_info())).await?;
+    let node2_name = node2.name().await;
+
+    assert_event!(membership_stream, Event::MemberJoined { name, .. } if name == node2_name);
+
+    let itinerary = Itinerary {
+        src: SrcLocation::Node(node2_name),
+        dst: DstLocation::Node(node1_name),
+        aggregation: Aggregation::None,
+    };
+    node2
+        .send_message(itinerary, Bytes::from_static(msg), None)
+        .await?;
+
+    assert_event!(
+        message_stream,
+        Event::MessageReceived { content, .. } if content == Bytes::from_static(msg)
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_request_response_between_nodes() -> Result<()> {
+    let request = b"ping";
+    let response = b"pong";
+
+    let (node1, mut event_stream) = create_node(Config {
+        first: true,
+        ..Default::default()
+    })
+    .await?;
+    let node1 = Arc::new(node1);
+    let node1_name = node1.name().await;
+
+    // answer the first request only
+    let responder = node1.clone();
+    let node_handler = tokio::spawn(async move {
+        while let Some(event) = event_stream.next().await {
+            if let Event::RequestReceived {
+                content, handle, ..
+            } = event
+            {
+                assert_eq!(content, Bytes::from_static(request));
+                let handle = handle
+                    .take()
+                    .ok_or_else(|| format_err!("request handle taken"))?;
+                return responder
+                    .respond(handle, Bytes::from_static(response))
+                    .await
+                    .map_err(|err| anyhow!(err));
+            }
+        }
+        Err(format_err!("request not received"))
+    });
+
+    let (node2, mut event_stream) =
+        create_node(config_with_contact(node1.our_connection_info())).await?;
+    assert_event!(
+        event_stream,
+        Event::EldersChanged {
+            self_status_change: NodeElderChange::Promoted,
+            ..
+        }
+    );
+    let node2_name = node2.name().await;
+
+    let itinerary = || Itinerary {
+        src: SrcLocation::Node(node2_name),
+        dst: DstLocation::Node(node1_name),
+        aggregation: Aggregation::None,
+    };
+
+    let received = node2
+        .request(itinerary(), Bytes::from_static(request), TIMEOUT)
+        .await?;
+    assert_eq!(received.content, Bytes::from_static(response));
+    assert_eq!(received.src, SrcLocation::Node(node1_name));
+    node_handler.await??;
+
+    let result = node2
+        .request(
+            itinerary(),
+            Bytes::from_static(request),
+            Duration::from_millis(500),
+        )
+        .await;
+    assert_matches!(result, Err(Error::RequestTimeout));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_message_with_receipt_between_nodes() -> Result<()> {
+    let msg = b"did you get this?";
+
+    let (node1, _event_stream) = create_node(Config {
+        first: true,
+        ..Default::default()
+    })
+    .await?;
+    let node1_name = node1.name().await;
+
+    let mut membership_stream = node1.subscribe(EventFilter::Membership).await;
+    let mut received_stream = node1.subscribe(EventFilter::Messages).await;
+
+    let (node2, _event_stream) =
+        create_node(config_with_contact(node1.our_connection_info())).await?;
+    let node2_name = node2.name().await;
+    let mut delivered_stream = node2.subscribe(EventFilter::Messages).await;
+
+    assert_event!(membership_stream, Event::MemberJoined { name, .. } if name == node2_name);
+
+    let delivery = node2
+        .send_message_with_receipt(
+            Itinerary {
+                src: SrcLocation::Node(node2_name),
+                dst: DstLocation::Node(node1_name),
+                aggregation: Aggregation::None,
+            },
+            Bytes::from_static(msg),
+            DeliveryPolicy::default(),
+        )
+        .await?;
+    let msg_id = delivery.msg_id();
+
+    tokio::time::timeout(TIMEOUT, delivery.delivered()).await??;
+    assert_event!(delivered_stream, Event::MessageDelivered { msg_id: id } if id == msg_id);
+    assert_event!(
+        received_stream,
+        Event::MessageReceived { content, .. } if content == Bytes::from_static(msg)
+    );
+
+    // Nobody acknowledges a message to a node that doesn't exist.
+    let delivery = node2
+        .send_message_with_receipt(
+            Itinerary {
+                src: SrcLocation::Node(node2_name),
+                dst: DstLocation::Node(XorName::random()),
+                aggregation: Aggregation::None,
+            },
+            Bytes::from_static(msg),
+            DeliveryPolicy {
+                retransmit_after: Duration::from_millis(200),
+                max_attempts: 2,
+            },
+        )
+        .await?;
+    assert_matches!(
+        tokio::time::timeout(TIMEOUT, delivery.delivered()).await?,
+        Err(Error::DeliveryTimeout)
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_large_message_between_nodes() -> Result<()> {
+    let content: Bytes = (0..(3 * 1024 * 1024 + 17)).map(|i| i as u8).collect();
+
+    let (node1, _event_stream) = create_node(Config {
+        first: true,
+        ..Default::default()
+    })
+    .await?;
+    let node1_name = node1.name().await;
+
+    let mut membership_stream = node1.subscribe(EventFilter::Membership).await;
+    let mut message_stream = node1.subscribe(EventFilter::Messages).await;
+
+    let (node2, _event_stream) =
+        create_node(config_with_contact(node1.our_connection_info())).await?;
+    let node2_name = node2.name().await;
+
+    assert_event!(membership_stream, Event::MemberJoined { name, .. } if name == node2_name);
+
+    node2
+        .send_large_message(node1_name, content.clone())
+        .await?;
+
+    assert_event!(
+        message_stream,
+        Event::MessageReceived { content: received, src: SrcLocation::Node(src), .. }
+            if received == content && src == node2_name
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_traced_message_between_nodes() -> Result<()> {
+    let msg = b"where did you go?";
+
+    let (node1, _event_stream) = create_node(Config {
+        first: true,
+        ..Default::default()
+    })
+    .await?;
+    let node1_name = node1.name().await;
+
+    let mut membership_stream = node1.subscribe(EventFilter::Membership).await;
+    let mut message_stream = node1.subscribe(EventFilter::Messages).await;
+
+    let (node2, _event_stream) =
+        create_node(config_with_contact(node1.our_connection_info())).await?;
+    let node2_name = node2.name().await;
+
+    assert_event!(membership_stream, Event::MemberJoined { name, .. } if name == node2_name);
+
+    let itinerary = Itinerary {
+        src: SrcLocation::Node(node2_name),
+        dst: DstLocation::Node(node1_name),
+        aggregation: Aggregation::None,
+    };
+    node2
+        .send_traced_message(itinerary, Bytes::from_static(msg))
+        .await?;
+
+    let trace = loop {
+        match tokio::time::timeout(TIMEOUT, message_stream.next()).await {
+            Ok(Some(Event::MessageReceived { content, trace, .. }))
+                if content == Bytes::from_static(msg) =>
+            {
+                break trace
+            }
+            Ok(_) => {}
+            Err(_) => return Err(anyhow!("Timeout when expecting the traced message")),
+        }
+    };
+    let trace = trace.ok_or_else(|| anyhow!("Message received without trace"))?;
+    assert_eq!(trace.len(), 1);
+    assert_eq!(trace[0].name, node2_name);
+    assert_eq!(trace[0].targets, vec![node1_name]);
+
+    let route = node2.trace_route(node1_name, TIMEOUT).await?;
+    let route: Vec<_> = route.iter().map(|hop| hop.name).collect();
+    assert_eq!(route, vec![node2_name]);
+
+    Ok(())
+}
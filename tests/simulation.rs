@@ -0,0 +1,76 @@
+// Copyright 2021 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under The General Public License (GPL), version 3.
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied. Please review the Licences for the specific language governing
+// permissions and limitations relating to use of the SAFE Network Software.
+
+mod utils;
+
+use anyhow::Result;
+use itertools::Itertools;
+use sn_routing::{Config, Event, PeerUtils, Simulation};
+use std::{iter, time::Duration};
+use tokio::{runtime, time};
+use utils::*;
+use xor_name::XorName;
+
+#[test]
+fn test_simulation_is_reproducible() -> Result<()> {
+    let first = simulate(7)?;
+    let second = simulate(7)?;
+    assert_eq!(first, second);
+
+    let other = simulate(8)?;
+    assert_ne!(first, other);
+
+    Ok(())
+}
+
+// Runs a small network on a fresh runtime and returns the names of its nodes and of its elders.
+fn simulate(seed: u64) -> Result<(Vec<XorName>, Vec<XorName>)> {
+    let runtime = runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()?;
+
+    runtime.block_on(async {
+        time::pause();
+
+        let simulation = Simulation::new(seed);
+
+        let (genesis_node, mut event_stream) = create_node(Config {
+            first: true,
+            ..simulation.config()
+        })
+        .await?;
+        assert_next_event!(event_stream, Event::EldersChanged { .. });
+
+        let mut nodes = vec![genesis_node];
+        for _ in 0..3 {
+            let mut config = simulation.config();
+            config.transport_config.hard_coded_contacts =
+                iter::once(nodes[0].our_connection_info()).collect();
+
+            let (node, _event_stream) = create_node(config).await?;
+            nodes.push(node);
+        }
+
+        simulation.run_for(Duration::from_secs(10)).await;
+
+        let mut names = vec![];
+        for node in &nodes {
+            names.push(node.name().await);
+        }
+
+        let elders = nodes[0]
+            .our_elders()
+            .await
+            .iter()
+            .map(|elder| *elder.name())
+            .sorted()
+            .collect();
+
+        Ok((names, elders))
+    })
+}
@@ -16,7 +16,7 @@ use futures::future;
 use itertools::Itertools;
 use sn_routing::{
     Config, Event, EventStream, NodeElderChange, Routing, SectionAuthorityProviderUtils,
-    TransportConfig, MIN_AGE,
+    TransportBackend, TransportConfig, MIN_AGE,
 };
 use std::{
     collections::{BTreeSet, HashSet},
@@ -92,11 +92,20 @@ macro_rules! assert_event {
 
 /// Create the given number of nodes and wait until they all connect.
 pub async fn create_connected_nodes(count: usize) -> Result<Vec<(Routing, EventStream)>> {
+    create_connected_nodes_with(count, TransportBackend::Quic).await
+}
+
+/// Create the given number of nodes on the given transport and wait until they all connect.
+pub async fn create_connected_nodes_with(
+    count: usize,
+    transport: TransportBackend,
+) -> Result<Vec<(Routing, EventStream)>> {
     let mut nodes = vec![];
 
     // Create the first node
     let (node, mut event_stream) = create_node(Config {
         first: true,
+        transport: transport.clone(),
         ..Default::default()
     })
     .await?;
@@ -107,7 +116,12 @@ pub async fn create_connected_nodes(count: usize) -> Result<Vec<(Routing, EventS
     nodes.push((node, event_stream));
 
     // Create the other nodes bootstrapping off the first node.
-    let other_nodes = (1..count).map(|_| create_node(config_with_contact(bootstrap_contact)));
+    let other_nodes = (1..count).map(|_| {
+        create_node(Config {
+            transport: transport.clone(),
+            ..config_with_contact(bootstrap_contact)
+        })
+    });
 
     for node in future::try_join_all(other_nodes).await? {
         nodes.push(node);